@@ -1,5 +1,6 @@
 use crate::{
-    hash::{hash_to_hex, hex_to_hash},
+    config::{Config, Scope},
+    hash::{hash_to_hex, Hash},
     repository::Repository,
     util::Xxh3HashSet,
 };
@@ -18,8 +19,71 @@ fn branch_exists(repo: &Repository, name: &str) -> bool {
     branch_path(repo, name).exists()
 }
 
-/// Print all local branches, marking the current one with *.
-pub fn list(repo: &Repository) -> Result<()> {
+/// The branch of a remote a local branch follows: `branch.<name>.remote` and `branch.<name>.merge`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Upstream {
+    pub remote: String,
+    pub branch: String,
+}
+
+impl Upstream {
+    /// Where fetch keeps the remote branch, e.g. `refs/remotes/origin/main`.
+    #[inline]
+    #[must_use]
+    pub fn tracking_ref(&self) -> String {
+        format!("refs/remotes/{}/{}", self.remote, self.branch)
+    }
+}
+
+#[inline]
+#[must_use]
+pub fn upstream(config: &Config, name: &str) -> Option<Upstream> {
+    Some(Upstream {
+        remote: config.get(&format!("branch.{name}.remote"))?.to_owned(),
+        branch: config.get(&format!("branch.{name}.merge"))?.to_owned(),
+    })
+}
+
+/// Make `name` track `remote_branch` of `remote`.
+pub fn set_upstream(repo: &Repository, name: &str, remote: &str, remote_branch: &str) -> Result<()> {
+    crate::config::edit(Some(&repo.root), Scope::Repo, |config| {
+        config.set(&format!("branch.{name}.remote"), remote)?;
+        config.set(&format!("branch.{name}.merge"), remote_branch)
+    })
+}
+
+/// `mog branch --set-upstream-to origin/main [name]`: `name` (default: the current branch)
+/// tracks a remote branch we have fetched.
+pub fn set_upstream_to(repo: &Repository, name: Option<&str>, upstream: &str) -> Result<()> {
+    let name = match name {
+        Some(name) => name.to_owned(),
+        None       => repo.current_branch()?.ok_or_else(|| anyhow::anyhow!("HEAD is detached, name the branch"))?,
+    };
+    if !branch_exists(repo, &name) {
+        bail!("branch '{name}' not found");
+    }
+
+    let Some((remote, remote_branch)) = upstream.split_once('/') else {
+        bail!("expected <remote>/<branch>, got '{upstream}'");
+    };
+    if repo.read_ref(&format!("refs/remotes/{remote}/{remote_branch}")).is_err() {
+        bail!("no remote branch '{upstream}' (fetch it first)");
+    }
+
+    set_upstream(repo, &name, remote, remote_branch)?;
+    println!("branch '{name}' now tracks '{upstream}'");
+    Ok(())
+}
+
+/// Commits only `local` has and commits only `upstream` has.
+pub fn ahead_behind(repo: &mut Repository, local: &Hash, upstream: &Hash) -> (usize, usize) {
+    let ours   = repo.reachable_commits(local);
+    let theirs = repo.reachable_commits(upstream);
+    (ours.difference(&theirs).count(), theirs.difference(&ours).count())
+}
+
+/// Print all local branches, marking the current one with *, and how each compares to its upstream.
+pub fn list(repo: &mut Repository) -> Result<()> {
     let heads_dir = repo.root.join(".mog/refs/heads");
     if !heads_dir.exists() {
         println!("no branches yet");
@@ -27,6 +91,7 @@ pub fn list(repo: &Repository) -> Result<()> {
     }
 
     let current = repo.current_branch().unwrap_or(None);
+    let config  = crate::config::load(Some(&repo.root))?;
 
     let mut branches = std::fs::read_dir(&heads_dir)?
         .filter_map(Result::ok)
@@ -37,12 +102,29 @@ pub fn list(repo: &Repository) -> Result<()> {
 
     for branch in branches {
         let marker = if current.as_deref() == Some(&branch) { "* " } else { "  " };
-        let hash = repo.read_ref(&format!("refs/heads/{branch}")).map_or_else(
-            |_| "?".to_string(),
+        let tip = repo.read_ref(&format!("refs/heads/{branch}")).ok();
+        let hash = tip.map_or_else(
+            || "?".to_string(),
             |h| hash_to_hex(&h)[..8].to_string()
         );
 
-        println!("{marker}{branch}  {hash}");
+        let tracking = match (upstream(&config, &branch), tip) {
+            (Some(upstream), Some(tip)) => {
+                let name = format!("{}/{}", upstream.remote, upstream.branch);
+                match repo.read_ref(&upstream.tracking_ref()) {
+                    Ok(theirs) => match ahead_behind(repo, &tip, &theirs) {
+                        (0, 0)          => format!("  [{name}]"),
+                        (ahead, 0)      => format!("  [{name}: ahead {ahead}]"),
+                        (0, behind)     => format!("  [{name}: behind {behind}]"),
+                        (ahead, behind) => format!("  [{name}: ahead {ahead}, behind {behind}]"),
+                    },
+                    Err(_) => format!("  [{name}: gone]"),
+                }
+            }
+            _ => String::new(),
+        };
+
+        println!("{marker}{branch}  {hash}{tracking}");
     }
 
     Ok(())
@@ -57,22 +139,15 @@ pub fn create(repo: &mut Repository, name: &str, target: Option<&str>) -> Result
     validate_branch_name(name)?;
 
     let hash = match target {
-        Some(t) => {
-            let branch_ref = format!("refs/heads/{t}");
-            let branch_path = repo.root.join(".mog").join(&branch_ref);
-            if branch_path.exists() {
-                repo.read_ref(&branch_ref)?
-            } else {
-                hex_to_hash(t)?
-            }
-        }
-        None => repo.read_head_commit()?,
+        Some(t) => repo.resolve_to_commit(t)?.0,
+        None    => repo.read_head_commit()?,
     };
 
     let object = repo.read_object(&hash)?;
     object.try_as_commit_id().map_err(|_| anyhow::anyhow!("target does not resolve to a commit"))?;
 
-    repo.write_ref(&format!("refs/heads/{name}"), &hash)?;
+    let reason = format!("branch: Created from {}", target.unwrap_or("HEAD"));
+    repo.write_ref(&format!("refs/heads/{name}"), &hash, &reason)?;
     println!("created branch '{name}' at {}", &hash_to_hex(&hash)[..8]);
 
     Ok(())
@@ -112,7 +187,8 @@ pub fn delete(repo: &mut Repository, name: &str) -> Result<()> {
         );
     }
 
-    std::fs::remove_file(branch_path(repo, name))?;
+    repo.delete_ref(&format!("refs/heads/{name}"), "branch: deleted")?;
+    forget_settings(repo, name)?;
     println!("deleted branch '{name}'");
     Ok(())
 }
@@ -128,7 +204,8 @@ pub fn force_delete(repo: &mut Repository, name: &str) -> Result<()> {
     }
 
     let hash = repo.read_ref(&format!("refs/heads/{name}"))?;
-    std::fs::remove_file(branch_path(repo, name))?;
+    repo.delete_ref(&format!("refs/heads/{name}"), "branch: force-deleted")?;
+    forget_settings(repo, name)?;
     println!("force-deleted branch '{name}' (was {})", &hash_to_hex(&hash)[..8]);
     Ok(())
 }
@@ -144,16 +221,37 @@ pub fn rename(repo: &Repository, old: &str, new: &str) -> Result<()> {
 
     validate_branch_name(new)?;
 
-    let hash = repo.read_ref(&format!("refs/heads/{old}"))?;
-    repo.write_ref(&format!("refs/heads/{new}"), &hash)?;
+    //
+    // The log moves with the branch, like the branch's history does.
+    //
+    let logs_dir = repo.root.join(".mog/logs/refs/heads");
+    if logs_dir.join(old).exists() {
+        std::fs::rename(logs_dir.join(old), logs_dir.join(new))?;
+    }
+
+    let hash   = repo.read_ref(&format!("refs/heads/{old}"))?;
+    let reason = format!("branch: renamed refs/heads/{old} to refs/heads/{new}");
+    repo.write_ref(&format!("refs/heads/{new}"), &hash, &reason)?;
     std::fs::remove_file(branch_path(repo, old))?;
 
+    //
+    // Settings (the upstream) move too, unless the new name already had some of its own.
+    //
+    let config = crate::config::load_scope(Some(&repo.root), Scope::Repo)?;
+    if config.subsections("branch").any(|sub| sub == old || sub == new) {
+        crate::config::edit(Some(&repo.root), Scope::Repo, |config| {
+            config.remove_section("branch", new);
+            config.rename_section("branch", old, new);
+            Ok(())
+        })?;
+    }
+
     //
     // If we renamed the currently checked out branch, update HEAD too
     //
     if repo.current_branch()?.as_deref() == Some(old) {
-        std::fs::write(
-            repo.root.join(".mog/HEAD"),
+        crate::lockfile::write_atomic(
+            &repo.root.join(".mog/HEAD"),
             format!("ref: refs/heads/{new}\n"),
         )?;
     }
@@ -162,8 +260,21 @@ pub fn rename(repo: &Repository, old: &str, new: &str) -> Result<()> {
     Ok(())
 }
 
-// Reject names that would break the filesystem or confuse path parsing.
-fn validate_branch_name(name: &str) -> Result<()> {
+/// Drop `[branch "name"]` from the repository config, if there is one.
+#[inline]
+fn forget_settings(repo: &Repository, name: &str) -> Result<()> {
+    let config = crate::config::load_scope(Some(&repo.root), Scope::Repo)?;
+    if config.subsections("branch").any(|sub| sub == name) {
+        crate::config::edit(Some(&repo.root), Scope::Repo, |config| {
+            config.remove_section("branch", name);
+            Ok(())
+        })?;
+    }
+    Ok(())
+}
+
+/// Reject names that would break the filesystem or confuse path parsing.
+pub fn validate_branch_name(name: &str) -> Result<()> {
     if name.is_empty() {
         bail!("branch name cannot be empty");
     }
@@ -176,6 +287,12 @@ fn validate_branch_name(name: &str) -> Result<()> {
     if name.starts_with('-') {
         bail!("branch name cannot start with '-'");
     }
+    if name.contains(['~', '^', ':']) || name.contains("..") {
+        bail!("branch name cannot contain '~', '^', ':' or '..' (they mean something in revisions)");
+    }
+    if name.contains("@{") {
+        bail!("branch name cannot contain '@{{'");
+    }
     if name == "HEAD" {
         bail!("'HEAD' is not a valid branch name");
     }
@@ -1,11 +1,10 @@
 use anyhow::Result;
-use crate::hash::hex_to_hash;
 use crate::repository::Repository;
 use crate::object::Object;
 use crate::tree::TreeEntryRef;
 
-pub fn cat_file(repo: &mut Repository, hash_str: &str, f: &mut dyn core::fmt::Write) -> Result<()> {
-    let hash = hex_to_hash(hash_str)?;
+pub fn cat_file(repo: &mut Repository, revision: &str, f: &mut dyn core::fmt::Write) -> Result<()> {
+    let hash = crate::revision::resolve_object(repo, revision)?;
     let object = repo.read_object(&hash)?;
 
     match object {
@@ -25,12 +24,23 @@ pub fn cat_file(repo: &mut Repository, hash_str: &str, f: &mut dyn core::fmt::Wr
             for parent in repo.commit.get_parents(id) {
                 writeln!(f, "parent {}", hex::encode(parent))?;
             }
+            writeln!(f, "author {}", repo.commit.get_author_signature(id))?;
+            writeln!(f, "committer {}", repo.commit.get_committer_signature(id))?;
+            writeln!(f, "\n{}", repo.commit.get_message(id))?;
+        }
+        Object::ChunkList(_) => {
+            let data = repo.read_blob_bytes_without_touching_cache(&hash)?;
+            writeln!(f, "{}", String::from_utf8_lossy(&data))?;
+        }
+        Object::Tag(id) => {
+            writeln!(f, "object {}", hex::encode(repo.tag.get_target(id)))?;
+            writeln!(f, "tag {}", repo.tag.get_name(id))?;
             writeln!(f,
-                "author {} {}",
-                repo.commit.get_author(id),
-                repo.commit.get_timestamp(id)
+                "tagger {} {}",
+                repo.tag.get_tagger(id),
+                repo.tag.get_timestamp(id)
             )?;
-            writeln!(f, "\n{}", repo.commit.get_message(id))?;
+            writeln!(f, "\n{}", repo.tag.get_message(id))?;
         }
     }
 
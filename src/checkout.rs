@@ -1,7 +1,8 @@
-use crate::hash::{hash_to_hex, hex_to_hash, Hash};
+use crate::hash::{hash_to_hex, Hash};
 use crate::index::Index;
 use crate::repository::Repository;
-use crate::object::{Object, MODE_DIR};
+use crate::object::{Object, MODE_DIR, MODE_EXEC, MODE_LINK};
+use crate::util::set_executable;
 use crate::storage::Storage;
 use crate::store::{BlobId, CommitId};
 use crate::tree::TreeEntry;
@@ -12,28 +13,26 @@ pub fn checkout(repo: &mut Repository, branch: &str) -> Result<()> {
     let branch_ref = format!("refs/heads/{branch}");
     let branch_path = repo.root.join(".mog").join(&branch_ref);
 
+    let from = match repo.current_branch()? {
+        Some(name) => name,
+        None       => repo.read_head_commit().map(|h| hash_to_hex(&h)[..8].to_owned()).unwrap_or_default(),
+    };
+
     if branch_path.exists() {
         let hash = repo.read_ref(&branch_ref)?;
         let object = repo.read_object(&hash)?;
         let commit_id = object.try_as_commit_id()?;
 
-        std::fs::write(
-            repo.root.join(".mog/HEAD"),
-            format!("ref: {branch_ref}\n"),
-        )?;
+        repo.attach_head(&branch_ref, &format!("checkout: moving from {from} to {branch}"))?;
 
         println!("Switched to branch '{branch}'");
         return checkout_commit(repo, commit_id);
     }
 
-    let hash = hex_to_hash(branch)?;
-    let object = repo.read_object(&hash)?;
-    checkout_commit(repo, object.try_as_commit_id()?)?;
+    let (hash, commit_id) = repo.resolve_to_commit(branch)?;
+    checkout_commit(repo, commit_id)?;
 
-    std::fs::write(
-        repo.root.join(".mog/HEAD"),
-        format!("{hash}\n", hash = hash_to_hex(&hash)),
-    )?;
+    repo.detach_head(&hash, &format!("checkout: moving from {from} to {}", hash_to_hex(&hash)))?;
 
     println!("HEAD is now at {} (detached)", &hash_to_hex(&hash)[..8]);
     println!("You are in detached HEAD state.");
@@ -46,14 +45,26 @@ pub fn checkout(repo: &mut Repository, branch: &str) -> Result<()> {
 pub fn checkout_path(repo: &mut Repository, target: &str, path: &str) -> Result<()> {
     let (_commit_hash, commit_id) = repo.resolve_to_commit(target)?;
     let tree_hash = repo.commit.get_tree(commit_id);
-    let (object, obj_hash) = repo.walk_tree_path(&tree_hash, path)?;
+    let (object, obj_hash, mode) = repo.walk_tree_path(&tree_hash, path)?;
     let mut index = Index::load(&repo.root)?;
 
     match object {
-        Object::Blob(blob_id) => {
+        Object::Blob(blob_id) if mode != MODE_LINK => {
             checkout_blob_to(repo, blob_id, path)?;
             let abs = repo.root.join(path);
-            let metadata = std::fs::metadata(&abs)?;
+            set_executable(&abs, mode == MODE_EXEC)?;
+            let metadata = std::fs::symlink_metadata(&abs)?;
+            index.add(path, obj_hash, &metadata);
+            index.save(&repo.root)?;
+            println!("restored '{path}'");
+        }
+        Object::Blob(_) | Object::ChunkList(_) => {
+            let abs = repo.root.join(path);
+            if let Some(parent) = abs.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            repo.write_entry_to_file(&obj_hash, mode, &abs)?;
+            let metadata = std::fs::symlink_metadata(&abs)?;
             index.add(path, obj_hash, &metadata);
             index.save(&repo.root)?;
             println!("restored '{path}'");
@@ -65,6 +76,7 @@ pub fn checkout_path(repo: &mut Repository, target: &str, path: &str) -> Result<
             println!("restored '{path}/'");
         }
         Object::Commit(_) => anyhow::bail!("unexpected commit object at '{path}'"),
+        Object::Tag(_)    => anyhow::bail!("unexpected tag object at '{path}'"),
     }
 
     Ok(())
@@ -76,6 +88,9 @@ pub fn checkout_blob_to(repo: &Repository, blob_id: BlobId, to: &str) -> Result<
     if let Some(parent) = path.parent() {
         std::fs::create_dir_all(parent)?;
     }
+    if std::fs::symlink_metadata(&path).is_ok_and(|m| m.is_symlink()) {
+        std::fs::remove_file(&path)?;
+    }
     let data = repo.blob.get(blob_id);
     std::fs::write(&path, data)?;
     Ok(())
@@ -97,6 +112,11 @@ pub fn checkout_tree_impl(
     tree_hash: Hash,
     prefix: &str,
 ) -> Result<()> {
+    struct Frame {
+        tree_hash: Hash,
+        prefix: Box<str>,
+    }
+
     // Flatten the target tree to know which paths should exist.
     let target_flat = crate::status::flatten_tree(repo, tree_hash)?;
 
@@ -116,18 +136,17 @@ pub fn checkout_tree_impl(
 
     // Then rebuild index from target tree.
     let mut new_index = crate::index::Index::default();
-
-    struct Frame {
-        tree_hash: Hash,
-        prefix: Box<str>,
-    }
+    let mut trees = Vec::new();
 
     let mut stack = vec![Frame { tree_hash, prefix: prefix.into() }];
     while let Some(Frame { tree_hash, prefix: frame_prefix }) = stack.pop() {
+        trees.push((frame_prefix.clone(), tree_hash)); // @Clone
         let entries = {
             let raw = repo.storage.read(&tree_hash)?;
-            let entries = crate::object::decode_tree_entries(raw)?;
-            Storage::evict_pages(raw);
+            let entries = crate::object::decode_tree_entries(&raw)?;
+            if let std::borrow::Cow::Borrowed(raw) = raw {
+                Storage::evict_pages(raw);
+            }
             entries
         };
 
@@ -146,20 +165,25 @@ pub fn checkout_tree_impl(
                 stack.push(Frame { tree_hash: hash, prefix: child_path });
             } else {
                 //
-                // Blob: read raw bytes directly, bypassing the blob store entirely.
+                // Blob or chunk list: stream straight to disk, bypassing the blob store entirely.
                 //
                 let path = repo.root.join(child_path.as_ref());
-                _ = repo.with_blob_bytes_without_touching_cache_and_evict_the_pages(
-                    &hash,
-                    |_repo, data| std::fs::write(&path, data)
-                )?;
+                repo.write_entry_to_file(&hash, mode, &path)?;
 
-                let meta = std::fs::metadata(&path)?;
+                let meta = std::fs::symlink_metadata(&path)?;
                 new_index.add(&child_path, hash, &meta);
             }
         }
     }
 
+    //
+    // The new index is exactly these trees, so seed its tree cache with them
+    // (after the adds, which would drop them again).
+    //
+    for (dir, hash) in trees {
+        new_index.cache_tree(&dir, hash);
+    }
+
     new_index.save(&repo.root)?;
 
     Ok(())
@@ -0,0 +1,319 @@
+//! Large files are stored as content-defined chunks, each an ordinary blob, plus a chunk list
+//! naming them in order. Cut points depend only on nearby bytes, so an edit only changes the
+//! chunks around it and every other chunk deduplicates against the previous version.
+//!
+//! Files up to `CHUNK_THRESHOLD` bytes stay a single blob. Which one a file becomes depends only
+//! on its length, so the same contents always hash the same way.
+
+use crate::hash::Hash;
+use crate::object::{encode_blob_and_hash, ObjectTag};
+use crate::repository::Repository;
+use crate::storage::MogStorage;
+use crate::store::{ChunkListId, ChunkListStore};
+use crate::wire::{Decode, Encode, ReadCursor, WriteCursor};
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use anyhow::{Result, bail};
+
+/// Files larger than this are chunked.
+pub const CHUNK_THRESHOLD: u64 = 1024 * 1024;
+
+const MIN_CHUNK: usize = 256 * 1024;
+const MAX_CHUNK: usize = 4 * 1024 * 1024;
+
+/// Past `MIN_CHUNK`, cut where the low 20 bits of the gear hash are zero: ~1 MiB more on average.
+const CUT_MASK: u64 = (1 << 20) - 1;
+
+crate::payload_triple! {
+    owned ChunkListPayloadOwned {
+        size: u64,
+        hashes: Box<[Hash]>,
+        lens: Box<[u32]>,
+    }
+    view ChunkListPayloadView<'a> {
+        size: u64,
+        hashes: &'a [Hash],
+        lens: &'a [u32],
+    }
+    ref ChunkListPayloadRef<'a> {
+        store: &'a ChunkListStore,
+        id: ChunkListId,
+    }
+    view_from_owned(o) {
+        ChunkListPayloadView {
+            size: o.size,
+            hashes: &o.hashes,
+            lens: &o.lens,
+        }
+    }
+    view_from_ref(r) {
+        ChunkListPayloadView {
+            size: r.store.get_size(r.id),
+            hashes: r.store.get_hashes(r.id),
+            lens: r.store.get_lens(r.id),
+        }
+    }
+}
+
+impl ChunkListPayloadOwned {
+    #[must_use]
+    pub fn new(size: u64, hashes: Box<[Hash]>, lens: Box<[u32]>) -> Self {
+        Self { size, hashes, lens }
+    }
+}
+
+impl Decode for ChunkListPayloadOwned {
+    fn decode(r: &mut ReadCursor<'_>) -> Result<Self> {
+        let size = r.read_u64()?;
+
+        let count = r.read_u32()? as usize;
+
+        let mut hashes = Vec::with_capacity(count);
+        for _ in 0..count {
+            hashes.push(r.read_hash()?);
+        }
+
+        let mut lens = Vec::with_capacity(count);
+        for _ in 0..count {
+            lens.push(r.read_u32()?);
+        }
+
+        Ok(ChunkListPayloadOwned::new(size, hashes.into(), lens.into()))
+    }
+}
+
+impl<'a> ChunkListPayloadRef<'a> {
+    #[must_use]
+    pub fn new(store: &'a ChunkListStore, id: ChunkListId) -> Self {
+        Self { store, id }
+    }
+}
+
+impl Encode for ChunkListPayloadView<'_> {
+    fn encode(&self, w: &mut WriteCursor<'_>) {
+        w.write_u64(self.size);
+
+        w.write_u32(self.hashes.len() as u32);
+
+        for hash in self.hashes {
+            w.write_hash(hash);
+        }
+
+        for len in self.lens {
+            w.write_u32(*len);
+        }
+    }
+}
+
+/// The chunk list encoded in `data`, or None if `data` is some other object.
+#[inline]
+pub fn decode_chunk_list(data: &[u8]) -> Result<Option<ChunkListPayloadOwned>> {
+    if data.len() < 5 { bail!("data too short"); }
+
+    if &data[0..4] != b"MG01" { bail!("invalid magic"); }
+    if data[4] != ObjectTag::ChunkList.as_byte() {
+        return Ok(None);
+    }
+
+    ChunkListPayloadOwned::decode(&mut ReadCursor::new(&data[5..])).map(Some)
+}
+
+//
+//
+// Storing and hashing file contents
+//
+//
+
+/// Store the file at `path` as a blob, or as chunks plus a chunk list if it is large.
+/// Large files are streamed; they are never fully in memory. A symlink is stored as its target.
+pub fn write_file(repo: &mut Repository<impl MogStorage>, path: &Path) -> Result<Hash> {
+    if std::fs::symlink_metadata(path)?.is_symlink() {
+        return write_contents(repo, &crate::util::read_symlink(path)?);
+    }
+
+    let file = File::open(path)?;
+    let len  = file.metadata()?.len();
+    write_encoded(repo, file, len)
+}
+
+/// Store in-memory file contents, chunked the same way `write_file` would.
+pub fn write_contents(repo: &mut Repository<impl MogStorage>, data: &[u8]) -> Result<Hash> {
+    write_encoded(repo, data, data.len() as u64)
+}
+
+/// Encoded objects are written in batches of about this many bytes: one lock and remap per
+/// batch instead of per chunk, while a huge file still never sits in memory whole.
+const WRITE_BATCH_BYTES: usize = 64 * 1024 * 1024;
+
+fn write_encoded(repo: &mut Repository<impl MogStorage>, reader: impl Read, len: u64) -> Result<Hash> {
+    let mut batch = WriteBatch::default();
+    let hash = encode_contents(reader, len, |hash, encoded| {
+        batch.push(hash, encoded);
+        if batch.data.len() >= WRITE_BATCH_BYTES {
+            batch.write(repo)?;
+        }
+        Ok(())
+    })?;
+
+    batch.write(repo)?;
+    Ok(hash)
+}
+
+/// Encoded objects back to back, each named by its hash and where it ends.
+#[derive(Default)]
+struct WriteBatch {
+    data:   Vec<u8>,
+    hashes: Vec<Hash>,
+    ends:   Vec<usize>,
+}
+
+impl WriteBatch {
+    #[inline]
+    fn push(&mut self, hash: &Hash, encoded: &[u8]) {
+        self.data.extend_from_slice(encoded);
+        self.hashes.push(*hash);
+        self.ends.push(self.data.len());
+    }
+
+    fn write(&mut self, repo: &mut Repository<impl MogStorage>) -> Result<()> {
+        let starts = std::iter::once(0).chain(self.ends.iter().copied());
+        let ranges = starts.zip(self.ends.iter().copied());
+        repo.storage.write_batch(self.hashes.iter().zip(ranges).map(|(hash, (start, end))| (*hash, &self.data[start..end])))?;
+
+        self.data.clear();
+        self.hashes.clear();
+        self.ends.clear();
+        Ok(())
+    }
+}
+
+/// Hash `write_file` would return, without storing anything.
+pub fn hash_file(path: &Path) -> Result<Hash> {
+    if std::fs::symlink_metadata(path)?.is_symlink() {
+        return Ok(hash_contents(&crate::util::read_symlink(path)?));
+    }
+
+    let file = File::open(path)?;
+    let len  = file.metadata()?.len();
+    encode_contents(file, len, |_, _| Ok(()))
+}
+
+/// Hash `write_contents` would return, without storing anything.
+#[must_use]
+pub fn hash_contents(data: &[u8]) -> Hash {
+    if data.len() as u64 <= CHUNK_THRESHOLD {
+        return crate::object::hash_blob(data);
+    }
+    encode_contents(data, data.len() as u64, |_, _| Ok(())).expect("reading from memory can't fail")
+}
+
+/// Encode `len` bytes from `reader` as a blob or as chunks plus a chunk list, handing every
+/// encoded object to `sink`. Returns the hash that names the contents.
+fn encode_contents(
+    mut reader: impl Read,
+    len:        u64,
+    mut sink:   impl FnMut(&Hash, &[u8]) -> Result<()>,
+) -> Result<Hash> {
+    let mut encoded = Vec::new();
+
+    if len <= CHUNK_THRESHOLD {
+        let mut data = Vec::with_capacity(len as usize);
+        reader.read_to_end(&mut data)?;
+
+        let hash = encode_blob_and_hash(&data, &mut encoded);
+        sink(&hash, &encoded)?;
+        return Ok(hash);
+    }
+
+    let mut size   = 0;
+    let mut hashes = Vec::new();
+    let mut lens   = Vec::new();
+    for_each_chunk(reader, |chunk| {
+        let hash = encode_blob_and_hash(chunk, &mut encoded);
+        sink(&hash, &encoded)?;
+
+        size += chunk.len() as u64;
+        hashes.push(hash);
+        lens.push(chunk.len() as u32);
+        Ok(())
+    })?;
+
+    encoded.clear();
+    encoded.extend_from_slice(b"MG01");
+    encoded.push(ObjectTag::ChunkList.as_byte());
+    ChunkListPayloadView { size, hashes: &hashes, lens: &lens }.encode(&mut WriteCursor::new(&mut encoded));
+
+    let hash = Hash::from(blake3::hash(&encoded));
+    sink(&hash, &encoded)?;
+    Ok(hash)
+}
+
+/// Split everything `reader` yields into content-defined chunks, holding at most `MAX_CHUNK` bytes.
+pub fn for_each_chunk(mut reader: impl Read, mut f: impl FnMut(&[u8]) -> Result<()>) -> Result<()> {
+    let mut buf    = vec![0; MAX_CHUNK];
+    let mut filled = 0;
+
+    loop {
+        while filled < MAX_CHUNK {
+            let n = reader.read(&mut buf[filled..])?;
+            if n == 0 {
+                break;
+            }
+            filled += n;
+        }
+
+        if filled == 0 {
+            return Ok(());
+        }
+
+        //
+        // A short buffer means the reader is done: whatever has no cut point is the last chunk.
+        //
+        let cut = cut_point(&buf[..filled]);
+        f(&buf[..cut])?;
+
+        buf.copy_within(cut..filled, 0);
+        filled -= cut;
+    }
+}
+
+/// Length of the first chunk of `data`.
+#[inline]
+fn cut_point(data: &[u8]) -> usize {
+    if data.len() <= MIN_CHUNK {
+        return data.len();
+    }
+
+    //
+    // Gear hash: each byte shifts the previous ones one bit further out,
+    // so the hash only depends on the last 64 bytes.
+    //
+    let mut h = 0u64;
+    for (i, &byte) in data.iter().enumerate().take(MAX_CHUNK).skip(MIN_CHUNK) {
+        h = (h << 1).wrapping_add(GEAR[byte as usize]);
+        if h & CUT_MASK == 0 {
+            return i + 1;
+        }
+    }
+
+    data.len().min(MAX_CHUNK)
+}
+
+static GEAR: [u64; 256] = {
+    let mut table = [0; 256];
+    let mut state = 0x9E37_79B9_7F4A_7C15u64;
+    let mut i = 0;
+    while i < 256 {
+        // splitmix64
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+};
@@ -3,43 +3,40 @@ use crate::object::Object;
 use crate::store::{CommitId, CommitStore};
 use crate::hash::{Hash, hash_to_hex};
 use crate::wire::{Decode, Encode, ReadCursor, WriteCursor};
+use crate::identity::{self, Identity, Role};
+use crate::date::format_tz;
 
-use std::fs;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::fmt;
 
-use anyhow::Result;
+use anyhow::{Result, bail};
 
 pub fn commit(
     repo: &mut Repository,
     tree: Hash,
-    parent: Option<Hash>,
-    author: &str,
+    parents: impl IntoIterator<Item = Hash>,
+    author: Option<&str>,
     message: &str,
 ) -> Result<Hash> {
-    let timestamp = SystemTime::now()
-        .duration_since(UNIX_EPOCH)?
-        .as_secs() as i64;
+    let author    = identity::author(&repo.root, author)?;
+    let committer = identity::resolve(&repo.root, Role::Committer)?;
+    let authored  = author.sign_now()?;
+    let committed = committer.sign(authored.timestamp, authored.tz_offset);
 
-    let parents = parent.into_iter().collect::<Vec<_>>();
-    let commit_id = repo.commit.push(tree, &parents, timestamp, author, message);
+    let parents = parents.into_iter().collect::<Vec<_>>();
+    let commit_id = repo.commit.push_signed(tree, &parents, &authored, &committed, message);
     let hash = repo.write_object(Object::Commit(commit_id));
 
-    let head = fs::read_to_string(repo.root.join(".mog/HEAD"))?;
-    let head = head.trim();
+    let subject = message.lines().next().unwrap_or_default();
+    let reason  = match parents.len() {
+        0 => format!("commit (initial): {subject}"),
+        1 => format!("commit: {subject}"),
+        _ => format!("commit (merge): {subject}"),
+    };
 
-    if let Some(refpath) = head.strip_prefix("ref: ") {
-        //
-        // Normal: update the branch HEAD points to
-        //
-        repo.write_ref(refpath.trim(), &hash)?;
-    } else {
-        //
-        // Detached HEAD: update HEAD directly to new commit
-        //
-        fs::write(
-            repo.root.join(".mog/HEAD"),
-            format!("{}\n", hash_to_hex(&hash))
-        )?;
+    let detached = repo.current_branch()?.is_none();
+    repo.update_head(&hash, &reason)?;
+
+    if detached {
         println!("Warning: committing in detached HEAD state");
         println!("Create a branch to keep this work: mog branch save-my-work");
     }
@@ -53,19 +50,110 @@ pub fn commit(
     Ok(hash)
 }
 
+/// Replace HEAD's commit with one built from `tree`, keeping its parents (and its message when `message` is None).
+/// The author stays unless `author` is given; the committer is whoever amends, now.
+/// The replaced commit stays in the reflog, so `HEAD@{1}` undoes the amend.
+pub fn amend(
+    repo: &mut Repository,
+    tree: Hash,
+    author: Option<&str>,
+    message: Option<&str>,
+) -> Result<Hash> {
+    let Ok(old) = repo.read_head_commit() else {
+        bail!("nothing to amend: no commits yet");
+    };
+
+    let old_id  = repo.read_object(&old)?.try_as_commit_id()?;
+    let parents = repo.commit.get_parents(old_id).to_vec();
+    let message = match message {
+        Some(message) => message.to_owned(),
+        None          => repo.commit.get_message(old_id).to_owned(),
+    };
+
+    let committer = identity::resolve(&repo.root, Role::Committer)?;
+    let committed = committer.sign_now()?;
+
+    let commit_id = match author {
+        Some(author) => {
+            let author = Identity::parse(author)?;
+            let authored = author.sign(committed.timestamp, committed.tz_offset);
+            repo.commit.push_signed(tree, &parents, &authored, &committed, &message)
+        }
+        None => {
+            let authored = repo.commit.get_author_signature(old_id);
+            let author   = Identity { name: authored.name.to_owned(), email: authored.email.to_owned() };
+            let authored = author.sign(authored.timestamp, authored.tz_offset);
+            repo.commit.push_signed(tree, &parents, &authored, &committed, &message)
+        }
+    };
+    let hash = repo.write_object(Object::Commit(commit_id));
+
+    //
+    // Flush before moving the ref so it never points at a commit that isn't stored.
+    //
+    repo.storage.flush()?;
+
+    let subject = message.lines().next().unwrap_or_default();
+    repo.update_head(&hash, &format!("commit (amend): {subject}"))?;
+
+    println!("Amended commit {} (was {})", hash_to_hex(&hash), hash_to_hex(&old));
+    Ok(hash)
+}
+
+/// Commits written from now on. Version 1 commits (author name and timestamp only)
+/// still decode, and re-encode byte for byte so their hashes don't change.
+pub const COMMIT_FORMAT_VERSION: u32 = 2;
+
+/// Who did something and when: a commit's author or committer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Signature<'a> {
+    pub name:      &'a str,
+    pub email:     &'a str,
+    pub timestamp: i64,
+    /// Minutes east of UTC where it happened.
+    pub tz_offset: i32,
+}
+
+impl Signature<'_> {
+    /// `Name <email>`, or just the name when there's no email.
+    #[must_use]
+    pub fn who(&self) -> String {
+        if self.email.is_empty() {
+            self.name.to_owned()
+        } else {
+            format!("{} <{}>", self.name, self.email)
+        }
+    }
+}
+
+/// As in git: `Name <email> 1700000000 +0100`.
+impl fmt::Display for Signature<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} {}", self.who(), self.timestamp, format_tz(self.tz_offset))
+    }
+}
+
 crate::payload_triple! {
     owned CommitPayloadOwned {
+        version: u32,
         tree: Hash,
         parents: Box<[Hash]>,
         timestamp: i64,
         author: Box<str>,
+        author_email: Box<str>,
+        author_tz: i32,
+        committer: Box<str>,
+        committer_email: Box<str>,
+        committer_timestamp: i64,
+        committer_tz: i32,
         message: Box<str>,
     }
     view CommitPayloadView<'a> {
+        version: u32,
         tree: Hash,
         parents: &'a [Hash],
-        timestamp: i64,
-        author: &'a str,
+        author: Signature<'a>,
+        committer: Signature<'a>,
         message: &'a str,
     }
     ref CommitPayloadRef<'a> {
@@ -74,34 +162,37 @@ crate::payload_triple! {
     }
     view_from_owned(o) {
         CommitPayloadView {
+            version: o.version,
             tree: o.tree,
             parents: &o.parents,
-            timestamp: o.timestamp,
-            author: &o.author,
+            author: o.author_signature(),
+            committer: o.committer_signature(),
             message: &o.message,
         }
     }
     view_from_ref(r) {
         CommitPayloadView {
+            version: r.store.get_version(r.id),
             tree: r.store.get_tree(r.id),
             parents: r.store.get_parents(r.id),
-            timestamp: r.store.get_timestamp(r.id),
-            author: r.store.get_author(r.id),
+            author: r.store.get_author_signature(r.id),
+            committer: r.store.get_committer_signature(r.id),
             message: r.store.get_message(r.id),
         }
     }
 }
 
 impl CommitPayloadOwned {
+    #[inline]
     #[must_use]
-    pub fn new(tree: Hash, parents: Box<[Hash]>, timestamp: i64, author: Box<str>, message: Box<str>) -> Self {
-        Self {
-            tree,
-            parents,
-            timestamp,
-            author,
-            message,
-        }
+    pub fn author_signature(&self) -> Signature<'_> {
+        Signature { name: &self.author, email: &self.author_email, timestamp: self.timestamp, tz_offset: self.author_tz }
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn committer_signature(&self) -> Signature<'_> {
+        Signature { name: &self.committer, email: &self.committer_email, timestamp: self.committer_timestamp, tz_offset: self.committer_tz }
     }
 }
 
@@ -117,17 +208,49 @@ impl Decode for CommitPayloadOwned {
 
         let timestamp = r.read_i64()?;
 
-        let author = r.read_len_prefixed_str()?.into_owned();
+        let author: Box<str> = r.read_len_prefixed_str()?.into();
+
+        let message = r.read_len_prefixed_str()?.into();
 
-        let message = r.read_len_prefixed_str()?.into_owned();
+        //
+        // Version 1 ends here: no email, no time zone, committed by its author.
+        //
+        if r.at_end() {
+            return Ok(Self {
+                version: 1,
+                tree,
+                parents: parents.into_boxed_slice(),
+                timestamp,
+                author_email: "".into(),
+                author_tz: 0,
+                committer: author.clone(),
+                committer_email: "".into(),
+                committer_timestamp: timestamp,
+                committer_tz: 0,
+                author,
+                message,
+            });
+        }
 
-        Ok(CommitPayloadOwned::new(
+        let version = r.read_u32()?;
+        if version != COMMIT_FORMAT_VERSION {
+            bail!("commit format version {version} is newer than this mog understands");
+        }
+
+        Ok(Self {
+            version,
             tree,
-            parents.into_boxed_slice(),
+            parents: parents.into_boxed_slice(),
             timestamp,
-            author.into(),
-            message.into(),
-        ))
+            author,
+            author_email: r.read_len_prefixed_str()?.into(),
+            author_tz: r.read_i32()?,
+            committer: r.read_len_prefixed_str()?.into(),
+            committer_email: r.read_len_prefixed_str()?.into(),
+            committer_timestamp: r.read_i64()?,
+            committer_tz: r.read_i32()?,
+            message,
+        })
     }
 }
 
@@ -147,10 +270,20 @@ impl Encode for CommitPayloadView<'_> {
             w.write_hash(p);
         }
 
-        w.write_i64(self.timestamp);
+        w.write_i64(self.author.timestamp);
 
-        w.write_len_prefixed_str(self.author);
+        w.write_len_prefixed_str(self.author.name);
 
         w.write_len_prefixed_str(self.message);
+
+        if self.version >= 2 {
+            w.write_u32(self.version);
+            w.write_len_prefixed_str(self.author.email);
+            w.write_i32(self.author.tz_offset);
+            w.write_len_prefixed_str(self.committer.name);
+            w.write_len_prefixed_str(self.committer.email);
+            w.write_i64(self.committer.timestamp);
+            w.write_i32(self.committer.tz_offset);
+        }
     }
 }
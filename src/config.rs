@@ -0,0 +1,354 @@
+//! Settings in git-style INI files: `.mog/config` for the repository, `~/.mogconfig`
+//! (or `$MOG_CONFIG_GLOBAL`) for the user. Repository values win.
+//!
+//! ```text
+//! [remote "origin"]
+//!     url = ssh://host/srv/project
+//! [branch "main"]
+//!     remote = origin
+//!     merge = main
+//! ```
+//!
+//! Keys are written `section.name` or `section.subsection.name` (`remote.origin.url`).
+//! Section and value names are case-insensitive, subsections are not.
+
+use crate::lockfile::LockFile;
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Result, bail};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    /// `.mog/config`
+    Repo,
+    /// `~/.mogconfig`, or `$MOG_CONFIG_GLOBAL`
+    User,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Section {
+    name:    String,
+    sub:     Option<String>,
+    entries: Vec<(String, String)>,
+}
+
+/// One config file, or several merged (later ones win).
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Config {
+    sections: Vec<Section>,
+}
+
+/// A key split into its parts, section and name lowercased.
+struct Key<'a> {
+    section: String,
+    sub:     Option<&'a str>,
+    name:    String,
+}
+
+impl<'a> Key<'a> {
+    fn parse(key: &'a str) -> Result<Self> {
+        let (Some((section, rest)), Some((_, name))) = (key.split_once('.'), key.rsplit_once('.')) else {
+            bail!("key '{key}' has no section, expected section.name");
+        };
+        let sub = rest.rsplit_once('.').map(|(sub, _)| sub);
+
+        if !is_identifier(section) || !is_identifier(name) {
+            bail!("invalid key '{key}'");
+        }
+        Ok(Self { section: section.to_ascii_lowercase(), sub, name: name.to_ascii_lowercase() })
+    }
+}
+
+impl Config {
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut config = Self::default();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(['#', ';']) {
+                continue;
+            }
+
+            if let Some(header) = line.strip_prefix('[') {
+                let Some(header) = header.strip_suffix(']') else {
+                    bail!("line {}: unterminated section header", i + 1);
+                };
+                let (name, sub) = match header.split_once(char::is_whitespace) {
+                    Some((name, sub)) => {
+                        let sub = sub.trim();
+                        let Some(sub) = sub.strip_prefix('"').and_then(|s| s.strip_suffix('"')) else {
+                            bail!("line {}: subsection must be quoted", i + 1);
+                        };
+                        (name, Some(sub.replace("\\\"", "\"").replace("\\\\", "\\")))
+                    }
+                    None => (header, None),
+                };
+                if !is_identifier(name) {
+                    bail!("line {}: invalid section name '{name}'", i + 1);
+                }
+                config.sections.push(Section { name: name.to_ascii_lowercase(), sub, entries: Vec::new() });
+                continue;
+            }
+
+            let Some(section) = config.sections.last_mut() else {
+                bail!("line {}: setting outside of any section", i + 1);
+            };
+            let (name, value) = line.split_once('=').unwrap_or((line, "true"));
+            let name = name.trim();
+            if !is_identifier(name) {
+                bail!("line {}: invalid name '{name}'", i + 1);
+            }
+            section.entries.push((name.to_ascii_lowercase(), parse_value(value.trim()).map_err(|e| anyhow::anyhow!("line {}: {e}", i + 1))?));
+        }
+        Ok(config)
+    }
+
+    #[must_use]
+    pub fn encode(&self) -> String {
+        use std::fmt::Write;
+
+        let mut out = String::new();
+        for section in &self.sections {
+            _ = match &section.sub {
+                Some(sub) => writeln!(out, "[{} \"{}\"]", section.name, sub.replace('\\', "\\\\").replace('"', "\\\"")),
+                None      => writeln!(out, "[{}]", section.name),
+            };
+            for (name, value) in &section.entries {
+                _ = writeln!(out, "\t{name} = {}", quote_value(value));
+            }
+        }
+        out
+    }
+
+    /// The file at `path`, or an empty config if there is none.
+    pub fn load_file(path: &Path) -> Result<Self> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => Self::parse(&text).map_err(|e| anyhow::anyhow!("{}: {e}", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Last value of `key`.
+    #[must_use]
+    pub fn get(&self, key: &str) -> Option<&str> {
+        let key = Key::parse(key).ok()?;
+        self.sections.iter()
+            .filter(|s| s.name == key.section && s.sub.as_deref() == key.sub)
+            .flat_map(|section| &section.entries)
+            .filter(|(name, _)| *name == key.name)
+            .map(|(_, value)| value.as_str())
+            .next_back()
+    }
+
+    /// Replace every value of `key` with `value`, or add it to (possibly a new) section.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        let key = Key::parse(key)?;
+        self.remove_entries(&key);
+
+        let section = match self.sections.iter().rposition(|s| s.name == key.section && s.sub.as_deref() == key.sub) {
+            Some(i) => &mut self.sections[i],
+            None => {
+                self.sections.push(Section { name: key.section.clone(), sub: key.sub.map(ToOwned::to_owned), entries: Vec::new() });
+                self.sections.last_mut().unwrap()
+            }
+        };
+        section.entries.push((key.name, value.to_owned()));
+        Ok(())
+    }
+
+    /// Remove every value of `key`. Returns whether there was one.
+    pub fn unset(&mut self, key: &str) -> Result<bool> {
+        let key = Key::parse(key)?;
+        let removed = self.remove_entries(&key);
+        self.sections.retain(|s| !s.entries.is_empty());
+        Ok(removed)
+    }
+
+    /// Drop `[section "sub"]` entirely, e.g. a deleted branch's settings.
+    pub fn remove_section(&mut self, section: &str, sub: &str) {
+        self.sections.retain(|s| !(s.name == section && s.sub.as_deref() == Some(sub)));
+    }
+
+    /// Rename `[section "old"]` to `[section "new"]`, e.g. when a branch is renamed.
+    pub fn rename_section(&mut self, section: &str, old: &str, new: &str) {
+        for s in &mut self.sections {
+            if s.name == section && s.sub.as_deref() == Some(old) {
+                s.sub = Some(new.to_owned());
+            }
+        }
+    }
+
+    /// Every (key, value), in file order.
+    pub fn entries(&self) -> impl Iterator<Item = (String, &str)> {
+        self.sections.iter().flat_map(|section| {
+            section.entries.iter().map(move |(name, value)| {
+                let key = match &section.sub {
+                    Some(sub) => format!("{}.{sub}.{name}", section.name),
+                    None      => format!("{}.{name}", section.name),
+                };
+                (key, value.as_str())
+            })
+        })
+    }
+
+    /// Subsection names of `section`, e.g. every remote.
+    pub fn subsections<'a>(&'a self, section: &'a str) -> impl Iterator<Item = &'a str> {
+        self.sections.iter()
+            .filter(move |s| s.name == section)
+            .filter_map(|s| s.sub.as_deref())
+    }
+
+    /// `other`'s settings override ours.
+    pub fn merge(&mut self, other: Self) {
+        self.sections.extend(other.sections);
+    }
+
+    fn remove_entries(&mut self, key: &Key<'_>) -> bool {
+        let mut removed = false;
+        for section in self.sections.iter_mut().filter(|s| s.name == key.section && s.sub.as_deref() == key.sub) {
+            let before = section.entries.len();
+            section.entries.retain(|(name, _)| *name != key.name);
+            removed |= section.entries.len() != before;
+        }
+        removed
+    }
+}
+
+#[inline]
+#[must_use]
+pub fn repo_path(root: &Path) -> PathBuf {
+    root.join(".mog/config")
+}
+
+/// `$MOG_CONFIG_GLOBAL`, else `~/.mogconfig`. None if there is no home directory.
+#[must_use]
+pub fn user_path() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os("MOG_CONFIG_GLOBAL") {
+        return Some(PathBuf::from(path));
+    }
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".mogconfig"))
+}
+
+#[inline]
+fn scope_path(root: Option<&Path>, scope: Scope) -> Result<PathBuf> {
+    match (scope, root) {
+        (Scope::Repo, Some(root)) => Ok(repo_path(root)),
+        (Scope::Repo, None)       => bail!("not a mog repository (use --global for the user config)"),
+        (Scope::User, _)          => user_path().ok_or_else(|| anyhow::anyhow!("no home directory for the user config")),
+    }
+}
+
+/// User settings overridden by the repository's (if `root` is one).
+pub fn load(root: Option<&Path>) -> Result<Config> {
+    let mut config = match user_path() {
+        Some(path) => Config::load_file(&path)?,
+        None       => Config::default(),
+    };
+    if let Some(root) = root {
+        config.merge(Config::load_file(&repo_path(root))?);
+    }
+    Ok(config)
+}
+
+/// Only the settings of one file.
+pub fn load_scope(root: Option<&Path>, scope: Scope) -> Result<Config> {
+    Config::load_file(&scope_path(root, scope)?)
+}
+
+/// Read, change and rewrite one config file, holding its lock throughout so concurrent edits don't get lost.
+/// Comments and layout are not preserved.
+pub fn edit<T>(root: Option<&Path>, scope: Scope, f: impl FnOnce(&mut Config) -> Result<T>) -> Result<T> {
+    let path = scope_path(root, scope)?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    let mut lock   = LockFile::acquire(&path)?;
+    let mut config = Config::load_file(&path)?;
+    let result     = f(&mut config)?;
+
+    lock.write_all(config.encode().as_bytes())?;
+    lock.commit()?;
+    Ok(result)
+}
+
+//
+//
+// mog config
+//
+//
+
+pub fn get(root: Option<&Path>, key: &str) -> Result<()> {
+    Key::parse(key)?;
+    match load(root)?.get(key) {
+        Some(value) => println!("{value}"),
+        None        => bail!("'{key}' is not set"),
+    }
+    Ok(())
+}
+
+pub fn set(root: Option<&Path>, scope: Scope, key: &str, value: &str) -> Result<()> {
+    edit(root, scope, |config| config.set(key, value))
+}
+
+pub fn unset(root: Option<&Path>, scope: Scope, key: &str) -> Result<()> {
+    if !edit(root, scope, |config| config.unset(key))? {
+        bail!("'{key}' is not set");
+    }
+    Ok(())
+}
+
+/// Print `key=value` for every setting, of one scope or (None) of both merged.
+pub fn list(root: Option<&Path>, scope: Option<Scope>) -> Result<()> {
+    let config = match scope {
+        Some(scope) => load_scope(root, scope)?,
+        None        => load(root)?,
+    };
+    for (key, value) in config.entries() {
+        println!("{key}={value}");
+    }
+    Ok(())
+}
+
+#[inline]
+fn is_identifier(s: &str) -> bool {
+    !s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
+
+/// A value as written after `=`: quotes group, `#` and `;` outside them start a comment.
+fn parse_value(raw: &str) -> Result<String> {
+    let mut value   = String::new();
+    let mut quoted  = false;
+    let mut chars   = raw.chars();
+    // Unquoted trailing whitespace is dropped, so remember where the value really ends.
+    let mut kept_len = 0;
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => { quoted = !quoted; kept_len = value.len(); continue; }
+            '#' | ';' if !quoted => break,
+            '\\' => match chars.next() {
+                Some('n')  => value.push('\n'),
+                Some('t')  => value.push('\t'),
+                Some('"')  => value.push('"'),
+                Some('\\') => value.push('\\'),
+                other      => bail!("invalid escape '\\{}'", other.map(String::from).unwrap_or_default()),
+            },
+            c => value.push(c),
+        }
+        if quoted || !c.is_whitespace() {
+            kept_len = value.len();
+        }
+    }
+    if quoted {
+        bail!("unterminated quote");
+    }
+    value.truncate(kept_len);
+    Ok(value)
+}
+
+fn quote_value(value: &str) -> String {
+    let escaped = value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n").replace('\t', "\\t");
+    let needs_quotes = value != value.trim() || value.contains(['#', ';']);
+    if needs_quotes { format!("\"{escaped}\"") } else { escaped }
+}
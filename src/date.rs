@@ -0,0 +1,291 @@
+//! Rendering commit times: seconds since the Unix epoch plus the UTC offset they were
+//! recorded in. Calendar math is Howard Hinnant's `civil_from_days`, so no date crate.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Result, bail};
+
+const WEEKDAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+const MONTHS:   [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DateFormat {
+    /// `Fri, 16 Oct 2026 14:03:07 +0200`, in the zone it was recorded in.
+    #[default]
+    Rfc2822,
+    /// `2026-10-16T14:03:07+02:00`, in the zone it was recorded in.
+    Iso,
+    /// `3 days ago`.
+    Relative,
+    /// `1792152187`.
+    Unix,
+    /// Like `Rfc2822`, but in this machine's time zone.
+    Local,
+}
+
+impl DateFormat {
+    pub fn parse(s: &str) -> Result<Self> {
+        Ok(match s {
+            "default" | "rfc" | "rfc2822" => Self::Rfc2822,
+            "iso" | "iso8601"             => Self::Iso,
+            "relative"                    => Self::Relative,
+            "unix" | "raw"                => Self::Unix,
+            "local"                       => Self::Local,
+            _ => bail!("unknown date format '{s}' (expected default, rfc2822, iso, relative, unix or local)"),
+        })
+    }
+}
+
+/// Render `timestamp`, recorded `tz_offset` minutes east of UTC. `now` is only used by `Relative`.
+#[must_use]
+pub fn format_date(timestamp: i64, tz_offset: i32, format: DateFormat, now: i64) -> String {
+    match format {
+        DateFormat::Rfc2822  => format_rfc2822(timestamp, tz_offset),
+        DateFormat::Local    => format_rfc2822(timestamp, local_tz_offset(timestamp)),
+        DateFormat::Iso      => format_iso(timestamp, tz_offset),
+        DateFormat::Relative => format_relative(timestamp, now),
+        DateFormat::Unix     => timestamp.to_string(),
+    }
+}
+
+fn format_rfc2822(timestamp: i64, tz_offset: i32) -> String {
+    let t = Civil::at(timestamp, tz_offset);
+    format!(
+        "{}, {} {} {} {:02}:{:02}:{:02} {}",
+        WEEKDAYS[t.weekday], t.day, MONTHS[t.month as usize - 1], t.year,
+        t.hour, t.minute, t.second, format_tz(tz_offset)
+    )
+}
+
+fn format_iso(timestamp: i64, tz_offset: i32) -> String {
+    let t = Civil::at(timestamp, tz_offset);
+    let tz = format_tz(tz_offset);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}{}:{}",
+        t.year, t.month, t.day, t.hour, t.minute, t.second, &tz[..3], &tz[3..]
+    )
+}
+
+fn format_relative(timestamp: i64, now: i64) -> String {
+    let seconds = now - timestamp;
+    if seconds < 0 {
+        return "in the future".to_owned();
+    }
+
+    let minutes = (seconds + 30) / 60;
+    let hours   = (minutes + 30) / 60;
+    let days    = (hours + 12) / 24;
+
+    let (n, unit) = if seconds < 90 {
+        (seconds, "second")
+    } else if minutes < 90 {
+        (minutes, "minute")
+    } else if hours < 36 {
+        (hours, "hour")
+    } else if days < 14 {
+        (days, "day")
+    } else if days < 70 {
+        ((days + 3) / 7, "week")
+    } else if days < 365 {
+        ((days + 15) / 30, "month")
+    } else {
+        ((days + 183) / 365, "year")
+    };
+
+    let s = if n == 1 { "" } else { "s" };
+    format!("{n} {unit}{s} ago")
+}
+
+/// Parse a `--since`/`--until` date, relative to `now`:
+///
+/// ```text
+/// 1792152187, @1792152187      seconds since the epoch
+/// 2026-10-16                   midnight, local time
+/// 2026-10-16 14:03[:07]        (or with a `T`), local time unless followed by `Z` or `+0200`
+/// 3 days ago, 2.weeks.ago      relative; also `now`, `today`, `yesterday`
+/// ```
+pub fn parse_date(s: &str, now: i64) -> Result<i64> {
+    let s = s.trim();
+    let digits = s.strip_prefix('@').unwrap_or(s);
+    if !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()) {
+        return Ok(digits.parse()?);
+    }
+
+    match s {
+        "now"       => return Ok(now),
+        "today"     => return Ok(start_of_local_day(now)),
+        "yesterday" => return Ok(start_of_local_day(now) - 86_400),
+        _ => {}
+    }
+
+    if let Some(ago) = parse_relative(s) {
+        return Ok(now - ago);
+    }
+
+    parse_absolute(s).ok_or_else(|| anyhow::anyhow!(
+        "can't understand date '{s}' (try 2026-10-16, '2026-10-16 14:00', '3 days ago' or a unix timestamp)"
+    ))
+}
+
+/// `3 days ago` or `3.days.ago`, in seconds.
+fn parse_relative(s: &str) -> Option<i64> {
+    let s = s.strip_suffix("ago")?.trim_end_matches(['.', ' ']);
+    let (n, unit) = s.split_once(['.', ' '])?;
+    let n = n.parse::<i64>().ok()?;
+
+    let unit = unit.trim().trim_end_matches('s');
+    let seconds = match unit {
+        "second" | "sec" => 1,
+        "minute" | "min" => 60,
+        "hour"           => 3600,
+        "day"            => 86_400,
+        "week"           => 7 * 86_400,
+        "month"          => 30 * 86_400,
+        "year"           => 365 * 86_400,
+        _ => return None,
+    };
+    Some(n * seconds)
+}
+
+/// `YYYY-MM-DD[( |T)HH:MM[:SS]][Z|±HHMM|±HH:MM]`.
+fn parse_absolute(s: &str) -> Option<i64> {
+    let (date, rest) = s.split_at(s.find([' ', 'T']).unwrap_or(s.len()));
+    let mut parts = date.splitn(3, '-');
+    let year  = parts.next()?.parse::<i64>().ok()?;
+    let month = parts.next()?.parse::<u32>().ok()?;
+    let day   = parts.next()?.parse::<u32>().ok()?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    let rest = rest.trim_start_matches([' ', 'T']);
+    let (time, zone) = rest.split_at(rest.find(['Z', '+', '-', ' ']).unwrap_or(rest.len()));
+    let (mut hour, mut minute, mut second) = (0, 0, 0);
+    if !time.is_empty() {
+        let mut parts = time.splitn(3, ':');
+        hour   = parts.next()?.parse::<i64>().ok()?;
+        minute = parts.next()?.parse::<i64>().ok()?;
+        second = parts.next().map_or(Some(0), |s| s.parse::<i64>().ok())?;
+        if hour > 23 || minute > 59 || second > 60 {
+            return None;
+        }
+    }
+
+    let local = days_from_civil(year, month, day) * 86_400 + hour * 3600 + minute * 60 + second;
+    let zone = zone.trim();
+    let offset = match zone {
+        ""  => local_tz_offset(local - i64::from(local_tz_offset(local)) * 60),
+        "Z" => 0,
+        _   => parse_tz(zone)?,
+    };
+    Some(local - i64::from(offset) * 60)
+}
+
+/// `+0200`, `-05:30` to minutes east of UTC.
+fn parse_tz(s: &str) -> Option<i32> {
+    let (sign, digits) = match s.as_bytes().first()? {
+        b'+' => (1, &s[1..]),
+        b'-' => (-1, &s[1..]),
+        _    => return None,
+    };
+    let digits = digits.replace(':', "");
+    if digits.len() != 4 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let hours   = digits[..2].parse::<i32>().ok()?;
+    let minutes = digits[2..].parse::<i32>().ok()?;
+    Some(sign * (hours * 60 + minutes))
+}
+
+fn start_of_local_day(timestamp: i64) -> i64 {
+    let offset = i64::from(local_tz_offset(timestamp)) * 60;
+    (timestamp + offset).div_euclid(86_400) * 86_400 - offset
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date (the inverse of `Civil::at`).
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era  = year.div_euclid(400);
+    let yoe  = year.rem_euclid(400);
+    let mp   = i64::from((month + 9) % 12);
+    let doy  = (153 * mp + 2) / 5 + i64::from(day) - 1;
+    let doe  = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// `+0130` style, as in git.
+#[must_use]
+pub fn format_tz(tz_offset: i32) -> String {
+    let sign = if tz_offset < 0 { '-' } else { '+' };
+    let abs  = tz_offset.unsigned_abs();
+    format!("{sign}{:02}{:02}", abs / 60, abs % 60)
+}
+
+/// Seconds since the Unix epoch.
+#[inline]
+pub fn now() -> Result<i64> {
+    Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64)
+}
+
+/// Minutes east of UTC of the local time zone at `timestamp`.
+#[cfg(unix)]
+#[must_use]
+pub fn local_tz_offset(timestamp: i64) -> i32 {
+    let time = timestamp as libc::time_t;
+    let mut tm = unsafe { std::mem::zeroed::<libc::tm>() };
+    if unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+        return 0;
+    }
+    (tm.tm_gmtoff / 60) as i32
+}
+
+#[cfg(not(unix))]
+#[must_use]
+pub fn local_tz_offset(_timestamp: i64) -> i32 {
+    0
+}
+
+/// Wall-clock fields of a moment in some zone.
+struct Civil {
+    year:    i64,
+    month:   u32,
+    day:     u32,
+    hour:    u32,
+    minute:  u32,
+    second:  u32,
+    /// 0 is Monday.
+    weekday: usize,
+}
+
+impl Civil {
+    fn at(timestamp: i64, tz_offset: i32) -> Self {
+        let local = timestamp + i64::from(tz_offset) * 60;
+        let days  = local.div_euclid(86_400);
+        let secs  = local.rem_euclid(86_400) as u32;
+
+        //
+        // civil_from_days: shift to eras of 400 years starting on March 1st,
+        // so the leap day is the last day of the (shifted) year.
+        //
+        let z   = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z.rem_euclid(146_097);
+        let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp  = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        let year  = yoe + era * 400 + i64::from(month <= 2);
+
+        Self {
+            year,
+            month,
+            day,
+            hour:    secs / 3600,
+            minute:  secs / 60 % 60,
+            second:  secs % 60,
+            // 1970-01-01 was a Thursday.
+            weekday: (days + 3).rem_euclid(7) as usize,
+        }
+    }
+}
@@ -0,0 +1,126 @@
+//! Binary deltas between two encoded objects.
+//!
+//! ```text
+//! u32 result length
+//! then ops until the end:
+//!   0  u32 offset  u32 len      copy `len` bytes of the base starting at `offset`
+//!   1  u32 len     bytes        insert literal bytes
+//! ```
+
+use crate::util::Xxh3HashMap;
+use crate::wire::{ReadCursor, WriteCursor};
+
+use anyhow::{Result, bail};
+use xxhash_rust::xxh3::xxh3_64;
+
+const OP_COPY:   u8 = 0;
+const OP_INSERT: u8 = 1;
+
+/// Granularity of base matches. Shorter runs cost more as a copy op than as literals.
+const BLOCK: usize = 16;
+
+/// Delta that rebuilds `target` from `base`, or None once it would exceed `max_len` bytes.
+#[must_use]
+pub fn compute(base: &[u8], target: &[u8], max_len: usize) -> Option<Vec<u8>> {
+    //
+    // Index the base at block boundaries, then look for those blocks at every target offset.
+    //
+    let mut blocks = Xxh3HashMap::default();
+    for offset in (0..base.len().saturating_sub(BLOCK - 1)).step_by(BLOCK) {
+        blocks.entry(xxh3_64(&base[offset..offset + BLOCK])).or_insert(offset);
+    }
+
+    let mut out = Vec::new();
+    WriteCursor::new(&mut out).write_u32(target.len() as u32);
+
+    let mut literal_start = 0;
+    let mut i = 0;
+    while i + BLOCK <= target.len() {
+        let Some(&offset) = blocks.get(&xxh3_64(&target[i..i + BLOCK])) else {
+            i += 1;
+            continue;
+        };
+        if base[offset..offset + BLOCK] != target[i..i + BLOCK] {
+            i += 1;
+            continue;
+        }
+
+        //
+        // Grow the match both ways; backwards only eats into the pending literal run.
+        //
+        let (mut target_start, mut base_start) = (i, offset);
+        while target_start > literal_start && base_start > 0 && target[target_start - 1] == base[base_start - 1] {
+            target_start -= 1;
+            base_start   -= 1;
+        }
+
+        let (mut target_end, mut base_end) = (i + BLOCK, offset + BLOCK);
+        while target_end < target.len() && base_end < base.len() && target[target_end] == base[base_end] {
+            target_end += 1;
+            base_end   += 1;
+        }
+
+        push_insert(&mut out, &target[literal_start..target_start]);
+        push_copy(&mut out, base_start, target_end - target_start);
+        if out.len() > max_len {
+            return None;
+        }
+
+        i             = target_end;
+        literal_start = target_end;
+    }
+
+    push_insert(&mut out, &target[literal_start..]);
+    (out.len() <= max_len).then_some(out)
+}
+
+/// Rebuild the target from `base` and a delta made by `compute`.
+pub fn apply(base: &[u8], delta: &[u8]) -> Result<Vec<u8>> {
+    let mut r = ReadCursor::new(delta);
+    let len = r.read_u32()? as usize;
+
+    let mut out = Vec::with_capacity(len);
+    while !r.at_end() {
+        match r.read_bytes(1)?[0] {
+            OP_COPY => {
+                let offset = r.read_u32()? as usize;
+                let n      = r.read_u32()? as usize;
+                let Some(src) = base.get(offset..offset + n) else {
+                    bail!("delta: copy of {n} bytes at {offset} is past the end of a {} byte base", base.len());
+                };
+                out.extend_from_slice(src);
+            }
+            OP_INSERT => {
+                let n = r.read_u32()? as usize;
+                out.extend_from_slice(r.read_bytes(n)?);
+            }
+            op => bail!("delta: unknown op {op}"),
+        }
+    }
+
+    if out.len() != len {
+        bail!("delta: produced {} bytes, expected {len}", out.len());
+    }
+
+    Ok(out)
+}
+
+#[inline]
+fn push_copy(out: &mut Vec<u8>, offset: usize, len: usize) {
+    let mut w = WriteCursor::new(out);
+    w.write_slice(&[OP_COPY]);
+    w.write_u32(offset as u32);
+    w.write_u32(len as u32);
+}
+
+#[inline]
+fn push_insert(out: &mut Vec<u8>, literal: &[u8]) {
+    if literal.is_empty() {
+        return;
+    }
+
+    let mut w = WriteCursor::new(out);
+    w.write_slice(&[OP_INSERT]);
+    w.write_u32(literal.len() as u32);
+    w.write_slice(literal);
+}
@@ -1,14 +1,19 @@
-use crate::hash::{hash_bytes, hex_to_hash};
-use crate::index::Index;
+use crate::chunk::hash_file;
+use crate::hash::Hash;
+use crate::index::{AsMetadata, Index};
+use crate::object::MODE_LINK;
 use crate::repository::Repository;
 use crate::status::SortedFlatTree;
+use crate::util::read_worktree_file;
 
 use std::io::Write as _;
 use std::io::BufWriter;
+use std::path::Path;
 
 use anyhow::Result;
 use imara_diff::{Algorithm, BasicLineDiffPrinter, Diff, InternedInput, UnifiedDiffConfig};
 
+#[derive(Clone, Copy)]
 pub enum DiffTarget<'a> {
     /// `mog diff` - working directory vs index
     WorkingVsIndex,
@@ -16,7 +21,7 @@ pub enum DiffTarget<'a> {
     Staged,
     /// `mog diff <branch>` - working directory vs branch tip
     Branch(&'a str),
-    /// `mog diff <hex>` - working directory vs commit
+    /// `mog diff <hex|tag|ref@{N}>` - working directory vs commit
     Commit(&'a str),
 }
 
@@ -27,11 +32,11 @@ pub fn diff(repo: &mut Repository, target: DiffTarget<'_>) -> Result<()> {
         DiffTarget::Staged         => diff_staged(repo),
         DiffTarget::Branch(name)   => {
             let flat = resolve_to_flat_tree(repo, name)?;
-            diff_working_vs_tree(repo, flat)
+            diff_working_vs_tree(repo, &flat)
         }
         DiffTarget::Commit(hex) => {
             let flat = resolve_commit_to_flat_tree(repo, hex)?;
-            diff_working_vs_tree(repo, flat)
+            diff_working_vs_tree(repo, &flat)
         }
     }
 }
@@ -52,32 +57,52 @@ fn diff_working_vs_index(repo: &mut Repository) -> Result<()> {
     let stdout = std::io::stdout();
     let mut out = BufWriter::new(stdout.lock());
 
-    for entry in &index {
+    for (i, entry) in index.iter().enumerate() {
         if repo.ignore.is_ignored_rel(entry.path) {
             continue;
         }
 
-        let Ok(on_disk) = std::fs::read(repo.root.join(entry.path)) else {
+        let abs = repo.root.join(entry.path);
+        let Some((disk_hash, disk_mode)) = hash_disk(&abs, Some((&index, i))) else {
             continue;
         };
-        if hash_bytes(&on_disk) == *entry.hash {
+        let modes = (entry.mode, disk_mode);
+        let same_contents = disk_hash == *entry.hash;
+        if same_contents && entry.mode == disk_mode {
             continue; // Unchanged!
         }
 
+        let Ok(on_disk) = read_worktree_file(&abs) else {
+            continue;
+        };
+
+        if entry.mode == MODE_LINK || disk_mode == MODE_LINK {
+            let before = repo.read_blob_bytes_without_touching_cache(entry.hash)?;
+            let before = Side { data: &before, is_link: entry.mode == MODE_LINK };
+            let after  = Side { data: &on_disk, is_link: disk_mode == MODE_LINK };
+            print_link_diff(Some(before), Some(after), entry.path, &mut out)?;
+            continue;
+        }
+
+        if same_contents {
+            print_diff("", "", entry.path, modes, &mut out)?; // Only the mode changed.
+            continue;
+        }
+
         let Ok(after) = std::str::from_utf8(&on_disk) else {
-            writeln!(out, "Binary files differ: {}", entry.path)?;
+            print_binary(entry.path, modes, &mut out)?;
             continue;
         };
 
-        let Ok(before_bytes) = repo.read_blob_bytes_without_touching_cache(&entry.hash) else {
+        let Ok(before_bytes) = repo.read_blob_bytes_without_touching_cache(entry.hash) else {
             continue;
         };
         let Ok(before) = std::str::from_utf8(&before_bytes) else {
-            writeln!(out, "Binary files differ: {}", entry.path)?;
+            print_binary(entry.path, modes, &mut out)?;
             continue;
         };
 
-        print_diff(before, after, entry.path, &mut out)?;
+        print_diff(before, after, entry.path, modes, &mut out)?;
     }
 
     Ok(())
@@ -86,10 +111,8 @@ fn diff_working_vs_index(repo: &mut Repository) -> Result<()> {
 fn diff_staged(repo: &mut Repository) -> Result<()> {
     let index = Index::load(&repo.root)?;
 
-    let head_flat = match resolve_head_to_flat_tree(repo) {
-        Ok(flat) => flat,
-        Err(_)   => SortedFlatTree::default(), // No commits yet, empty tree!
-    };
+    // No commits yet, empty tree!
+    let head_flat = resolve_head_to_flat_tree(repo).unwrap_or_default();
 
     let stdout = std::io::stdout();
     let mut out = BufWriter::new(stdout.lock());
@@ -99,41 +122,61 @@ fn diff_staged(repo: &mut Repository) -> Result<()> {
             continue;
         }
 
-        match head_flat.lookup(entry.path) {
-            Some(head_hash) => {
-                if head_hash == *entry.hash {
+        match head_flat.lookup_entry(entry.path) {
+            Some((head_hash, head_mode)) => {
+                let modes = (head_mode, entry.mode);
+                let same_contents = head_hash == *entry.hash;
+                if same_contents && head_mode == entry.mode {
                     continue; // Unchanged!
                 }
 
+                if head_mode == MODE_LINK || entry.mode == MODE_LINK {
+                    let before = repo.read_blob_bytes_without_touching_cache(&head_hash)?;
+                    let after  = repo.read_blob_bytes_without_touching_cache(entry.hash)?;
+                    let before = Side { data: &before, is_link: head_mode == MODE_LINK };
+                    let after  = Side { data: &after, is_link: entry.mode == MODE_LINK };
+                    print_link_diff(Some(before), Some(after), entry.path, &mut out)?;
+                    continue;
+                }
+
+                if same_contents {
+                    print_diff("", "", entry.path, modes, &mut out)?; // Only the mode changed.
+                    continue;
+                }
+
                 let Ok(before_bytes) = repo.read_blob_bytes_without_touching_cache(&head_hash) else {
                     continue;
                 };
                 let Ok(before) = std::str::from_utf8(&before_bytes) else {
-                    writeln!(out, "Binary files differ: {}", entry.path)?;
+                    print_binary(entry.path, modes, &mut out)?;
                     continue;
                 };
 
-                let Ok(after_bytes) = repo.read_blob_bytes_without_touching_cache(&entry.hash) else {
+                let Ok(after_bytes) = repo.read_blob_bytes_without_touching_cache(entry.hash) else {
                     continue;
                 };
                 let Ok(after) = std::str::from_utf8(&after_bytes) else {
-                    writeln!(out, "Binary files differ: {}", entry.path)?;
+                    print_binary(entry.path, modes, &mut out)?;
                     continue;
                 };
 
-                print_diff(before, after, entry.path, &mut out)?;
+                print_diff(before, after, entry.path, modes, &mut out)?;
             }
             None => {
                 // New file - didn't exist in HEAD.
-                let Ok(after_bytes) = repo.read_blob_bytes_without_touching_cache(&entry.hash) else {
+                let Ok(after_bytes) = repo.read_blob_bytes_without_touching_cache(entry.hash) else {
                     continue;
                 };
+                if entry.mode == MODE_LINK {
+                    print_link_diff(None, Some(Side { data: &after_bytes, is_link: true }), entry.path, &mut out)?;
+                    continue;
+                }
                 let Ok(after) = std::str::from_utf8(&after_bytes) else {
                     writeln!(out, "Binary files differ: {}", entry.path)?;
                     continue;
                 };
 
-                print_diff("", after, entry.path, &mut out)?;
+                print_diff("", after, entry.path, (entry.mode, entry.mode), &mut out)?;
             }
         }
     }
@@ -141,7 +184,9 @@ fn diff_staged(repo: &mut Repository) -> Result<()> {
     Ok(())
 }
 
-fn diff_working_vs_tree(repo: &mut Repository, flat: SortedFlatTree) -> Result<()> {
+fn diff_working_vs_tree(repo: &mut Repository, flat: &SortedFlatTree) -> Result<()> {
+    let index = Index::load(&repo.root)?;
+
     let stdout = std::io::stdout();
     let mut out = BufWriter::new(stdout.lock());
 
@@ -153,8 +198,15 @@ fn diff_working_vs_tree(repo: &mut Repository, flat: SortedFlatTree) -> Result<(
         }
 
         let blob_hash = flat.hashes[i];
+        let tree_mode = flat.modes[i];
 
-        let Ok(on_disk) = std::fs::read(repo.root.join(path)) else {
+        //
+        // Staged as the target has it: the index stat data can vouch for the file on disk.
+        //
+        let abs    = repo.root.join(path);
+        let staged = index.find(path).filter(|&i| index.hashes[i] == blob_hash).map(|i| (&index, i));
+
+        let Some((disk_hash, disk_mode)) = hash_disk(&abs, staged) else {
             //
             // File deleted locally vs target - show as pure removal.
             //
@@ -162,20 +214,43 @@ fn diff_working_vs_tree(repo: &mut Repository, flat: SortedFlatTree) -> Result<(
             let Ok(before_bytes) = repo.read_blob_bytes_without_touching_cache(&blob_hash) else {
                 continue;
             };
+            if tree_mode == MODE_LINK {
+                print_link_diff(Some(Side { data: &before_bytes, is_link: true }), None, path, &mut out)?;
+                continue;
+            }
             let Ok(before) = std::str::from_utf8(&before_bytes) else {
                 writeln!(out, "Binary files differ: {path}")?;
                 continue;
             };
-            print_diff(before, "", path, &mut out)?;
+            print_diff(before, "", path, (tree_mode, tree_mode), &mut out)?;
             continue;
         };
 
-        if hash_bytes(&on_disk) == blob_hash {
+        let modes = (tree_mode, disk_mode);
+        let same_contents = disk_hash == blob_hash;
+        if same_contents && tree_mode == disk_mode {
             continue; // Unchanged!
         }
 
+        let Ok(on_disk) = read_worktree_file(&abs) else {
+            continue;
+        };
+
+        if tree_mode == MODE_LINK || disk_mode == MODE_LINK {
+            let before = repo.read_blob_bytes_without_touching_cache(&blob_hash)?;
+            let before = Side { data: &before, is_link: tree_mode == MODE_LINK };
+            let after  = Side { data: &on_disk, is_link: disk_mode == MODE_LINK };
+            print_link_diff(Some(before), Some(after), path, &mut out)?;
+            continue;
+        }
+
+        if same_contents {
+            print_diff("", "", path, modes, &mut out)?; // Only the mode changed.
+            continue;
+        }
+
         let Ok(after) = std::str::from_utf8(&on_disk) else {
-            writeln!(out, "Binary files differ: {path}")?;
+            print_binary(path, modes, &mut out)?;
             continue;
         };
 
@@ -183,11 +258,11 @@ fn diff_working_vs_tree(repo: &mut Repository, flat: SortedFlatTree) -> Result<(
             continue;
         };
         let Ok(before) = std::str::from_utf8(&before_bytes) else {
-            writeln!(out, "Binary files differ: {path}")?;
+            print_binary(path, modes, &mut out)?;
             continue;
         };
 
-        print_diff(before, after, path, &mut out)?;
+        print_diff(before, after, path, modes, &mut out)?;
     }
 
     //
@@ -196,21 +271,24 @@ fn diff_working_vs_tree(repo: &mut Repository, flat: SortedFlatTree) -> Result<(
     //
     //
 
-    let index = Index::load(&repo.root)?;
     for entry in &index {
         if repo.ignore.is_ignored_rel(entry.path) || flat.lookup(entry.path).is_some() {
             continue;
         }
 
-        let Ok(on_disk) = std::fs::read(repo.root.join(entry.path)) else {
+        let Some((on_disk, disk_mode)) = read_disk(&repo.root.join(entry.path)) else {
             continue;
         };
+        if disk_mode == MODE_LINK {
+            print_link_diff(None, Some(Side { data: &on_disk, is_link: true }), entry.path, &mut out)?;
+            continue;
+        }
         let Ok(after) = std::str::from_utf8(&on_disk) else {
             writeln!(out, "Binary files differ: {}", entry.path)?;
             continue;
         };
 
-        print_diff("", after, entry.path, &mut out)?;
+        print_diff("", after, entry.path, (disk_mode, disk_mode), &mut out)?;
     }
 
     Ok(())
@@ -233,10 +311,8 @@ fn resolve_to_flat_tree(repo: &mut Repository, branch: &str) -> Result<SortedFla
 }
 
 #[inline]
-fn resolve_commit_to_flat_tree(repo: &mut Repository, commit_hex: &str) -> Result<SortedFlatTree> {
-    let hash = hex_to_hash(commit_hex)?;
-    let obj = repo.read_object(&hash)?;
-    let commit_id = obj.try_as_commit_id()?;
+fn resolve_commit_to_flat_tree(repo: &mut Repository, target: &str) -> Result<SortedFlatTree> {
+    let (_, commit_id) = repo.resolve_to_commit(target)?;
     let tree_hash = repo.commit.get_tree(commit_id);
     crate::status::flatten_tree(repo, tree_hash)
 }
@@ -250,27 +326,113 @@ fn resolve_head_to_flat_tree(repo: &mut Repository) -> Result<SortedFlatTree> {
     crate::status::flatten_tree(repo, tree_hash)
 }
 
+/// Hash and mode a working directory entry would be staged with, without holding it in memory.
+/// If `staged` names its index entry and the stat data says it hasn't changed since, the file
+/// isn't read at all.
+#[inline]
+fn hash_disk(abs: &Path, staged: Option<(&Index, usize)>) -> Option<(Hash, u32)> {
+    let meta = std::fs::symlink_metadata(abs).ok()?;
+    if let Some((index, i)) = staged {
+        if !index.is_dirty(i, &meta) {
+            return Some((index.hashes[i], index.modes[i]));
+        }
+    }
+    Some((hash_file(abs).ok()?, meta.mode()))
+}
+
+/// Contents of a working directory entry and the mode it would be staged with
+/// (for a symlink, the contents are its target).
+#[inline]
+fn read_disk(abs: &Path) -> Option<(Vec<u8>, u32)> {
+    let mode = std::fs::symlink_metadata(abs).ok()?.mode();
+    let data = read_worktree_file(abs).ok()?;
+    Some((data, mode))
+}
+
+/// One side of a change involving a symlink.
+#[derive(Clone, Copy)]
+struct Side<'a> {
+    data:    &'a [u8],
+    is_link: bool,
+}
+
+/// A symlink target is a single line without a newline, printed the way git does.
+/// A file that became a symlink (or back) has no meaningful line diff; just say so.
+fn print_link_diff(
+    before: Option<Side<'_>>,
+    after:  Option<Side<'_>>,
+    path:   &str,
+    out:    &mut BufWriter<impl std::io::Write>,
+) -> Result<()> {
+    if let (Some(before), Some(after)) = (before, after) {
+        if before.is_link != after.is_link {
+            let kind = |side: Side<'_>| if side.is_link { "symlink" } else { "file" };
+            writeln!(out, "Type changed: {path} ({} -> {})", kind(before), kind(after))?;
+            return Ok(());
+        }
+    }
+
+    let old_range = if before.is_some() { "1" } else { "0,0" };
+    let new_range = if after.is_some()  { "1" } else { "0,0" };
+
+    writeln!(out, "--- a/{path}")?;
+    writeln!(out, "+++ b/{path}")?;
+    writeln!(out, "@@ -{old_range} +{new_range} @@")?;
+    for (sign, side) in [('-', before), ('+', after)] {
+        if let Some(side) = side {
+            writeln!(out, "{sign}{}", String::from_utf8_lossy(side.data))?;
+            writeln!(out, "\\ No newline at end of file")?;
+        }
+    }
+    writeln!(out)?;
+
+    Ok(())
+}
+
+/// `modes` is (before, after); a difference is printed as git's `old mode`/`new mode` header,
+/// even when the contents are the same.
 #[inline]
 fn print_diff(
     before: &str,
     after: &str,
     path: &str,
+    modes: (u32, u32),
     out: &mut BufWriter<impl std::io::Write>,
 ) -> Result<()> {
     let input = InternedInput::new(before, after);
     let mut diff = Diff::compute(Algorithm::Histogram, &input);
     diff.postprocess_lines(&input);
 
-    if diff.hunks().next().is_none() {
+    let has_hunks = diff.hunks().next().is_some();
+    if !has_hunks && modes.0 == modes.1 {
         return Ok(()); // Empty diff!
     }
 
-    let printer = BasicLineDiffPrinter(&input.interner);
-    let unified = diff.unified_diff(&printer, UnifiedDiffConfig::default(), &input);
-
+    print_mode_change(modes, out)?;
     writeln!(out, "--- a/{path}")?;
     writeln!(out, "+++ b/{path}")?;
-    writeln!(out, "{unified}")?;
 
+    if has_hunks {
+        let printer = BasicLineDiffPrinter(&input.interner);
+        let unified = diff.unified_diff(&printer, UnifiedDiffConfig::default(), &input);
+        writeln!(out, "{unified}")?;
+    }
+
+    Ok(())
+}
+
+#[inline]
+fn print_binary(path: &str, modes: (u32, u32), out: &mut BufWriter<impl std::io::Write>) -> Result<()> {
+    print_mode_change(modes, out)?;
+    writeln!(out, "Binary files differ: {path}")?;
+    Ok(())
+}
+
+#[inline]
+fn print_mode_change((old, new): (u32, u32), out: &mut BufWriter<impl std::io::Write>) -> Result<()> {
+    if old != new {
+        writeln!(out, "old mode {old:o}")?;
+        writeln!(out, "new mode {new:o}")?;
+    }
     Ok(())
 }
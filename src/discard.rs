@@ -1,4 +1,4 @@
-use crate::{index::Index, repository::Repository, stage::{classify_patterns, walk_matching}, status::SortedFlatTree};
+use crate::{index::Index, object::{MODE_EXEC, MODE_LINK}, repository::Repository, stage::{classify_patterns, walk_matching}, status::SortedFlatTree, util::set_executable};
 
 use std::path::{Path, PathBuf};
 
@@ -7,7 +7,7 @@ use walkdir::WalkDir;
 use rayon::prelude::*;
 
 pub fn discard(repo: &mut Repository, patterns: &[PathBuf]) -> Result<()> {
-    let index = Index::load(&repo.root)?;
+    let mut index = Index::load(&repo.root)?;
     if patterns.is_empty() {
         return discard_all(repo, &index);
     }
@@ -26,24 +26,22 @@ pub fn discard(repo: &mut Repository, patterns: &[PathBuf]) -> Result<()> {
     };
 
     let current_dir = &repo.root;
-    let (literal_roots, combined_re) = classify_patterns(patterns, &current_dir);
+    let (literal_roots, combined_re) = classify_patterns(patterns, current_dir);
     let matched = walk_matching(current_dir, &repo.ignore, &literal_roots, combined_re.as_ref());
 
     let mut restored = 0usize;
+    let mut to_unstage = Vec::new();
     for (_abs, rel_str) in matched {
         let abs = repo.root.join(rel_str.as_ref());
-        match head_flat.lookup(&rel_str) {
-            Some(head_hash) => {
+        match head_flat.lookup_entry(&rel_str) {
+            Some((head_hash, head_mode)) => {
                 //
                 // In HEAD: restore to HEAD version.
                 //
                 if let Some(parent) = abs.parent() {
                     std::fs::create_dir_all(parent)?;
                 }
-                repo.with_blob_bytes_without_touching_cache_and_evict_the_pages(
-                    &head_hash,
-                    |_repo, data| std::fs::write(&abs, data)
-                )?;
+                repo.write_entry_to_file(&head_hash, head_mode, &abs)?;
                 restored += 1;
             }
 
@@ -51,24 +49,23 @@ pub fn discard(repo: &mut Repository, patterns: &[PathBuf]) -> Result<()> {
                 Some(i) if head_flat.is_empty() => {
                     // No commits yet, index is the source of truth, restore from it.
                     let hash = index.hashes[i];
-                    repo.with_blob_bytes_without_touching_cache_and_evict_the_pages(
-                        &hash,
-                        |_repo, data| std::fs::write(&abs, data)
-                    )?;
+                    repo.write_entry_to_file(&hash, index.modes[i], &abs)?;
                     restored += 1;
                 }
                 _ => {
                     // In HEAD but not committed (or no index entry), delete it.
                     _ = std::fs::remove_file(&abs);
-                    let mut index = Index::load(&repo.root)?;
-                    index.remove(&rel_str);
-                    index.save(&repo.root)?;
+                    to_unstage.push(rel_str);
                     restored += 1;
                 }
             }
         }
     }
 
+    if index.remove_many(&to_unstage) > 0 {
+        index.save(&repo.root)?;
+    }
+
     println!("Discarded changes in {restored} file(s)");
 
     Ok(())
@@ -84,7 +81,7 @@ fn discard_all(repo: &mut Repository, index: &Index) -> Result<()> {
         .filter_entry(|e| !repo.ignore.is_ignored_abs(e.path()))
         .filter_map(Result::ok)
     {
-        if !entry.file_type().is_file() { continue; }
+        if !entry.file_type().is_file() && !entry.file_type().is_symlink() { continue; }
 
         let path = entry.path();
         let Ok(rel) = path.strip_prefix(&repo.root) else { continue };
@@ -103,31 +100,46 @@ fn discard_all(repo: &mut Repository, index: &Index) -> Result<()> {
     remove_empty_dirs(&repo.root)?;
 
     //
-    // Read blobs sequentially, evict pages as we go.
+    // Read blobs sequentially, evict pages as we go. Chunked files and symlinks are written
+    // to disk right away.
     //
-    let mut blobs: Vec<(Box<[u8]>, Box<Path>)> = Vec::with_capacity(index.count);
+    let mut blobs: Vec<(Box<[u8]>, Box<Path>, u32)> = Vec::with_capacity(index.count);
     for i in 0..index.count {
         let hash = index.hashes[i];
         let abs  = repo.root.join(index.get_path(i)).into_boxed_path();
+        if index.sizes[i] > crate::chunk::CHUNK_THRESHOLD || index.modes[i] == MODE_LINK {
+            if let Some(parent) = abs.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            repo.write_entry_to_file(&hash, index.modes[i], &abs)?;
+            continue;
+        }
         {
             let data = repo.with_blob_bytes_without_touching_cache_and_evict_the_pages(
                 &hash,
                 |_repo, data| anyhow::Ok(data.into())
             )?;
 
-            blobs.push((data, abs));
+            blobs.push((data, abs, index.modes[i]));
         }
     }
 
     //
     // Write to disk in parallel.
     //
-    blobs.par_iter().try_for_each(|(data, abs)| -> Result<()> {
+    blobs.par_iter().try_for_each(|(data, abs, mode)| -> Result<()> {
         if let Some(parent) = abs.parent() {
             std::fs::create_dir_all(parent)?;
         }
 
+        //
+        // A symlink where a file belongs is replaced, not written through.
+        //
+        if std::fs::symlink_metadata(abs).is_ok_and(|m| m.is_symlink()) {
+            std::fs::remove_file(abs)?;
+        }
         std::fs::write(abs, data)?;
+        set_executable(abs, *mode == MODE_EXEC)?;
         Ok(())
     })?;
 
@@ -137,8 +149,9 @@ fn discard_all(repo: &mut Repository, index: &Index) -> Result<()> {
 
 pub fn remove_empty_dirs(root: &Path) -> Result<()> {
     for entry in std::fs::read_dir(root)?.filter_map(Result::ok) {
+        if !entry.file_type()?.is_dir() { continue }
+
         let path = entry.path();
-        if !path.is_dir() { continue }
 
         if path.ends_with(".mog") { continue; }
 
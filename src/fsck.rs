@@ -0,0 +1,293 @@
+use crate::hash::{hash_to_hex, Hash};
+use crate::index::Index;
+use crate::object::ObjectTag;
+use crate::repository::Repository;
+use crate::storage::StorageIssue;
+use crate::util::{Xxh3HashMap, Xxh3HashSet};
+
+use anyhow::{Result, bail};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FindingKind {
+    /// An entry in `objects.bin` that doesn't decode or doesn't match its hash.
+    Corrupt,
+    /// The hash table or header disagrees with the entries.
+    BadTable,
+    /// Bytes in `objects.bin` nothing accounts for. Harmless, `gc` drops them.
+    Unaccounted,
+    /// A ref, object or index entry names an object that isn't stored.
+    Missing,
+    /// `.mog/index` doesn't decode or an entry doesn't match the object it names.
+    BadIndex,
+    /// Unreachable, and no other object refers to it: the tip of some lost history.
+    Dangling,
+    /// Unreachable, but referred to by another unreachable object.
+    Unreachable,
+}
+
+impl FindingKind {
+    #[inline]
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Corrupt     => "corrupt",
+            Self::BadTable    => "bad-table",
+            Self::Unaccounted => "unaccounted",
+            Self::Missing     => "missing",
+            Self::BadIndex    => "bad-index",
+            Self::Dangling    => "dangling",
+            Self::Unreachable => "unreachable",
+        }
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn is_error(self) -> bool {
+        matches!(self, Self::Corrupt | Self::BadTable | Self::Missing | Self::BadIndex)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub kind:   FindingKind,
+    pub hash:   Option<Hash>,
+    pub detail: String,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct FsckReport {
+    pub objects:  usize,
+    pub findings: Vec<Finding>,
+}
+
+impl FsckReport {
+    #[inline]
+    #[must_use]
+    pub fn count(&self, kind: FindingKind) -> usize {
+        self.findings.iter().filter(|f| f.kind == kind).count()
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn errors(&self) -> usize {
+        self.findings.iter().filter(|f| f.kind.is_error()).count()
+    }
+
+    #[inline]
+    fn push(&mut self, kind: FindingKind, hash: Option<Hash>, detail: String) {
+        self.findings.push(Finding { kind, hash, detail });
+    }
+}
+
+/// Check `objects.bin`, the object graph and the index, and print what is wrong.
+/// With `porcelain`, print one tab-separated `kind hash detail` line per finding and nothing else.
+pub fn fsck(repo: &mut Repository, porcelain: bool) -> Result<()> {
+    let report = check(repo)?;
+
+    for Finding { kind, hash, detail } in &report.findings {
+        let hex = hash.as_ref().map_or_else(|| "-".to_owned(), hash_to_hex);
+        if porcelain {
+            println!("{}\t{hex}\t{detail}", kind.name());
+        } else if kind.is_error() {
+            println!("error: {} {hex}: {detail}", kind.name());
+        } else if *kind == FindingKind::Unaccounted {
+            println!("warning: {detail}");
+        } else if *kind == FindingKind::Dangling {
+            println!("dangling {detail} {hex}");
+        }
+    }
+
+    let errors = report.errors();
+    if !porcelain {
+        println!(
+            "checked {} objects: {errors} error(s), {} dangling, {} unreachable",
+            report.objects,
+            report.count(FindingKind::Dangling),
+            report.count(FindingKind::Dangling) + report.count(FindingKind::Unreachable),
+        );
+    }
+
+    if errors > 0 {
+        bail!("fsck found {errors} error(s)");
+    }
+    Ok(())
+}
+
+pub fn check(repo: &mut Repository) -> Result<FsckReport> {
+    //
+    // Objects written earlier in this process must be visible through the mmap.
+    //
+    repo.storage.flush()?;
+    repo.storage.remap()?;
+
+    let mut report = FsckReport::default();
+
+    //
+    //
+    // Storage: every entry re-hashed, the hash table checked against the entries.
+    //
+    //
+
+    let storage = repo.storage.verify();
+    report.objects = storage.objects;
+
+    let mut corrupt = Xxh3HashSet::default();
+    for issue in storage.issues {
+        match issue {
+            StorageIssue::BadOffset { bucket, offset } => report.push(
+                FindingKind::BadTable, None,
+                format!("bucket {bucket} points at {offset}, which is not an entry"),
+            ),
+            StorageIssue::Corrupt { hash, offset, reason } => {
+                corrupt.insert(hash);
+                report.push(FindingKind::Corrupt, Some(hash), format!("entry at {offset}: {reason}"));
+            }
+            StorageIssue::Unfindable { hash, offset } => report.push(
+                FindingKind::BadTable, Some(hash),
+                format!("entry at {offset} can't be found through the hash table"),
+            ),
+            StorageIssue::CountMismatch { header, table } => report.push(
+                FindingKind::BadTable, None,
+                format!("header counts {header} objects, hash table holds {table}"),
+            ),
+            StorageIssue::Unaccounted { offset, len } => report.push(
+                FindingKind::Unaccounted, None,
+                format!("{len} unaccounted bytes at offset {offset}"),
+            ),
+        }
+    }
+
+    //
+    //
+    // Object graph: everything an object refers to must be stored.
+    //
+    //
+
+    let mut hashes = Vec::new();
+    repo.storage.for_each_hash(|hash| hashes.push(*hash));
+
+    let mut kinds      = Xxh3HashMap::default();
+    let mut referenced = Xxh3HashSet::default();
+    let mut edges      = Xxh3HashMap::<Hash, Vec<Hash>>::default();
+    let mut children   = Vec::new();
+    for hash in &hashes {
+        if corrupt.contains(hash) {
+            continue;
+        }
+        let Ok(data) = repo.storage.read(hash) else {
+            continue;
+        };
+        let Some(tag) = data.get(4).copied().and_then(ObjectTag::from_byte) else {
+            report.push(FindingKind::Corrupt, Some(*hash), "unknown object type".to_owned());
+            continue;
+        };
+        kinds.insert(*hash, tag);
+
+        children.clear();
+        if let Err(e) = crate::object::push_object_children(&data, &mut children) {
+            report.push(FindingKind::Corrupt, Some(*hash), format!("{} doesn't decode: {e}", tag.name()));
+            continue;
+        }
+
+        for child in &children {
+            if !repo.storage.exists(child) {
+                report.push(FindingKind::Missing, Some(*child), format!("referenced by {} {}", tag.name(), hash_to_hex(hash)));
+            }
+        }
+        referenced.extend(children.iter().copied());
+        edges.insert(*hash, children.clone());
+    }
+
+    //
+    //
+    // Roots: refs, reflogs, merge/rebase state, stashes and the index.
+    //
+    //
+
+    let mut roots = crate::gc::history_roots(repo)?;
+    for root in &roots {
+        if !repo.storage.exists(root) {
+            report.push(FindingKind::Missing, Some(*root), "referenced by a ref, reflog or HEAD".to_owned());
+        }
+    }
+
+    match crate::stash::dirty_trees(repo) {
+        Ok(trees) => roots.extend(trees),
+        Err(e)    => report.push(FindingKind::Missing, None, format!("can't read stash entries: {e}")),
+    }
+
+    match Index::load(&repo.root) {
+        Ok(index) => {
+            check_index(repo, &index, &kinds, &mut report);
+            roots.extend(index.hashes.iter().copied());
+        }
+        Err(e) => report.push(FindingKind::BadIndex, None, format!("index doesn't decode: {e}")),
+    }
+
+    //
+    //
+    // Reachability
+    //
+    //
+
+    //
+    // Walked over the edges decoded above: corrupt objects are findings here, not errors.
+    //
+    let mut live  = Xxh3HashSet::default();
+    let mut stack = roots;
+    while let Some(hash) = stack.pop() {
+        if live.insert(hash) {
+            if let Some(children) = edges.get(&hash) {
+                stack.extend_from_slice(children);
+            }
+        }
+    }
+
+    for hash in &hashes {
+        if live.contains(hash) {
+            continue;
+        }
+
+        let kind = if referenced.contains(hash) { FindingKind::Unreachable } else { FindingKind::Dangling };
+        let name = kinds.get(hash).map_or("object", |tag| tag.name());
+        report.push(kind, Some(*hash), name.to_owned());
+    }
+
+    Ok(report)
+}
+
+/// Every entry must name a stored file object of the size it was staged with.
+fn check_index(repo: &Repository, index: &Index, kinds: &Xxh3HashMap<Hash, ObjectTag>, report: &mut FsckReport) {
+    for (i, entry) in index.iter().enumerate() {
+        if index.find(entry.path) != Some(i) {
+            report.push(FindingKind::BadIndex, Some(*entry.hash), format!("'{}' is staged more than once", entry.path));
+        }
+
+        let size = match kinds.get(entry.hash) {
+            None if !repo.storage.exists(entry.hash) => {
+                report.push(FindingKind::Missing, Some(*entry.hash), format!("staged as '{}'", entry.path));
+                continue;
+            }
+            Some(ObjectTag::Blob | ObjectTag::ChunkList) => {
+                let Ok(raw) = repo.storage.read(entry.hash) else { continue };
+                match crate::chunk::decode_chunk_list(&raw) {
+                    Ok(Some(list)) => list.size,
+                    Ok(None)       => crate::object::decode_blob_bytes(&raw).map_or(0, |data| data.len() as u64),
+                    Err(_)         => continue,
+                }
+            }
+            Some(tag) => {
+                report.push(FindingKind::BadIndex, Some(*entry.hash), format!("'{}' is staged as a {}", entry.path, tag.name()));
+                continue;
+            }
+            None => continue, // Corrupt, already reported.
+        };
+
+        if size != entry.size {
+            report.push(
+                FindingKind::BadIndex, Some(*entry.hash),
+                format!("'{}' is staged with size {} but the object holds {size} bytes", entry.path, entry.size),
+            );
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use crate::hash::{hex_to_hash, Hash};
+use crate::index::Index;
+use crate::repository::Repository;
+
+use std::fs;
+
+use anyhow::Result;
+
+/// Drop every object not reachable from a ref, the index, a reflog or in-progress merge/rebase state,
+/// and compact `objects.bin`. With `dry_run` only report what would be reclaimed.
+pub fn gc(repo: &mut Repository, dry_run: bool) -> Result<()> {
+    //
+    // Objects written earlier in this process must be visible through the mmap before marking.
+    //
+    repo.storage.flush()?;
+    repo.storage.remap()?;
+
+    let roots = collect_roots(repo)?;
+    let live  = repo.reachable_objects(roots)?;
+
+    let stats = if dry_run {
+        repo.storage.compact_stats(&live)
+    } else {
+        repo.storage.compact(&live)?
+    };
+
+    let reclaimed = stats.bytes_before.saturating_sub(stats.bytes_after);
+    if dry_run {
+        println!(
+            "{} of {} objects unreachable, {reclaimed} bytes reclaimable",
+            stats.removed,
+            stats.kept + stats.removed
+        );
+    } else {
+        println!("removed {} unreachable objects, freed {reclaimed} bytes", stats.removed);
+    }
+
+    Ok(())
+}
+
+/// Every hash something outside `objects.bin` still points at.
+pub fn collect_roots(repo: &mut Repository) -> Result<Vec<Hash>> {
+    let mut roots = history_roots(repo)?;
+
+    roots.extend(crate::stash::dirty_trees(repo)?);
+
+    let index = Index::load(&repo.root)?;
+    roots.extend(index.hashes.iter().copied());
+
+    Ok(roots)
+}
+
+/// Hashes held by refs, reflogs and merge/rebase state: the starting points of history.
+/// Mostly commits and tags, but callers must expect anything.
+pub fn history_roots(repo: &Repository) -> Result<Vec<Hash>> {
+    let mog_dir = repo.root.join(".mog");
+    let mut roots = Vec::new();
+
+    //
+    // Refs (heads, tags, remotes, stash) and loose state files (HEAD, MERGE_HEAD, rebase state).
+    // Anything that doesn't parse as a hash (symbolic HEAD, head-name, conflict lists) is skipped.
+    //
+    let mut stack = vec![mog_dir.join("refs"), mog_dir.join("rebase")];
+    while let Some(dir) = stack.pop() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+
+        for entry in entries {
+            let path = entry?.path();
+            if path.is_dir() {
+                stack.push(path);
+            } else {
+                push_hashes_in_file(&path, &mut roots);
+            }
+        }
+    }
+
+    push_hashes_in_file(&mog_dir.join("HEAD"), &mut roots);
+    push_hashes_in_file(&mog_dir.join("MERGE_HEAD"), &mut roots);
+
+    roots.extend(crate::reflog::all_hashes(repo)?);
+
+    Ok(roots)
+}
+
+#[inline]
+fn push_hashes_in_file(path: &std::path::Path, out: &mut Vec<Hash>) {
+    let Ok(content) = fs::read_to_string(path) else {
+        return;
+    };
+    out.extend(content.lines().filter_map(|line| hex_to_hash(line.trim()).ok()));
+}
@@ -16,3 +16,17 @@ pub fn hex_to_hash(s: &str) -> Result<Hash> {
     let bytes = hex::decode(s)?;
     bytes.try_into().map_err(|_| anyhow::anyhow!("invalid hash length"))
 }
+
+/// Whether the hex form of `hash` starts with `prefix` (hex digits, either case).
+#[must_use]
+pub fn hash_has_hex_prefix(hash: &Hash, prefix: &str) -> bool {
+    if prefix.len() > 64 {
+        return false;
+    }
+
+    prefix.chars().enumerate().all(|(i, c)| {
+        let byte   = hash[i / 2];
+        let nibble = if i % 2 == 0 { byte >> 4 } else { byte & 0xF };
+        c.to_digit(16) == Some(u32::from(nibble))
+    })
+}
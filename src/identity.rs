@@ -0,0 +1,119 @@
+//! Who is making a commit or tag.
+//!
+//! Names and emails come from, first to last: `MOG_AUTHOR_NAME`/`MOG_AUTHOR_EMAIL`
+//! (`MOG_COMMITTER_NAME`/`MOG_COMMITTER_EMAIL` for the committer), `user.name`/`user.email`
+//! in `mog config`, and finally the login name with no email.
+
+use crate::commit::Signature;
+use crate::config::{self, Config};
+use crate::date::{local_tz_offset, now};
+
+use std::fmt;
+use std::path::Path;
+
+use anyhow::{Result, bail};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Author,
+    Committer,
+}
+
+impl Role {
+    #[inline]
+    #[must_use]
+    fn env_vars(self) -> (&'static str, &'static str) {
+        match self {
+            Self::Author    => ("MOG_AUTHOR_NAME", "MOG_AUTHOR_EMAIL"),
+            Self::Committer => ("MOG_COMMITTER_NAME", "MOG_COMMITTER_EMAIL"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Identity {
+    pub name:  String,
+    /// Empty when nobody told us.
+    pub email: String,
+}
+
+impl Identity {
+    /// `Name <email>`, or just `Name`.
+    pub fn parse(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let (name, email) = match s.split_once('<') {
+            Some((name, rest)) => {
+                let Some(email) = rest.strip_suffix('>') else {
+                    bail!("bad identity '{s}': expected 'Name <email>'");
+                };
+                (name.trim(), email.trim())
+            }
+            None => (s, ""),
+        };
+
+        if name.is_empty() {
+            bail!("bad identity '{s}': empty name");
+        }
+        if email.contains(['<', '>']) {
+            bail!("bad identity '{s}': expected 'Name <email>'");
+        }
+
+        Ok(Self { name: name.to_owned(), email: email.to_owned() })
+    }
+
+    /// Resolve `role` from `config` and the environment (looked up through `env`),
+    /// falling back to the login name.
+    pub fn from_config(config: &Config, env: impl Fn(&str) -> Option<String>, role: Role) -> Self {
+        let (name_var, email_var) = role.env_vars();
+        let lookup = |var: &str, key: &str| {
+            env(var)
+                .filter(|v| !v.trim().is_empty())
+                .or_else(|| config.get(key).map(str::to_owned))
+                .map(|v| v.trim().to_owned())
+        };
+
+        let name = lookup(name_var, "user.name")
+            .or_else(|| env("USER").or_else(|| env("USERNAME")))
+            .unwrap_or_else(|| "unknown".to_owned());
+        let email = lookup(email_var, "user.email").unwrap_or_default();
+
+        Self { name, email }
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn sign(&self, timestamp: i64, tz_offset: i32) -> Signature<'_> {
+        Signature { name: &self.name, email: &self.email, timestamp, tz_offset }
+    }
+
+    /// Signed right now, in the local time zone.
+    #[inline]
+    pub fn sign_now(&self) -> Result<Signature<'_>> {
+        let timestamp = now()?;
+        Ok(self.sign(timestamp, local_tz_offset(timestamp)))
+    }
+}
+
+impl fmt::Display for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.email.is_empty() {
+            write!(f, "{}", self.name)
+        } else {
+            write!(f, "{} <{}>", self.name, self.email)
+        }
+    }
+}
+
+/// `role` for the repository at `root`, from the process environment and `mog config`.
+pub fn resolve(root: &Path, role: Role) -> Result<Identity> {
+    let config = config::load(Some(root))?;
+    Ok(Identity::from_config(&config, |var| std::env::var(var).ok(), role))
+}
+
+/// The author for a new commit: `--author` when given, otherwise resolved.
+pub fn author(root: &Path, given: Option<&str>) -> Result<Identity> {
+    match given {
+        Some(given) => Identity::parse(given),
+        None        => resolve(root, Role::Author),
+    }
+}
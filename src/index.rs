@@ -1,5 +1,5 @@
 use crate::hash::Hash;
-use crate::object::{MODE_DIR, MODE_EXEC, MODE_FILE};
+use crate::object::{MODE_DIR, MODE_EXEC, MODE_FILE, MODE_LINK};
 use crate::repository::Repository;
 use crate::object::Object;
 use crate::storage::MogStorage;
@@ -16,7 +16,7 @@ use anyhow::{Result, bail};
 use xxhash_rust::xxh3::xxh3_64;
 
 const INDEX_MAGIC: &[u8; 4] = b"MOGI";
-const INDEX_VERSION: u32 = 1;
+const INDEX_VERSION: u32 = 2;
 
 // On-disk binary layout:
 //
@@ -26,32 +26,68 @@ const INDEX_VERSION: u32 = 1;
 // [modes: u32 * count]
 // [hashes: [u8; 32] * count]
 // [mtimes: i64 * count]
+// [mtime_nsecs: u32 * count]     (v2)
+// [ctimes: i64 * count]          (v2)
+// [ctime_nsecs: u32 * count]     (v2)
+// [inodes: u64 * count]          (v2)
+// [devices: u64 * count]         (v2)
 // [sizes: u64 * count]
 // [path_offsets: u32 * count]
 // [paths_blob_len: u32]
 // [paths_blob: u8 * paths_blob_len]
+// [extensions...]                (optional)
 //
-// Per-entry fixed cost: 4 + 32 + 8 + 8 + 4 = 56 bytes
-// Total = 12 + count * 56 + 4 + paths_blob_len
+// Per-entry fixed cost: 4 + 32 + 8 + 4 + 8 + 4 + 8 + 8 + 8 + 4 = 88 bytes (56 in v1)
+// Total = 12 + count * 88 + 4 + paths_blob_len + extensions
+//
+// Version 1 indexes (no v2 columns) still load; their entries read as changed once and
+// get the missing stat data the next time they are staged.
+//
+// Entries are written sorted by path, so a loaded index iterates in path order until
+// something is added.
+//
+// Each extension is [signature: 4][len: u32][data: u8 * len]; unknown ones are skipped.
+//
+// "TREE": tree hashes of directories nothing under has changed since they were last built.
+// [count: u32]
+// [path_len: u32][path: u8 * path_len][hash: 32]   (count times, root is the empty path)
 
 pub const MINIMAL_HEADER_SIZE_IN_BYTES: usize = 12; // magic, version and count
 pub const PATHS_BLOB_LEN_SIZE_IN_BYTES: usize = 4;
-pub const ENTRY_SIZE_IN_BYTES: usize = 56;
+pub const ENTRY_SIZE_IN_BYTES: usize = 88;
+pub const EXTENSION_HEADER_SIZE_IN_BYTES: usize = 8; // signature and length
+
+const TREE_CACHE_SIGNATURE: &[u8; 4] = b"TREE";
 
 #[derive(Default, Clone)]
 pub struct Index {
     pub count: usize,
 
-    pub modes:  Vec<u32>,
-    pub hashes: Vec<Hash>,
-    pub mtimes: Vec<i64>,
-    pub sizes:  Vec<u64>,
+    pub modes:       Vec<u32>,
+    pub hashes:      Vec<Hash>,
+    pub mtimes:      Vec<i64>,
+    pub mtime_nsecs: Vec<u32>,
+    pub ctimes:      Vec<i64>,
+    pub ctime_nsecs: Vec<u32>,
+    pub inodes:      Vec<u64>,
+    pub devices:     Vec<u64>,
+    pub sizes:       Vec<u64>,
 
     pub path_offsets: Vec<u32>,
     pub paths_blob:   Vec<u8>,
 
     /// Path hash -> entry index (or indices on collision). No duplicate path storage.
     path_index: Xxh3HashMap<u64, Vec<usize>>,
+
+    /// Directory path ("" for the root) -> hash of its tree, for directories nothing under
+    /// has changed since. `add` and `remove` drop the entries along the changed path, so
+    /// `write_tree` and `status` only revisit directories that actually changed.
+    tree_cache: Xxh3HashMap<Box<str>, Hash>,
+
+    /// Mtime (secs, nsecs) of the index file this was loaded from. A file modified in the
+    /// same instant the index was written may have changed again without its stat data
+    /// showing it, so entries not older than this are always treated as dirty ("racy").
+    loaded_mtime: Option<(i64, u32)>,
 }
 
 #[derive(Debug)]
@@ -87,13 +123,7 @@ impl<'index> Iterator for IndexIter<'index> {
             return None;
         }
 
-        let e = IndexEntryRef {
-            mode:  self.mog_index.modes[self.index],
-            hash:  &self.mog_index.hashes[self.index],
-            mtime: self.mog_index.mtimes[self.index],
-            size:  self.mog_index.sizes[self.index],
-            path:  self.mog_index.get_path(self.index),
-        };
+        let e = self.mog_index.entry(self.index);
 
         self.index += 1;
 
@@ -111,8 +141,11 @@ impl Index {
             return Ok(Self::default());
         }
 
-        let data = fs::read(path)?;
-        Self::decode(&data)
+        let data = fs::read(&path)?;
+        let mut index = Self::decode(&data)?;
+        let meta = fs::metadata(&path)?;
+        index.loaded_mtime = Some((meta.mtime_secs(), meta.mtime_nsecs()));
+        Ok(index)
     }
 
     #[inline]
@@ -120,9 +153,15 @@ impl Index {
         let _span = tracy::span!("Index::save");
 
         let path = repo_root.join(".mog/index");
-        fs::write(path, self.encode())?;
+        let mut lock = crate::lockfile::LockFile::acquire(&path)?;
 
-        Ok(())
+        //
+        // The lock was just created, so its mtime is "now" on the filesystem's own clock:
+        // anything modified at or after it is racy against the file about to be written.
+        //
+        let meta = lock.metadata()?;
+        lock.write_all(&self.encode(Some((meta.mtime_secs(), meta.mtime_nsecs()))))?;
+        lock.commit()
     }
 
     #[inline]
@@ -131,10 +170,16 @@ impl Index {
         self.modes.clear();
         self.hashes.clear();
         self.mtimes.clear();
+        self.mtime_nsecs.clear();
+        self.ctimes.clear();
+        self.ctime_nsecs.clear();
+        self.inodes.clear();
+        self.devices.clear();
         self.sizes.clear();
         self.path_offsets.clear();
         self.paths_blob.clear();
         self.path_index.clear();
+        self.tree_cache.clear();
     }
 
     #[inline]
@@ -143,18 +188,60 @@ impl Index {
         IndexIter { mog_index: self, index: 0 }
     }
 
+    /// Entries in path order.
+    #[inline]
+    pub fn iter_sorted(&self) -> impl Iterator<Item = IndexEntryRef<'_>> {
+        self.sorted_order().into_iter().map(|i| self.entry(i))
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn entry(&self, i: usize) -> IndexEntryRef<'_> {
+        IndexEntryRef {
+            mode:  self.modes[i],
+            hash:  &self.hashes[i],
+            mtime: self.mtimes[i],
+            size:  self.sizes[i],
+            path:  self.get_path(i),
+        }
+    }
+
+    /// Entry indices in path order. Loaded indexes are already sorted, so that's usually
+    /// just a check; entries added since make it a sort.
+    #[must_use]
+    pub fn sorted_order(&self) -> Vec<usize> {
+        let mut order = (0..self.count).collect::<Vec<_>>();
+        if !order.is_sorted_by_key(|&i| self.get_path(i)) {
+            order.sort_unstable_by_key(|&i| self.get_path(i));
+        }
+        order
+    }
+
     #[inline]
     fn total_size_in_bytes(&self) -> usize {
         MINIMAL_HEADER_SIZE_IN_BYTES +
             (self.count * ENTRY_SIZE_IN_BYTES) +
             PATHS_BLOB_LEN_SIZE_IN_BYTES +
-            self.paths_blob.len()
+            self.paths_blob.len() +
+            self.tree_cache_size_in_bytes()
     }
 
     #[inline]
-    fn encode(&self) -> Vec<u8> {
+    fn tree_cache_size_in_bytes(&self) -> usize {
+        if self.tree_cache.is_empty() {
+            return 0;
+        }
+        EXTENSION_HEADER_SIZE_IN_BYTES + 4 +
+            self.tree_cache.keys().map(|dir| 4 + dir.len() + 32).sum::<usize>()
+    }
+
+    /// `written_at` is the time of the write in progress; entries modified no earlier than it
+    /// get their mtime smudged.
+    #[inline]
+    fn encode(&self, written_at: Option<(i64, u32)>) -> Vec<u8> {
         let fixed = self.total_size_in_bytes();
         let mut buf = Vec::with_capacity(fixed);
+        let order = self.sorted_order();
 
         //
         // Header
@@ -163,17 +250,54 @@ impl Index {
         buf.extend_from_slice(&INDEX_VERSION.to_le_bytes());
         buf.extend_from_slice(&(self.count as u32).to_le_bytes());
 
-        for m in &self.modes        { buf.extend_from_slice(&m.to_le_bytes()); }
-        for h in &self.hashes       { buf.extend_from_slice(h); }
-        for t in &self.mtimes       { buf.extend_from_slice(&t.to_le_bytes()); }
-        for s in &self.sizes        { buf.extend_from_slice(&s.to_le_bytes()); }
-        for o in &self.path_offsets { buf.extend_from_slice(&o.to_le_bytes()); }
+        for &i in &order { buf.extend_from_slice(&self.modes[i].to_le_bytes()); }
+        for &i in &order { buf.extend_from_slice(&self.hashes[i]); }
+        //
+        // Entries modified in the same tick as this write get their mtime smudged: once the index
+        // is rewritten later than them, nothing would flag them anymore, so make them look
+        // changed until they're re-hashed.
+        //
+        for &i in &order {
+            let racy = written_at.is_some_and(|at| (self.mtimes[i], self.mtime_nsecs[i]) >= at);
+            let t = if racy { 0 } else { self.mtimes[i] };
+            buf.extend_from_slice(&t.to_le_bytes());
+        }
+        for &i in &order { buf.extend_from_slice(&self.mtime_nsecs[i].to_le_bytes()); }
+        for &i in &order { buf.extend_from_slice(&self.ctimes[i].to_le_bytes()); }
+        for &i in &order { buf.extend_from_slice(&self.ctime_nsecs[i].to_le_bytes()); }
+        for &i in &order { buf.extend_from_slice(&self.inodes[i].to_le_bytes()); }
+        for &i in &order { buf.extend_from_slice(&self.devices[i].to_le_bytes()); }
+        for &i in &order { buf.extend_from_slice(&self.sizes[i].to_le_bytes()); }
+
+        let mut offset = 0u32;
+        for &i in &order {
+            buf.extend_from_slice(&offset.to_le_bytes());
+            offset += self.get_path(i).len() as u32;
+        }
 
         //
         // Paths blob
         //
         buf.extend_from_slice(&(self.paths_blob.len() as u32).to_le_bytes());
-        buf.extend_from_slice(&self.paths_blob);
+        for &i in &order { buf.extend_from_slice(self.get_path(i).as_bytes()); }
+
+        //
+        // Tree cache extension, sorted so the same cache always encodes the same way
+        //
+        if !self.tree_cache.is_empty() {
+            let mut dirs = self.tree_cache.iter().collect::<Vec<_>>();
+            dirs.sort_unstable_by_key(|&(dir, _)| dir);
+
+            let len = self.tree_cache_size_in_bytes() - EXTENSION_HEADER_SIZE_IN_BYTES;
+            buf.extend_from_slice(TREE_CACHE_SIGNATURE);
+            buf.extend_from_slice(&(len as u32).to_le_bytes());
+            buf.extend_from_slice(&(dirs.len() as u32).to_le_bytes());
+            for (dir, hash) in dirs {
+                buf.extend_from_slice(&(dir.len() as u32).to_le_bytes());
+                buf.extend_from_slice(dir.as_bytes());
+                buf.extend_from_slice(hash);
+            }
+        }
 
         buf
     }
@@ -194,7 +318,7 @@ impl Index {
         }
 
         let version = u32::from_le_bytes(data[4..8].try_into()?);
-        if version != INDEX_VERSION {
+        if version != 1 && version != INDEX_VERSION {
             bail!("unsupported index version {version}");
         }
 
@@ -249,6 +373,20 @@ impl Index {
         let mut mtimes = Vec::with_capacity(count);
         for _ in 0..count { mtimes.push(read_i64!()); }
 
+        // Nanoseconds, ctimes, inodes and devices (zero in v1, which never matches a real file)
+        let mut mtime_nsecs = vec![0; count];
+        let mut ctimes      = vec![0; count];
+        let mut ctime_nsecs = vec![0; count];
+        let mut inodes      = vec![0; count];
+        let mut devices     = vec![0; count];
+        if version >= 2 {
+            for n in &mut mtime_nsecs { *n = read_u32!(); }
+            for t in &mut ctimes      { *t = read_i64!(); }
+            for n in &mut ctime_nsecs { *n = read_u32!(); }
+            for i in &mut inodes      { *i = read_u64!(); }
+            for d in &mut devices     { *d = read_u64!(); }
+        }
+
         // Sizes
         let mut sizes = Vec::with_capacity(count);
         for _ in 0..count { sizes.push(read_u64!()); }
@@ -260,16 +398,62 @@ impl Index {
         // Paths blob
         let blob_len = read_u32!() as usize;
         let paths_blob = data[cur..cur + blob_len].to_vec();
+        cur += blob_len;
+
+        //
+        //
+        // Extensions
+        //
+        //
+
+        let mut tree_cache = Xxh3HashMap::default();
+        while cur < data.len() {
+            if data.len() - cur < EXTENSION_HEADER_SIZE_IN_BYTES {
+                bail!("truncated index extension");
+            }
+            let signature = &data[cur..cur + 4];
+            cur += 4;
+            let len = read_u32!() as usize;
+            if data.len() - cur < len {
+                bail!("truncated index extension");
+            }
+            let end = cur + len;
+
+            if signature == TREE_CACHE_SIGNATURE && len >= 4 {
+                let dirs = read_u32!();
+                for _ in 0..dirs {
+                    if end - cur < 4 {
+                        bail!("truncated index tree cache");
+                    }
+                    let dir_len = read_u32!() as usize;
+                    if end - cur < dir_len + 32 {
+                        bail!("truncated index tree cache");
+                    }
+                    let dir = str_from_utf8_data_shouldve_been_valid_or_we_got_hacked(&data[cur..cur + dir_len]);
+                    cur += dir_len;
+                    tree_cache.insert(dir.into(), read_u256!());
+                }
+            }
+
+            cur = end;
+        }
 
         let mut index = Self {
             count,
             modes,
             hashes,
             mtimes,
+            mtime_nsecs,
+            ctimes,
+            ctime_nsecs,
+            inodes,
+            devices,
             sizes,
             path_offsets,
             paths_blob,
             path_index: HashMap::default(),
+            tree_cache,
+            loaded_mtime: None,
         };
         index.build_path_index();
         Ok(index)
@@ -324,23 +508,39 @@ impl Index {
         let path_str = path.as_ref();
 
         let mtime = meta.mtime_secs();
-        let mode = if meta.is_executable() { MODE_EXEC } else { MODE_FILE };
+        let mode = meta.mode();
         let size = meta.size_bytes();
 
         let h = Self::path_hash(path_str);
         if let Some(i) = self.path_index.get(&h).and_then(|list| {
             list.iter().copied().find(|&idx| self.get_path(idx) == path_str)
         }) {
-            self.modes[i]  = mode;
-            self.hashes[i] = hash;
-            self.mtimes[i] = mtime;
-            self.sizes[i]  = size;
+            if self.hashes[i] != hash || self.modes[i] != mode {
+                self.invalidate_tree_cache(path_str);
+            }
+
+            self.modes[i]       = mode;
+            self.hashes[i]      = hash;
+            self.mtimes[i]      = mtime;
+            self.mtime_nsecs[i] = meta.mtime_nsecs();
+            self.ctimes[i]      = meta.ctime_secs();
+            self.ctime_nsecs[i] = meta.ctime_nsecs();
+            self.inodes[i]      = meta.inode();
+            self.devices[i]     = meta.device();
+            self.sizes[i]       = size;
             return;
         }
 
+        self.invalidate_tree_cache(path_str);
+
         self.modes.push(mode);
         self.hashes.push(hash);
         self.mtimes.push(mtime);
+        self.mtime_nsecs.push(meta.mtime_nsecs());
+        self.ctimes.push(meta.ctime_secs());
+        self.ctime_nsecs.push(meta.ctime_nsecs());
+        self.inodes.push(meta.inode());
+        self.devices.push(meta.device());
         self.sizes.push(size);
         self.path_offsets.push(self.paths_blob.len() as u32);
         self.paths_blob.extend_from_slice(path_str.as_bytes());
@@ -348,50 +548,58 @@ impl Index {
         self.count += 1;
     }
 
+    /// Removing many paths? Use `remove_many`, this is a pass over the whole index each time.
+    #[inline]
     pub fn remove(&mut self, path: impl AsRef<str>) -> bool {
-        let path_str = path.as_ref();
-        let h = Self::path_hash(path_str);
-        let (pos, i) = match self.path_index.get(&h) {
-            Some(list) => {
-                let Some(pos) = list.iter().position(|&idx| self.get_path(idx) == path_str) else {
-                    return false;
-                };
-                (pos, list[pos])
+        self.remove_many([path]) == 1
+    }
+
+    /// Remove all of `paths` (missing ones and repeats are fine) in a single pass over the
+    /// columns, keeping the rest in order. Returns how many entries went away.
+    pub fn remove_many(&mut self, paths: impl IntoIterator<Item = impl AsRef<str>>) -> usize {
+        let _span = tracy::span!("Index::remove_many");
+
+        let mut doomed = vec![false; self.count];
+        let mut removed = 0;
+        for path in paths {
+            let path_str = path.as_ref();
+            if let Some(i) = self.find(path_str) {
+                if !doomed[i] {
+                    doomed[i] = true;
+                    removed += 1;
+                    self.invalidate_tree_cache(path_str);
+                }
             }
-            None => return false,
-        };
+        }
 
-        self.modes.remove(i);
-        self.hashes.remove(i);
-        self.mtimes.remove(i);
-        self.sizes.remove(i);
+        if removed == 0 {
+            return 0;
+        }
+
+        retain_unless(&mut self.modes,       &doomed);
+        retain_unless(&mut self.hashes,      &doomed);
+        retain_unless(&mut self.mtimes,      &doomed);
+        retain_unless(&mut self.mtime_nsecs, &doomed);
+        retain_unless(&mut self.ctimes,      &doomed);
+        retain_unless(&mut self.ctime_nsecs, &doomed);
+        retain_unless(&mut self.inodes,      &doomed);
+        retain_unless(&mut self.devices,     &doomed);
+        retain_unless(&mut self.sizes,       &doomed);
 
         let owned_path_offsets = core::mem::take(&mut self.path_offsets);
         let owned_path_blob = core::mem::take(&mut self.paths_blob);
+        self.paths_blob.reserve(owned_path_blob.len());
 
-        for index in (0..self.count).filter(|&j| j != i) {
+        for index in (0..self.count).filter(|&j| !doomed[j]) {
             let p = Self::get_path_impl(self.count, &owned_path_offsets, &owned_path_blob, index);
             self.path_offsets.push(self.paths_blob.len() as u32);
             self.paths_blob.extend_from_slice(p.as_bytes());
         }
 
-        self.count -= 1;
-        let list = self.path_index.get_mut(&h).unwrap();
-        list.remove(pos);
+        self.count -= removed;
+        self.build_path_index();
 
-        if list.is_empty() {
-            self.path_index.remove(&h);
-        }
-
-        for list in self.path_index.values_mut() {
-            for idx in list.iter_mut() {
-                if *idx > i {
-                    *idx -= 1;
-                }
-            }
-        }
-
-        true
+        removed
     }
 
     /// Recursively update index entries for all files under a checked-out tree.
@@ -408,10 +616,10 @@ impl Index {
 
             let object = repo.read_object(&hash)?;
             match object {
-                Object::Blob(_) => {
+                Object::Blob(_) | Object::ChunkList(_) => {
                     if prefix.is_empty() {
                         let abs = repo.root.join(name.as_ref());
-                        let metadata = fs::metadata(&abs)?;
+                        let metadata = fs::symlink_metadata(&abs)?;
                         self.add(name, hash, &metadata);
                     } else {
                         let mut path = String::with_capacity(prefix.len() + 1 + name.len());
@@ -420,7 +628,7 @@ impl Index {
                         path.push_str(&name);
 
                         let abs = repo.root.join(&path);
-                        let metadata = fs::metadata(&abs)?;
+                        let metadata = fs::symlink_metadata(&abs)?;
 
                         self.add(&path, hash, &metadata);
                     }
@@ -440,37 +648,96 @@ impl Index {
                     self.update_from_tree_recursive(repo, sub_id, &path)?;
                 }
 
-                Object::Commit(_) => {}
+                Object::Commit(_) | Object::Tag(_) => {}
             }
         }
 
         Ok(())
     }
 
-    // Fast dirty check: compare mtime + size before hashing.
+    // Fast dirty check: compare stat data (mtime, ctime, inode, device, size) before hashing,
+    // and the mode, since a chmod (or a file turned into a symlink) doesn't have to touch the rest.
     // Returns true if the file MIGHT be modified (triggers full hash check).
     #[inline]
     #[must_use]
     pub fn is_dirty(&self, i: usize, meta: &impl AsMetadata) -> bool {
         let _span = tracy::span!("Index::is_dirty");
 
-        let mtime = meta.mtime_secs();
+        self.mtimes[i]      != meta.mtime_secs()  ||
+        self.mtime_nsecs[i] != meta.mtime_nsecs() ||
+        self.ctimes[i]      != meta.ctime_secs()  ||
+        self.ctime_nsecs[i] != meta.ctime_nsecs() ||
+        self.inodes[i]      != meta.inode()       ||
+        self.devices[i]     != meta.device()      ||
+        self.sizes[i]       != meta.size_bytes()  ||
+        self.modes[i]       != meta.mode()        ||
+        self.is_racy(i)
+    }
+
+    /// The entry's file was last modified no earlier than the index was written, so a later
+    /// write in the same timestamp tick would leave its stat data unchanged. Only the
+    /// contents can tell.
+    #[inline]
+    #[must_use]
+    pub fn is_racy(&self, i: usize) -> bool {
+        self.loaded_mtime.is_some_and(|written| (self.mtimes[i], self.mtime_nsecs[i]) >= written)
+    }
+
+    /// Hash of the tree last built for `dir` ("" for the root), if nothing under it changed since.
+    #[inline]
+    #[must_use]
+    pub fn cached_tree(&self, dir: &str) -> Option<Hash> {
+        self.tree_cache.get(dir).copied()
+    }
 
-        self.mtimes[i] != mtime || self.sizes[i] != meta.size_bytes()
+    /// Record that the entries under `dir` make up the tree `hash`.
+    #[inline]
+    pub fn cache_tree(&mut self, dir: &str, hash: Hash) {
+        self.tree_cache.insert(dir.into(), hash);
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn cached_tree_count(&self) -> usize {
+        self.tree_cache.len()
+    }
+
+    /// `path` changed: every directory above it now needs rebuilding.
+    #[inline]
+    fn invalidate_tree_cache(&mut self, path: &str) {
+        if self.tree_cache.is_empty() {
+            return;
+        }
+
+        self.tree_cache.remove("");
+        for (slash, _) in path.match_indices('/') {
+            self.tree_cache.remove(&path[..slash]);
+        }
     }
 
     // Build and write tree objects from index entries.
     // Groups entries by directory, recursively builds subtrees bottom-up.
+    // Directories still in the tree cache are reused as-is, and everything built gets cached,
+    // so save the index afterwards to keep the next call cheap.
     #[inline]
-    pub fn write_tree(&self, repo: &mut Repository<impl MogStorage>) -> Result<Hash> {
+    pub fn write_tree(&mut self, repo: &mut Repository<impl MogStorage>) -> Result<Hash> {
+        if let Some(hash) = self.cached_tree("") {
+            if repo.storage.exists(&hash) {
+                return Ok(hash);
+            }
+        }
+
         //
         // Sort entries by path
         //
 
-        let mut order = (0..self.count).collect::<Vec<_>>();
-        order.sort_unstable_by_key(|&i| self.get_path(i));
+        let order = self.sorted_order();
 
-        let sorted_paths  = order.iter().map(|&i| self.get_path(i)).collect::<Vec<_>>();
+        //
+        // Paths borrow only the path columns, leaving the tree cache free to update.
+        //
+        let (count, path_offsets, paths_blob) = (self.count, &self.path_offsets, &self.paths_blob);
+        let sorted_paths  = order.iter().map(|&i| Self::get_path_impl(count, path_offsets, paths_blob, i)).collect::<Vec<_>>();
         let sorted_modes  = order.iter().map(|&i| self.modes[i]).collect::<Vec<_>>();
         let sorted_hashes = order.iter().map(|&i| self.hashes[i]).collect::<Vec<_>>();
 
@@ -483,6 +750,7 @@ impl Index {
             &sorted_paths,
             &sorted_modes,
             &sorted_hashes,
+            &mut self.tree_cache,
             "",   // current directory prefix
             0,    // start index
         )?;
@@ -491,13 +759,26 @@ impl Index {
     }
 }
 
+/// Drop the elements of `column` whose `doomed` flag is set.
+#[inline]
+fn retain_unless<T>(column: &mut Vec<T>, doomed: &[bool]) {
+    let mut i = 0;
+    column.retain(|_| {
+        let keep = !doomed[i];
+        i += 1;
+        keep
+    });
+}
+
 // Builds a tree for `dir` by consuming a contiguous slice of sorted entries.
+// Subdirectories found in `tree_cache` are skipped over; the ones built are added to it.
 // Returns (tree_hash, how_many_entries_consumed).
 fn build_tree(
     repo: &mut Repository<impl MogStorage>,
     paths:  &[&str],
     modes:  &[u32],
     hashes: &[Hash],
+    tree_cache: &mut Xxh3HashMap<Box<str>, Hash>,
     dir:    &str,         // current directory prefix e.g. "src/foo"
     start:  usize,        // where in the `paths` we are
 ) -> Result<(Hash, usize)> {
@@ -544,6 +825,7 @@ fn build_tree(
             let tree_id = repo.tree.push(&done.tree_entries_buffer);
             let hash = repo.write_object(Object::Tree(tree_id));
             let consumed = i - done.start;
+            tree_cache.insert(done.dir.into(), hash); // @Clone
 
             if let Some(parent) = stack.last_mut() {
                 let name = done.name_in_parent.expect("non-root frame must have a name");
@@ -604,6 +886,27 @@ fn build_tree(
                     &path_norm[..cur_dir_len + 1 + slash]
                 };
 
+                //
+                // Unchanged since it was last built - reuse its tree and skip everything under it
+                //
+                if let Some(&hash) = tree_cache.get(subdir_full) {
+                    if repo.storage.exists(&hash) {
+                        let under = |p: &&str| {
+                            let p = p.trim_start_matches('/');
+                            p.starts_with(subdir_full) && p.as_bytes().get(subdir_full.len()) == Some(&b'/')
+                        };
+                        i += paths[i..].partition_point(under);
+
+                        let top = stack.last_mut().expect("non-empty stack");
+                        top.tree_entries_buffer.push(TreeEntry {
+                            mode: MODE_DIR,
+                            hash,
+                            name: subdir_name.into() // @Clone
+                        });
+                        continue;
+                    }
+                }
+
                 stack.push(Frame {
                     dir: subdir_full,
                     start: i,
@@ -620,15 +923,39 @@ pub struct FakeMeta { pub mtime: i64, pub size: u64 }
 
 impl Index {
     #[inline]
-    pub fn encode_for_test(&self) -> Vec<u8> { self.encode() }
+    #[must_use]
+    pub fn encode_for_test(&self) -> Vec<u8> { self.encode(None) }
     #[inline]
     pub fn decode_for_test(data: &[u8]) -> Result<Self> { Self::decode(data) }
 }
 
 pub trait AsMetadata {
     fn mtime_secs(&self) -> i64;
+    /// Sub-second part of the mtime.
+    fn mtime_nsecs(&self) -> u32;
+    /// Inode change time; 0 where the platform has none.
+    fn ctime_secs(&self) -> i64;
+    fn ctime_nsecs(&self) -> u32;
+    /// 0 where the platform has none.
+    fn inode(&self) -> u64;
+    /// 0 where the platform has none.
+    fn device(&self) -> u64;
     fn size_bytes(&self) -> u64;
     fn is_executable(&self) -> bool;
+    /// Metadata of the link itself, from `symlink_metadata`.
+    fn is_symlink(&self) -> bool;
+
+    /// Tree mode the file would be recorded with.
+    #[inline]
+    fn mode(&self) -> u32 {
+        if self.is_symlink() {
+            MODE_LINK
+        } else if self.is_executable() {
+            MODE_EXEC
+        } else {
+            MODE_FILE
+        }
+    }
 }
 
 impl AsMetadata for fs::Metadata {
@@ -639,15 +966,55 @@ impl AsMetadata for fs::Metadata {
             .map_or(0, |d| d.as_secs() as i64)
     }
 
+    #[inline]
+    fn mtime_nsecs(&self) -> u32 {
+        self.modified().ok()
+            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+            .map_or(0, |d| d.subsec_nanos())
+    }
+
+    #[inline]
+    fn ctime_secs(&self) -> i64 {
+        #[cfg(unix)] { std::os::unix::fs::MetadataExt::ctime(self) }
+        #[cfg(not(unix))] { 0 }
+    }
+
+    #[inline]
+    fn ctime_nsecs(&self) -> u32 {
+        #[cfg(unix)] { std::os::unix::fs::MetadataExt::ctime_nsec(self) as u32 }
+        #[cfg(not(unix))] { 0 }
+    }
+
+    #[inline]
+    fn inode(&self) -> u64 {
+        #[cfg(unix)] { std::os::unix::fs::MetadataExt::ino(self) }
+        #[cfg(not(unix))] { 0 }
+    }
+
+    #[inline]
+    fn device(&self) -> u64 {
+        #[cfg(unix)] { std::os::unix::fs::MetadataExt::dev(self) }
+        #[cfg(not(unix))] { 0 }
+    }
+
     #[inline]
     fn size_bytes(&self) -> u64 { self.len() }
 
     #[inline]
     fn is_executable(&self) -> bool { is_executable(self) }
+
+    #[inline]
+    fn is_symlink(&self) -> bool { self.file_type().is_symlink() }
 }
 
 impl AsMetadata for FakeMeta {
     fn mtime_secs(&self) -> i64 { self.mtime }
+    fn mtime_nsecs(&self) -> u32 { 0 }
+    fn ctime_secs(&self) -> i64 { 0 }
+    fn ctime_nsecs(&self) -> u32 { 0 }
+    fn inode(&self) -> u64 { 0 }
+    fn device(&self) -> u64 { 0 }
     fn size_bytes(&self) -> u64 { self.size }
     fn is_executable(&self) -> bool { false }
+    fn is_symlink(&self) -> bool { false }
 }
@@ -64,3 +64,19 @@ pub mod stash;
 pub mod discard;
 pub mod storage_mock;
 pub mod diff;
+pub mod merge;
+pub mod rebase;
+pub mod reflog;
+pub mod tag;
+pub mod revision;
+pub mod gc;
+pub mod delta;
+pub mod repack;
+pub mod chunk;
+pub mod fsck;
+pub mod lockfile;
+pub mod remote;
+pub mod protocol;
+pub mod config;
+pub mod identity;
+pub mod date;
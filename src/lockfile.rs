@@ -0,0 +1,162 @@
+//! Crash-safe replacement of small state files (index, refs, HEAD).
+//!
+//! New contents go to `<path>.lock`, created exclusively so two writers can't interleave,
+//! get fsynced, and are renamed over `path`. A crash at any point leaves either the old file
+//! or the new one, plus possibly the `.lock`. Writers of that file refuse while it exists, and
+//! once it's old enough that no live command can still be holding it, every command stops until
+//! someone looks at it (see `ensure_no_stale_locks`).
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{Result, bail};
+
+pub const LOCK_SUFFIX: &str = ".lock";
+
+/// A lock is only held for one small write, so one this old was left by a command that died.
+pub const STALE_LOCK_AGE: Duration = Duration::from_mins(1);
+
+pub struct LockFile {
+    path:      PathBuf,
+    lock_path: PathBuf,
+    /// None once committed.
+    file:      Option<File>,
+}
+
+impl LockFile {
+    /// Take the lock for `path`. Fails if someone else holds it, or a crashed command left it behind.
+    pub fn acquire(path: &Path) -> Result<Self> {
+        let lock_path = lock_path_for(path);
+        match OpenOptions::new().write(true).create_new(true).open(&lock_path) {
+            Ok(file) => Ok(Self { path: path.to_owned(), lock_path, file: Some(file) }),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => bail!(locked_message(&lock_path)),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    #[inline]
+    pub fn write_all(&mut self, data: &[u8]) -> Result<()> {
+        let Some(file) = self.file.as_mut() else {
+            bail!("lock for '{}' already committed", self.path.display());
+        };
+        file.write_all(data)?;
+        Ok(())
+    }
+
+    #[inline]
+    pub fn metadata(&self) -> Result<fs::Metadata> {
+        let Some(file) = self.file.as_ref() else {
+            bail!("lock for '{}' already committed", self.path.display());
+        };
+        Ok(file.metadata()?)
+    }
+
+    /// Make the written contents durable and move them over the target, releasing the lock.
+    pub fn commit(mut self) -> Result<()> {
+        let Some(file) = self.file.take() else {
+            bail!("lock for '{}' already committed", self.path.display());
+        };
+        file.sync_all()?;
+        drop(file);
+
+        if let Err(e) = fs::rename(&self.lock_path, &self.path) {
+            _ = fs::remove_file(&self.lock_path);
+            return Err(e.into());
+        }
+
+        //
+        // The rename itself lives in the directory, which needs its own fsync.
+        //
+        #[cfg(unix)]
+        if let Some(dir) = self.path.parent() {
+            File::open(dir)?.sync_all()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        //
+        // Never committed (error or early return): the target is untouched, just release the lock.
+        //
+        if self.file.take().is_some() {
+            _ = fs::remove_file(&self.lock_path);
+        }
+    }
+}
+
+/// Replace `path` with `data` so readers and crashes only ever see the old or the new contents.
+#[inline]
+pub fn write_atomic(path: &Path, data: impl AsRef<[u8]>) -> Result<()> {
+    let mut lock = LockFile::acquire(path)?;
+    lock.write_all(data.as_ref())?;
+    lock.commit()
+}
+
+/// Refuse to go on if a stale `.lock` is lying around under `.mog`: a command crashed mid-write
+/// and someone should check what it left before it's cleared. Fresh locks belong to commands
+/// still running; readers don't mind them, and writers of the same file stop at `acquire`.
+pub fn ensure_no_stale_locks(mog_dir: &Path) -> Result<()> {
+    if let Some(lock_path) = find_stale_lock(mog_dir) {
+        bail!(
+            "'{}' was left behind by an interrupted mog command.\n\
+             Check that the file it guards is intact, then remove the lock and try again.",
+            lock_path.display()
+        );
+    }
+    Ok(())
+}
+
+#[inline]
+fn is_stale(lock_path: &Path) -> bool {
+    fs::symlink_metadata(lock_path)
+        .and_then(|meta| meta.modified())
+        .is_ok_and(|mtime| mtime.elapsed().is_ok_and(|age| age >= STALE_LOCK_AGE))
+}
+
+#[inline]
+fn find_stale_lock(mog_dir: &Path) -> Option<PathBuf> {
+    for name in ["index", "HEAD", "config"] {
+        let lock_path = lock_path_for(&mog_dir.join(name));
+        if is_stale(&lock_path) {
+            return Some(lock_path);
+        }
+    }
+
+    let mut stack = vec![mog_dir.join("refs")];
+    while let Some(dir) = stack.pop() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.filter_map(Result::ok) {
+            let path = entry.path();
+            if path.is_dir() {
+                stack.push(path);
+            } else if path.to_string_lossy().ends_with(LOCK_SUFFIX) && is_stale(&path) {
+                return Some(path);
+            }
+        }
+    }
+
+    None
+}
+
+#[inline]
+fn lock_path_for(path: &Path) -> PathBuf {
+    let mut lock_path = path.as_os_str().to_owned();
+    lock_path.push(LOCK_SUFFIX);
+    lock_path.into()
+}
+
+#[inline]
+fn locked_message(lock_path: &Path) -> String {
+    format!(
+        "'{}' exists: another mog command is running, or one was interrupted.\n\
+         If no other mog command is running, remove the file and try again.",
+        lock_path.display()
+    )
+}
@@ -1,32 +1,559 @@
-use crate::hash::hash_to_hex;
+//! `mog log`: select commits from revisions and ranges, filter them, and print them as
+//! blocks, one-liners or a `--format` template, optionally beside an ASCII graph.
+//!
+//! Commits come out newest first (by commit time), but never before one of their children,
+//! so the graph only ever draws edges downwards.
+
+use crate::date::{self, DateFormat};
+use crate::hash::{hash_to_hex, Hash};
+use crate::object::MODE_DIR;
 use crate::repository::Repository;
+use crate::revision::RevRange;
+use crate::util::{Xxh3HashMap, Xxh3HashSet};
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::fmt::Write;
+
+use anyhow::{Result, bail};
+use regex::Regex;
+
+const SHORT_HASH_LEN: usize = 8;
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Hash, author, date and the whole message.
+    #[default]
+    Medium,
+    /// Hash, author and subject.
+    Short,
+    /// Like `Medium`, with the committer too.
+    Full,
+    /// `<short hash> <subject>`.
+    Oneline,
+    /// A template with `%` placeholders, see `expand_template`.
+    Template(String),
+}
+
+impl LogFormat {
+    /// `oneline`, `short`, `medium`, `full`, `format:<template>`, or a bare template containing `%`.
+    pub fn parse(s: &str) -> Result<Self> {
+        Ok(match s {
+            "oneline" => Self::Oneline,
+            "short"   => Self::Short,
+            "medium"  => Self::Medium,
+            "full"    => Self::Full,
+            _ => match s.strip_prefix("format:").or_else(|| s.strip_prefix("tformat:")) {
+                Some(template)            => Self::Template(template.to_owned()),
+                None if s.contains('%')   => Self::Template(s.to_owned()),
+                None => bail!("unknown log format '{s}' (expected oneline, short, medium, full or format:<template>)"),
+            },
+        })
+    }
+}
 
-use anyhow::Result;
+#[derive(Debug, Default)]
+pub struct LogOptions {
+    /// Revisions (`main`, `HEAD~2`), ranges (`A..B`, `A...B`) and exclusions (`^A`). Empty means HEAD.
+    pub revisions: Vec<String>,
+    /// Start from every branch, tag and remote-tracking branch, and HEAD.
+    pub all:       bool,
+    pub max_count: Option<usize>,
+    /// Matched against `Name <email>` of the author.
+    pub author:    Option<Regex>,
+    /// Matched against the message.
+    pub grep:      Option<Regex>,
+    /// Commit times, seconds since the epoch.
+    pub since:     Option<i64>,
+    pub until:     Option<i64>,
+    /// Only commits that change something under these paths (relative to the repository root).
+    pub paths:     Vec<String>,
+    pub graph:     bool,
+    pub format:    LogFormat,
+    pub date:      DateFormat,
+}
 
-pub fn log(repo: &mut Repository, f: &mut dyn core::fmt::Write) -> Result<()> {
-    let Ok(mut current) = repo.read_head_commit() else {
+pub fn log(repo: &mut Repository, opts: &LogOptions, f: &mut dyn core::fmt::Write) -> Result<()> {
+    if opts.revisions.is_empty() && !opts.all && repo.read_head_commit().is_err() {
         writeln!(f, "[looks like no commits yet brudda]")?;
         return Ok(());
-    };
+    }
+
+    let selected = select_commits(repo, opts)?;
+    let ordered  = order_commits(repo, &selected)?;
+
+    let paths = opts.paths.iter()
+        .map(|p| p.trim_start_matches("./").trim_matches('/').to_owned())
+        .collect::<Vec<_>>();
+
+    let mut matching = Vec::new();
+    for hash in ordered {
+        if matches(repo, &hash, opts, &paths)? {
+            matching.push(hash);
+        }
+    }
+
+    let now = date::now()?;
+    let shown = &matching[..matching.len().min(opts.max_count.unwrap_or(usize::MAX))];
+
+    if !opts.graph {
+        for hash in shown {
+            f.write_str(&format_commit(repo, hash, &opts.format, opts.date, now)?)?;
+        }
+        return Ok(());
+    }
+
+    let matching = matching.iter().copied().collect::<Xxh3HashSet<_>>();
+    let shown_set = shown.iter().copied().collect::<Xxh3HashSet<_>>();
+    let mut graph = Graph::default();
+    for hash in shown {
+        let parents = graph_parents(repo, hash, &selected, &matching)?
+            .into_iter()
+            .filter(|p| shown_set.contains(p))
+            .collect::<Vec<_>>();
+
+        let text = format_commit(repo, hash, &opts.format, opts.date, now)?;
+        graph.write(*hash, &parents, &text, f)?;
+    }
+
+    Ok(())
+}
+
+//
+//
+// Selecting and ordering commits
+//
+//
+
+/// Everything reachable from the included tips and not from the excluded ones.
+fn select_commits(repo: &mut Repository, opts: &LogOptions) -> Result<Xxh3HashSet<Hash>> {
+    let mut include   = Vec::new();
+    let mut exclude   = Vec::new();
+    let mut symmetric = Xxh3HashSet::default();
+
+    for spec in &opts.revisions {
+        if let Some(excluded) = spec.strip_prefix('^') {
+            exclude.push(crate::revision::resolve_commit(repo, excluded)?.0);
+            continue;
+        }
+
+        match crate::revision::parse_range(repo, spec)? {
+            RevRange::Single(hash) => include.push(hash),
+            RevRange::Range { exclude: e, include: i } => {
+                include.push(i);
+                exclude.push(e);
+            }
+            range @ RevRange::Symmetric { .. } => {
+                symmetric.extend(crate::revision::range_commits(repo, &range)?);
+            }
+        }
+    }
+
+    if opts.all {
+        include.extend(all_tips(repo)?);
+    } else if opts.revisions.iter().all(|spec| spec.starts_with('^')) {
+        include.push(repo.read_head_commit()?);
+    }
+
+    let mut selected = symmetric;
+    for tip in include {
+        selected.extend(repo.reachable_commits(&tip));
+    }
+    for tip in exclude {
+        for hash in repo.reachable_commits(&tip) {
+            selected.remove(&hash);
+        }
+    }
+
+    Ok(selected)
+}
 
-    loop {
-        let object = repo.read_object(&current)?;
-        let Ok(commit_id) = object.try_as_commit_id() else {
+/// HEAD and the commits every branch, tag and remote-tracking branch point at.
+fn all_tips(repo: &mut Repository) -> Result<Vec<Hash>> {
+    let mut refs = Vec::new();
+    let mut stack = vec!["refs/heads".to_owned(), "refs/tags".to_owned(), "refs/remotes".to_owned()];
+    while let Some(dir) = stack.pop() {
+        let Ok(entries) = std::fs::read_dir(repo.root.join(".mog").join(&dir)) else {
             continue;
         };
+        for entry in entries.filter_map(Result::ok) {
+            let Ok(name) = entry.file_name().into_string() else { continue };
+            if entry.path().is_dir() {
+                stack.push(format!("{dir}/{name}"));
+            } else if !name.ends_with(crate::lockfile::LOCK_SUFFIX) {
+                refs.push(repo.read_ref(&format!("{dir}/{name}"))?);
+            }
+        }
+    }
 
-        writeln!(f, "commit {}", hash_to_hex(&current))?;
-        writeln!(f, "Author: {}", repo.commit.get_author(commit_id))?;
-        writeln!(f, "Date: {}", repo.commit.get_timestamp(commit_id))?;
-        writeln!(f, "\n    {}", repo.commit.get_message(commit_id))?;
-        writeln!(f)?;
+    let mut tips = repo.read_head_commit().ok().into_iter().collect::<Vec<_>>();
+    for hash in refs {
+        //
+        // Tags may point at trees or blobs; those have no history to show.
+        //
+        if let Ok((commit, _)) = repo.peel_to_commit(&hash) {
+            tips.push(commit);
+        }
+    }
 
-        let parents = repo.commit.get_parents(commit_id);
-        if parents.is_empty() {
-            break;
+    Ok(tips)
+}
+
+/// Newest commit time first, but children always before their parents.
+fn order_commits(repo: &mut Repository, selected: &Xxh3HashSet<Hash>) -> Result<Vec<Hash>> {
+    let mut children = Xxh3HashMap::<Hash, usize>::default();
+    let mut times    = Xxh3HashMap::<Hash, i64>::default();
+    for hash in selected {
+        let commit_id = repo.read_object(hash)?.try_as_commit_id()?;
+        times.insert(*hash, repo.commit.get_committer_signature(commit_id).timestamp);
+        for parent in repo.commit.get_parents(commit_id) {
+            if selected.contains(parent) {
+                *children.entry(*parent).or_default() += 1;
+            }
+        }
+    }
+
+    let mut ready = selected.iter()
+        .filter(|h| !children.contains_key(*h))
+        .map(|h| (times[h], *h))
+        .collect::<BinaryHeap<_>>();
+
+    let mut ordered = Vec::with_capacity(selected.len());
+    while let Some((_, hash)) = ready.pop() {
+        ordered.push(hash);
+
+        let commit_id = repo.read_object(&hash)?.try_as_commit_id()?;
+        for parent in repo.commit.get_parents(commit_id) {
+            let Some(waiting) = children.get_mut(parent) else { continue };
+            *waiting -= 1;
+            if *waiting == 0 {
+                children.remove(parent);
+                ready.push((times[parent], *parent));
+            }
+        }
+    }
+
+    Ok(ordered)
+}
+
+//
+//
+// Filters
+//
+//
+
+fn matches(repo: &mut Repository, hash: &Hash, opts: &LogOptions, paths: &[String]) -> Result<bool> {
+    let commit_id = repo.read_object(hash)?.try_as_commit_id()?;
+    let committed = repo.commit.get_committer_signature(commit_id).timestamp;
+
+    if opts.since.is_some_and(|since| committed < since) || opts.until.is_some_and(|until| committed > until) {
+        return Ok(false);
+    }
+    if let Some(author) = &opts.author {
+        if !author.is_match(&repo.commit.get_author_signature(commit_id).who()) {
+            return Ok(false);
         }
-        current = parents[0];
     }
+    if let Some(grep) = &opts.grep {
+        if !grep.is_match(repo.commit.get_message(commit_id)) {
+            return Ok(false);
+        }
+    }
+
+    if paths.is_empty() {
+        return Ok(true);
+    }
+
+    let tree    = repo.commit.get_tree(commit_id);
+    let parents = repo.commit.get_parents(commit_id).to_vec();
+    let ours    = paths_at(repo, &tree, paths);
+
+    if parents.is_empty() {
+        return Ok(ours.iter().any(Option::is_some));
+    }
+
+    //
+    // Like git: a commit that leaves the paths as some parent had them is not interesting
+    // (for a merge, that side's history already shows the change).
+    //
+    for parent in parents {
+        let parent_id = repo.read_object(&parent)?.try_as_commit_id()?;
+        let tree = repo.commit.get_tree(parent_id);
+        if paths_at(repo, &tree, paths) == ours {
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
+
+/// The blob or tree hash and mode at each path (`None` where it doesn't exist). Comparing these
+/// between a commit and its parent tells whether anything under the path changed without diffing.
+fn paths_at(repo: &mut Repository, tree: &Hash, paths: &[String]) -> Vec<Option<(Hash, u32)>> {
+    paths.iter()
+        .map(|path| if path.is_empty() || path == "." {
+            Some((*tree, MODE_DIR))
+        } else {
+            repo.walk_tree_path(tree, path).ok().map(|(_, hash, mode)| (hash, mode))
+        })
+        .collect()
+}
+
+/// Parents of `hash` as drawn in the graph: the nearest `matching` ancestors along each parent,
+/// skipping commits the filters hid, so the lines still connect.
+fn graph_parents(
+    repo: &mut Repository,
+    hash: &Hash,
+    selected: &Xxh3HashSet<Hash>,
+    matching: &Xxh3HashSet<Hash>,
+) -> Result<Vec<Hash>> {
+    let commit_id = repo.read_object(hash)?.try_as_commit_id()?;
+    let mut stack = repo.commit.get_parents(commit_id).iter().rev().copied().collect::<Vec<_>>();
+
+    let mut parents = Vec::new();
+    let mut seen = Xxh3HashSet::default();
+    while let Some(hash) = stack.pop() {
+        if !seen.insert(hash) || !selected.contains(&hash) {
+            continue;
+        }
+        if matching.contains(&hash) {
+            if !parents.contains(&hash) {
+                parents.push(hash);
+            }
+            continue;
+        }
+        let commit_id = repo.read_object(&hash)?.try_as_commit_id()?;
+        stack.extend(repo.commit.get_parents(commit_id).iter().rev());
+    }
+
+    Ok(parents)
+}
+
+//
+//
+// Formatting
+//
+//
+
+/// One commit as text, ending in a newline.
+fn format_commit(repo: &mut Repository, hash: &Hash, format: &LogFormat, date: DateFormat, now: i64) -> Result<String> {
+    let commit_id = repo.read_object(hash)?.try_as_commit_id()?;
+    let author    = repo.commit.get_author_signature(commit_id);
+    let committer = repo.commit.get_committer_signature(commit_id);
+    let parents   = repo.commit.get_parents(commit_id);
+    let message   = repo.commit.get_message(commit_id);
+    let subject   = message.lines().next().unwrap_or_default();
+
+    let mut out = String::new();
+    match format {
+        LogFormat::Oneline => {
+            writeln!(out, "{} {subject}", short_hex(hash))?;
+        }
+        LogFormat::Template(template) => {
+            let text = expand_template(template, hash, repo.commit.get_tree(commit_id), parents, &author, &committer, message, date, now);
+            writeln!(out, "{text}")?;
+        }
+        LogFormat::Short | LogFormat::Medium | LogFormat::Full => {
+            writeln!(out, "commit {}", hash_to_hex(hash))?;
+            if parents.len() > 1 {
+                let parents = parents.iter().map(short_hex).collect::<Vec<_>>();
+                writeln!(out, "Merge: {}", parents.join(" "))?;
+            }
+            writeln!(out, "Author: {}", author.who())?;
+            if *format == LogFormat::Full {
+                writeln!(out, "Commit: {}", committer.who())?;
+            }
+            if *format == LogFormat::Medium {
+                writeln!(out, "Date:   {}", date::format_date(author.timestamp, author.tz_offset, date, now))?;
+            }
+            writeln!(out)?;
+
+            let body = if *format == LogFormat::Short { subject } else { message.trim_end() };
+            for line in body.lines() {
+                writeln!(out, "    {line}")?;
+            }
+            writeln!(out)?;
+        }
+    }
+
+    Ok(out)
+}
+
+/// Expand `--format` placeholders, mostly as in git:
+///
+/// ```text
+/// %H %h        commit hash, abbreviated       %T %t   tree hash, abbreviated
+/// %P %p        parent hashes, abbreviated     %s %b %B subject, body, whole message
+/// %an %ae      author name, email             %cn %ce committer name, email
+/// %ad %ar %at %ai   author date (--date style), relative, unix, ISO; %c… for the committer
+/// %n %%        newline, percent
+/// ```
+///
+/// Anything else is copied as is.
+#[allow(clippy::too_many_arguments)]
+#[must_use]
+pub fn expand_template(
+    template: &str,
+    hash: &Hash,
+    tree: Hash,
+    parents: &[Hash],
+    author: &crate::commit::Signature<'_>,
+    committer: &crate::commit::Signature<'_>,
+    message: &str,
+    date: DateFormat,
+    now: i64,
+) -> String {
+    let subject = message.lines().next().unwrap_or_default();
+    let body = message.split_once('\n').map_or("", |(_, body)| body.trim_start_matches('\n').trim_end());
+
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(at) = rest.find('%') {
+        out.push_str(&rest[..at]);
+        rest = &rest[at + 1..];
+
+        let (expanded, used) = match rest.as_bytes() {
+            [b'H', ..] => (hash_to_hex(hash), 1),
+            [b'h', ..] => (short_hex(hash), 1),
+            [b'T', ..] => (hash_to_hex(&tree), 1),
+            [b't', ..] => (short_hex(&tree), 1),
+            [b'P', ..] => (parents.iter().map(hash_to_hex).collect::<Vec<_>>().join(" "), 1),
+            [b'p', ..] => (parents.iter().map(short_hex).collect::<Vec<_>>().join(" "), 1),
+            [b's', ..] => (subject.to_owned(), 1),
+            [b'b', ..] => (body.to_owned(), 1),
+            [b'B', ..] => (message.to_owned(), 1),
+            [b'n', ..] => ("\n".to_owned(), 1),
+            [b'%', ..] => ("%".to_owned(), 1),
+            [who @ (b'a' | b'c'), field, ..] => {
+                let sig = if *who == b'a' { author } else { committer };
+                let value = match field {
+                    b'n' => Some(sig.name.to_owned()),
+                    b'e' => Some(sig.email.to_owned()),
+                    b'd' => Some(date::format_date(sig.timestamp, sig.tz_offset, date, now)),
+                    b'r' => Some(date::format_date(sig.timestamp, sig.tz_offset, DateFormat::Relative, now)),
+                    b't' => Some(sig.timestamp.to_string()),
+                    b'i' => Some(date::format_date(sig.timestamp, sig.tz_offset, DateFormat::Iso, now)),
+                    _    => None,
+                };
+                match value {
+                    Some(value) => (value, 2),
+                    None        => ("%".to_owned(), 0),
+                }
+            }
+            _ => ("%".to_owned(), 0),
+        };
+
+        out.push_str(&expanded);
+        rest = &rest[used..];
+    }
+    out.push_str(rest);
+
+    out
+}
+
+#[inline]
+fn short_hex(hash: &Hash) -> String {
+    hash_to_hex(hash)[..SHORT_HASH_LEN].to_owned()
+}
+
+//
+//
+// Graph
+//
+//
+
+/// Lanes of the ASCII graph: `lanes[i]` is the commit the line in column `i` leads to.
+#[derive(Default)]
+struct Graph {
+    lanes: Vec<Hash>,
+}
+
+impl Graph {
+    /// Draw `commit` (with its already-rewritten `parents`) next to `text`:
+    ///
+    /// ```text
+    /// *   merge          the commit row
+    /// |\                 edges moving to their new columns, one column per row
+    /// | * feature
+    /// * | main
+    /// |/
+    /// * base
+    /// ```
+    fn write(&mut self, commit: Hash, parents: &[Hash], text: &str, f: &mut dyn core::fmt::Write) -> Result<()> {
+        let col = match self.lanes.iter().position(|h| *h == commit) {
+            Some(col) => col,
+            None => {
+                self.lanes.push(commit);
+                self.lanes.len() - 1
+            }
+        };
+
+        //
+        // The lanes after this commit: its parents take its column, other lanes waiting for it
+        // end here, and everything else keeps its order (closing the gaps).
+        //
+        let mut next = Vec::with_capacity(self.lanes.len() + parents.len());
+        for (i, lane) in self.lanes.iter().enumerate() {
+            if i == col {
+                for parent in parents {
+                    if !next.contains(parent) {
+                        next.push(*parent);
+                    }
+                }
+            } else if *lane != commit && !next.contains(lane) {
+                next.push(*lane);
+            }
+        }
+
+        let column_of = |hash: &Hash| next.iter().position(|h| h == hash).expect("every lane continues somewhere");
+        let mut edges = Vec::new();
+        for (i, lane) in self.lanes.iter().enumerate() {
+            if i == col {
+                edges.extend(parents.iter().map(|p| (col, column_of(p))));
+            } else if *lane == commit {
+                edges.extend(parents.first().map(|p| (i, column_of(p))));
+            } else {
+                edges.push((i, column_of(lane)));
+            }
+        }
+
+        let width = self.lanes.len().max(next.len()) * 2;
+        let mut lines = text.lines();
+
+        let commit_row = (0..self.lanes.len()).map(|i| if i == col { "*" } else { "|" }).collect::<Vec<_>>().join(" ");
+        write_row(f, &commit_row, width, lines.next())?;
+
+        while edges.iter().any(|(from, to)| from != to) {
+            let mut row = vec![b' '; width];
+            for (from, to) in &mut edges {
+                match (*to).cmp(from) {
+                    Ordering::Greater => {
+                        row[*from * 2 + 1] = b'\\';
+                        *from += 1;
+                    }
+                    Ordering::Less => {
+                        row[*from * 2 - 1] = b'/';
+                        *from -= 1;
+                    }
+                    Ordering::Equal => row[*from * 2] = b'|',
+                }
+            }
+            write_row(f, &String::from_utf8_lossy(&row), width, lines.next())?;
+        }
+
+        let continuation = vec!["|"; next.len()].join(" ");
+        for line in lines {
+            write_row(f, &continuation, width, Some(line))?;
+        }
+
+        self.lanes = next;
+        Ok(())
+    }
+}
 
+#[inline]
+fn write_row(f: &mut dyn core::fmt::Write, graph: &str, width: usize, text: Option<&str>) -> Result<()> {
+    let line = format!("{graph:<width$}{}", text.unwrap_or_default());
+    writeln!(f, "{}", line.trim_end())?;
     Ok(())
 }
@@ -1,4 +1,4 @@
-use mog::{diff::DiffTarget, repository::Repository};
+use mog::{config::Scope, diff::DiffTarget, repository::Repository};
 
 use std::path::PathBuf;
 
@@ -30,7 +30,33 @@ enum StashAction {
     Drop { index: Option<usize> },
 }
 
-// TODO(#5): Merge command
+#[derive(Subcommand)]
+enum ConfigAction {
+    /// Print a value (the repository's, else the user's).
+    Get { key: String },
+    /// Set a value in the repository config, or the user config with --global.
+    Set {
+        key: String,
+        value: String,
+        #[arg(long)]
+        global: bool,
+    },
+    /// Remove a value from the repository config, or the user config with --global.
+    Unset {
+        key: String,
+        #[arg(long)]
+        global: bool,
+    },
+    /// Print every setting as key=value.
+    List {
+        /// Only the user config.
+        #[arg(long, conflicts_with = "local")]
+        global: bool,
+        /// Only the repository config.
+        #[arg(long)]
+        local: bool,
+    },
+}
 
 #[derive(Subcommand)]
 enum Commands {
@@ -38,6 +64,43 @@ enum Commands {
     Init {
         path: Option<PathBuf>,
     },
+    /// Copy a repository, remembering it as remote "origin".
+    Clone {
+        /// Path to a repository, or ssh://[user@]host[:port]/path.
+        source: String,
+        directory: Option<PathBuf>,
+    },
+    /// Download branches, tags and their objects from a remote into refs/remotes/<remote>/.
+    Fetch {
+        /// Remote name, path to a repository, or ssh:// URL (default: the upstream's remote, else origin).
+        remote: Option<String>,
+    },
+    /// Send a branch and the objects it needs to a remote.
+    Push {
+        /// Remote name, path to a repository, or ssh:// URL (default: the upstream's remote, else origin).
+        remote: Option<String>,
+        /// Branch to push (default: the current one).
+        branch: Option<String>,
+        /// Overwrite the remote branch even if it isn't a fast-forward.
+        #[arg(short = 'f', long)]
+        force: bool,
+        /// Make the branch track the one it was pushed to.
+        #[arg(short = 'u', long)]
+        set_upstream: bool,
+    },
+    /// Get and set repository or user options.
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+    /// Answer a fetch or push from another mog (run by it, e.g. over ssh).
+    Serve {
+        /// Speak the protocol on stdin/stdout.
+        #[arg(long)]
+        stdio: bool,
+        /// Repository to serve (default: the current directory).
+        path: Option<PathBuf>,
+    },
     /// Add paths to the index
     Stage {
         files: Vec<PathBuf>,
@@ -50,13 +113,17 @@ enum Commands {
     Checkpoint,
     /// Make a commit.
     Commit {
-        #[arg(short = 'm')]
-        message: String,
+        /// Commit message (required unless amending).
+        #[arg(short = 'm', required_unless_present = "amend")]
+        message: Option<String>,
 
-        // TODO(#4): Commit `--ammend` flag
+        /// Replace the HEAD commit instead of creating a new one on top of it.
+        #[arg(long)]
+        amend: bool,
 
-        #[arg(long, default_value = "Your Name")]
-        author: String,
+        /// `Name <email>`; defaults to MOG_AUTHOR_NAME/MOG_AUTHOR_EMAIL, then user.name/user.email.
+        #[arg(long)]
+        author: Option<String>,
     },
     /// Discard working directory changes, restoring to index state.
     Discard {
@@ -77,8 +144,96 @@ enum Commands {
         /// Compare working directory vs branch/commit.
         target: Option<String>
     },
-    /// Log all commits.
-    Log,
+    /// Show commit history.
+    Log {
+        /// Revisions or ranges to start from (`HEAD~3`, `main..feature`, `a...b`, `^excluded`); defaults to HEAD.
+        revisions: Vec<String>,
+
+        /// Only commits that change these paths (give them after `--`).
+        #[arg(last = true)]
+        paths: Vec<String>,
+
+        /// Start from every branch, tag and remote-tracking branch.
+        #[arg(long)]
+        all: bool,
+
+        /// Show at most this many commits.
+        #[arg(short = 'n', long = "max-count")]
+        max_count: Option<usize>,
+
+        /// One line per commit (short hash and subject).
+        #[arg(long, conflicts_with = "format")]
+        oneline: bool,
+
+        /// Draw the commit graph next to the log.
+        #[arg(long)]
+        graph: bool,
+
+        /// Only commits whose author (`Name <email>`) matches this regex.
+        #[arg(long)]
+        author: Option<String>,
+
+        /// Only commits whose message matches this regex.
+        #[arg(long)]
+        grep: Option<String>,
+
+        /// Only commits made at or after this date (`2026-10-01`, `'2 weeks ago'`, a unix timestamp).
+        #[arg(long, visible_alias = "after")]
+        since: Option<String>,
+
+        /// Only commits made at or before this date.
+        #[arg(long, visible_alias = "before")]
+        until: Option<String>,
+
+        /// oneline, short, medium, full, or `format:<template>` with placeholders like %h %s %an %ad.
+        #[arg(long, visible_alias = "pretty")]
+        format: Option<String>,
+
+        /// How to show dates: default (RFC 2822), iso, relative, unix, or local (RFC 2822 in this machine's zone).
+        #[arg(long, default_value = "default")]
+        date: String,
+    },
+    /// Show the history of a ref, newest first.
+    Reflog {
+        /// HEAD, a branch name or a full ref path.
+        #[arg(default_value = "HEAD")]
+        refname: String,
+    },
+    /// Merge a branch or commit into HEAD.
+    Merge {
+        /// Branch or commit to merge.
+        #[arg(required_unless_present = "abort")]
+        target: Option<String>,
+
+        #[arg(short = 'm')]
+        message: Option<String>,
+
+        /// `Name <email>`; defaults to MOG_AUTHOR_NAME/MOG_AUTHOR_EMAIL, then user.name/user.email.
+        #[arg(long)]
+        author: Option<String>,
+
+        /// Abort a conflicted merge and restore HEAD.
+        #[arg(long, conflicts_with_all = ["target", "message"])]
+        abort: bool,
+    },
+    /// Replay commits of the current branch on top of another branch or commit.
+    Rebase {
+        /// Branch or commit to replay onto.
+        #[arg(required_unless_present_any = ["continue_", "skip", "abort"])]
+        upstream: Option<String>,
+
+        /// Commit the resolved conflict and keep going.
+        #[arg(long = "continue", conflicts_with_all = ["upstream", "skip", "abort"])]
+        continue_: bool,
+
+        /// Drop the commit that stopped on a conflict and keep going.
+        #[arg(long, conflicts_with_all = ["upstream", "abort"])]
+        skip: bool,
+
+        /// Stop and restore the branch to where it was.
+        #[arg(long, conflicts_with = "upstream")]
+        abort: bool,
+    },
     /// Switch to (and possibly creating) a branch and update the working directory.
     Checkout {
         branch: String,
@@ -110,6 +265,53 @@ enum Commands {
         /// Rename: mog branch -m old new
         #[arg(short = 'm', long = "rename", num_args = 2, conflicts_with_all = ["delete", "force_delete"])]
         rename_to: Vec<String>,
+
+        /// Track a fetched remote branch: mog branch --set-upstream-to origin/main [name]
+        #[arg(short = 'u', long = "set-upstream-to", conflicts_with_all = ["delete", "force_delete", "rename_to", "at"])]
+        set_upstream_to: Option<String>,
+    },
+    /// List all tags, or Create or Delete a tag.
+    Tag {
+        /// Name of tag to create (omit to list tags)
+        name: Option<String>,
+
+        /// Tag this commit, branch or tag instead of HEAD
+        #[arg(requires = "name")]
+        target: Option<String>,
+
+        /// Create an annotated tag object (needs -m)
+        #[arg(short = 'a', long, requires = "name")]
+        annotate: bool,
+
+        /// Create an annotated tag object with this message
+        #[arg(short = 'm', requires = "name")]
+        message: Option<String>,
+
+        /// `Name <email>`; defaults to the author identity.
+        #[arg(long)]
+        tagger: Option<String>,
+
+        /// Delete tag
+        #[arg(short = 'd', long, conflicts_with_all = ["name", "list"])]
+        delete: Option<String>,
+
+        /// List tags
+        #[arg(short = 'l', long, conflicts_with = "name")]
+        list: bool,
+    },
+    /// Remove unreachable objects and compact the object database.
+    Gc {
+        /// Only report how much would be reclaimed.
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Store file history as delta chains and rewrite the object database.
+    Repack,
+    /// Verify the object database, the object graph and the index.
+    Fsck {
+        /// One tab-separated `kind hash detail` line per finding, for scripts.
+        #[arg(long)]
+        porcelain: bool,
     },
     /// Show working tree status (staged, modified, deleted, untracked)
     Status,
@@ -119,7 +321,7 @@ enum Commands {
         write: bool,
         file: PathBuf,
     },
-    /// Cat Blob/Tree/Commit by a hash.
+    /// Cat Blob/Tree/Commit/Tag by a revision (hash, short hash, ref, `HEAD~2`...).
     CatFile {
         hash: String,
     },
@@ -139,6 +341,56 @@ fn main() -> Result<()> {
             println!("Initialized empty mog repository in {}/.mog", path.display());
         }
 
+        Commands::Clone { source, directory } => {
+            mog::remote::clone(&source, directory.as_deref())?;
+        }
+
+        Commands::Fetch { remote } => {
+            let mut repo = Repository::open(".")?;
+            let remote = match remote {
+                Some(remote) => remote,
+                None         => mog::remote::default_remote(&repo)?,
+            };
+            mog::remote::fetch(&mut repo, &remote)?;
+        }
+
+        Commands::Push { remote, branch, force, set_upstream } => {
+            let mut repo = Repository::open(".")?;
+            let remote = match remote {
+                Some(remote) => remote,
+                None         => mog::remote::default_remote(&repo)?,
+            };
+            let branch = match branch {
+                Some(branch) => branch,
+                None         => repo.current_branch()?.ok_or_else(|| anyhow::anyhow!("HEAD is detached, name the branch to push"))?,
+            };
+            mog::remote::push(&mut repo, &remote, &branch, force, set_upstream)?;
+        }
+
+        Commands::Config { action } => {
+            let repo = Repository::open(".").ok();
+            let root = repo.as_ref().map(|repo| &*repo.root);
+            let scope = |global| if global { Scope::User } else { Scope::Repo };
+            match action {
+                ConfigAction::Get   { key }             => mog::config::get(root, &key)?,
+                ConfigAction::Set   { key, value, global } => mog::config::set(root, scope(global), &key, &value)?,
+                ConfigAction::Unset { key, global }     => mog::config::unset(root, scope(global), &key)?,
+                ConfigAction::List  { global, local }   => {
+                    let only = if global { Some(Scope::User) } else if local { Some(Scope::Repo) } else { None };
+                    mog::config::list(root, only)?;
+                }
+            }
+        }
+
+        Commands::Serve { stdio, path } => {
+            if !stdio {
+                anyhow::bail!("only --stdio is supported");
+            }
+            let mut repo = Repository::open(path.unwrap_or_else(|| PathBuf::from(".")))?;
+            let conn = mog::protocol::Connection::new(std::io::stdin(), std::io::stdout());
+            mog::protocol::serve(&mut repo, conn)?;
+        }
+
         Commands::HashObject { write, file } => {
             let mut repo = Repository::open(".")?;
             mog::hash_object::hash_object(&mut repo, &file, write)?;
@@ -157,13 +409,63 @@ fn main() -> Result<()> {
             println!("{}", mog::hash::hash_to_hex(&hash));
         }
 
-        Commands::Log => {
+        Commands::Log {
+            revisions, paths, all, max_count, oneline, graph, author, grep, since, until, format, date,
+        } => {
             let mut repo = Repository::open(".")?;
+            let now = mog::date::now()?;
+            let format = match (oneline, format) {
+                (true, _)          => mog::log::LogFormat::Oneline,
+                (false, Some(fmt)) => mog::log::LogFormat::parse(&fmt)?,
+                (false, None)      => mog::log::LogFormat::default(),
+            };
+            let opts = mog::log::LogOptions {
+                revisions,
+                all,
+                max_count,
+                author: author.as_deref().map(regex::Regex::new).transpose()?,
+                grep:   grep.as_deref().map(regex::Regex::new).transpose()?,
+                since:  since.as_deref().map(|s| mog::date::parse_date(s, now)).transpose()?,
+                until:  until.as_deref().map(|s| mog::date::parse_date(s, now)).transpose()?,
+                paths,
+                graph,
+                format,
+                date: mog::date::DateFormat::parse(&date)?,
+            };
+            let mut buf = String::new();
+            mog::log::log(&mut repo, &opts, &mut buf)?;
+            print!("{buf}");
+        }
+
+        Commands::Reflog { refname } => {
+            let repo = Repository::open(".")?;
             let mut buf = String::new();
-            mog::log::log(&mut repo, &mut buf)?;
+            mog::reflog::show(&repo, &refname, &mut buf)?;
             print!("{buf}");
         }
 
+        Commands::Merge { target, message, author, abort } => {
+            let mut repo = Repository::open(".")?;
+            if abort {
+                mog::merge::merge_abort(&mut repo)?;
+            } else if let Some(target) = target {
+                mog::merge::merge(&mut repo, &target, author.as_deref(), message.as_deref())?;
+            }
+        }
+
+        Commands::Rebase { upstream, continue_, skip, abort } => {
+            let mut repo = Repository::open(".")?;
+            if continue_ {
+                mog::rebase::rebase_continue(&mut repo)?;
+            } else if skip {
+                mog::rebase::rebase_skip(&mut repo)?;
+            } else if abort {
+                mog::rebase::rebase_abort(&mut repo)?;
+            } else if let Some(upstream) = upstream {
+                mog::rebase::rebase(&mut repo, &upstream)?;
+            }
+        }
+
         Commands::Checkout { branch, path, new_branch } => {
             let mut repo = Repository::open(".")?;
             if new_branch {
@@ -218,10 +520,45 @@ fn main() -> Result<()> {
             }
         }
 
-        Commands::Branch { name, at, delete, force_delete, rename_to } => {
+        Commands::Gc { dry_run } => {
+            let mut repo = Repository::open(".")?;
+            mog::gc::gc(&mut repo, dry_run)?;
+        }
+
+        Commands::Repack => {
             let mut repo = Repository::open(".")?;
+            mog::repack::repack(&mut repo)?;
+        }
+
+        Commands::Fsck { porcelain } => {
+            let mut repo = Repository::open(".")?;
+            mog::fsck::fsck(&mut repo, porcelain)?;
+        }
 
-            if let Some(branch) = delete {
+        Commands::Tag { name, target, annotate, message, tagger, delete, list } => {
+            let mut repo = Repository::open(".")?;
+
+            if annotate && message.is_none() {
+                anyhow::bail!("an annotated tag needs a message (-m)");
+            }
+
+            if let Some(tag) = delete {
+                mog::tag::delete(&repo, &tag)?;
+            } else if list {
+                mog::tag::list(&mut repo)?;
+            } else if let Some(name) = name {
+                mog::tag::create(&mut repo, &name, target.as_deref(), tagger.as_deref(), message.as_deref())?;
+            } else {
+                mog::tag::list(&mut repo)?;
+            }
+        }
+
+        Commands::Branch { name, at, delete, force_delete, rename_to, set_upstream_to } => {
+            let mut repo = Repository::open(".")?;
+
+            if let Some(upstream) = set_upstream_to {
+                mog::branch::set_upstream_to(&repo, name.as_deref(), &upstream)?;
+            } else if let Some(branch) = delete {
                 mog::branch::delete(&mut repo, &branch)?;
             } else if let Some(branch) = force_delete {
                 mog::branch::force_delete(&mut repo, &branch)?;
@@ -230,7 +567,7 @@ fn main() -> Result<()> {
             } else if let Some(name) = name {
                 mog::branch::create(&mut repo, &name, at.as_deref())?;
             } else {
-                mog::branch::list(&repo)?;
+                mog::branch::list(&mut repo)?;
             }
         }
 
@@ -249,16 +586,34 @@ fn main() -> Result<()> {
             mog::status::status(&mut repo)?;
         }
 
-        Commands::Commit { message, author } => {
+        Commands::Commit { message, amend, author } => {
             let mut repo = Repository::open(".")?;
-            let index = mog::index::Index::load(&repo.root)?;
+            if mog::rebase::rebase_in_progress(&repo) {
+                anyhow::bail!("a rebase is in progress (stage your resolution and use 'mog rebase --continue')");
+            }
+            let mut index = mog::index::Index::load(&repo.root)?;
             if index.count == 0 {
                 eprintln!("nothing staged to commit (use 'mog add <file>'...)");
                 return Ok(());
             }
+            if amend {
+                if mog::merge::merge_in_progress(&repo) {
+                    anyhow::bail!("cannot amend while a merge is in progress (commit or 'mog merge --abort' first)");
+                }
+                let tree = index.write_tree(&mut repo)?;
+                mog::commit::amend(&mut repo, tree, author.as_deref(), message.as_deref())?;
+                index.save(&repo.root)?;
+                return Ok(());
+            }
+            let message = message.unwrap_or_default();
+            let merge_head = mog::merge::resolved_merge_head(&repo)?;
             let tree = index.write_tree(&mut repo)?;
             let parent = repo.read_head_commit().ok();
-            mog::commit::commit(&mut repo, tree, parent, &author, &message)?;
+            mog::commit::commit(&mut repo, tree, parent.into_iter().chain(merge_head), author.as_deref(), &message)?;
+            index.save(&repo.root)?;
+            if merge_head.is_some() {
+                mog::merge::clear_merge_state(&repo)?;
+            }
         }
     }
 
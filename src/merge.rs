@@ -0,0 +1,546 @@
+use crate::hash::{hash_to_hex, hex_to_hash, Hash};
+use crate::index::Index;
+use crate::chunk::hash_contents;
+use crate::repository::Repository;
+use crate::status::{flatten_tree, SortedFlatTree};
+use crate::object::{MODE_EXEC, MODE_LINK};
+use crate::util::{read_worktree_file, Xxh3HashSet};
+
+use std::fs;
+
+use anyhow::{Result, bail};
+use imara_diff::{Algorithm, Diff, Hunk, InternedInput};
+
+const MERGE_HEAD: &str = ".mog/MERGE_HEAD";
+const MERGE_CONFLICTS: &str = ".mog/MERGE_CONFLICTS";
+
+const MARKER_OURS:   &str = "<<<<<<<";
+const MARKER_SEP:    &str = "=======";
+const MARKER_THEIRS: &str = ">>>>>>>";
+
+pub fn merge(repo: &mut Repository, target: &str, author: Option<&str>, message: Option<&str>) -> Result<()> {
+    if merge_in_progress(repo) {
+        bail!("a merge is already in progress (resolve it and commit, or use 'mog merge --abort')");
+    }
+    if crate::rebase::rebase_in_progress(repo) {
+        bail!("cannot merge while a rebase is in progress");
+    }
+
+    let ours = repo.read_head_commit().map_err(|_| anyhow::anyhow!("cannot merge: no commits yet"))?;
+    let (theirs, _) = repo.resolve_to_commit(target)?;
+
+    ensure_clean_worktree(repo, "merge")?;
+
+    let base = merge_base(repo, &ours, &theirs)?;
+
+    if base == Some(theirs) {
+        println!("Already up to date.");
+        return Ok(());
+    }
+
+    if base == Some(ours) {
+        //
+        // Fast-forward: nothing to merge, just move HEAD forward.
+        //
+        let commit_id = repo.read_object(&theirs)?.try_as_commit_id()?;
+        crate::checkout::checkout_commit(repo, commit_id)?;
+        repo.update_head(&theirs, &format!("merge {target}: Fast-forward"))?;
+        println!("Fast-forward to {}", &hash_to_hex(&theirs)[..8]);
+        return Ok(());
+    }
+
+    let base_flat = match base {
+        Some(base) => flatten_commit(repo, &base)?,
+        None       => SortedFlatTree::default(),
+    };
+    let ours_flat   = flatten_commit(repo, &ours)?;
+    let theirs_flat = flatten_commit(repo, &theirs)?;
+
+    //
+    //
+    // Tree-level three-way merge over the union of all paths.
+    //
+    //
+
+    let mut index = Index::load(&repo.root)?;
+    let TreeMerge { conflicts, updated } = merge_trees(
+        repo, &mut index, &base_flat, &ours_flat, &theirs_flat, "HEAD", target
+    )?;
+
+    index.save(&repo.root)?;
+
+    if !conflicts.is_empty() {
+        repo.storage.flush()?;
+        fs::write(repo.root.join(MERGE_HEAD), format!("{}\n", hash_to_hex(&theirs)))?;
+
+        let mut list = conflicts.join("\n");
+        list.push('\n');
+        fs::write(repo.root.join(MERGE_CONFLICTS), list)?;
+
+        println!("Automatic merge failed; fix conflicts, stage them, then commit the result.");
+        return Ok(());
+    }
+
+    let default_message = format!("Merge '{target}'");
+    let message = message.unwrap_or(&default_message);
+
+    let tree = index.write_tree(repo)?;
+    crate::commit::commit(repo, tree, [ours, theirs], author, message)?;
+    index.save(&repo.root)?;
+    println!("Merged '{target}', updated {updated} path(s)");
+
+    Ok(())
+}
+
+pub struct TreeMerge {
+    /// Paths left with conflict markers (or modify/delete conflicts) in the working directory.
+    pub conflicts: Vec<String>,
+    /// Paths cleanly updated in the working directory and `index`.
+    pub updated: usize,
+}
+
+/// Three-way merge of flattened trees into the working directory and `index`.
+/// The working directory and `index` must currently match `ours`.
+/// Conflicted paths are left unstaged, clean results are staged.
+pub fn merge_trees(
+    repo:         &mut Repository,
+    index:        &mut Index,
+    base:         &SortedFlatTree,
+    ours:         &SortedFlatTree,
+    theirs:       &SortedFlatTree,
+    ours_label:   &str,
+    theirs_label: &str,
+) -> Result<TreeMerge> {
+    let mut paths = Vec::with_capacity(ours.len() + theirs.len());
+    for flat in [base, ours, theirs] {
+        paths.extend((0..flat.len()).map(|i| flat.get_path(i)));
+    }
+    paths.sort_unstable();
+    paths.dedup();
+
+    //
+    // Theirs brings a file where ours has none: refuse before touching the worktree if
+    // something untracked already sits there, so a refused merge leaves nothing half-applied.
+    //
+    for &path in &paths {
+        let t = theirs.lookup_entry(path);
+        if t.is_none() || base.lookup_entry(path) == t || ours.lookup_entry(path).is_some() {
+            continue;
+        }
+        if fs::symlink_metadata(repo.root.join(path)).is_ok() {
+            bail!("untracked file '{path}' would be overwritten by merge");
+        }
+    }
+
+    let mut conflicts = Vec::new();
+    let mut removed   = Vec::new();
+    let mut updated   = 0usize;
+
+    for path in paths {
+        let b = base.lookup_entry(path);
+        let o = ours.lookup_entry(path);
+        let t = theirs.lookup_entry(path);
+
+        if o == t || b == t {
+            continue; // Ours already has the right content.
+        }
+
+        let abs = repo.root.join(path);
+
+        if b == o {
+            //
+            // Only theirs changed: take it.
+            //
+            match t {
+                Some((hash, mode)) => {
+                    write_blob_to(repo, &hash, mode, path)?;
+                    index.add(path, hash, &fs::symlink_metadata(&abs)?);
+                }
+                None => {
+                    _ = fs::remove_file(&abs);
+                    removed.push(path);
+                }
+            }
+            updated += 1;
+            continue;
+        }
+
+        //
+        // Both sides changed the path differently.
+        //
+        match (o, t) {
+            //
+            // A link target has no lines to merge.
+            //
+            (Some((_, o_mode)), Some((_, t_mode))) if o_mode == MODE_LINK || t_mode == MODE_LINK => {
+                println!("CONFLICT (symlink): {path} differs on both sides, kept ours");
+                conflicts.push(path.to_owned());
+            }
+            (Some((o, o_mode)), Some((t, t_mode))) => {
+                //
+                // A mode change only theirs made survives ours changing the content.
+                //
+                let mode = match b {
+                    Some((_, b_mode)) if b_mode == o_mode => t_mode,
+                    _                                     => o_mode,
+                };
+
+                let base_text = match b {
+                    Some((b, _)) => blob_text(repo, &b)?,
+                    None         => Some(String::new()),
+                };
+                let ours_text   = blob_text(repo, &o)?;
+                let theirs_text = blob_text(repo, &t)?;
+
+                let (Some(base_text), Some(ours_text), Some(theirs_text)) = (base_text, ours_text, theirs_text) else {
+                    println!("CONFLICT (binary): {path} differs on both sides, kept ours");
+                    conflicts.push(path.to_owned());
+                    continue;
+                };
+
+                let merged = merge_lines(&base_text, &ours_text, &theirs_text, ours_label, theirs_label);
+                fs::write(&abs, merged.text.as_bytes())?;
+                crate::util::set_executable(&abs, mode == MODE_EXEC)?;
+
+                if merged.conflicts == 0 {
+                    let hash = crate::chunk::write_contents(repo, merged.text.as_bytes())?;
+                    index.add(path, hash, &fs::symlink_metadata(&abs)?);
+                    updated += 1;
+                } else {
+                    //
+                    // Smudge the entry so the next `stage` re-hashes the file even if
+                    // the resolution lands within the same second and with the same size.
+                    //
+                    if let Some(i) = index.find(path) {
+                        index.mtimes[i] = 0;
+                    }
+                    println!("CONFLICT (content): merge conflict in {path}");
+                    conflicts.push(path.to_owned());
+                }
+            }
+            (None, Some((t, t_mode))) => {
+                write_blob_to(repo, &t, t_mode, path)?;
+                println!("CONFLICT (modify/delete): {path} deleted in {ours_label} and modified in {theirs_label}");
+                conflicts.push(path.to_owned());
+            }
+            (Some(_), None) => {
+                println!("CONFLICT (modify/delete): {path} deleted in {theirs_label} and modified in {ours_label}");
+                conflicts.push(path.to_owned());
+            }
+            (None, None) => unreachable!("o == t handled above"),
+        }
+    }
+
+    index.remove_many(removed);
+
+    Ok(TreeMerge { conflicts, updated })
+}
+
+#[inline]
+#[must_use]
+pub fn merge_in_progress(repo: &Repository) -> bool {
+    repo.root.join(MERGE_HEAD).exists()
+}
+
+/// Throw away an in-progress conflicted merge and restore the working tree to HEAD.
+pub fn merge_abort(repo: &mut Repository) -> Result<()> {
+    if !merge_in_progress(repo) {
+        bail!("no merge in progress");
+    }
+
+    for path in read_conflicts(repo) {
+        _ = fs::remove_file(repo.root.join(&path));
+    }
+
+    let head = repo.read_head_commit()?;
+    let commit_id = repo.read_object(&head)?.try_as_commit_id()?;
+    crate::checkout::checkout_commit(repo, commit_id)?;
+
+    clear_merge_state(repo)?;
+    println!("Merge aborted");
+    Ok(())
+}
+
+/// If a merge is in progress, check that every conflicted path was resolved and staged,
+/// and return the commit being merged in (the second parent of the merge commit).
+pub fn resolved_merge_head(repo: &Repository) -> Result<Option<Hash>> {
+    let merge_head_path = repo.root.join(MERGE_HEAD);
+    if !merge_head_path.exists() {
+        return Ok(None);
+    }
+
+    ensure_conflicts_resolved(repo, &read_conflicts(repo))?;
+
+    let content = fs::read_to_string(merge_head_path)?;
+    Ok(Some(hex_to_hash(content.trim())?))
+}
+
+/// Every conflicted path must be free of conflict markers and staged as it is on disk
+/// (or removed from both disk and index).
+pub fn ensure_conflicts_resolved(repo: &Repository, conflicts: &[String]) -> Result<()> {
+    let index = Index::load(&repo.root)?;
+    for path in conflicts {
+        let staged = index.find(path).map(|i| index.hashes[i]);
+
+        match read_worktree_file(&repo.root.join(path)) {
+            Ok(data) => {
+                if has_conflict_markers(&data) {
+                    bail!("unresolved conflict in '{path}' (remove the conflict markers and stage it)");
+                }
+                if staged != Some(hash_contents(&data)) {
+                    bail!("conflict in '{path}' is resolved but not staged (use 'mog stage {path}')");
+                }
+            }
+            Err(_) => if staged.is_some() {
+                bail!("conflict in '{path}' is resolved but not staged (use 'mog stage {path}')");
+            }
+        }
+    }
+    Ok(())
+}
+
+#[inline]
+pub fn clear_merge_state(repo: &Repository) -> Result<()> {
+    _ = fs::remove_file(repo.root.join(MERGE_CONFLICTS));
+    fs::remove_file(repo.root.join(MERGE_HEAD))?;
+    Ok(())
+}
+
+/// Best common ancestor of `ours` and `theirs`, or None if the histories are unrelated.
+pub fn merge_base(repo: &mut Repository, ours: &Hash, theirs: &Hash) -> Result<Option<Hash>> {
+    let ours_reachable = repo.reachable_commits(ours);
+
+    //
+    // Walk back from theirs, stopping at the first common commit on every path.
+    //
+    let mut candidates = Vec::new();
+    let mut visited    = Xxh3HashSet::default();
+    let mut stack      = vec![*theirs];
+
+    while let Some(hash) = stack.pop() {
+        if !visited.insert(hash) {
+            continue;
+        }
+
+        if ours_reachable.contains(&hash) {
+            candidates.push(hash);
+            continue;
+        }
+
+        let commit_id = repo.read_object(&hash)?.try_as_commit_id()?;
+        stack.extend_from_slice(repo.commit.get_parents(commit_id));
+    }
+
+    //
+    // Drop candidates that are ancestors of other candidates, prefer the newest of the rest.
+    //
+    let mut best: Option<(i64, Hash)> = None;
+    for &candidate in &candidates {
+        let mut dominated = false;
+        for &other in &candidates {
+            if other != candidate && repo.reachable_commits(&other).contains(&candidate) {
+                dominated = true;
+                break;
+            }
+        }
+        if dominated {
+            continue;
+        }
+
+        let commit_id = repo.read_object(&candidate)?.try_as_commit_id()?;
+        let timestamp = repo.commit.get_timestamp(commit_id);
+        if best.is_none_or(|(best_ts, _)| timestamp > best_ts) {
+            best = Some((timestamp, candidate));
+        }
+    }
+
+    Ok(best.map(|(_, hash)| hash))
+}
+
+pub struct LineMerge {
+    pub text: String,
+    /// Number of conflict regions written into `text`.
+    pub conflicts: usize,
+}
+
+/// Line-level three-way merge. Overlapping changes become `<<<<<<<`/`=======`/`>>>>>>>` regions.
+#[must_use]
+pub fn merge_lines(base: &str, ours: &str, theirs: &str, ours_label: &str, theirs_label: &str) -> LineMerge {
+    let base_lines   = line_offsets(base);
+    let ours_lines   = line_offsets(ours);
+    let theirs_lines = line_offsets(theirs);
+
+    let ours_hunks   = line_hunks(base, ours);
+    let theirs_hunks = line_hunks(base, theirs);
+
+    let mut text      = String::with_capacity(base.len().max(ours.len()).max(theirs.len()));
+    let mut conflicts = 0;
+    let mut pos       = 0u32; // Base line position copied so far.
+    let (mut i, mut j) = (0, 0);
+
+    loop {
+        let start = match (ours_hunks.get(i), theirs_hunks.get(j)) {
+            (None,    None)    => break,
+            (Some(o), None)    => o.before.start,
+            (None,    Some(t)) => t.before.start,
+            (Some(o), Some(t)) => o.before.start.min(t.before.start),
+        };
+
+        //
+        // Grow the group while hunks from either side touch it.
+        //
+        let (i0, j0) = (i, j);
+        let mut end = start;
+        loop {
+            if let Some(o) = ours_hunks.get(i).filter(|o| o.before.start <= end) {
+                end = end.max(o.before.end);
+                i += 1;
+                continue;
+            }
+            if let Some(t) = theirs_hunks.get(j).filter(|t| t.before.start <= end) {
+                end = end.max(t.before.end);
+                j += 1;
+                continue;
+            }
+            break;
+        }
+
+        text.push_str(slice_lines(base, &base_lines, pos, start));
+
+        let ours_part   = side_range(&ours_hunks[i0..i], start, end).map(|(s, e)| slice_lines(ours, &ours_lines, s, e));
+        let theirs_part = side_range(&theirs_hunks[j0..j], start, end).map(|(s, e)| slice_lines(theirs, &theirs_lines, s, e));
+
+        match (ours_part, theirs_part) {
+            (Some(o), None) => text.push_str(o),
+            (None, Some(t)) => text.push_str(t),
+            (Some(o), Some(t)) if o == t => text.push_str(o),
+            (Some(o), Some(t)) => {
+                conflicts += 1;
+                push_marker(&mut text, MARKER_OURS, Some(ours_label));
+                push_side(&mut text, o);
+                push_marker(&mut text, MARKER_SEP, None);
+                push_side(&mut text, t);
+                push_marker(&mut text, MARKER_THEIRS, Some(theirs_label));
+            }
+            (None, None) => unreachable!("group always contains at least one hunk"),
+        }
+
+        pos = end;
+    }
+
+    text.push_str(slice_lines(base, &base_lines, pos, base_lines.len() as u32 - 1));
+
+    LineMerge { text, conflicts }
+}
+
+#[inline]
+#[must_use]
+pub fn has_conflict_markers(data: &[u8]) -> bool {
+    data.split(|&b| b == b'\n').any(|line| {
+        line.starts_with(MARKER_OURS.as_bytes()) || line.starts_with(MARKER_THEIRS.as_bytes())
+    })
+}
+
+//
+//
+// Helpers
+//
+//
+
+pub fn ensure_clean_worktree(repo: &mut Repository, action: &str) -> Result<()> {
+    let buckets = crate::status::collect_status(repo)?;
+    if !buckets.staged_new_modified.is_empty()
+        || !buckets.staged_deleted.is_empty()
+        || !buckets.modified.is_empty()
+        || !buckets.deleted.is_empty()
+    {
+        bail!("cannot {action} with uncommitted changes (commit or stash them first)");
+    }
+    Ok(())
+}
+
+#[inline]
+pub fn flatten_commit(repo: &mut Repository, commit: &Hash) -> Result<SortedFlatTree> {
+    let commit_id = repo.read_object(commit)?.try_as_commit_id()?;
+    let tree_hash = repo.commit.get_tree(commit_id);
+    flatten_tree(repo, tree_hash)
+}
+
+#[inline]
+fn read_conflicts(repo: &Repository) -> Vec<String> {
+    let Ok(content) = fs::read_to_string(repo.root.join(MERGE_CONFLICTS)) else {
+        return Vec::new();
+    };
+    content.lines().filter(|l| !l.is_empty()).map(ToOwned::to_owned).collect()
+}
+
+/// Blob contents as text, or None if it isn't valid UTF-8.
+#[inline]
+fn blob_text(repo: &Repository, hash: &Hash) -> Result<Option<String>> {
+    let bytes = repo.read_blob_bytes_without_touching_cache(hash)?;
+    Ok(String::from_utf8(bytes.to_vec()).ok())
+}
+
+#[inline]
+pub fn write_blob_to(repo: &Repository, hash: &Hash, mode: u32, path: &str) -> Result<()> {
+    let abs = repo.root.join(path);
+    if let Some(parent) = abs.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    repo.write_entry_to_file(hash, mode, &abs)
+}
+
+#[inline]
+fn line_hunks(base: &str, side: &str) -> Vec<Hunk> {
+    let input = InternedInput::new(base, side);
+    let mut diff = Diff::compute(Algorithm::Histogram, &input);
+    diff.postprocess_lines(&input);
+    diff.hunks().collect()
+}
+
+/// Byte offset where each line starts, plus a trailing `text.len()` sentinel.
+/// Lines are split the same way imara-diff tokenizes them (newline included).
+#[inline]
+fn line_offsets(text: &str) -> Vec<usize> {
+    let mut offsets = vec![0];
+    offsets.extend(text.match_indices('\n').map(|(i, _)| i + 1));
+    if offsets.last() != Some(&text.len()) {
+        offsets.push(text.len());
+    }
+    offsets
+}
+
+#[inline]
+fn slice_lines<'a>(text: &'a str, offsets: &[usize], start: u32, end: u32) -> &'a str {
+    &text[offsets[start as usize]..offsets[end as usize]]
+}
+
+/// Map the base range `[start, end)` of a group onto one side's lines.
+/// None if this side has no hunks in the group (i.e. it kept the base lines).
+#[inline]
+fn side_range(hunks: &[Hunk], start: u32, end: u32) -> Option<(u32, u32)> {
+    let first = hunks.first()?;
+    let last  = hunks.last()?;
+    Some((
+        first.after.start - (first.before.start - start),
+        last.after.end + (end - last.before.end),
+    ))
+}
+
+#[inline]
+fn push_side(text: &mut String, side: &str) {
+    text.push_str(side);
+    if !side.is_empty() && !side.ends_with('\n') {
+        text.push('\n');
+    }
+}
+
+#[inline]
+fn push_marker(text: &mut String, marker: &str, label: Option<&str>) {
+    text.push_str(marker);
+    if let Some(label) = label {
+        text.push(' ');
+        text.push_str(label);
+    }
+    text.push('\n');
+}
@@ -1,11 +1,10 @@
-use crate::{hash::Hash, store::{BlobId, BlobStore, CommitId, Stores, TreeId}, tree::{TreeEntry, TreePayloadOwned}, wire::{Decode, ReadCursor}};
+use crate::{chunk::ChunkListPayloadOwned, commit::CommitPayloadOwned, hash::Hash, store::{BlobId, BlobStore, ChunkListId, CommitId, Stores, TagId, TreeId}, tag::TagPayloadOwned, tree::{TreeEntry, TreePayloadOwned}, wire::{Decode, ReadCursor}};
 
 use anyhow::{bail, Result};
 
 pub const MODE_FILE: u32 = 0o100_644;
 pub const MODE_EXEC: u32 = 0o100_755;
 pub const MODE_DIR:  u32 = 0o040_000;
-#[allow(unused)]
 pub const MODE_LINK: u32 = 0o120_000;
 
 /// Copyable. Data lives in stores.
@@ -14,6 +13,9 @@ pub enum Object {
     Blob(BlobId),
     Tree(TreeId),
     Commit(CommitId),
+    Tag(TagId),
+    /// A large file's contents, split into blobs.
+    ChunkList(ChunkListId),
 }
 
 impl Object {
@@ -33,6 +35,22 @@ impl Object {
         }
     }
 
+    #[inline]
+    pub fn try_as_tag_id(self) -> Result<TagId> {
+        match self {
+            Self::Tag(t) => Ok(t),
+            _ => bail!("not a tag"),
+        }
+    }
+
+    #[inline]
+    pub fn try_as_chunk_list_id(self) -> Result<ChunkListId> {
+        match self {
+            Self::ChunkList(c) => Ok(c),
+            _ => bail!("not a chunk list"),
+        }
+    }
+
     #[inline]
     #[allow(unused)]
     pub fn try_as_blob_id(self) -> Result<BlobId> {
@@ -50,6 +68,8 @@ pub enum ObjectTag {
     Blob = 0x1,
     Tree = 0x2,
     Commit = 0x4,
+    Tag = 0x8,
+    ChunkList = 0x10,
 }
 
 impl ObjectTag {
@@ -60,6 +80,8 @@ impl ObjectTag {
             0x1 => Some(Self::Blob),
             0x2 => Some(Self::Tree),
             0x4 => Some(Self::Commit),
+            0x8 => Some(Self::Tag),
+            0x10 => Some(Self::ChunkList),
             _ => None,
         }
     }
@@ -69,6 +91,18 @@ impl ObjectTag {
     pub fn as_byte(self) -> u8 {
         self as u8
     }
+
+    #[inline]
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Blob      => "blob",
+            Self::Tree      => "tree",
+            Self::Commit    => "commit",
+            Self::Tag       => "tag",
+            Self::ChunkList => "chunk-list",
+        }
+    }
 }
 
 /// Hash of object encoded from stores.
@@ -141,3 +175,33 @@ pub fn decode_tree_entries(data: &[u8]) -> Result<Box<[TreeEntry]>> {
     let p = TreePayloadOwned::decode(&mut r)?;
     Ok(p.entries)
 }
+
+/// Push the hashes an encoded object refers to: tree entries, commit tree + parents, tag target.
+#[inline]
+pub fn push_object_children(data: &[u8], out: &mut Vec<Hash>) -> Result<()> {
+    if data.len() < 5 { bail!("data too short"); }
+
+    if &data[0..4] != b"MG01" { bail!("invalid magic"); }
+
+    let mut r = ReadCursor::new(&data[5..]);
+    match ObjectTag::from_byte(data[4]) {
+        Some(ObjectTag::Blob) => {}
+        Some(ObjectTag::Tree) => {
+            out.extend(TreePayloadOwned::decode(&mut r)?.entries.iter().map(|e| e.hash));
+        }
+        Some(ObjectTag::Commit) => {
+            let p = CommitPayloadOwned::decode(&mut r)?;
+            out.push(p.tree);
+            out.extend_from_slice(&p.parents);
+        }
+        Some(ObjectTag::Tag) => {
+            out.push(TagPayloadOwned::decode(&mut r)?.target);
+        }
+        Some(ObjectTag::ChunkList) => {
+            out.extend_from_slice(&ChunkListPayloadOwned::decode(&mut r)?.hashes);
+        }
+        None => bail!("unknown object type"),
+    }
+
+    Ok(())
+}
@@ -0,0 +1,538 @@
+//! How `fetch`, `push` and `clone` talk to `mog serve --stdio` on the other end of a pipe
+//! (ssh, a container exec, a socketpair in tests).
+//!
+//! Every message is a frame: a u32 length and that many bytes, laid out with `wire` cursors.
+//!
+//! ```text
+//! client                                  server
+//!   hello (magic, version, service)  -->
+//!                                    <--  refs (HEAD, branches, tags)
+//! fetch:
+//!   wants                            -->
+//!   haves (a round of commits)       -->
+//!                                    <--  acks (the ones it has)
+//!   ... more rounds ...
+//!   done                             -->
+//!                                    <--  pack
+//! push:
+//!   update (branch, old, new, force) -->
+//!   pack                             -->
+//!                                    <--  status (ok, or why not)
+//! ```
+//!
+//! A pack is a header frame (magic, object count) and one frame per object: its hash and its
+//! `MG01` encoding, which says what kind of object it is. Every object is re-hashed on arrival,
+//! and the receiver checks the pack is complete before any ref points into it.
+
+use crate::hash::{hash_bytes, hash_to_hex, Hash};
+use crate::object::ObjectTag;
+use crate::reflog::ZERO_HASH;
+use crate::repository::Repository;
+use crate::util::Xxh3HashSet;
+use crate::wire::{ReadCursor, WriteCursor};
+
+use std::collections::VecDeque;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::process::{Child, Command, Stdio};
+
+use anyhow::{Result, bail};
+
+const MAGIC:      &[u8; 4] = b"MGP1";
+const PACK_MAGIC: &[u8; 4] = b"MGPK";
+const VERSION:    u32      = 1;
+
+/// Nothing legitimate comes close; stops a confused peer from making us allocate gigabytes.
+const MAX_FRAME_BYTES: usize = 1 << 30;
+
+/// Commits offered per negotiation round, and in total before the client gives up looking for common history.
+const HAVES_PER_ROUND: usize = 64;
+const MAX_HAVES:       usize = 4096;
+
+/// How many object bytes a receiver collects before writing them to storage.
+const WRITE_BATCH_BYTES: usize = 64 << 20;
+
+const NEGOTIATE_HAVES: u32 = 1;
+const NEGOTIATE_DONE:  u32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Service {
+    Fetch,
+    Push,
+}
+
+impl Service {
+    #[inline]
+    fn to_u32(self) -> u32 {
+        match self {
+            Self::Fetch => 1,
+            Self::Push  => 2,
+        }
+    }
+
+    #[inline]
+    fn from_u32(v: u32) -> Result<Self> {
+        match v {
+            1 => Ok(Self::Fetch),
+            2 => Ok(Self::Push),
+            _ => bail!("protocol: unknown service {v}"),
+        }
+    }
+}
+
+/// What a repository offers: where its HEAD is, and the tips of its branches and tags.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Advertisement {
+    /// The checked-out branch, None when detached.
+    pub head_branch: Option<String>,
+    /// The checked-out commit, None when the branch has no commits yet.
+    pub head:        Option<Hash>,
+    pub heads:       Vec<(String, Hash)>,
+    pub tags:        Vec<(String, Hash)>,
+}
+
+impl Advertisement {
+    fn encode(&self, w: &mut WriteCursor<'_>) {
+        w.write_len_prefixed_str(self.head_branch.as_deref().unwrap_or(""));
+        w.write_hash(self.head.as_ref().unwrap_or(&ZERO_HASH));
+        for refs in [&self.heads, &self.tags] {
+            w.write_u32(refs.len() as u32);
+            for (name, hash) in refs {
+                w.write_len_prefixed_str(name);
+                w.write_hash(hash);
+            }
+        }
+    }
+
+    fn decode(r: &mut ReadCursor<'_>) -> Result<Self> {
+        let head_branch = r.read_len_prefixed_str()?;
+        let head        = r.read_hash()?;
+        if !head_branch.is_empty() {
+            check_ref_name(&head_branch, crate::branch::validate_branch_name)?;
+        }
+
+        //
+        // The names end up in paths under `.mog/refs`, so a hostile server mustn't get to pick them freely.
+        //
+        let mut refs = [Vec::new(), Vec::new()];
+        let validators: [fn(&str) -> Result<()>; 2] = [crate::branch::validate_branch_name, crate::tag::validate_tag_name];
+        for (list, validate) in refs.iter_mut().zip(validators) {
+            let count = r.read_u32()?;
+            for _ in 0..count {
+                let name = r.read_len_prefixed_str()?.into_owned();
+                check_ref_name(&name, validate)?;
+                list.push((name, r.read_hash()?));
+            }
+        }
+        let [heads, tags] = refs;
+
+        Ok(Self {
+            head_branch: Some(head_branch.into_owned()).filter(|b| !b.is_empty()),
+            head:        Some(head).filter(|h| *h != ZERO_HASH),
+            heads,
+            tags,
+        })
+    }
+}
+
+#[inline]
+fn check_ref_name(name: &str, validate: fn(&str) -> Result<()>) -> Result<()> {
+    validate(name).map_err(|e| anyhow::anyhow!("protocol: bad ref name '{name}': {e}"))
+}
+
+/// The refs `repo` offers to whoever fetches from it.
+pub fn advertise(repo: &Repository) -> Result<Advertisement> {
+    Ok(Advertisement {
+        head_branch: repo.current_branch()?,
+        head:        repo.read_head_commit().ok(),
+        heads:       crate::remote::list_refs(repo, "refs/heads")?,
+        tags:        crate::remote::list_refs(repo, "refs/tags")?,
+    })
+}
+
+/// A framed, buffered byte stream to the other side, and the process behind it if we started one.
+pub struct Connection {
+    reader: BufReader<Box<dyn Read>>,
+    writer: BufWriter<Box<dyn Write>>,
+    child:  Option<Child>,
+}
+
+impl Connection {
+    #[inline]
+    pub fn new(reader: impl Read + 'static, writer: impl Write + 'static) -> Self {
+        Self {
+            reader: BufReader::new(Box::new(reader)),
+            writer: BufWriter::new(Box::new(writer)),
+            child:  None,
+        }
+    }
+
+    /// Run `command` and talk to it over its stdin and stdout. Its stderr goes to ours.
+    pub fn spawn(mut command: Command) -> Result<Self> {
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .map_err(|e| anyhow::anyhow!("cannot run {}: {e}", command.get_program().display()))?;
+
+        let (Some(stdin), Some(stdout)) = (child.stdin.take(), child.stdout.take()) else {
+            bail!("cannot talk to {}", command.get_program().display());
+        };
+
+        let mut connection = Self::new(stdout, stdin);
+        connection.child = Some(child);
+        Ok(connection)
+    }
+
+    /// Say goodbye: flush, hang up, and wait for the process we started, if any.
+    pub fn finish(self) -> Result<()> {
+        let Self { reader, mut writer, child } = self;
+        writer.flush()?;
+        drop(writer);
+        drop(reader);
+
+        if let Some(mut child) = child {
+            let status = child.wait()?;
+            if !status.success() {
+                bail!("remote side exited with {status}");
+            }
+        }
+        Ok(())
+    }
+
+    #[inline]
+    pub fn send(&mut self, build: impl FnOnce(&mut WriteCursor<'_>)) -> Result<()> {
+        let mut buf = Vec::new();
+        build(&mut WriteCursor::new(&mut buf));
+
+        self.writer.write_all(&(buf.len() as u32).to_le_bytes())?;
+        self.writer.write_all(&buf)?;
+        Ok(())
+    }
+
+    /// Next frame. Anything we sent is flushed first, since the other side may be waiting for it.
+    #[inline]
+    pub fn recv(&mut self) -> Result<Vec<u8>> {
+        self.writer.flush()?;
+
+        let mut len = [0u8; 4];
+        if let Err(e) = self.reader.read_exact(&mut len) {
+            if e.kind() == std::io::ErrorKind::UnexpectedEof {
+                bail!("protocol: the remote side hung up");
+            }
+            return Err(e.into());
+        }
+
+        let len = u32::from_le_bytes(len) as usize;
+        if len > MAX_FRAME_BYTES {
+            bail!("protocol: frame of {len} bytes is too large");
+        }
+
+        let mut frame = vec![0u8; len];
+        self.reader.read_exact(&mut frame)?;
+        Ok(frame)
+    }
+}
+
+//
+//
+// Client
+//
+//
+
+/// Open a session for `service` and read what the server offers.
+pub fn hello(conn: &mut Connection, service: Service) -> Result<Advertisement> {
+    conn.send(|w| {
+        w.write_slice(MAGIC);
+        w.write_u32(VERSION);
+        w.write_u32(service.to_u32());
+    })?;
+
+    let frame = conn.recv()?;
+    Advertisement::decode(&mut ReadCursor::new(&frame))
+}
+
+/// Get everything reachable from `tips` that `repo` lacks. Offers the server commits we have,
+/// newest first, so it can leave out what we share. Returns how many objects arrived.
+pub fn fetch_pack(conn: &mut Connection, repo: &mut Repository, tips: &[Hash]) -> Result<usize> {
+    let wants = tips.iter().copied().filter(|tip| !repo.storage.exists(tip)).collect::<Vec<_>>();
+    conn.send(|w| write_hashes(w, &wants))?;
+    if wants.is_empty() {
+        return Ok(0);
+    }
+
+    //
+    // Walk back from our own refs a round at a time. An acked commit is common history, so
+    // its ancestors needn't be offered; the parents of the others go into the queue.
+    //
+    let mut queue   = our_tips(repo)?.into_iter().collect::<VecDeque<_>>();
+    let mut offered = Xxh3HashSet::default();
+    let mut total   = 0;
+    while !queue.is_empty() && total < MAX_HAVES {
+        let mut round = Vec::with_capacity(HAVES_PER_ROUND);
+        while round.len() < HAVES_PER_ROUND {
+            let Some(hash) = queue.pop_front() else { break };
+            if offered.insert(hash) {
+                round.push(hash);
+            }
+        }
+        if round.is_empty() {
+            break;
+        }
+        total += round.len();
+
+        conn.send(|w| {
+            w.write_u32(NEGOTIATE_HAVES);
+            write_hashes(w, &round);
+        })?;
+        let acks = read_hashes(&mut ReadCursor::new(&conn.recv()?))?.into_iter().collect::<Xxh3HashSet<_>>();
+
+        for hash in round.iter().filter(|hash| !acks.contains(*hash)) {
+            if let Ok(commit_id) = repo.read_object(hash).and_then(crate::object::Object::try_as_commit_id) {
+                queue.extend(repo.commit.get_parents(commit_id));
+            }
+        }
+    }
+
+    conn.send(|w| w.write_u32(NEGOTIATE_DONE))?;
+    receive_pack(conn, repo, &wants)
+}
+
+/// Send `new` and whatever the server lacks of it, and ask it to point `branch` there.
+/// `old` is the value we saw advertised; the server refuses if the branch moved since.
+pub fn push_pack(
+    conn: &mut Connection,
+    repo: &Repository,
+    refs: &Advertisement,
+    branch: &str,
+    old: Option<Hash>,
+    new: Hash,
+    force: bool
+) -> Result<()> {
+    let theirs = refs.heads.iter()
+        .chain(&refs.tags)
+        .map(|(_, hash)| *hash)
+        .filter(|hash| repo.storage.exists(hash));
+    let shared  = repo.reachable_objects(theirs)?;
+    let objects = crate::remote::missing_objects(repo, &[new], |hash| shared.contains(hash))?;
+
+    conn.send(|w| {
+        w.write_len_prefixed_str(branch);
+        w.write_hash(old.as_ref().unwrap_or(&ZERO_HASH));
+        w.write_hash(&new);
+        w.write_u32(u32::from(force));
+    })?;
+    send_pack(conn, repo, &objects)?;
+
+    let frame = conn.recv()?;
+    let mut r = ReadCursor::new(&frame);
+    if r.read_u32()? != 0 {
+        bail!("{}", r.read_len_prefixed_str()?);
+    }
+    Ok(())
+}
+
+/// Every commit our refs point at: where the search for common history starts.
+fn our_tips(repo: &Repository) -> Result<Vec<Hash>> {
+    let mut tips = Vec::new();
+    for dir in ["refs/heads", "refs/tags"] {
+        tips.extend(crate::remote::list_refs(repo, dir)?.into_iter().map(|(_, hash)| hash));
+    }
+
+    let remotes = repo.root.join(".mog/refs/remotes");
+    for entry in std::fs::read_dir(remotes).into_iter().flatten().filter_map(Result::ok) {
+        let Ok(name) = entry.file_name().into_string() else { continue };
+        tips.extend(crate::remote::list_refs(repo, &format!("refs/remotes/{name}"))?.into_iter().map(|(_, hash)| hash));
+    }
+
+    tips.extend(repo.read_head_commit().ok());
+    Ok(tips)
+}
+
+//
+//
+// Server
+//
+//
+
+/// Answer one fetch or push from the client on `conn`.
+pub fn serve(repo: &mut Repository, mut conn: Connection) -> Result<()> {
+    let frame = conn.recv()?;
+    let mut r = ReadCursor::new(&frame);
+    if r.read_bytes(4)? != MAGIC {
+        bail!("protocol: not a mog client");
+    }
+    let version = r.read_u32()?;
+    if version != VERSION {
+        bail!("protocol: client speaks version {version}, this mog speaks {VERSION}");
+    }
+    let service = Service::from_u32(r.read_u32()?)?;
+
+    let refs = advertise(repo)?;
+    conn.send(|w| refs.encode(w))?;
+
+    match service {
+        Service::Fetch => serve_fetch(repo, &mut conn)?,
+        Service::Push  => serve_push(repo, &mut conn)?,
+    }
+    conn.finish()
+}
+
+fn serve_fetch(repo: &mut Repository, conn: &mut Connection) -> Result<()> {
+    let wants = read_hashes(&mut ReadCursor::new(&conn.recv()?))?;
+    if wants.is_empty() {
+        return Ok(());
+    }
+
+    let mut common = Vec::new();
+    loop {
+        let frame = conn.recv()?;
+        let mut r = ReadCursor::new(&frame);
+        match r.read_u32()? {
+            NEGOTIATE_HAVES => {
+                let acks = read_hashes(&mut r)?
+                    .into_iter()
+                    .filter(|hash| repo.storage.exists(hash))
+                    .collect::<Vec<_>>();
+                conn.send(|w| write_hashes(w, &acks))?;
+                common.extend(acks);
+            }
+            NEGOTIATE_DONE => break,
+            other => bail!("protocol: unexpected negotiation message {other}"),
+        }
+    }
+
+    let shared  = repo.reachable_objects(common)?;
+    let objects = crate::remote::missing_objects(repo, &wants, |hash| shared.contains(hash))?;
+    send_pack(conn, repo, &objects)
+}
+
+fn serve_push(repo: &mut Repository, conn: &mut Connection) -> Result<()> {
+    let frame = conn.recv()?;
+    let mut r = ReadCursor::new(&frame);
+    let branch = r.read_len_prefixed_str()?.into_owned();
+    let old    = Some(r.read_hash()?).filter(|h| *h != ZERO_HASH);
+    let new    = r.read_hash()?;
+    let force  = r.read_u32()? != 0;
+
+    //
+    // The name becomes a path under `.mog/refs/heads`: check it before anything is stored.
+    //
+    let result = match check_ref_name(&branch, crate::branch::validate_branch_name) {
+        Ok(()) => {
+            receive_pack(conn, repo, &[new])?;
+            crate::remote::update_pushed_branch(repo, &branch, old, new, force, "push: over the wire")
+        }
+        Err(e) => {
+            skip_pack(conn)?;
+            Err(e)
+        }
+    };
+    conn.send(|w| match &result {
+        Ok(())  => w.write_u32(0),
+        Err(e) => {
+            w.write_u32(1);
+            w.write_len_prefixed_str(&e.to_string());
+        }
+    })
+}
+
+//
+//
+// Packs
+//
+//
+
+fn send_pack(conn: &mut Connection, repo: &Repository, objects: &[Hash]) -> Result<()> {
+    conn.send(|w| {
+        w.write_slice(PACK_MAGIC);
+        w.write_u64(objects.len() as u64);
+    })?;
+
+    for hash in objects {
+        let data = repo.storage.read(hash)?;
+        conn.send(|w| {
+            w.write_hash(hash);
+            w.write_slice(&data);
+        })?;
+    }
+    Ok(())
+}
+
+/// Read a pack into storage, re-hashing every object, then make sure everything reachable
+/// from `tips` is now stored. Returns how many objects arrived.
+fn receive_pack(conn: &mut Connection, repo: &mut Repository, tips: &[Hash]) -> Result<usize> {
+    let header = conn.recv()?;
+    let mut r = ReadCursor::new(&header);
+    if r.read_bytes(4)? != PACK_MAGIC {
+        bail!("protocol: expected a pack");
+    }
+    let count = r.read_u64()?;
+
+    let mut received    = Xxh3HashSet::default();
+    let mut batch       = Vec::<(Hash, Vec<u8>)>::new();
+    let mut batch_bytes = 0;
+    for i in 0..count {
+        let mut frame = conn.recv()?;
+        let hash = ReadCursor::new(&frame).read_hash()?;
+        let data = frame.split_off(32);
+
+        if hash_bytes(&data) != hash {
+            bail!("pack: object {} doesn't match its hash", hash_to_hex(&hash));
+        }
+        if !data.starts_with(b"MG01") || data.get(4).copied().and_then(ObjectTag::from_byte).is_none() {
+            bail!("pack: object {} is not a mog object", hash_to_hex(&hash));
+        }
+
+        received.insert(hash);
+        batch_bytes += data.len();
+        batch.push((hash, data));
+
+        if batch_bytes >= WRITE_BATCH_BYTES || i + 1 == count {
+            repo.storage.write_batch(batch.iter().map(|(hash, data)| (*hash, data.as_slice())))?;
+            batch.clear();
+            batch_bytes = 0;
+        }
+    }
+
+    repo.storage.flush()?;
+    repo.storage.remap()?;
+
+    //
+    // Walk what arrived, stopping at what was already here (and so already complete).
+    // Anything neither received nor stored means the pack had holes.
+    //
+    crate::remote::missing_objects(repo, tips, |hash| !received.contains(hash) && repo.storage.exists(hash))?;
+
+    Ok(received.len())
+}
+
+/// Read a pack and throw it away, so the sender isn't left blocked writing it.
+fn skip_pack(conn: &mut Connection) -> Result<()> {
+    let header = conn.recv()?;
+    let mut r = ReadCursor::new(&header);
+    if r.read_bytes(4)? != PACK_MAGIC {
+        bail!("protocol: expected a pack");
+    }
+    for _ in 0..r.read_u64()? {
+        conn.recv()?;
+    }
+    Ok(())
+}
+
+#[inline]
+fn write_hashes(w: &mut WriteCursor<'_>, hashes: &[Hash]) {
+    w.write_u32(hashes.len() as u32);
+    for hash in hashes {
+        w.write_hash(hash);
+    }
+}
+
+#[inline]
+fn read_hashes(r: &mut ReadCursor<'_>) -> Result<Vec<Hash>> {
+    let count = r.read_u32()? as usize;
+    if count > r.remaining().len() / 32 {
+        bail!("protocol: hash list is longer than its frame");
+    }
+    (0..count).map(|_| r.read_hash()).collect()
+}
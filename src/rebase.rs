@@ -0,0 +1,353 @@
+use crate::hash::{hash_to_hex, hex_to_hash, Hash};
+use crate::index::Index;
+use crate::identity::{self, Identity, Role};
+use crate::merge::{ensure_clean_worktree, ensure_conflicts_resolved, flatten_commit, merge_base, merge_trees, TreeMerge};
+use crate::object::Object;
+use crate::repository::Repository;
+use crate::status::SortedFlatTree;
+
+use std::collections::VecDeque;
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{Result, bail};
+
+const REBASE_DIR: &str = ".mog/rebase";
+
+/// State of a rebase in progress, written before HEAD moves so a stop (a conflict, or an error)
+/// can always be continued or aborted. Stored as one small file per field under `.mog/rebase/`.
+struct RebaseState {
+    /// Branch ref that was checked out (e.g. `refs/heads/feature`), None if HEAD was detached.
+    head_name: Option<String>,
+    orig_head: Hash,
+    onto:      Hash,
+    /// Tip of the commits replayed so far.
+    new_head:  Hash,
+    /// Commit that stopped on a conflict.
+    current:   Option<Hash>,
+    /// Commits still to replay, oldest first.
+    todo:      VecDeque<Hash>,
+    conflicts: Vec<String>,
+}
+
+impl RebaseState {
+    #[inline]
+    fn dir(repo: &Repository) -> PathBuf {
+        repo.root.join(REBASE_DIR)
+    }
+
+    fn save(&self, repo: &Repository) -> Result<()> {
+        let dir = Self::dir(repo);
+        fs::create_dir_all(&dir)?;
+
+        fs::write(dir.join("head-name"), self.head_name.as_deref().unwrap_or_default())?;
+        fs::write(dir.join("orig-head"), hash_to_hex(&self.orig_head))?;
+        fs::write(dir.join("onto"),      hash_to_hex(&self.onto))?;
+        fs::write(dir.join("new-head"),  hash_to_hex(&self.new_head))?;
+        fs::write(dir.join("current"),   self.current.as_ref().map(hash_to_hex).unwrap_or_default())?;
+
+        let todo = self.todo.iter().map(|h| hash_to_hex(h) + "\n").collect::<String>();
+        fs::write(dir.join("todo"), todo)?;
+
+        let conflicts = self.conflicts.iter().map(|p| p.clone() + "\n").collect::<String>();
+        fs::write(dir.join("conflicts"), conflicts)?;
+
+        Ok(())
+    }
+
+    fn load(repo: &Repository) -> Result<Self> {
+        let dir = Self::dir(repo);
+        if !dir.exists() {
+            bail!("no rebase in progress");
+        }
+
+        let read = |name: &str| -> Result<String> {
+            Ok(fs::read_to_string(dir.join(name))?.trim().to_owned())
+        };
+
+        let head_name = Some(read("head-name")?).filter(|s| !s.is_empty());
+        let current   = Some(read("current")?).filter(|s| !s.is_empty()).map(|s| hex_to_hash(&s)).transpose()?;
+        let todo      = read("todo")?.lines().map(hex_to_hash).collect::<Result<VecDeque<_>>>()?;
+        let conflicts = read("conflicts")?.lines().map(ToOwned::to_owned).collect();
+
+        Ok(Self {
+            head_name,
+            orig_head: hex_to_hash(&read("orig-head")?)?,
+            onto:      hex_to_hash(&read("onto")?)?,
+            new_head:  hex_to_hash(&read("new-head")?)?,
+            current,
+            todo,
+            conflicts,
+        })
+    }
+}
+
+#[inline]
+#[must_use]
+pub fn rebase_in_progress(repo: &Repository) -> bool {
+    repo.root.join(REBASE_DIR).exists()
+}
+
+/// Replay the first-parent chain from HEAD back to the merge base on top of `upstream`.
+pub fn rebase(repo: &mut Repository, upstream: &str) -> Result<()> {
+    if rebase_in_progress(repo) {
+        bail!("a rebase is already in progress (use 'mog rebase --continue', '--skip' or '--abort')");
+    }
+    if crate::merge::merge_in_progress(repo) {
+        bail!("cannot rebase while a merge is in progress");
+    }
+
+    let orig_head = repo.read_head_commit().map_err(|_| anyhow::anyhow!("cannot rebase: no commits yet"))?;
+    let (onto, onto_id) = repo.resolve_to_commit(upstream)?;
+
+    ensure_clean_worktree(repo, "rebase")?;
+
+    let base = merge_base(repo, &orig_head, &onto)?;
+    if base == Some(onto) {
+        println!("Current branch is up to date.");
+        return Ok(());
+    }
+
+    //
+    // Collect commits to replay, oldest first. The first-parent chain may only reach upstream
+    // history through a merge's other parent, so stop at anything upstream already has.
+    //
+    let upstream_commits = repo.reachable_commits(&onto);
+    let mut todo    = Vec::new();
+    let mut current = orig_head;
+    while Some(current) != base && !upstream_commits.contains(&current) {
+        todo.push(current);
+        let commit_id = repo.read_object(&current)?.try_as_commit_id()?;
+        match repo.commit.get_parents(commit_id).first() {
+            Some(parent) => current = *parent,
+            None         => break,
+        }
+    }
+    todo.reverse();
+
+    let head_name = read_head_name(repo)?;
+
+    let state = RebaseState {
+        head_name,
+        orig_head,
+        onto,
+        new_head: onto,
+        current: None,
+        todo: todo.into(),
+        conflicts: Vec::new(),
+    };
+
+    //
+    // Saved before anything moves, so 'mog rebase --abort' can always get back.
+    //
+    state.save(repo)?;
+
+    crate::checkout::checkout_commit(repo, onto_id)?;
+    repo.detach_head(&onto, &format!("rebase (start): checkout {upstream}"))?;
+
+    replay(repo, state)
+}
+
+/// Commit the resolved conflict and keep replaying.
+pub fn rebase_continue(repo: &mut Repository) -> Result<()> {
+    let mut state = RebaseState::load(repo)?;
+    ensure_conflicts_resolved(repo, &state.conflicts)?;
+
+    state.conflicts.clear();
+    if let Some(pick) = state.current.take() {
+        let mut index = Index::load(&repo.root)?;
+        commit_pick(repo, &mut state, pick, &mut index)?;
+    }
+
+    replay(repo, state)
+}
+
+/// Drop the commit that stopped on a conflict and keep replaying.
+pub fn rebase_skip(repo: &mut Repository) -> Result<()> {
+    let mut state = RebaseState::load(repo)?;
+
+    for path in &state.conflicts {
+        _ = fs::remove_file(repo.root.join(path));
+    }
+
+    let commit_id = repo.read_object(&state.new_head)?.try_as_commit_id()?;
+    crate::checkout::checkout_commit(repo, commit_id)?;
+
+    if let Some(pick) = state.current.take() {
+        println!("Skipped {}", &hash_to_hex(&pick)[..8]);
+    }
+    state.conflicts.clear();
+
+    replay(repo, state)
+}
+
+/// Stop rebasing and restore the branch and working tree to where they were before.
+pub fn rebase_abort(repo: &mut Repository) -> Result<()> {
+    let state = RebaseState::load(repo)?;
+
+    for path in &state.conflicts {
+        _ = fs::remove_file(repo.root.join(path));
+    }
+
+    let commit_id = repo.read_object(&state.orig_head)?.try_as_commit_id()?;
+    crate::checkout::checkout_commit(repo, commit_id)?;
+
+    match &state.head_name {
+        Some(refname) => repo.attach_head(refname, &format!("rebase (abort): returning to {refname}"))?,
+        None          => repo.detach_head(&state.orig_head, "rebase (abort)")?,
+    }
+
+    fs::remove_dir_all(RebaseState::dir(repo))?;
+    println!("Rebase aborted");
+    Ok(())
+}
+
+//
+//
+// Helpers
+//
+//
+
+fn replay(repo: &mut Repository, mut state: RebaseState) -> Result<()> {
+    while let Some(pick) = state.todo.pop_front() {
+        let new_head = state.new_head;
+        match replay_pick(repo, &mut state, pick) {
+            Ok(true) => {}
+            //
+            // Stopped on a conflict: an error, so scripts can tell the rebase isn't done.
+            //
+            Ok(false) => {
+                repo.storage.flush()?;
+                state.save(repo)?;
+                bail!(
+                    "resolve the conflicts, stage them, then run 'mog rebase --continue'.\n\
+                     Use 'mog rebase --skip' to drop this commit or 'mog rebase --abort' to stop."
+                );
+            }
+
+            //
+            // Anything but a conflict: keep the pick queued so '--continue' retries it,
+            // unless it was already committed and recorded and only moving HEAD failed.
+            //
+            Err(e) => {
+                if state.new_head == new_head {
+                    state.todo.push_front(pick);
+                }
+                state.save(repo)?;
+                return Err(e.context(format!(
+                    "could not replay {}; fix the problem and run 'mog rebase --continue', or 'mog rebase --abort'",
+                    &hash_to_hex(&pick)[..8]
+                )));
+            }
+        }
+    }
+
+    finish(repo, &state)
+}
+
+/// Apply `pick`'s delta against its parent on top of the new tip and commit it.
+/// Returns false when it stopped on a conflict, recorded in `state`.
+fn replay_pick(repo: &mut Repository, state: &mut RebaseState, pick: Hash) -> Result<bool> {
+    let pick_id = repo.read_object(&pick)?.try_as_commit_id()?;
+    let parent  = repo.commit.get_parents(pick_id).first().copied();
+    let label   = format!(
+        "{} ({})",
+        &hash_to_hex(&pick)[..8],
+        repo.commit.get_message(pick_id).lines().next().unwrap_or_default()
+    );
+
+    let base_flat = match parent {
+        Some(parent) => flatten_commit(repo, &parent)?,
+        None         => SortedFlatTree::default(),
+    };
+    let ours_flat   = flatten_commit(repo, &state.new_head)?;
+    let theirs_flat = flatten_commit(repo, &pick)?;
+
+    let mut index = Index::load(&repo.root)?;
+    let TreeMerge { conflicts, .. } = merge_trees(
+        repo, &mut index, &base_flat, &ours_flat, &theirs_flat, "HEAD", &label
+    )?;
+    index.save(&repo.root)?;
+
+    if !conflicts.is_empty() {
+        state.current   = Some(pick);
+        state.conflicts = conflicts;
+
+        println!("Could not apply {label}");
+        return Ok(false);
+    }
+
+    commit_pick(repo, state, pick, &mut index)?;
+    Ok(true)
+}
+
+/// Write the replayed commit from `index`, preserving the original author, message and timestamp.
+/// Whoever runs the rebase becomes the committer.
+fn commit_pick(repo: &mut Repository, state: &mut RebaseState, pick: Hash, index: &mut Index) -> Result<()> {
+    let tree = index.write_tree(repo)?;
+
+    let head_id = repo.read_object(&state.new_head)?.try_as_commit_id()?;
+    if tree == repo.commit.get_tree(head_id) {
+        println!("Dropped {} (its changes are already upstream)", &hash_to_hex(&pick)[..8]);
+        return state.save(repo);
+    }
+
+    let pick_id   = repo.read_object(&pick)?.try_as_commit_id()?;
+    let authored  = repo.commit.get_author_signature(pick_id);
+    let author    = Identity { name: authored.name.to_owned(), email: authored.email.to_owned() };
+    let authored  = author.sign(authored.timestamp, authored.tz_offset);
+    let message   = repo.commit.get_message(pick_id).to_owned();
+
+    let committer = identity::resolve(&repo.root, Role::Committer)?;
+    let committed = committer.sign_now()?;
+
+    let commit_id = repo.commit.push_signed(tree, &[state.new_head], &authored, &committed, &message);
+    let new_head  = repo.write_object(Object::Commit(commit_id));
+
+    //
+    // The next pick reads this commit back, so make it visible through the mmap.
+    //
+    repo.storage.flush()?;
+    repo.storage.remap()?;
+    index.save(&repo.root)?;
+
+    //
+    // Record the pick as done before HEAD moves, so a crash from here on can't replay it again.
+    //
+    state.new_head = new_head;
+    state.save(repo)?;
+
+    let subject = message.lines().next().unwrap_or_default();
+    repo.detach_head(&state.new_head, &format!("rebase (pick): {subject}"))
+}
+
+fn finish(repo: &mut Repository, state: &RebaseState) -> Result<()> {
+    repo.storage.flush()?;
+
+    let onto = hash_to_hex(&state.onto);
+    match &state.head_name {
+        Some(refname) => {
+            repo.write_ref(refname, &state.new_head, &format!("rebase (finish): {refname} onto {onto}"))?;
+            repo.attach_head(refname, &format!("rebase (finish): returning to {refname}"))?;
+        }
+        None => repo.detach_head(&state.new_head, &format!("rebase (finish): onto {onto}"))?,
+    }
+
+    let dir = RebaseState::dir(repo);
+    if dir.exists() {
+        fs::remove_dir_all(dir)?;
+    }
+
+    println!(
+        "Successfully rebased onto {}, HEAD is now at {}",
+        &hash_to_hex(&state.onto)[..8],
+        &hash_to_hex(&state.new_head)[..8]
+    );
+    Ok(())
+}
+
+#[inline]
+fn read_head_name(repo: &Repository) -> Result<Option<String>> {
+    let head = fs::read_to_string(repo.root.join(".mog/HEAD"))?;
+    Ok(head.trim().strip_prefix("ref: ").map(|r| r.trim().to_owned()))
+}
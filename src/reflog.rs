@@ -0,0 +1,163 @@
+//! Append-only history of ref updates under `.mog/logs/`, one file per ref.
+//!
+//! Line format: `<old hex> <new hex> <unix seconds> <actor>\t<reason>`.
+//! A zero hash as `old` means the ref was created, as `new` that it was deleted.
+//! Logs of deleted branches are kept, so `name@{N}` can still recover them.
+
+use crate::hash::{hash_to_hex, hex_to_hash, Hash};
+use crate::repository::Repository;
+use crate::storage::MogStorage;
+
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::Write as _;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Result, bail};
+
+pub const ZERO_HASH: Hash = [0u8; 32];
+
+pub struct ReflogEntry {
+    pub old:       Hash,
+    pub new:       Hash,
+    pub timestamp: i64,
+    pub actor:     Box<str>,
+    pub reason:    Box<str>,
+}
+
+/// Append one entry to the log of `refname` (e.g. `HEAD` or `refs/heads/main`).
+pub fn append(
+    repo:    &Repository<impl MogStorage>,
+    refname: &str,
+    old:     &Hash,
+    new:     &Hash,
+    reason:  &str,
+) -> Result<()> {
+    let path = repo.root.join(".mog/logs").join(refname);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)?
+        .as_secs() as i64;
+
+    // Keep every entry on one line no matter what the caller passed in.
+    let actor  = actor().replace(['\t', '\n'], " ");
+    let reason = reason.replace('\n', " ");
+
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{} {} {timestamp} {actor}\t{reason}", hash_to_hex(old), hash_to_hex(new))?;
+
+    Ok(())
+}
+
+/// Read all entries of `refname`'s log, oldest first. Missing log reads as empty.
+pub fn read(repo: &Repository<impl MogStorage>, refname: &str) -> Result<Vec<ReflogEntry>> {
+    let Ok(content) = fs::read_to_string(repo.root.join(".mog/logs").join(refname)) else {
+        return Ok(Vec::new());
+    };
+
+    content.lines().filter(|l| !l.is_empty()).map(parse_line).collect()
+}
+
+/// Every hash mentioned by any reflog, old and new sides alike. `ZERO_HASH` (no value) is left out.
+pub fn all_hashes(repo: &Repository<impl MogStorage>) -> Result<Vec<Hash>> {
+    let mut hashes = Vec::new();
+    let mut stack  = vec![repo.root.join(".mog/logs")];
+
+    while let Some(dir) = stack.pop() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+
+        for entry in entries {
+            let path = entry?.path();
+            if path.is_dir() {
+                stack.push(path);
+                continue;
+            }
+
+            for line in fs::read_to_string(&path)?.lines().filter(|l| !l.is_empty()) {
+                let entry = parse_line(line)?;
+                hashes.extend([entry.old, entry.new].into_iter().filter(|h| *h != ZERO_HASH));
+            }
+        }
+    }
+
+    Ok(hashes)
+}
+
+/// Hash `refname` pointed to `n` updates ago (`0` is the latest entry).
+pub fn nth_entry(repo: &Repository<impl MogStorage>, refname: &str, n: usize) -> Result<Hash> {
+    let entries = read(repo, refname)?;
+    if n >= entries.len() {
+        bail!("log for '{refname}' only has {} entries", entries.len());
+    }
+
+    let hash = entries[entries.len() - 1 - n].new;
+    if hash == ZERO_HASH {
+        bail!("'{refname}@{{{n}}}' is a deletion, try an older entry");
+    }
+
+    Ok(hash)
+}
+
+/// Print `name`'s log newest first, `git reflog` style.
+pub fn show(repo: &Repository<impl MogStorage>, name: &str, f: &mut dyn fmt::Write) -> Result<()> {
+    let refname = refname_for(name);
+    let entries = read(repo, &refname)?;
+    if entries.is_empty() {
+        writeln!(f, "no reflog for '{refname}'")?;
+        return Ok(());
+    }
+
+    let label = if name.is_empty() || name == "@" { "HEAD" } else { name };
+    for (n, entry) in entries.iter().rev().enumerate() {
+        writeln!(f, "{} {label}@{{{n}}}: {}", &hash_to_hex(&entry.new)[..8], entry.reason)?;
+    }
+
+    Ok(())
+}
+
+/// Split `name@{N}` into `(name, N)`. `name` may be empty, meaning HEAD.
+#[must_use]
+pub fn parse_selector(target: &str) -> Option<(&str, usize)> {
+    let (name, n) = target.strip_suffix('}')?.rsplit_once("@{")?;
+    Some((name, n.parse().ok()?))
+}
+
+/// Map a user-facing ref name to the path of its log: `HEAD`, `refs/...` or a branch name.
+#[must_use]
+pub fn refname_for(name: &str) -> String {
+    match name {
+        "" | "@" | "HEAD"              => "HEAD".to_owned(),
+        _ if name.starts_with("refs/") => name.to_owned(),
+        _                              => format!("refs/heads/{name}"),
+    }
+}
+
+/// Who to record as making the update.
+#[must_use]
+pub fn actor() -> String {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_else(|_| "unknown".to_owned())
+}
+
+fn parse_line(line: &str) -> Result<ReflogEntry> {
+    let (head, reason) = line.split_once('\t').unwrap_or((line, ""));
+
+    let mut parts = head.splitn(4, ' ');
+    let (Some(old), Some(new), Some(timestamp)) = (parts.next(), parts.next(), parts.next()) else {
+        bail!("malformed reflog line: '{line}'");
+    };
+
+    Ok(ReflogEntry {
+        old:       hex_to_hash(old)?,
+        new:       hex_to_hash(new)?,
+        timestamp: timestamp.parse()?,
+        actor:     parts.next().unwrap_or_default().into(),
+        reason:    reason.into(),
+    })
+}
@@ -0,0 +1,446 @@
+use crate::hash::{hash_bytes, hash_to_hex, Hash};
+use crate::config::Scope;
+use crate::protocol::{Advertisement, Connection, Service};
+use crate::repository::Repository;
+use crate::util::Xxh3HashSet;
+
+use std::borrow::Cow;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{Result, bail};
+
+/// How many object bytes `transfer_objects` reads before handing them to the other storage.
+const TRANSFER_BATCH_BYTES: usize = 64 << 20;
+
+/// Where a remote repository lives.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Location {
+    /// On this filesystem: objects are copied straight between the two `objects.bin`.
+    Path(PathBuf),
+    /// `ssh://[user@]host[:port]/path`: we run `mog serve --stdio <path>` over there and speak [`crate::protocol`].
+    Ssh { host: String, port: Option<u16>, path: String },
+}
+
+impl Location {
+    pub fn parse(location: &str) -> Result<Self> {
+        let Some(rest) = location.strip_prefix("ssh://") else {
+            return Ok(Self::Path(PathBuf::from(location)));
+        };
+
+        let Some((authority, path)) = rest.split_once('/') else {
+            bail!("'{location}' has no path, expected ssh://host/path");
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, Some(port.parse().map_err(|_| anyhow::anyhow!("bad port in '{location}'"))?)),
+            None               => (authority, None),
+        };
+        if host.is_empty() {
+            bail!("'{location}' has no host");
+        }
+
+        //
+        // ssh://host/~/repo is relative to the home directory over there, like with git.
+        //
+        let path = path.strip_prefix("~/").map_or_else(|| format!("/{path}"), ToOwned::to_owned);
+        Ok(Self::Ssh { host: host.to_owned(), port, path })
+    }
+
+    /// What to call the repository: the last component of its path.
+    #[must_use]
+    pub fn basename(&self) -> Option<&str> {
+        match self {
+            Self::Path(path)       => path.file_name()?.to_str(),
+            Self::Ssh { path, .. } => path.trim_end_matches('/').rsplit('/').next().filter(|name| !name.is_empty()),
+        }
+    }
+}
+
+impl std::fmt::Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Path(path)                        => write!(f, "{}", path.display()),
+            Self::Ssh { host, port: None, path }    => write!(f, "ssh://{host}/{}", path.trim_start_matches('/')),
+            Self::Ssh { host, port: Some(p), path } => write!(f, "ssh://{host}:{p}/{}", path.trim_start_matches('/')),
+        }
+    }
+}
+
+/// A repository to fetch from or push to.
+pub struct Remote {
+    /// None when given as a bare location: objects move, but no remote-tracking refs are kept.
+    pub name:     Option<String>,
+    pub location: Location,
+}
+
+/// The other end of a fetch, push or clone, ready to talk.
+pub enum Peer {
+    Local(Box<Repository>),
+    Pipe(Connection),
+}
+
+impl Peer {
+    /// Open the repository, or start `mog serve --stdio` on the remote host
+    /// (through `$MOG_SSH` if set, e.g. `ssh -i key`, else `ssh`).
+    pub fn connect(location: &Location) -> Result<Self> {
+        match location {
+            Location::Path(path) => Ok(Self::Local(Box::new(Repository::open(path)?))),
+            Location::Ssh { host, port, path } => {
+                let ssh = std::env::var("MOG_SSH").unwrap_or_else(|_| "ssh".to_owned());
+                let mut words = ssh.split_whitespace();
+                let mut command = Command::new(words.next().unwrap_or("ssh"));
+                command.args(words);
+                if let Some(port) = port {
+                    command.arg("-p").arg(port.to_string());
+                }
+                command.arg(host).arg(format!("mog serve --stdio {}", shell_quote(path)));
+                Ok(Self::Pipe(Connection::spawn(command)?))
+            }
+        }
+    }
+}
+
+/// `remote` is a remote name (`remote.<name>.url` in the config) or the location of a repository.
+pub fn resolve_remote(repo: &Repository, remote: &str) -> Result<Remote> {
+    if !remote.contains(['/', '\\']) {
+        let config = crate::config::load(Some(&repo.root))?;
+        if let Some(url) = config.get(&format!("remote.{remote}.url")) {
+            return Ok(Remote { name: Some(remote.to_owned()), location: Location::parse(url)? });
+        }
+    }
+
+    let location = Location::parse(remote)?;
+    match &location {
+        Location::Path(path) if !path.join(".mog").is_dir() => {
+            bail!("'{remote}' is neither a remote nor a path to a mog repository")
+        }
+        _ => Ok(Remote { name: None, location }),
+    }
+}
+
+/// Remember `location` as remote `name`.
+pub fn add_remote(repo: &Repository, name: &str, location: &Location) -> Result<()> {
+    if name.is_empty() || name.contains(['/', '\\', ' ', '\t', '"']) || name.ends_with(crate::lockfile::LOCK_SUFFIX) {
+        bail!("invalid remote name '{name}'");
+    }
+
+    crate::config::edit(Some(&repo.root), Scope::Repo, |config| {
+        if config.get(&format!("remote.{name}.url")).is_some() {
+            bail!("remote '{name}' already exists");
+        }
+        config.set(&format!("remote.{name}.url"), &location.to_string())
+    })
+}
+
+/// The remote to use when none is given: the current branch's upstream remote, else "origin".
+pub fn default_remote(repo: &Repository) -> Result<String> {
+    let config = crate::config::load(Some(&repo.root))?;
+    let upstream = repo.current_branch()?.and_then(|branch| crate::branch::upstream(&config, &branch));
+    Ok(upstream.map_or_else(|| "origin".to_owned(), |upstream| upstream.remote))
+}
+
+/// Copy the repository at `source` (a path or an `ssh://` URL) into `dest` (default: a directory
+/// named like the source), with `source` as remote "origin", and check out what the source has checked out.
+pub fn clone(source: &str, dest: Option<&Path>) -> Result<()> {
+    let mut location = Location::parse(source)?;
+    let peer = Peer::connect(&location)?;
+    if let Peer::Local(source) = &peer {
+        location = Location::Path(source.root.to_path_buf());
+    }
+
+    let dest = match dest {
+        Some(dest) => dest.to_owned(),
+        None       => PathBuf::from(location.basename().ok_or_else(|| anyhow::anyhow!("cannot name the clone, pass a directory"))?),
+    };
+    if dest.exists() && fs::read_dir(&dest)?.next().is_some() {
+        bail!("destination '{}' already exists and is not empty", dest.display());
+    }
+
+    let mut repo = Repository::init(&dest)?;
+    add_remote(&repo, "origin", &location)?;
+
+    let remote = Remote { name: Some("origin".to_owned()), location };
+    let refs = fetch_from(&mut repo, &remote, peer)?;
+
+    //
+    // Check out the same branch (or detached commit) as the source.
+    //
+    let reason = format!("clone: from {}", remote.location);
+    match (refs.head_branch, refs.head) {
+        (Some(branch), Some(head)) => {
+            repo.write_ref(&format!("refs/heads/{branch}"), &head, &reason)?;
+            repo.attach_head(&format!("refs/heads/{branch}"), &reason)?;
+            crate::branch::set_upstream(&repo, &branch, "origin", &branch)?;
+        }
+        (None, Some(head)) => repo.detach_head(&head, &reason)?,
+        (Some(branch), None) => repo.attach_head(&format!("refs/heads/{branch}"), &reason)?,
+        (None, None) => {}
+    }
+
+    if let Ok(head) = repo.read_head_commit() {
+        let commit_id = repo.read_object(&head)?.try_as_commit_id()?;
+        crate::checkout::checkout_commit(&mut repo, commit_id)?;
+    }
+
+    println!("Cloned into '{}'", dest.display());
+    Ok(())
+}
+
+/// Copy the objects of every branch and tag of `remote` that we lack, and point
+/// `refs/remotes/<remote>/*` at its branches. Tags are only created, never moved.
+pub fn fetch(repo: &mut Repository, remote: &str) -> Result<()> {
+    let remote = resolve_remote(repo, remote)?;
+    let peer   = Peer::connect(&remote.location)?;
+    fetch_from(repo, &remote, peer)?;
+    Ok(())
+}
+
+/// `fetch` from an already connected `peer`. Returns what it advertised.
+pub fn fetch_from(repo: &mut Repository, remote: &Remote, peer: Peer) -> Result<Advertisement> {
+    let (refs, tags, copied) = match peer {
+        Peer::Local(source) => {
+            let refs   = crate::protocol::advertise(&source)?;
+            let tags   = new_tags(repo, &refs);
+            let tips   = refs.heads.iter().chain(&tags).map(|(_, hash)| *hash).collect::<Vec<_>>();
+            let copied = transfer_objects(&source, repo, &tips)?;
+            (refs, tags, copied)
+        }
+        Peer::Pipe(mut conn) => {
+            let refs   = crate::protocol::hello(&mut conn, Service::Fetch)?;
+            let tags   = new_tags(repo, &refs);
+            let tips   = refs.heads.iter().chain(&tags).map(|(_, hash)| *hash).collect::<Vec<_>>();
+            let copied = crate::protocol::fetch_pack(&mut conn, repo, &tips)?;
+            conn.finish()?;
+            (refs, tags, copied)
+        }
+    };
+
+    println!("From {}", remote.location);
+
+    let Some(name) = &remote.name else {
+        println!("fetched {copied} object(s)");
+        return Ok(refs);
+    };
+
+    let reason = format!("fetch: from {}", remote.location);
+    for (branch, hash) in &refs.heads {
+        let tracking = format!("refs/remotes/{name}/{branch}");
+        match repo.read_ref(&tracking).ok() {
+            Some(old) if old == *hash => continue,
+            Some(old) if repo.reachable_commits(hash).contains(&old) => {
+                println!("   {}..{} {branch} -> {name}/{branch}", &hash_to_hex(&old)[..8], &hash_to_hex(hash)[..8]);
+            }
+            Some(old) => {
+                println!(" + {}...{} {branch} -> {name}/{branch} (forced update)", &hash_to_hex(&old)[..8], &hash_to_hex(hash)[..8]);
+            }
+            None => println!(" * [new branch] {branch} -> {name}/{branch}"),
+        }
+        repo.write_ref(&tracking, hash, &reason)?;
+    }
+
+    for (tag, hash) in &tags {
+        repo.write_ref(&format!("refs/tags/{tag}"), hash, &reason)?;
+        println!(" * [new tag] {tag}");
+    }
+
+    //
+    // Branches deleted on the other side.
+    //
+    for (branch, _) in list_refs(repo, &format!("refs/remotes/{name}"))? {
+        if !refs.heads.iter().any(|(b, _)| *b == branch) {
+            repo.delete_ref(&format!("refs/remotes/{name}/{branch}"), &reason)?;
+            println!(" - [deleted] {name}/{branch}");
+        }
+    }
+
+    Ok(refs)
+}
+
+/// Tags `refs` has that we don't. Existing tags are never moved by a fetch.
+#[inline]
+fn new_tags(repo: &Repository, refs: &Advertisement) -> Vec<(String, Hash)> {
+    refs.tags.iter()
+        .filter(|(name, _)| repo.read_ref(&format!("refs/tags/{name}")).is_err())
+        .cloned()
+        .collect()
+}
+
+/// Send `branch` and the objects it needs to `remote`. Only fast-forwards unless `force`.
+/// If the branch is checked out over there, its working tree (which must be clean) moves along.
+/// With `set_upstream`, `branch` then tracks the branch it was pushed to.
+pub fn push(repo: &mut Repository, remote: &str, branch: &str, force: bool, set_upstream: bool) -> Result<()> {
+    let remote = resolve_remote(repo, remote)?;
+    let peer   = Peer::connect(&remote.location)?;
+    push_to(repo, &remote, peer, branch, force)?;
+
+    if set_upstream {
+        let Some(name) = &remote.name else {
+            bail!("can only track branches of a named remote, not '{}'", remote.location);
+        };
+        crate::branch::set_upstream(repo, branch, name, branch)?;
+        println!("branch '{branch}' now tracks '{name}/{branch}'");
+    }
+    Ok(())
+}
+
+/// `push` to an already connected `peer`.
+pub fn push_to(repo: &mut Repository, remote: &Remote, peer: Peer, branch: &str, force: bool) -> Result<()> {
+    let refname = format!("refs/heads/{branch}");
+    let Ok(new) = repo.read_ref(&refname) else {
+        bail!("no local branch '{branch}'");
+    };
+
+    let (refs, mut peer) = match peer {
+        Peer::Local(target) => (crate::protocol::advertise(&target)?, Peer::Local(target)),
+        Peer::Pipe(mut conn) => (crate::protocol::hello(&mut conn, Service::Push)?, Peer::Pipe(conn)),
+    };
+    let old = refs.heads.iter().find(|(b, _)| b == branch).map(|(_, hash)| *hash);
+
+    if old == Some(new) {
+        println!("Everything up-to-date");
+        return match peer {
+            Peer::Local(_)  => Ok(()),
+            Peer::Pipe(conn) => conn.finish(),
+        };
+    }
+
+    //
+    // Checked here too so a rejected push doesn't send anything. We can only tell it's a
+    // fast-forward if we have their commit; if we don't, they have commits we don't.
+    //
+    let fast_forward = old.is_none_or(|old| repo.storage.exists(&old) && repo.reachable_commits(&new).contains(&old));
+    if !fast_forward && !force {
+        bail!(
+            "rejected: '{branch}' on {} has commits you don't have (fetch and merge first, or push --force)",
+            remote.location
+        );
+    }
+
+    let reason = format!("push: from {}", repo.root.display());
+    match &mut peer {
+        Peer::Local(target) => {
+            transfer_objects(repo, target, &[new])?;
+            update_pushed_branch(target, branch, old, new, force, &reason)?;
+        }
+        Peer::Pipe(conn) => crate::protocol::push_pack(conn, repo, &refs, branch, old, new, force)?,
+    }
+    if let Peer::Pipe(conn) = peer {
+        conn.finish()?;
+    }
+
+    if let Some(name) = &remote.name {
+        repo.write_ref(&format!("refs/remotes/{name}/{branch}"), &new, "update by push")?;
+    }
+
+    println!("To {}", remote.location);
+    match old {
+        Some(old) if fast_forward => println!("   {}..{} {branch} -> {branch}", &hash_to_hex(&old)[..8], &hash_to_hex(&new)[..8]),
+        Some(old) => println!(" + {}...{} {branch} -> {branch} (forced update)", &hash_to_hex(&old)[..8], &hash_to_hex(&new)[..8]),
+        None      => println!(" * [new branch] {branch} -> {branch}"),
+    }
+    Ok(())
+}
+
+/// The receiving end of a push, once the objects are stored: point `branch` from `old` at `new`.
+/// Fails if the branch moved since the pusher looked, or if it isn't a fast-forward and not `force`.
+/// If the branch is checked out, the working tree must be clean and is moved along.
+pub fn update_pushed_branch(target: &mut Repository, branch: &str, old: Option<Hash>, new: Hash, force: bool, reason: &str) -> Result<()> {
+    crate::branch::validate_branch_name(branch)?;
+    if target.read_object(&new).and_then(crate::object::Object::try_as_commit_id).is_err() {
+        bail!("rejected: {} is not a commit", &hash_to_hex(&new)[..8]);
+    }
+
+    let refname = format!("refs/heads/{branch}");
+    if target.read_ref(&refname).ok() != old {
+        bail!("rejected: '{branch}' changed while pushing, fetch and try again");
+    }
+    if let Some(old) = old {
+        if !force && !target.reachable_commits(&new).contains(&old) {
+            bail!("rejected: '{branch}' is not a fast-forward (fetch and merge first, or push --force)");
+        }
+    }
+
+    if target.current_branch()?.as_deref() == Some(branch) {
+        crate::merge::ensure_clean_worktree(target, "push into a checked out branch")?;
+        let commit_id = target.read_object(&new)?.try_as_commit_id()?;
+        crate::checkout::checkout_commit(target, commit_id)?;
+        target.update_head(&new, reason)
+    } else {
+        target.write_ref(&refname, &new, reason)
+    }
+}
+
+/// Single-quote `s` for a POSIX shell.
+#[inline]
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+/// Copy everything reachable from `tips` in `from` that `to` lacks, verifying each object's hash.
+/// Returns how many objects were copied.
+pub fn transfer_objects(from: &Repository, to: &mut Repository, tips: &[Hash]) -> Result<usize> {
+    let missing = missing_objects(from, tips, |hash| to.storage.exists(hash))?;
+
+    let mut batch       = Vec::<(Hash, Cow<'_, [u8]>)>::new();
+    let mut batch_bytes = 0;
+    for (i, hash) in missing.iter().enumerate() {
+        let data = from.storage.read(hash)?;
+        if hash_bytes(&data) != *hash {
+            bail!("object {} is corrupt in {}", hash_to_hex(hash), from.root.display());
+        }
+
+        batch_bytes += data.len();
+        batch.push((*hash, data));
+
+        if batch_bytes >= TRANSFER_BATCH_BYTES || i + 1 == missing.len() {
+            to.storage.write_batch(batch.iter().map(|(hash, data)| (*hash, data.as_ref())))?;
+            batch.clear();
+            batch_bytes = 0;
+        }
+    }
+
+    to.storage.flush()?;
+    to.storage.remap()?;
+    Ok(missing.len())
+}
+
+/// Objects reachable from `tips` in `from` for which `have` is false. Whatever `have` reports
+/// present is assumed complete (everything it refers to is there too), so the walk stops at it.
+pub fn missing_objects(from: &Repository, tips: &[Hash], have: impl Fn(&Hash) -> bool) -> Result<Vec<Hash>> {
+    let mut missing = Vec::new();
+    let mut visited = Xxh3HashSet::default();
+    let mut stack   = tips.to_vec();
+
+    while let Some(hash) = stack.pop() {
+        if !visited.insert(hash) || have(&hash) {
+            continue;
+        }
+
+        let data = from.storage.read(&hash)
+            .map_err(|_| anyhow::anyhow!("object {} is missing from {}", hash_to_hex(&hash), from.root.display()))?;
+        crate::object::push_object_children(&data, &mut stack)?;
+        missing.push(hash);
+    }
+
+    Ok(missing)
+}
+
+/// (name, hash) of every ref directly under `dir` (e.g. `refs/heads`), sorted by name.
+pub fn list_refs(repo: &Repository, dir: &str) -> Result<Vec<(String, Hash)>> {
+    let Ok(entries) = fs::read_dir(repo.root.join(".mog").join(dir)) else {
+        return Ok(Vec::new());
+    };
+
+    let mut refs = Vec::new();
+    for entry in entries.filter_map(Result::ok) {
+        let Ok(name) = entry.file_name().into_string() else { continue };
+        if !entry.path().is_file() || name.ends_with(crate::lockfile::LOCK_SUFFIX) {
+            continue;
+        }
+        refs.push((name.clone(), repo.read_ref(&format!("{dir}/{name}"))?));
+    }
+
+    refs.sort_unstable();
+    Ok(refs)
+}
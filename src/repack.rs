@@ -0,0 +1,85 @@
+use crate::hash::Hash;
+use crate::repository::Repository;
+use crate::storage::MAX_DELTA_DEPTH;
+use crate::util::{Xxh3HashMap, Xxh3HashSet};
+
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+
+/// Re-encode the blobs of every file's history as delta chains and rewrite `objects.bin`.
+/// Nothing is dropped, that is `gc`'s job.
+pub fn repack(repo: &mut Repository) -> Result<()> {
+    repo.storage.flush()?;
+    repo.storage.remap()?;
+
+    let plan  = delta_plan(repo)?;
+    let stats = repo.storage.repack(&plan)?;
+
+    println!(
+        "repacked {} objects, {} stored as deltas, {} -> {} bytes",
+        stats.objects,
+        stats.deltas,
+        stats.bytes_before,
+        stats.bytes_after
+    );
+
+    Ok(())
+}
+
+/// (blob, delta base) pairs, bases first. Per path, the newest version is stored whole and each
+/// older one against the version after it, since recent versions are the ones read most.
+pub fn delta_plan(repo: &mut Repository) -> Result<Vec<(Hash, Option<Hash>)>> {
+    //
+    // All commits in history, newest first.
+    //
+    let mut commits = Xxh3HashSet::default();
+    for root in crate::gc::history_roots(repo)? {
+        if let Ok((commit, _)) = repo.peel_to_commit(&root) {
+            if !commits.contains(&commit) {
+                commits.extend(repo.reachable_commits(&commit));
+            }
+        }
+    }
+
+    let mut by_time = Vec::with_capacity(commits.len());
+    for hash in commits {
+        let commit_id = repo.read_object(&hash)?.try_as_commit_id()?;
+        by_time.push((repo.commit.get_timestamp(commit_id), hash));
+    }
+    by_time.sort_unstable_by(|a, b| b.cmp(a));
+
+    //
+    // Distinct versions of each path, newest first.
+    //
+    let mut versions = BTreeMap::<Box<str>, Vec<Hash>>::new();
+    for (_, commit) in by_time {
+        let flat = crate::merge::flatten_commit(repo, &commit)?;
+        for i in 0..flat.len() {
+            let chain = versions.entry(flat.get_path(i).into()).or_default();
+            if chain.last() != Some(&flat.hashes[i]) {
+                chain.push(flat.hashes[i]);
+            }
+        }
+    }
+
+    //
+    // A blob shared by several paths (or coming back after a revert) is planned once, the first
+    // time it is seen, so every base is planned before its dependents and chains can't loop.
+    //
+    let mut depths = Xxh3HashMap::<Hash, u8>::default();
+    let mut plan   = Vec::new();
+    for chain in versions.values() {
+        let mut newer = None;
+        for &hash in chain {
+            if !depths.contains_key(&hash) {
+                let base = newer.filter(|base| depths[base] < MAX_DELTA_DEPTH);
+                depths.insert(hash, base.map_or(0, |base| depths[&base] + 1));
+                plan.push((hash, base));
+            }
+            newer = Some(hash);
+        }
+    }
+
+    Ok(plan)
+}
@@ -1,17 +1,21 @@
 use crate::cache::ObjectCache;
 use crate::ignore::Ignore;
+use crate::lockfile::{write_atomic, LockFile};
 use crate::storage::{MogStorage, Storage};
 use crate::object::{encode_blob_and_hash, hash_object, Object};
 use crate::storage_mock::MockStorage;
 use crate::store::{CommitId, Stores};
 use crate::hash::{Hash, hash_to_hex, hex_to_hash};
+use crate::reflog::ZERO_HASH;
 use crate::tree::TreeEntry;
 use crate::util::Xxh3HashSet;
 
+use std::borrow::Cow;
+use std::io::Write;
 use std::ops::{Deref, DerefMut};
 use std::path::{Path, PathBuf};
 
-use anyhow::{Result, bail};
+use anyhow::{Context, Result, bail};
 
 pub struct Repository<S: MogStorage = Storage> {
     pub root: Box<Path>,
@@ -40,6 +44,7 @@ impl Repository<Storage> {
         std::fs::create_dir_all(&mog_dir)?;
         std::fs::create_dir_all(mog_dir.join("refs/heads"))?;
         std::fs::create_dir_all(mog_dir.join("refs/remotes"))?;
+        std::fs::create_dir_all(mog_dir.join("refs/tags"))?;
 
         std::fs::write(
             mog_dir.join("HEAD"),
@@ -83,6 +88,8 @@ target/\n\
             bail!("not a mog repository");
         }
 
+        crate::lockfile::ensure_no_stale_locks(&mog_dir)?;
+
         let root = path.canonicalize()?.into_boxed_path();
         Ok(Self {
             ignore: Ignore::load(&root)?,
@@ -115,30 +122,44 @@ impl<S: MogStorage> Repository<S> {
             return self.stores.decode_and_push_object(cached);
         }
         let data = self.storage.read(hash)?;
-        let object = self.stores.decode_and_push_object(data)?;
-        self.object_cache.insert(*hash, data.to_vec()); // @Clone
+        let object = self.stores.decode_and_push_object(&data)?;
+        self.object_cache.insert(*hash, data.into_owned()); // @Clone when stored raw
         Ok(object)
     }
 
     #[inline]
     pub fn read_object_without_touching_cache(&mut self, hash: &Hash) -> Result<Object> {
         let data = self.storage.read(hash)?;
-        let object = self.stores.decode_and_push_object(data)?; // @Incomplete: Don't push to stores
+        let object = self.stores.decode_and_push_object(&data)?; // @Incomplete: Don't push to stores
         Ok(object)
     }
 
     #[inline]
     pub fn read_tree_entries_without_touching_cache(&self, hash: &Hash) -> Result<Box<[TreeEntry]>> {
         let data = self.storage.read(hash)?;
-        crate::object::decode_tree_entries(data)
+        crate::object::decode_tree_entries(&data)
     }
 
+    /// File contents of a blob or chunk list, borrowed from the mmap when it is a blob stored raw.
     #[inline]
-    pub fn read_blob_bytes_without_touching_cache(&self, hash: &Hash) -> Result<&[u8]> {
-        let data = self.storage.read(hash)?;
-        crate::object::decode_blob_bytes(data)
+    pub fn read_blob_bytes_without_touching_cache(&self, hash: &Hash) -> Result<Cow<'_, [u8]>> {
+        let raw = self.storage.read(hash)?;
+        if let Some(list) = crate::chunk::decode_chunk_list(&raw)? {
+            let mut data = Vec::with_capacity(list.size as usize);
+            for chunk in &list.hashes {
+                let raw = self.storage.read(chunk)?;
+                data.extend_from_slice(crate::object::decode_blob_bytes(&raw)?);
+            }
+            return Ok(Cow::Owned(data));
+        }
+
+        match raw {
+            Cow::Borrowed(data) => crate::object::decode_blob_bytes(data).map(Cow::Borrowed),
+            Cow::Owned(data)    => Ok(Cow::Owned(crate::object::decode_blob_bytes(&data)?.to_vec())), // @Clone
+        }
     }
 
+    /// Chunked files are put back together in memory first, prefer `for_each_file_chunk` for large ones.
     #[inline]
     pub fn with_blob_bytes_without_touching_cache_and_evict_the_pages<T, E: Into<anyhow::Error>>(
         &self,
@@ -146,19 +167,83 @@ impl<S: MogStorage> Repository<S> {
         callback: impl FnOnce(&Self, &[u8]) -> std::result::Result<T, E>
     ) -> Result<T> {
         let raw = self.storage.read(hash)?;
-        let data = crate::object::decode_blob_bytes(raw)?;
+        if crate::chunk::decode_chunk_list(&raw)?.is_some() {
+            let data = self.read_blob_bytes_without_touching_cache(hash)?;
+            return callback(self, &data).map_err(Into::into);
+        }
+
+        let data = crate::object::decode_blob_bytes(&raw)?;
         let result = callback(self, data);
 
-        Storage::evict_pages(raw);
+        if let Cow::Borrowed(raw) = raw {
+            S::evict_pages(raw);
+        }
 
-        result.map_err(|e| e.into())
+        result.map_err(Into::into)
+    }
+
+    /// Call `f` with a file's contents piece by piece: once for a blob, once per chunk for a chunk list.
+    /// Pages are evicted as soon as `f` is done with them, so large files never sit in memory.
+    pub fn for_each_file_chunk(&self, hash: &Hash, mut f: impl FnMut(&[u8]) -> Result<()>) -> Result<()> {
+        let raw = self.storage.read(hash)?;
+        let Some(list) = crate::chunk::decode_chunk_list(&raw)? else {
+            let result = f(crate::object::decode_blob_bytes(&raw)?);
+            if let Cow::Borrowed(raw) = raw {
+                S::evict_pages(raw);
+            }
+            return result;
+        };
+
+        for chunk in &list.hashes {
+            let raw = self.storage.read(chunk)?;
+            let result = f(crate::object::decode_blob_bytes(&raw)?);
+            if let Cow::Borrowed(raw) = raw {
+                S::evict_pages(raw);
+            }
+            result?;
+        }
+
+        Ok(())
+    }
+
+    /// Write the contents of a blob or chunk list to `path`, streaming chunk by chunk.
+    /// A symlink already at `path` is replaced, never written through.
+    #[inline]
+    pub fn write_blob_to_file(&self, hash: &Hash, path: &Path) -> Result<()> {
+        if std::fs::symlink_metadata(path).is_ok_and(|m| m.is_symlink()) {
+            std::fs::remove_file(path)?;
+        }
+
+        let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        self.for_each_file_chunk(hash, |data| Ok(file.write_all(data)?))?;
+        file.flush()?;
+        Ok(())
+    }
+
+    /// Check out a tree entry to `path`: a `MODE_LINK` blob becomes a symlink to the target
+    /// it holds, anything else a regular file, executable for `MODE_EXEC`.
+    pub fn write_entry_to_file(&self, hash: &Hash, mode: u32, path: &Path) -> Result<()> {
+        if mode != crate::object::MODE_LINK {
+            self.write_blob_to_file(hash, path)?;
+            crate::util::set_executable(path, mode == crate::object::MODE_EXEC)?;
+            return Ok(());
+        }
+
+        let mut target = Vec::new();
+        self.for_each_file_chunk(hash, |data| { target.extend_from_slice(data); Ok(()) })?;
+
+        if std::fs::symlink_metadata(path).is_ok_and(|m| !m.is_dir()) {
+            std::fs::remove_file(path)?;
+        }
+        crate::util::create_symlink(&target, path)?;
+        Ok(())
     }
 
     #[inline]
     pub fn read_blob_bytes_without_touching_stores(&mut self, hash: &Hash) -> Result<&[u8]> {
         if !self.object_cache.contains(hash) {
             let data = self.storage.read(hash)?;
-            self.object_cache.insert(*hash, data.to_vec());
+            self.object_cache.insert(*hash, data.into_owned());
         }
         let cached = self.object_cache.get(hash).unwrap();
         crate::object::decode_blob_bytes(cached)
@@ -192,16 +277,67 @@ impl<S: MogStorage> Repository<S> {
         hex_to_hash(content.trim())
     }
 
+    /// Point `refname` at `hash`, recording the move in its reflog.
     #[inline]
-    pub fn write_ref(&self, refname: &str, hash: &Hash) -> Result<()> {
+    pub fn write_ref(&self, refname: &str, hash: &Hash, reason: &str) -> Result<()> {
         let path = self.root.join(".mog").join(refname);
-
         if let Some(parent) = path.parent() {
             std::fs::create_dir_all(parent)?;
         }
 
-        std::fs::write(path, format!("{}\n", hash_to_hex(hash)))?;
-        Ok(())
+        //
+        // Read the old value under the lock so a concurrent writer can't slip in between.
+        //
+        let mut lock = LockFile::acquire(&path)?;
+        let old = self.read_ref(refname).unwrap_or(ZERO_HASH);
+
+        lock.write_all(format!("{}\n", hash_to_hex(hash)).as_bytes())?;
+        lock.commit()?;
+        crate::reflog::append(self, refname, &old, hash, reason)
+    }
+
+    /// Remove `refname`. Its reflog is kept (ending in a deletion) so the old tip stays recoverable.
+    #[inline]
+    pub fn delete_ref(&self, refname: &str, reason: &str) -> Result<()> {
+        let path = self.root.join(".mog").join(refname);
+        let _lock = LockFile::acquire(&path)?;
+
+        let old = self.read_ref(refname)?;
+        std::fs::remove_file(path)?;
+        crate::reflog::append(self, refname, &old, &ZERO_HASH, reason)
+    }
+
+    /// Move HEAD to `hash`: updates the checked-out branch, or HEAD itself when detached.
+    #[inline]
+    pub fn update_head(&self, hash: &Hash, reason: &str) -> Result<()> {
+        let old  = self.read_head_commit().unwrap_or(ZERO_HASH);
+        let head = std::fs::read_to_string(self.root.join(".mog/HEAD"))?;
+
+        if let Some(refpath) = head.trim().strip_prefix("ref: ") {
+            self.write_ref(refpath.trim(), hash, reason)?;
+        } else {
+            write_atomic(&self.root.join(".mog/HEAD"), format!("{}\n", hash_to_hex(hash)))?;
+        }
+
+        crate::reflog::append(self, "HEAD", &old, hash, reason)
+    }
+
+    /// Point HEAD at the branch ref `refname` (e.g. `refs/heads/main`).
+    #[inline]
+    pub fn attach_head(&self, refname: &str, reason: &str) -> Result<()> {
+        let old = self.read_head_commit().unwrap_or(ZERO_HASH);
+        write_atomic(&self.root.join(".mog/HEAD"), format!("ref: {refname}\n"))?;
+
+        let new = self.read_ref(refname).unwrap_or(ZERO_HASH);
+        crate::reflog::append(self, "HEAD", &old, &new, reason)
+    }
+
+    /// Detach HEAD at `hash`.
+    #[inline]
+    pub fn detach_head(&self, hash: &Hash, reason: &str) -> Result<()> {
+        let old = self.read_head_commit().unwrap_or(ZERO_HASH);
+        write_atomic(&self.root.join(".mog/HEAD"), format!("{}\n", hash_to_hex(hash)))?;
+        crate::reflog::append(self, "HEAD", &old, hash, reason)
     }
 
     /// Read the commit hash HEAD currently points to,
@@ -238,21 +374,23 @@ impl<S: MogStorage> Repository<S> {
         }
     }
 
-    /// Resolve branch or hex to (`commit_hash`, `CommitId`).
+    /// Resolve a revision expression (see [`crate::revision`]) to (`commit_hash`, `CommitId`).
     #[inline]
     pub fn resolve_to_commit(&mut self, target: &str) -> Result<(Hash, CommitId)> {
-        let branch_ref = format!("refs/heads/{target}");
-        let branch_path = self.root.join(".mog").join(&branch_ref);
-
-        let hash = if branch_path.exists() {
-            self.read_ref(&branch_ref)?
-        } else {
-            hex_to_hash(target)?
-        };
+        crate::revision::resolve_commit(self, target)
+    }
 
-        let object = self.read_object(&hash)?;
-        let commit_id = object.try_as_commit_id()?;
-        Ok((hash, commit_id))
+    /// Follow (possibly nested) annotated tags from `hash` down to the commit they point at.
+    #[inline]
+    pub fn peel_to_commit(&mut self, hash: &Hash) -> Result<(Hash, CommitId)> {
+        let mut hash = *hash;
+        loop {
+            match self.read_object(&hash)? {
+                Object::Commit(commit_id) => return Ok((hash, commit_id)),
+                Object::Tag(tag_id)       => hash = self.tag.get_target(tag_id),
+                _                         => bail!("not a commit"),
+            }
+        }
     }
 
     /// Walk commit graph from start, collecting reachable hashes.
@@ -278,8 +416,30 @@ impl<S: MogStorage> Repository<S> {
         visited
     }
 
-    /// Walk tree at `tree_hash` following path; return (Object, `entry_hash`).
-    pub fn walk_tree_path(&mut self, tree_hash: &Hash, path: &str) -> Result<(Object, Hash)> {
+    /// Walk everything reachable from `starts`: commits, tags, trees and blobs.
+    /// Decodes straight from storage, so the stores and object cache stay untouched.
+    /// Missing objects are skipped; they still end up in the returned set. An object that is
+    /// stored but can't be read or decoded is an error: its subgraph would be lost otherwise.
+    pub fn reachable_objects(&self, starts: impl IntoIterator<Item = Hash>) -> Result<Xxh3HashSet<Hash>> {
+        let mut visited = Xxh3HashSet::default();
+        let mut stack = starts.into_iter().collect::<Vec<_>>();
+
+        while let Some(hash) = stack.pop() {
+            if !visited.insert(hash) || !self.storage.exists(&hash) {
+                continue;
+            }
+
+            let data = self.storage.read(&hash)
+                .with_context(|| format!("can't read object {}", hash_to_hex(&hash)))?;
+            crate::object::push_object_children(&data, &mut stack)
+                .with_context(|| format!("object {} doesn't decode", hash_to_hex(&hash)))?;
+        }
+
+        Ok(visited)
+    }
+
+    /// Walk tree at `tree_hash` following path; return (Object, `entry_hash`, `entry_mode`).
+    pub fn walk_tree_path(&mut self, tree_hash: &Hash, path: &str) -> Result<(Object, Hash, u32)> {
         let object = self.read_object(tree_hash)?;
         let mut current_id = object.try_as_tree_id()?;
 
@@ -303,11 +463,11 @@ impl<S: MogStorage> Repository<S> {
         }
 
         let last = components[components.len() - 1];
-        let hash = self.tree
-            .find_entry(current_id, last)
+        let (hash, mode) = self.tree
+            .find_entry_with_mode(current_id, last)
             .ok_or_else(|| anyhow::anyhow!("path not found: '{last}'"))?;
 
         let object = self.read_object(&hash)?;
-        Ok((object, hash))
+        Ok((object, hash, mode))
     }
 }
@@ -0,0 +1,191 @@
+//! Revision expressions, shared by every command that takes a commit.
+//!
+//! ```text
+//! HEAD, @            current commit
+//! main, v1.0         branch, then tag
+//! refs/heads/main    full ref path
+//! 3fa9c1             unique abbreviated hash (4+ hex digits)
+//! main@{2}, @{1}     reflog entry
+//! <rev>~N            N-th first-parent ancestor
+//! <rev>^N            N-th parent (^0 is the commit itself)
+//! A..B               commits reachable from B but not A
+//! A...B              commits reachable from either but not both
+//! ```
+
+use crate::hash::{hash_has_hex_prefix, hex_to_hash, Hash};
+use crate::repository::Repository;
+use crate::storage::MogStorage;
+use crate::store::CommitId;
+use crate::util::Xxh3HashSet;
+
+use anyhow::{Result, bail};
+
+const MIN_PREFIX_LEN: usize = 4;
+
+/// A parsed revision expression: one commit, or a set of commits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RevRange {
+    Single(Hash),
+    /// `A..B`: reachable from `include`, minus reachable from `exclude`.
+    Range { exclude: Hash, include: Hash },
+    /// `A...B`: reachable from exactly one side.
+    Symmetric { left: Hash, right: Hash },
+}
+
+/// Parse `spec`, allowing `A..B` and `A...B`. An empty side means HEAD.
+pub fn parse_range(repo: &mut Repository<impl MogStorage>, spec: &str) -> Result<RevRange> {
+    if let Some((left, right)) = spec.split_once("...") {
+        return Ok(RevRange::Symmetric {
+            left:  resolve_commit(repo, or_head(left))?.0,
+            right: resolve_commit(repo, or_head(right))?.0,
+        });
+    }
+
+    if let Some((exclude, include)) = spec.split_once("..") {
+        return Ok(RevRange::Range {
+            exclude: resolve_commit(repo, or_head(exclude))?.0,
+            include: resolve_commit(repo, or_head(include))?.0,
+        });
+    }
+
+    Ok(RevRange::Single(resolve_commit(repo, spec)?.0))
+}
+
+/// Resolve `spec` to a single commit, peeling annotated tags.
+pub fn resolve_commit(repo: &mut Repository<impl MogStorage>, spec: &str) -> Result<(Hash, CommitId)> {
+    if spec.contains("..") {
+        bail!("'{spec}' is a range, expected a single revision");
+    }
+
+    let hash = resolve_object(repo, spec)?;
+    repo.peel_to_commit(&hash)
+}
+
+/// Resolve `spec` to an object hash. Tags are only peeled when a `~`/`^` suffix needs a commit.
+pub fn resolve_object(repo: &mut Repository<impl MogStorage>, spec: &str) -> Result<Hash> {
+    if spec.is_empty() {
+        bail!("empty revision");
+    }
+
+    //
+    // Split the base name from the `~N` / `^N` navigation suffix.
+    // `@{N}` only ever appears in the base, and neither `~` nor `^` can appear inside it.
+    //
+    let split = spec.find(['~', '^']).unwrap_or(spec.len());
+    let (base, mut suffix) = spec.split_at(split);
+
+    let mut hash = resolve_base(repo, base)
+        .map_err(|e| anyhow::anyhow!("unknown revision '{spec}': {e}"))?;
+
+    while let Some(op) = suffix.chars().next() {
+        suffix = &suffix[1..];
+
+        let digits = suffix.find(|c: char| !c.is_ascii_digit()).unwrap_or(suffix.len());
+        let n = if digits == 0 { 1 } else { suffix[..digits].parse::<usize>()? };
+        suffix = &suffix[digits..];
+
+        let (commit, mut commit_id) = repo.peel_to_commit(&hash)?;
+        hash = commit;
+
+        match op {
+            '~' => for _ in 0..n {
+                let Some(parent) = repo.commit.get_parents(commit_id).first().copied() else {
+                    bail!("'{spec}': history is not that deep");
+                };
+                hash      = parent;
+                commit_id = repo.read_object(&hash)?.try_as_commit_id()?;
+            }
+            '^' => if n > 0 {
+                let Some(parent) = repo.commit.get_parents(commit_id).get(n - 1).copied() else {
+                    bail!("'{spec}': commit {} has no parent #{n}", &crate::hash::hash_to_hex(&hash)[..8]);
+                };
+                hash = parent;
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    Ok(hash)
+}
+
+/// All commits selected by `range`, newest first (single revisions select their whole history).
+pub fn range_commits(repo: &mut Repository<impl MogStorage>, range: &RevRange) -> Result<Vec<Hash>> {
+    let mut commits = match *range {
+        RevRange::Single(start) => repo.reachable_commits(&start).into_iter().collect::<Vec<_>>(),
+        RevRange::Range { exclude, include } => {
+            let excluded = repo.reachable_commits(&exclude);
+            repo.reachable_commits(&include)
+                .into_iter()
+                .filter(|h| !excluded.contains(h))
+                .collect()
+        }
+        RevRange::Symmetric { left, right } => {
+            let left  = repo.reachable_commits(&left);
+            let right = repo.reachable_commits(&right);
+            left.symmetric_difference(&right).copied().collect()
+        }
+    };
+
+    let mut keyed = Vec::with_capacity(commits.len());
+    for hash in commits.drain(..) {
+        let commit_id = repo.read_object(&hash)?.try_as_commit_id()?;
+        keyed.push((repo.commit.get_timestamp(commit_id), hash));
+    }
+    keyed.sort_unstable_by(|a, b| b.cmp(a));
+
+    Ok(keyed.into_iter().map(|(_, h)| h).collect())
+}
+
+//
+//
+// Helpers
+//
+//
+
+#[inline]
+fn or_head(side: &str) -> &str {
+    if side.is_empty() { "HEAD" } else { side }
+}
+
+fn resolve_base(repo: &mut Repository<impl MogStorage>, base: &str) -> Result<Hash> {
+    if base == "HEAD" || base == "@" {
+        return repo.read_head_commit().map_err(|_| anyhow::anyhow!("HEAD has no commits yet"));
+    }
+
+    if let Some((name, n)) = crate::reflog::parse_selector(base) {
+        return crate::reflog::nth_entry(repo, &crate::reflog::refname_for(name), n);
+    }
+
+    let mog_dir = repo.root.join(".mog");
+    for refname in [base.to_owned(), format!("refs/heads/{base}"), format!("refs/tags/{base}"), format!("refs/remotes/{base}")] {
+        if refname.starts_with("refs/") && mog_dir.join(&refname).is_file() {
+            return repo.read_ref(&refname);
+        }
+    }
+
+    if base.len() == 64 {
+        return hex_to_hash(base);
+    }
+
+    if base.len() >= MIN_PREFIX_LEN && base.chars().all(|c| c.is_ascii_hexdigit()) {
+        return resolve_prefix(repo, base);
+    }
+
+    bail!("not a branch, tag or hash")
+}
+
+fn resolve_prefix(repo: &Repository<impl MogStorage>, prefix: &str) -> Result<Hash> {
+    let mut matches = Xxh3HashSet::default();
+    repo.storage.for_each_hash(|hash| {
+        if hash_has_hex_prefix(hash, prefix) {
+            matches.insert(*hash);
+        }
+    });
+
+    let mut matches = matches.into_iter();
+    match (matches.next(), matches.next()) {
+        (Some(hash), None) => Ok(hash),
+        (None, _)          => bail!("no object starts with '{prefix}'"),
+        (Some(_), Some(_)) => bail!("short hash '{prefix}' is ambiguous, use more digits"),
+    }
+}
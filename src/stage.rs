@@ -16,14 +16,12 @@ use walkdir::WalkDir;
 use regex::Regex;
 
 const STAGE_BATCH_MAX_BYTES: usize = 1024 * 1024;
-const STAGE_MAX_FILE_BYTES:  usize = 1024 * 1024;
 
 pub fn stage(repo: &mut Repository, paths: &[PathBuf]) -> Result<()> {
     let _span = tracy::span!("stage");
 
     let staged_successfully        = AtomicUsize::new(0); // @Metric
     let bytes_staged_successfully  = AtomicUsize::new(0); // @Metric
-    let mut refused_over_limit     = 0; // @Metric
 
     let current_dir = &repo.root;
     let mut index   = Index::load(current_dir)?;
@@ -36,7 +34,7 @@ pub fn stage(repo: &mut Repository, paths: &[PathBuf]) -> Result<()> {
 
     let default = [PathBuf::from(".")];
     let patterns = if paths.is_empty() { &default } else { paths };
-    let (literal_roots, combined_re) = classify_patterns(patterns, &current_dir);
+    let (literal_roots, combined_re) = classify_patterns(patterns, current_dir);
 
     //
     //
@@ -48,18 +46,19 @@ pub fn stage(repo: &mut Repository, paths: &[PathBuf]) -> Result<()> {
 
     //
     //
-    // Filter to dirty files within the size limit.
+    // Filter to dirty files. Large ones are chunked separately.
     //
     //
 
     let mut files_to_process = Vec::<FileMeta>::new();
+    let mut large_files      = Vec::<FileMeta>::new();
 
     for (path, rel_norm_string) in files_to_stage {
         if repo.ignore.is_ignored_rel(&rel_norm_string) {
             continue;
         }
 
-        let metadata = match fs::metadata(&path) {
+        let metadata = match fs::symlink_metadata(&path) {
             Ok(m)  => m,
             Err(e) => {
                 eprintln!("metadata error for {}: {}", path.display(), e);
@@ -67,28 +66,30 @@ pub fn stage(repo: &mut Repository, paths: &[PathBuf]) -> Result<()> {
             }
         };
 
-        if metadata.len() > STAGE_MAX_FILE_BYTES as u64 {
-            refused_over_limit += 1;
-            continue;
-        }
-
+        //
+        // The staged version is the natural delta base for the new one.
+        //
+        let mut base = None;
         if let Some(i) = index.find(rel_norm_string.as_ref()) {
             if !index.is_dirty(i, &metadata) {
                 continue;
             }
+            base = Some(index.hashes[i]);
         }
 
-        files_to_process.push(FileMeta {
+        let is_large = metadata.len() > crate::chunk::CHUNK_THRESHOLD;
+        let file = FileMeta {
             path: path,
             rel_norm: PathBuf::from(rel_norm_string.into_string()).into(),
             meta: metadata,
-        });
-    }
+            base,
+        };
 
-    if refused_over_limit > 0 {
-        eprintln!(
-            "Refused to stage {refused_over_limit} file(s) over 1 MiB (max {STAGE_MAX_FILE_BYTES} bytes)",
-        );
+        if is_large {
+            large_files.push(file);
+        } else {
+            files_to_process.push(file);
+        }
     }
 
     //
@@ -100,16 +101,15 @@ pub fn stage(repo: &mut Repository, paths: &[PathBuf]) -> Result<()> {
     let removed_successfully = {
         let mut to_remove = Vec::new();
         for i in 0..index.count {
+            //
+            // Not `exists()`: that follows links, and a dangling symlink is still tracked.
+            //
             let abs = repo.root.join(index.get_path(i));
-            if !abs.exists() {
+            if fs::symlink_metadata(&abs).is_err() {
                 to_remove.push(index.get_path(i).to_owned());
             }
         }
-        for path in &to_remove {
-            index.remove(path);
-        }
-
-        to_remove.len()
+        index.remove_many(&to_remove)
     };
 
     //
@@ -137,7 +137,12 @@ pub fn stage(repo: &mut Repository, paths: &[PathBuf]) -> Result<()> {
         // Read, encode, and hash in parallel.
         //
         let processed = batch.into_par_iter().filter_map(|file| {
-            let data = match fs::read(&file.path) {
+            let data = if file.meta.is_symlink() {
+                crate::util::read_symlink(&file.path)
+            } else {
+                fs::read(&file.path)
+            };
+            let data = match data {
                 Ok(d)  => d,
                 Err(e) => {
                     eprintln!("read error for {}: {}", file.path.display(), e);
@@ -173,13 +178,35 @@ pub fn stage(repo: &mut Repository, paths: &[PathBuf]) -> Result<()> {
             let offset = encoded_buf.len() as u32;
             let len    = encoded.len() as u32;
             encoded_buf.extend_from_slice(&encoded);
-            file_infos.push(FileInfo { hash, offset, len });
+            file_infos.push(FileInfo { hash, offset, len, base: file_meta.base });
             file_metas.push(file_meta);
         }
 
         flush_batch(repo, &mut index, &encoded_buf, &file_infos, &file_metas)?;
     }
 
+    //
+    //
+    // Large files: streamed through the chunker one at a time, never fully in memory.
+    //
+    //
+
+    for file in &large_files {
+        let _span = tracy::span!("stage::large_file");
+
+        let hash = match crate::chunk::write_file(repo, &file.path) {
+            Ok(h)  => h,
+            Err(e) => {
+                eprintln!("read error for {}: {}", file.path.display(), e);
+                continue;
+            }
+        };
+
+        index.add(file.rel_norm.to_str().unwrap(), hash, &file.meta);
+        staged_successfully.fetch_add(1, Ordering::Relaxed);
+        bytes_staged_successfully.fetch_add(file.meta.len() as usize, Ordering::Relaxed);
+    }
+
     repo.storage.sync()?;
     index.save(&repo.root)?;
 
@@ -217,11 +244,11 @@ pub fn classify_patterns(
             Cow::Owned(current_dir.join(p))
         };
 
-        if candidate.exists() {
+        if fs::symlink_metadata(&*candidate).is_ok() {
             //
             // Canonicalize once here so we don't repeat it per-file in the walk.
             //
-            match candidate.canonicalize() {
+            match canonicalize_no_follow(&candidate) {
                 Ok(canon) => literal_roots.push(canon),
                 Err(e)    => eprintln!("Cannot canonicalize '{}': {}", candidate.display(), e),
             }
@@ -248,7 +275,20 @@ pub fn classify_patterns(
     (literal_roots, combined_re)
 }
 
-/// Walk repo, returning (`abs_path`, `rel_norm_string`) for every non-ignored file
+/// Canonicalize `path` without resolving it when it is itself a symlink, so naming a
+/// link stages the link rather than whatever it points to.
+fn canonicalize_no_follow(path: &Path) -> std::io::Result<PathBuf> {
+    let is_link = fs::symlink_metadata(path)?.is_symlink();
+    match (is_link, path.parent(), path.file_name()) {
+        (true, Some(parent), Some(name)) => {
+            let parent = if parent.as_os_str().is_empty() { Path::new(".") } else { parent };
+            Ok(parent.canonicalize()?.join(name))
+        }
+        _ => path.canonicalize(),
+    }
+}
+
+/// Walk repo, returning (`abs_path`, `rel_norm_string`) for every non-ignored file or symlink
 /// that matches `literal_roots` or `combined_re`.
 #[must_use]
 pub fn walk_matching(
@@ -264,7 +304,7 @@ pub fn walk_matching(
         .filter_entry(|e| !ignore.is_ignored_abs(e.path()))
     {
         let Ok(entry) = entry else { continue };
-        if !entry.file_type().is_file() { continue }
+        if !entry.file_type().is_file() && !entry.file_type().is_symlink() { continue }
 
         let path = entry.into_path().into_boxed_path();
         let Ok(rel) = path.strip_prefix(repo_root) else { continue };
@@ -286,13 +326,16 @@ pub fn walk_matching(
 struct FileInfo {
     hash: Hash,
     offset: u32,
-    len: u32
+    len: u32,
+    base: Option<Hash>,
 }
 
 struct FileMeta {
     path:     Box<Path>,
     rel_norm: Box<Path>,
     meta:     fs::Metadata,
+    /// Hash of the version already in the index, if any.
+    base:     Option<Hash>,
 }
 
 struct ProcessedFile<'a> {
@@ -314,10 +357,10 @@ fn flush_batch(
 
     let _span = tracy::span!("stage::flush");
 
-    let hash_and_data_iter = file_infos.iter().map(|FileInfo { hash, offset, len }| {
-        (*hash, &encoded_buf[*offset as usize..*offset as usize + *len as usize])
+    let hash_and_data_iter = file_infos.iter().map(|FileInfo { hash, offset, len, base }| {
+        (*hash, &encoded_buf[*offset as usize..*offset as usize + *len as usize], *base)
     });
-    repo.storage.write_batch(hash_and_data_iter)?;
+    repo.storage.write_batch_with_delta_bases(hash_and_data_iter)?;
 
     for (FileMeta { rel_norm, meta, .. }, FileInfo { hash, .. }) in file_metas.iter().zip(file_infos.iter()) {
         index.add(rel_norm.to_str().unwrap(), *hash, meta);
@@ -1,9 +1,9 @@
 use crate::repository::Repository;
-use crate::index::Index;
-use crate::object::{Object, MODE_FILE, MODE_EXEC};
+use crate::index::{AsMetadata, Index};
+use crate::object::Object;
 use crate::tree::TreeEntry;
 use crate::hash::{hash_to_hex, Hash};
-use crate::util::is_executable;
+use crate::lockfile::LockFile;
 
 use std::fs;
 use std::path::Path;
@@ -39,23 +39,19 @@ pub fn stash(repo: &mut Repository) -> Result<()> {
     for i in 0..index.count {
         let path_str = index.get_path(i);
         let abs      = repo.root.join(path_str);
-        let Ok(meta) = fs::metadata(&abs) else { continue };
-
-        let mtime = meta.modified().ok()
-            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
-            .map_or(0, |d| d.as_secs() as i64);
-
-        let size = meta.len();
-        if index.mtimes[i] == mtime && index.sizes[i] == size {
+        let Ok(meta) = fs::symlink_metadata(&abs) else { continue };
+        if !index.is_dirty(i, &meta) {
             continue;
         }
 
-        let data = fs::read(&abs)?;
-        let hash = repo.write_blob(&data);
+        let hash = crate::chunk::write_file(repo, &abs)?;
+        if hash == index.hashes[i] && meta.mode() == index.modes[i] {
+            continue;
+        }
         dirty_entries.push(TreeEntry {
             hash,
             name: path_str.into(),
-            mode: if is_executable(&meta) { MODE_EXEC } else { MODE_FILE },
+            mode: meta.mode(),
         });
     }
     let dirty_tree_id   = repo.tree.push(&dirty_entries);
@@ -106,8 +102,16 @@ pub fn stash(repo: &mut Repository) -> Result<()> {
 
     let refs_dir = repo.root.join(".mog/refs/stash");
     fs::create_dir_all(&refs_dir)?;
+
+    //
+    // The new entry waits in `0.lock` while the others move up, so a crash in between
+    // leaves it recoverable instead of lost.
+    //
+    let _stack = LockFile::acquire(&refs_dir)?;
+    let mut new_ref = LockFile::acquire(&refs_dir.join("0"))?;
+    new_ref.write_all(format!("{}\n", hash_to_hex(&stash_hash)).as_bytes())?;
     shift_stash_refs_up(repo)?;
-    fs::write(refs_dir.join("0"), format!("{}\n", hash_to_hex(&stash_hash)))?;
+    new_ref.commit()?;
 
     //
     //
@@ -142,15 +146,13 @@ pub fn stash(repo: &mut Repository) -> Result<()> {
             for j in 0..head_flat.len() {
                 let path_str = head_flat.get_path(j);
                 let hash     = head_flat.hashes[j];
+                let mode     = head_flat.modes[j];
                 let abs      = repo.root.join(path_str);
                 if let Some(parent) = abs.parent() { fs::create_dir_all(parent)?; }
 
-                repo.with_blob_bytes_without_touching_cache_and_evict_the_pages(
-                    &hash,
-                    |_repo, data| std::fs::write(&abs, data)
-                )?;
+                repo.write_entry_to_file(&hash, mode, &abs)?;
 
-                let meta = fs::metadata(&abs)?;
+                let meta = fs::symlink_metadata(&abs)?;
                 new_index.add(path_str, hash, &meta);
             }
 
@@ -198,6 +200,8 @@ pub fn stash_pop(repo: &mut Repository) -> Result<()> {
 
     let stash_hash = repo.read_ref("refs/stash/0")?;
     apply_stash(repo, stash_hash)?;
+
+    let _stack = LockFile::acquire(&repo.root.join(".mog/refs/stash"))?;
     fs::remove_file(&stash_ref)?;
     shift_stash_refs_down(repo)?;
 
@@ -210,6 +214,8 @@ pub fn stash_drop(repo: &Repository, index: usize) -> Result<()> {
     if !stash_ref.exists() {
         bail!("no stash entry stash@{{{index}}}");
     }
+
+    let _stack = LockFile::acquire(&repo.root.join(".mog/refs/stash"))?;
     fs::remove_file(&stash_ref)?;
     shift_stash_refs_down_from(repo, index)?;
     println!("Dropped stash@{{{index}}}");
@@ -290,7 +296,33 @@ fn shift_stash_refs_down(repo: &Repository) -> Result<()> {
     shift_stash_refs_down_from(repo, 0)
 }
 
+/// Dirty trees of every stash. They are only referenced from the stash commit messages,
+/// so anything walking the object graph (gc) has to ask for them explicitly.
+#[inline]
+pub fn dirty_trees(repo: &mut Repository) -> Result<Vec<Hash>> {
+    let refs_dir = repo.root.join(".mog/refs/stash");
+    if !refs_dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut trees = Vec::new();
+    for n in read_stash_indexes(&refs_dir)?.collect::<Vec<_>>() {
+        let stash_hash = repo.read_ref(&format!("refs/stash/{n}"))?;
+        let commit_id  = repo.read_object(&stash_hash)?.try_as_commit_id()?;
+        trees.extend(parse_dirty_tree(repo.commit.get_message(commit_id)));
+    }
+
+    Ok(trees)
+}
+
 #[inline]
+fn parse_dirty_tree(message: &str) -> Option<Hash> {
+    message
+        .lines()
+        .find(|l| l.starts_with("dirty="))
+        .and_then(|l| crate::hash::hex_to_hash(l.trim_start_matches("dirty=")).ok())
+}
+
 fn read_stash_indexes(refs_dir: impl AsRef<Path>) -> Result<impl Iterator<Item = u32>> {
     Ok(fs::read_dir(refs_dir)?
         .filter_map(Result::ok)
@@ -304,10 +336,7 @@ fn apply_stash(repo: &mut Repository, stash_hash: Hash) -> Result<()> {
     let message   = repo.commit.get_message(commit_id).to_string();
     let tree_hash = repo.commit.get_tree(commit_id);
 
-    let dirty_tree_hash = message
-        .lines()
-        .find(|l| l.starts_with("dirty="))
-        .and_then(|l| crate::hash::hex_to_hash(l.trim_start_matches("dirty=")).ok());
+    let dirty_tree_hash = parse_dirty_tree(&message);
 
     //
     //
@@ -321,19 +350,16 @@ fn apply_stash(repo: &mut Repository, stash_hash: Hash) -> Result<()> {
     let mut index      = Index::load(&repo.root)?;
 
     for j in 0..n {
-        let TreeEntry { hash, name, .. } = repo.tree.get_entry(staged_tree_id, j);
+        let TreeEntry { hash, name, mode } = repo.tree.get_entry(staged_tree_id, j);
         let abs = repo.root.join(name.as_ref());
 
         if let Some(parent) = abs.parent() {
             fs::create_dir_all(parent)?;
         }
 
-        repo.with_blob_bytes_without_touching_cache_and_evict_the_pages(
-            &hash,
-            |_repo, data| std::fs::write(&abs, data)
-        )?;
+        repo.write_entry_to_file(&hash, mode, &abs)?;
 
-        let meta = fs::metadata(&abs)?;
+        let meta = fs::symlink_metadata(&abs)?;
         index.add(name.as_ref(), hash, &meta);
     }
 
@@ -348,13 +374,10 @@ fn apply_stash(repo: &mut Repository, stash_hash: Hash) -> Result<()> {
         let dirty_tree_id = dirty_obj.try_as_tree_id()?;
         let m             = repo.tree.entry_count(dirty_tree_id);
         for j in 0..m {
-            let TreeEntry { hash, name, .. } = repo.tree.get_entry(dirty_tree_id, j);
+            let TreeEntry { hash, name, mode } = repo.tree.get_entry(dirty_tree_id, j);
             let abs  = repo.root.join(name.as_ref());
 
-            repo.with_blob_bytes_without_touching_cache_and_evict_the_pages(
-                &hash,
-                |_repo, data| std::fs::write(&abs, data)
-            )?;
+            repo.write_entry_to_file(&hash, mode, &abs)?;
 
             //
             // Don't update index, dirty files should show as modified.
@@ -1,12 +1,12 @@
 use crate::hash::Hash;
 use crate::ignore::Ignore;
-use crate::index::Index;
-use crate::object::MODE_DIR;
+use crate::index::{AsMetadata, Index};
+use crate::object::{MODE_DIR, MODE_FILE};
 use crate::repository::Repository;
 use crate::storage::MogStorage;
 use crate::store::TreeId;
 use crate::tree::TreeEntryRef;
-use crate::util::{stdout_is_tty, str_from_utf8_data_shouldve_been_valid_or_we_got_hacked};
+use crate::util::{stdout_is_tty, str_from_utf8_data_shouldve_been_valid_or_we_got_hacked, Xxh3HashSet};
 
 use std::borrow::Cow;
 use std::path::Path;
@@ -17,24 +17,7 @@ use walkdir::WalkDir;
 use rayon::prelude::*;
 
 pub fn status(repo: &mut Repository) -> Result<()> {
-    let index = Index::load(&repo.root)?;
-    let head_commit = repo.read_head_commit().ok();
-    let head_tree = head_commit
-        .and_then(|hash| repo.read_object(&hash).ok())
-        .and_then(|obj| obj.try_as_commit_id().ok())
-        .map(|id| repo.commit.get_tree(id));
-
-    let head_flat = match head_tree {
-        Some(tree_hash) => flatten_tree(repo, tree_hash)?,
-        None => SortedFlatTree {
-            path_blob: Box::default(),
-            path_offsets: [0].into(),
-            hashes: Box::default(),
-            sorted_order: Box::default(),
-        },
-    };
-
-    let buckets = collect_status_impl(&index, &head_flat, &repo.root, &repo.ignore);
+    let buckets = collect_status(repo)?;
     print_status(&buckets, &mut std::io::stdout())?;
     Ok(())
 }
@@ -43,35 +26,47 @@ pub struct FlatTreeBuilder {
     path_blob:    Vec<u8>,
     path_offsets: Vec<u32>,
     hashes:       Vec<Hash>,
+    modes:        Vec<u32>,
 }
 
 impl FlatTreeBuilder {
     #[inline]
+    #[must_use]
     pub fn new() -> Self {
         Self {
             path_blob:    Vec::new(),
             path_offsets: Vec::new(),
             hashes:       Vec::new(),
+            modes:        Vec::new(),
         }
     }
 
     #[inline]
+    #[must_use]
     pub fn with_capacity(n: usize) -> Self {
         Self {
             path_blob:    Vec::with_capacity(n * 16),
             path_offsets: Vec::with_capacity(n + 1),
             hashes:       Vec::with_capacity(n),
+            modes:        Vec::with_capacity(n),
         }
     }
 
     #[inline]
     pub fn push(&mut self, path: &str, hash: Hash) {
+        self.push_with_mode(path, hash, MODE_FILE);
+    }
+
+    #[inline]
+    pub fn push_with_mode(&mut self, path: &str, hash: Hash, mode: u32) {
         self.path_offsets.push(self.path_blob.len() as u32);
         self.path_blob.extend_from_slice(path.as_bytes());
         self.hashes.push(hash);
+        self.modes.push(mode);
     }
 
     #[inline]
+    #[must_use]
     pub fn build(mut self) -> SortedFlatTree {
         //
         // Sentinel entry so get_path can always use path_offsets[i+1].
@@ -81,6 +76,7 @@ impl FlatTreeBuilder {
         let path_blob    = self.path_blob.into_boxed_slice();
         let path_offsets = self.path_offsets.into_boxed_slice();
         let hashes       = self.hashes.into_boxed_slice();
+        let modes        = self.modes.into_boxed_slice();
 
         let mut sorted_order = (0..hashes.len()).collect::<Vec<_>>();
         sorted_order.sort_unstable_by(|&a, &b| {
@@ -101,6 +97,7 @@ impl FlatTreeBuilder {
             path_blob,
             path_offsets,
             hashes,
+            modes,
             sorted_order: sorted_order.into_boxed_slice(),
         }
     }
@@ -122,6 +119,9 @@ pub struct SortedFlatTree {
     /// Hash for path at index i.
     pub hashes: Box<[Hash]>,
 
+    /// Tree mode for path at index i (`MODE_FILE`, `MODE_EXEC` or `MODE_LINK`).
+    pub modes: Box<[u32]>,
+
     /// Sorted by path for lookup: `sorted_order`[j] = index into `path_offsets/hashes`.
     pub sorted_order: Box<[usize]>,
 }
@@ -151,6 +151,13 @@ impl SortedFlatTree {
     #[inline]
     #[must_use]
     pub fn lookup(&self, path: &str) -> Option<Hash> {
+        self.lookup_entry(path).map(|(hash, _)| hash)
+    }
+
+    /// Like `lookup`, with the entry's mode.
+    #[inline]
+    #[must_use]
+    pub fn lookup_entry(&self, path: &str) -> Option<(Hash, u32)> {
         let sorted = &self.sorted_order;
         let mut lo = 0;
         let mut hi = sorted.len();
@@ -160,7 +167,7 @@ impl SortedFlatTree {
             let p = self.get_path(i);
             match path.as_bytes().cmp(p.as_bytes()) {
                 std::cmp::Ordering::Less => hi = mid,
-                std::cmp::Ordering::Equal => return Some(self.hashes[i]),
+                std::cmp::Ordering::Equal => return Some((self.hashes[i], self.modes[i])),
                 std::cmp::Ordering::Greater => lo = mid + 1,
             }
         }
@@ -168,7 +175,36 @@ impl SortedFlatTree {
     }
 }
 
+#[inline]
 pub fn flatten_tree(repo: &mut Repository<impl MogStorage>, tree_hash: Hash) -> Result<SortedFlatTree> {
+    flatten_tree_impl(repo, tree_hash, |_, _| false)
+}
+
+/// HEAD as `status` needs it: like `flatten_tree`, but directories the index's tree cache
+/// holds with the same hash are neither read nor flattened. Everything staged under them
+/// matches HEAD, so they're returned as `unchanged_dirs` ("" when the whole tree is).
+pub fn flatten_tree_against_index(
+    repo: &mut Repository<impl MogStorage>,
+    tree_hash: Hash,
+    index: &Index,
+) -> Result<(SortedFlatTree, Xxh3HashSet<Box<str>>)> {
+    let mut unchanged_dirs = Xxh3HashSet::default();
+    let flat = flatten_tree_impl(repo, tree_hash, |dir, hash| {
+        let unchanged = index.cached_tree(dir) == Some(*hash);
+        if unchanged {
+            unchanged_dirs.insert(dir.into());
+        }
+        unchanged
+    })?;
+    Ok((flat, unchanged_dirs))
+}
+
+/// Flatten `tree_hash`, leaving out the directories `skip_dir(path, tree_hash)` says to.
+fn flatten_tree_impl(
+    repo: &mut Repository<impl MogStorage>,
+    tree_hash: Hash,
+    mut skip_dir: impl FnMut(&str, &Hash) -> bool,
+) -> Result<SortedFlatTree> {
     struct Frame {
         tree_id: TreeId,
         prefix: Box<str>
@@ -184,6 +220,11 @@ pub fn flatten_tree(repo: &mut Repository<impl MogStorage>, tree_hash: Hash) ->
     let mut path_blob = Vec::new();
     let mut path_offsets = Vec::new();
     let mut hashes = Vec::new();
+    let mut modes = Vec::new();
+
+    if skip_dir("", &tree_hash) {
+        return Ok(SortedFlatTree::default());
+    }
 
     let object = repo.read_object(&tree_hash)?;
     let root_id = object.try_as_tree_id()?;
@@ -199,12 +240,16 @@ pub fn flatten_tree(repo: &mut Repository<impl MogStorage>, tree_hash: Hash) ->
             let TreeEntryRef { mode, hash, name } = repo.tree.get_entry_ref(frame.tree_id, j);
 
             if mode == MODE_DIR {
-                let path = if frame.prefix.is_empty() {
+                let path: Box<str> = if frame.prefix.is_empty() {
                     Cow::Borrowed(name)
                 } else {
                     format!("{}/{}", frame.prefix, name).into()
                 }.into();
 
+                if skip_dir(&path, &hash) {
+                    continue;
+                }
+
                 let object = repo.read_object(&hash)?;
                 let sub_id = object.try_as_tree_id()?;
                 stack.push(Frame {
@@ -225,6 +270,7 @@ pub fn flatten_tree(repo: &mut Repository<impl MogStorage>, tree_hash: Hash) ->
                 path_blob.extend_from_slice(name.as_bytes());
             }
             hashes.push(hash);
+            modes.push(mode);
         }
     }
     path_offsets.push(path_blob.len() as u32);
@@ -241,6 +287,7 @@ pub fn flatten_tree(repo: &mut Repository<impl MogStorage>, tree_hash: Hash) ->
         path_blob: crate::util::vec_into_boxed_slice_noshrink(path_blob),
         path_offsets: crate::util::vec_into_boxed_slice_noshrink(path_offsets),
         hashes: crate::util::vec_into_boxed_slice_noshrink(hashes),
+        modes: crate::util::vec_into_boxed_slice_noshrink(modes),
         sorted_order,
     })
 }
@@ -252,7 +299,7 @@ pub struct StatusBuckets {
     /// Staged delete: in HEAD, not in index.
     pub staged_deleted: Vec<Box<str>>,
 
-    /// In index, file on disk exists but content differs (mtime/size).
+    /// In index, file on disk exists but content (or mode) differs.
     pub modified: Vec<Box<str>>,
     /// In index, file missing on disk.
     pub deleted: Vec<Box<str>>,
@@ -262,21 +309,31 @@ pub struct StatusBuckets {
 }
 
 pub fn collect_status(repo: &mut Repository) -> Result<StatusBuckets> {
-    let index    = Index::load(&repo.root)?;
-    let head_flat = match repo.read_head_commit().ok() {
+    let index = Index::load(&repo.root)?;
+    let (head_flat, unchanged_dirs) = match repo.read_head_commit().ok() {
         Some(h) => {
             let obj = repo.read_object(&h)?;
             let cid = obj.try_as_commit_id()?;
-            flatten_tree(repo, repo.commit.get_tree(cid))?
+            flatten_tree_against_index(repo, repo.commit.get_tree(cid), &index)?
         }
-        None => SortedFlatTree::default(),
+        None => (SortedFlatTree::default(), Xxh3HashSet::default()),
     };
-    Ok(collect_status_impl(&index, &head_flat, &repo.root, &repo.ignore))
+    Ok(collect_status_impl(&index, &head_flat, &unchanged_dirs, &repo.root, &repo.ignore))
+}
+
+/// `path` is under one of `dirs` (or `dirs` holds the root).
+#[inline]
+fn is_under_any(path: &str, dirs: &Xxh3HashSet<Box<str>>) -> bool {
+    !dirs.is_empty() && (
+        dirs.contains("") ||
+        path.match_indices('/').any(|(slash, _)| dirs.contains(&path[..slash]))
+    )
 }
 
 fn collect_status_impl(
     index: &Index,
     head: &SortedFlatTree,
+    unchanged_dirs: &Xxh3HashSet<Box<str>>,
     repo_root: &Path,
     ignore: &Ignore,
 ) -> StatusBuckets {
@@ -291,26 +348,22 @@ fn collect_status_impl(
     let index_results = (0..index.count).into_par_iter().map(|i| {
         let path_str = index.get_path(i);
         let abs = repo_root.join(path_str);
-        let head_hash = head.lookup(path_str);
-        let index_hash = index.hashes[i];
-
-        let staged = head_hash != Some(index_hash);
-
-        let disk = match fs::metadata(&abs) {
-            Ok(meta) => {
-                let mtime = meta
-                    .modified()
-                    .ok()
-                    .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
-                    .map_or(0, |d| d.as_secs() as i64);
-
-                let size = meta.len();
-                if index.mtimes[i] != mtime || index.sizes[i] != size {
-                    DiskState::Modified
-                } else {
-                    DiskState::Clean
-                }
+        let index_entry = (index.hashes[i], index.modes[i]);
+
+        let staged = !is_under_any(path_str, unchanged_dirs)
+            && head.lookup_entry(path_str) != Some(index_entry);
+
+        let disk = match fs::symlink_metadata(&abs) {
+            //
+            // Stat data only says it might have changed (a touch, a racy entry); the contents
+            // and mode decide. Mode changes include a chmod, or a file that became a symlink.
+            //
+            Ok(meta) if index.is_dirty(i, &meta) => {
+                let same = meta.mode() == index.modes[i]
+                    && crate::chunk::hash_file(&abs).is_ok_and(|hash| hash == index.hashes[i]);
+                if same { DiskState::Clean } else { DiskState::Modified }
             }
+            Ok(_) => DiskState::Clean,
 
             Err(_) => DiskState::Deleted,
         };
@@ -349,7 +402,7 @@ fn collect_status_impl(
         .filter_entry(|e| !ignore.is_ignored_abs(e.path()))
         .filter_map(Result::ok)
     {
-        if !entry.file_type().is_file() { continue; }
+        if !entry.file_type().is_file() && !entry.file_type().is_symlink() { continue; }
 
         let path = entry.path();
 
@@ -1,43 +1,130 @@
 use crate::hash::Hash;
 use crate::tracy;
+use crate::util::{Xxh3HashMap, Xxh3HashSet};
 
-use std::path::Path;
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
 use std::fs::{File, OpenOptions};
 
 use anyhow::{Result, bail};
 use memmap2::{MmapMut, MmapOptions};
 use libc::{madvise, MADV_DONTNEED, MADV_SEQUENTIAL, MADV_WILLNEED};
+use rayon::prelude::*;
 
 pub trait MogStorage {
     fn exists(&self, hash: &Hash) -> bool;
-    fn read<'a>(&'a self, hash: &Hash) -> Result<&'a [u8]>;
+    /// Encoded object bytes: borrowed when stored raw, owned when they had to be decompressed.
+    fn read<'a>(&'a self, hash: &Hash) -> Result<Cow<'a, [u8]>>;
     fn write(&mut self, hash: Hash, data: impl Into<Box<[u8]>>);
     fn write_batch<'a>(&mut self, writes: impl Iterator<Item = (Hash, &'a [u8])>) -> Result<()>;
     fn flush(&mut self) -> Result<()>;
     fn sync(&mut self) -> Result<()>;
     fn evict_pages(data: &[u8]);
+    /// Visit the hash of every stored object, in no particular order.
+    fn for_each_hash(&self, f: impl FnMut(&Hash));
 }
 
 impl MogStorage for Storage {
     fn exists(&self, hash: &Hash) -> bool { self.exists(hash) }
-    fn read<'a>(&'a self, hash: &Hash) -> Result<&'a [u8]> { self.read(hash) }
+    fn read<'a>(&'a self, hash: &Hash) -> Result<Cow<'a, [u8]>> { self.read(hash) }
     fn write(&mut self, hash: Hash, data: impl Into<Box<[u8]>>) { self.write(hash, data) }
     fn write_batch<'a>(&mut self, writes: impl Iterator<Item = (Hash, &'a [u8])>) -> Result<()> { self.write_batch(writes) }
     fn flush(&mut self) -> Result<()> { self.flush() }
     fn sync(&mut self) -> Result<()> { self.sync() }
     fn evict_pages(_data: &[u8]) {}
+    fn for_each_hash(&self, f: impl FnMut(&Hash)) { self.for_each_hash(f) }
 }
 
+//
+// Header layout (little endian):
+//
+//   0..4    magic "MOGS"
+//   4..8    version
+//   8..16   object count
+//   16..24  offset of the first object written after the initial table (informational)
+//   24..32  hash table offset         (v2+, v1: right after the header)
+//   32..40  hash table bucket count   (v2+, v1: 2M)
+//   40..44  max load factor, permille (v2+, v1: default)
+//
+// The hash table is an array of u64 object offsets (0 = empty) probed linearly.
+// When it gets too full a bigger one is appended at the end of the file and the header
+// repointed at it, so object offsets never change. The old table becomes garbage for gc.
+//
+// Each entry is hash(32) + size(4) + stored bytes. The top bits of size are flags:
+//
+//   bit 31  (v3+) zstd-compressed: u32 uncompressed length followed by one zstd frame
+//   bit 30  (v4+) delta: once decompressed, base hash(32) + chain depth(1) + ops from `crate::delta`
+//
+// Hashes are always over the full uncompressed encoding, so neither flag ever changes an object id.
+//
 const MAGIC: &[u8; 4] = b"MOGS";
-const VERSION: u32 = 1;
+const VERSION: u32 = 4;
 
 const HEADER_SIZE: usize = 128;
-const HASH_TABLE_BUCKETS: usize = 1 << 21;  // 2M buckets
-const HASH_TABLE_SIZE: usize = HASH_TABLE_BUCKETS * 8;  // 16MB
-const DATA_START: u64 = (HEADER_SIZE + HASH_TABLE_SIZE) as u64;
+
+const V1_HASH_TABLE_BUCKETS: usize = 1 << 21;  // 2M buckets, 16MB
+const INITIAL_HASH_TABLE_BUCKETS: usize = 1 << 12;  // 4K buckets, 32KB
+const DEFAULT_MAX_LOAD_PERMILLE: u32 = 700;
 
 const ENTRY_HEADER_SIZE: usize = 36; // hash(32) + size(4)
 
+const ENTRY_COMPRESSED: u32 = 1 << 31;
+const ENTRY_DELTA:      u32 = 1 << 30;
+const ENTRY_SIZE_MASK:  u32 = ENTRY_DELTA - 1;
+
+/// Below this a zstd frame header eats most of what compression could save.
+const MIN_COMPRESS_SIZE: usize = 64;
+const ZSTD_LEVEL: i32 = 3;
+
+const DELTA_RECORD_HEADER_SIZE: usize = 33; // base hash(32) + depth(1)
+
+/// Longest chain of deltas `read` may have to resolve for one object.
+pub const MAX_DELTA_DEPTH: u8 = 16;
+
+/// Small objects resolve faster from a full copy than through a base.
+const MIN_DELTA_SIZE: usize = 1024;
+
+/// How much data `compact` copies per `write_batch` call.
+const COMPACT_BATCH_BYTES: usize = 64 << 20;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CompactStats {
+    pub kept:         usize,
+    pub removed:      usize,
+    pub bytes_before: u64,
+    pub bytes_after:  u64,
+}
+
+/// One problem `verify` found in `objects.bin`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StorageIssue {
+    /// A hash table bucket points outside the data region or at a cut-off entry.
+    BadOffset { bucket: usize, offset: u64 },
+    /// The entry doesn't decode, or its object doesn't hash to the hash it is stored under.
+    Corrupt { hash: Hash, offset: u64, reason: String },
+    /// The table lists the entry but probing for its hash finds another one (or none), so it can't be read.
+    Unfindable { hash: Hash, offset: u64 },
+    /// The header's object count disagrees with the hash table.
+    CountMismatch { header: u64, table: u64 },
+    /// Bytes that are neither an entry nor a retired hash table, e.g. left by an interrupted write.
+    Unaccounted { offset: u64, len: u64 },
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct StorageCheck {
+    /// Entries the hash table points at.
+    pub objects: usize,
+    pub issues:  Vec<StorageIssue>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RepackStats {
+    pub objects:      usize,
+    pub deltas:       usize,
+    pub bytes_before: u64,
+    pub bytes_after:  u64,
+}
+
 pub struct PendingStorageWrite {
     pub hash: Hash,
     pub data: Box<[u8]>,
@@ -45,8 +132,13 @@ pub struct PendingStorageWrite {
 
 // TODO(#2): Mock storage for tests
 pub struct Storage {
+    path: PathBuf,
     file: File,
     mmap: MmapMut,
+    /// Where the live hash table starts, and its size. Bucket count is always a power of two.
+    table_offset: usize,
+    buckets: usize,
+    max_load_permille: u32,
     /// Cached file length so `write_batch` doesn't call `metadata()` every chunk.
     file_len: u64,
     /// Encoded bytes only. No Object clone.
@@ -71,6 +163,10 @@ impl Storage {
     }
 
     fn create_new(path: &Path) -> Result<Self> {
+        Self::create_with_buckets(path, INITIAL_HASH_TABLE_BUCKETS)
+    }
+
+    fn create_with_buckets(path: &Path, buckets: usize) -> Result<Self> {
         let _span = tracy::span!("Storage::create_new");
 
         let file = OpenOptions::new()
@@ -80,7 +176,7 @@ impl Storage {
             .truncate(true)
             .open(path)?;
 
-        let initial_size = HEADER_SIZE + HASH_TABLE_SIZE;
+        let initial_size = HEADER_SIZE + buckets * 8;
         file.set_len(initial_size as u64)?;
 
         let mut mmap = unsafe { MmapOptions::new().map_mut(&file)? };
@@ -95,13 +191,23 @@ impl Storage {
 
         // Write header
         mmap[0..4].copy_from_slice(MAGIC);
-        mmap[4..8].copy_from_slice(&VERSION.to_le_bytes());
         mmap[8..16].copy_from_slice(&0u64.to_le_bytes());  // count
-        mmap[16..24].copy_from_slice(&DATA_START.to_le_bytes());
-
-        mmap.flush()?;
-
-        Ok(Self { file, mmap, file_len: initial_size as u64, pending_writes: Vec::new() })
+        mmap[16..24].copy_from_slice(&(initial_size as u64).to_le_bytes());
+
+        let mut storage = Self {
+            path: path.to_owned(),
+            file,
+            mmap,
+            table_offset: HEADER_SIZE,
+            buckets,
+            max_load_permille: DEFAULT_MAX_LOAD_PERMILLE,
+            file_len: initial_size as u64,
+            pending_writes: Vec::new(),
+        };
+        storage.write_table_header();
+        storage.mmap.flush()?;
+
+        Ok(storage)
     }
 
     fn open_existing(path: &Path) -> Result<Self> {
@@ -118,48 +224,147 @@ impl Storage {
             bail!("invalid object database magic");
         }
 
-        let file_len = file.metadata()?.len();
-        let ht_end = HEADER_SIZE + HASH_TABLE_SIZE;
+        let (table_offset, buckets, max_load_permille) = read_table_fields(&mmap)?;
 
-        unsafe {
-            //
-            // Eagerly load only the header + hash table we'll probe it on every lookup.
-            //
-            madvise(
-                mmap.as_ptr() as *mut libc::c_void,
-                ht_end.min(mmap.len()),
-                MADV_WILLNEED,
-            );
+        let file_len = mmap.len() as u64;
+        let ht_end = table_offset + buckets * 8;
 
+        unsafe {
             //
-            // Tell the kernel it can evict all object data pages immediately.
+            // Tell the kernel it can evict all object data pages immediately...
             //
-            let data_len = mmap.len().saturating_sub(ht_end);
+            let data_len = mmap.len().saturating_sub(HEADER_SIZE);
             if data_len > 0 {
                 madvise(
-                    mmap.as_ptr().add(ht_end) as *mut libc::c_void,
+                    mmap.as_ptr().add(HEADER_SIZE) as *mut libc::c_void,
                     data_len,
                     MADV_DONTNEED,
                 );
             }
+
+            //
+            // ...but eagerly load the header + hash table, we'll probe it on every lookup.
+            //
+            madvise(
+                mmap.as_ptr() as *mut libc::c_void,
+                HEADER_SIZE,
+                MADV_WILLNEED,
+            );
+            madvise(
+                mmap.as_ptr().add(table_offset) as *mut libc::c_void,
+                ht_end - table_offset,
+                MADV_WILLNEED,
+            );
         }
 
-        Ok(Self { file, mmap, file_len, pending_writes: Vec::new() })
+        Ok(Self {
+            path: path.to_owned(),
+            file,
+            mmap,
+            table_offset,
+            buckets,
+            max_load_permille,
+            file_len,
+            pending_writes: Vec::new(),
+        })
+    }
+
+    /// Object count and load factor of the live hash table.
+    #[inline]
+    #[must_use]
+    pub fn load(&self) -> (u64, f64) {
+        let count = self.count();
+        (count, count as f64 / self.buckets as f64)
+    }
+
+    #[inline]
+    fn count(&self) -> u64 {
+        u64::from_le_bytes(self.mmap[8..16].try_into().unwrap())
+    }
+
+    /// Smallest power-of-two table that holds `count` objects under the load limit.
+    #[inline]
+    fn buckets_for(count: usize, max_load_permille: u32) -> usize {
+        let mut buckets = INITIAL_HASH_TABLE_BUCKETS;
+        while count * 1000 > buckets * max_load_permille as usize {
+            buckets *= 2;
+        }
+        buckets
     }
 
+    /// Write the table fields and the current version. Upgrades older files in place:
+    /// their layout is still valid, they just have no compressed or delta entries yet.
     #[inline]
-    fn hash_to_bucket(hash: &Hash) -> usize {
+    fn write_table_header(&mut self) {
+        self.mmap[4..8].copy_from_slice(&VERSION.to_le_bytes());
+        self.mmap[24..32].copy_from_slice(&(self.table_offset as u64).to_le_bytes());
+        self.mmap[32..40].copy_from_slice(&(self.buckets as u64).to_le_bytes());
+        self.mmap[40..44].copy_from_slice(&self.max_load_permille.to_le_bytes());
+    }
+
+    /// Append a table of `new_buckets` at the end of the file, move every entry into it
+    /// and repoint the header. Object data stays where it is.
+    fn grow_table(&mut self, new_buckets: usize) -> Result<()> {
+        let _span = tracy::span!("Storage::grow_table");
+
+        let new_offset = self.file_len as usize;
+        self.file_len += (new_buckets * 8) as u64;
+        self.file.set_len(self.file_len)?;
+
+        //
+        // Map the new table (and any objects flushed since the last remap, we read their hashes).
+        //
+        self.remap()?;
+
+        let old_offsets = (0..self.buckets)
+            .map(|bucket| self.get_bucket_offset(bucket))
+            .filter(|&offset| offset != 0)
+            .collect::<Vec<_>>();
+
+        self.table_offset = new_offset;
+        self.buckets      = new_buckets;
+
+        for offset in old_offsets {
+            let pos  = offset as usize;
+            let hash = self.mmap[pos..pos + 32].try_into()?;
+            self.insert_bucket(&hash, offset)?;
+        }
+
+        //
+        // Only now point the header at the new table: a crash before this leaves the old one live.
+        //
+        self.write_table_header();
+        Ok(())
+    }
+
+    #[inline]
+    fn insert_bucket(&mut self, hash: &Hash, offset: u64) -> Result<()> {
+        let bucket = self.hash_to_bucket(hash);
+        let mut current_bucket = bucket;
+        loop {
+            if self.get_bucket_offset(current_bucket) == 0 {
+                self.set_bucket_offset(current_bucket, offset);
+                return Ok(());
+            }
+
+            current_bucket = (current_bucket + 1) & (self.buckets - 1);
+            if current_bucket == bucket { bail!("hash table full"); }
+        }
+    }
+
+    #[inline]
+    fn hash_to_bucket(&self, hash: &Hash) -> usize {
         let _span = tracy::span!("Storage::hash_to_bucket");
 
         let h = u64::from_le_bytes(hash[..8].try_into().unwrap());
-        (h as usize) % HASH_TABLE_BUCKETS
+        (h as usize) & (self.buckets - 1)
     }
 
     #[inline]
     fn get_bucket_offset(&self, bucket: usize) -> u64 {
         let _span = tracy::span!("Storage::get_bucket_offset");
 
-        let offset = HEADER_SIZE + bucket * 8;
+        let offset = self.table_offset + bucket * 8;
         u64::from_le_bytes(self.mmap[offset..offset + 8].try_into().unwrap())
     }
 
@@ -167,7 +372,7 @@ impl Storage {
     fn set_bucket_offset(&mut self, bucket: usize, value: u64) {
         let _span = tracy::span!("Storage::set_bucket_offset");
 
-        let offset = HEADER_SIZE + bucket * 8;
+        let offset = self.table_offset + bucket * 8;
         self.mmap[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
     }
 
@@ -176,65 +381,447 @@ impl Storage {
     pub fn exists(&self, hash: &Hash) -> bool {
         let _span = tracy::span!("Storage::exists");
 
-        let bucket = Self::hash_to_bucket(hash);
+        self.find_entry(hash).is_some()
+    }
+
+    /// Read encoded object bytes by hash, decompressing if the entry is compressed.
+    #[inline]
+    pub fn read(&self, hash: &Hash) -> Result<Cow<'_, [u8]>> {
+        let _span = tracy::span!("Storage::read");
+
+        self.read_at_depth(hash, 0)
+    }
+
+    fn read_at_depth(&self, hash: &Hash, depth: u8) -> Result<Cow<'_, [u8]>> {
+        let Some(pos) = self.find_entry(hash) else {
+            bail!("object not found");
+        };
+
+        self.read_entry_at_depth(pos, hash, depth)
+    }
+
+    /// Encoded object bytes of the entry at `pos`, resolving its delta chain.
+    fn read_entry_at_depth(&self, pos: usize, hash: &Hash, depth: u8) -> Result<Cow<'_, [u8]>> {
+        let logical = self.entry_logical_bytes(pos)?;
+        if !self.entry_is_delta(pos) {
+            return Ok(logical);
+        }
+
+        //
+        // Bounded by MAX_DELTA_DEPTH when written; the check only stops a corrupted chain from looping.
+        //
+        if depth >= MAX_DELTA_DEPTH {
+            bail!("delta chain of {} is deeper than {MAX_DELTA_DEPTH}", crate::hash::hash_to_hex(hash));
+        }
+
+        let (base, _, ops) = split_delta_record(&logical)?;
+        let base_bytes = self.read_at_depth(&base, depth + 1)?;
+        crate::delta::apply(&base_bytes, ops).map(Cow::Owned)
+    }
+
+    /// Whether the object is stored compressed. None if it isn't in storage.
+    #[inline]
+    #[must_use]
+    pub fn is_compressed(&self, hash: &Hash) -> Option<bool> {
+        self.find_entry(hash).map(|pos| self.entry_is_compressed(pos))
+    }
+
+    /// The base the object is stored as a delta against. None if it is stored whole (or missing).
+    #[inline]
+    #[must_use]
+    pub fn delta_base(&self, hash: &Hash) -> Option<Hash> {
+        let pos = self.find_entry(hash)?;
+        self.entry_delta_base(pos).ok().flatten().map(|(base, _)| base)
+    }
+
+    /// File offset of the entry for `hash`.
+    /// Entries another process appended past the end of our mapping count as missing until `remap`.
+    #[inline]
+    fn find_entry(&self, hash: &Hash) -> Option<usize> {
+        let bucket = self.hash_to_bucket(hash);
         let mut current_bucket = bucket;
 
         loop {
             let offset = self.get_bucket_offset(current_bucket);
 
             if offset == 0 {
-                return false;
+                return None;
             }
 
             let pos = offset as usize;
-            if pos + 32 > self.mmap.len() {
-                return false;
+            if !self.entry_is_mapped(pos) {
+                return None;
             }
 
             if self.mmap[pos..pos + 32] == hash[..] {
-                return true;
+                return Some(pos);
             }
 
-            current_bucket = (current_bucket + 1) % HASH_TABLE_BUCKETS;
+            current_bucket = (current_bucket + 1) & (self.buckets - 1);
             if current_bucket == bucket {
-                return false;
+                return None;
             }
         }
     }
 
-    /// Read encoded object bytes by hash.
+    /// Visit the hash of every stored object by walking the hash table.
     #[inline]
-    pub fn read(&self, hash: &Hash) -> Result<&[u8]> {
-        let _span = tracy::span!("Storage::read");
+    pub fn for_each_hash(&self, mut f: impl FnMut(&Hash)) {
+        let _span = tracy::span!("Storage::for_each_hash");
 
-        let bucket = Self::hash_to_bucket(hash);
-        let mut current_bucket = bucket;
+        for (hash, _) in self.entries() {
+            f(&hash);
+        }
+    }
 
-        loop {
-            let offset = self.get_bucket_offset(current_bucket);
+    /// Sizes `compact` would produce if only `keep` survived. Nothing is written.
+    #[must_use]
+    pub fn compact_stats(&self, keep: &Xxh3HashSet<Hash>) -> CompactStats {
+        let keep = self.with_delta_bases(keep);
+
+        let mut stats = CompactStats {
+            bytes_before: self.file_len,
+            ..CompactStats::default()
+        };
+
+        let mut data_bytes = 0;
+        for (hash, pos) in self.entries() {
+            if keep.contains(&hash) {
+                stats.kept += 1;
+                data_bytes += (ENTRY_HEADER_SIZE + self.entry_size(pos)) as u64;
+            } else {
+                stats.removed += 1;
+            }
+        }
+
+        let buckets = Self::buckets_for(stats.kept, self.max_load_permille);
+        stats.bytes_after = (HEADER_SIZE + buckets * 8) as u64 + data_bytes;
+
+        stats
+    }
+
+    /// Rewrite `objects.bin` with only the objects in `keep` and a freshly built hash table.
+    /// The new file is written next to the old one and renamed over it, so a crash leaves either intact.
+    pub fn compact(&mut self, keep: &Xxh3HashSet<Hash>) -> Result<CompactStats> {
+        let _span = tracy::span!("Storage::compact");
+
+        self.flush()?;
+        let _lock = self.lock_for_write()?;
+        let stats = self.compact_stats(keep);
+        let keep  = self.with_delta_bases(keep);
+
+        //
+        // Copy survivors in their current file order so the new file keeps the old locality.
+        //
+        let mut survivors = self.entries().filter(|(hash, _)| keep.contains(hash)).collect::<Vec<_>>();
+        survivors.sort_unstable_by_key(|&(_, pos)| pos);
+
+        let tmp_path = self.path.with_extension("bin.gc");
+        {
+            //
+            // Size the table for the survivors up front so it never has to grow mid-copy.
+            //
+            let mut fresh = Self::create_with_buckets(&tmp_path, Self::buckets_for(stats.kept, self.max_load_permille))?;
+
+            self.copy_entries(&mut fresh, &survivors)?;
+            fresh.sync()?;
+        }
+
+        std::fs::rename(&tmp_path, &self.path)?;
+        *self = Self::open_existing(&self.path)?;
+
+        Ok(stats)
+    }
+
+    /// Rewrite `objects.bin` re-encoding the `plan` objects, in order, as deltas against the given
+    /// base (or whole when None, or when the delta doesn't pay off). Bases must come before the
+    /// objects that use them. Everything else is copied as stored.
+    pub fn repack(&mut self, plan: &[(Hash, Option<Hash>)]) -> Result<RepackStats> {
+        let _span = tracy::span!("Storage::repack");
+
+        self.flush()?;
+        let _lock = self.lock_for_write()?;
+
+        let mut stats = RepackStats {
+            bytes_before: self.file_len,
+            ..RepackStats::default()
+        };
+
+        //
+        // Depth each planned object ends up at if every delta is taken. Chains the caller made
+        // too deep are cut by storing the offending object whole.
+        //
+        let mut depths = Xxh3HashMap::default();
+        let mut planned = Vec::with_capacity(plan.len());
+        for &(hash, base) in plan {
+            if !self.exists(&hash) || depths.contains_key(&hash) {
+                continue;
+            }
+
+            let base = base
+                .filter(|base| self.exists(base))
+                .and_then(|base| Some((base, *depths.get(&base)? + 1)))
+                .filter(|&(_, depth)| depth <= MAX_DELTA_DEPTH);
+
+            depths.insert(hash, base.map_or(0, |(_, depth)| depth));
+            planned.push((hash, base));
+        }
+
+        //
+        // Other deltas are stored whole: their base may be re-encoded deeper than their recorded depth assumes.
+        //
+        let mut rest = Vec::new();
+        for (hash, pos) in self.entries() {
+            if depths.contains_key(&hash) {
+                continue;
+            }
+            if self.entry_is_delta(pos) {
+                planned.push((hash, None));
+            } else {
+                rest.push((hash, pos));
+            }
+        }
+        rest.sort_unstable_by_key(|&(_, pos)| pos);
+
+        let tmp_path = self.path.with_extension("bin.repack");
+        {
+            let mut fresh = Self::create_with_buckets(&tmp_path, Self::buckets_for(self.count() as usize, self.max_load_permille))?;
+            self.copy_entries(&mut fresh, &rest)?;
+
+            let mut batch_bytes = 0;
+            let mut start       = 0;
+            for end in 1..=planned.len() {
+                batch_bytes += self.find_entry(&planned[end - 1].0).map_or(0, |pos| self.entry_size(pos));
+                if batch_bytes < COMPACT_BATCH_BYTES && end < planned.len() {
+                    continue;
+                }
+
+                let entries = planned[start..end]
+                    .par_iter()
+                    .map(|&(hash, base)| {
+                        let encoded = self.read(&hash)?;
+                        let (size_field, stored) = match base {
+                            Some((base, depth)) => encode_entry(&encoded, Some((&base, depth, &self.read(&base)?))),
+                            None                => encode_entry(&encoded, None),
+                        };
+                        Ok((hash, size_field, Cow::Owned(stored.into_owned())))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+
+                stats.deltas += entries.iter().filter(|(_, size_field, _)| size_field & ENTRY_DELTA != 0).count();
+                fresh.append_entries(&entries)?;
+
+                start       = end;
+                batch_bytes = 0;
+            }
+
+            fresh.sync()?;
+            stats.objects     = fresh.count() as usize;
+            stats.bytes_after = fresh.file_len;
+        }
+
+        std::fs::rename(&tmp_path, &self.path)?;
+        *self = Self::open_existing(&self.path)?;
+
+        Ok(stats)
+    }
+
+    /// Walk the data region in file order, re-hashing every entry and checking the hash table
+    /// against it. Only sees what has been flushed and remapped.
+    #[must_use]
+    pub fn verify(&self) -> StorageCheck {
+        let _span = tracy::span!("Storage::verify");
+
+        let mut check = StorageCheck::default();
+
+        let data_start = (u64::from_le_bytes(self.mmap[16..24].try_into().unwrap()) as usize).max(HEADER_SIZE);
+        let table      = self.table_offset..self.table_offset + self.buckets * 8;
+        let file_end   = self.mmap.len();
 
+        //
+        // Every bucket must point at a whole entry in the data region.
+        //
+        let mut positions = Vec::new();
+        for bucket in 0..self.buckets {
+            let offset = self.get_bucket_offset(bucket);
             if offset == 0 {
-                bail!("object not found");
+                continue;
             }
 
             let pos = offset as usize;
+            if pos < data_start || table.contains(&pos) || !self.entry_is_mapped(pos) {
+                check.issues.push(StorageIssue::BadOffset { bucket, offset });
+                continue;
+            }
+            positions.push(pos);
+        }
+        positions.sort_unstable();
 
-            if self.mmap[pos..pos + 32] == hash[..] {
-                let size = u32::from_le_bytes(
-                    self.mmap[pos + 32..pos + 36].try_into()?
-                ) as usize;
+        if self.count() != positions.len() as u64 {
+            check.issues.push(StorageIssue::CountMismatch { header: self.count(), table: positions.len() as u64 });
+        }
 
-                let data = &self.mmap[pos + 36..pos + 36 + size];
-                return Ok(data);
+        //
+        // Walk the entries in file order. Whatever lies between them must be the live table
+        // or a table retired by `grow_table`, whose words are all empty or entry offsets.
+        //
+        let known = positions.iter().map(|&pos| pos as u64).collect::<Xxh3HashSet<_>>();
+        let mut cursor = data_start;
+        for &pos in positions.iter().chain(std::iter::once(&file_end)) {
+            if pos > cursor {
+                for (start, end) in [(cursor, pos.min(table.start)), (cursor.max(table.end), pos)] {
+                    if start < end && !self.is_retired_table(start, end, &known) {
+                        check.issues.push(StorageIssue::Unaccounted { offset: start as u64, len: (end - start) as u64 });
+                    }
+                }
+            }
+            if pos == file_end {
+                break;
             }
 
-            current_bucket = (current_bucket + 1) % HASH_TABLE_BUCKETS;
-            if current_bucket == bucket {
-                bail!("object not found");
+            let hash: Hash = self.mmap[pos..pos + 32].try_into().unwrap();
+            check.objects += 1;
+            cursor = cursor.max(pos + ENTRY_HEADER_SIZE + self.entry_size(pos));
+
+            if self.find_entry(&hash) != Some(pos) {
+                check.issues.push(StorageIssue::Unfindable { hash, offset: pos as u64 });
+                continue;
             }
+
+            let reason = match self.read_entry_at_depth(pos, &hash, 0) {
+                Ok(encoded) if crate::hash::hash_bytes(&encoded) == hash => continue,
+                Ok(_)  => "contents don't match the hash".to_owned(),
+                Err(e) => e.to_string(),
+            };
+            check.issues.push(StorageIssue::Corrupt { hash, offset: pos as u64, reason });
+        }
+
+        check
+    }
+
+    #[inline]
+    fn is_retired_table(&self, start: usize, end: usize, known: &Xxh3HashSet<u64>) -> bool {
+        (end - start).is_multiple_of(8) && self.mmap[start..end].chunks_exact(8).all(|word| {
+            let offset = u64::from_le_bytes(word.try_into().unwrap());
+            offset == 0 || known.contains(&offset)
+        })
+    }
+
+    /// Append `entries` of this file to `fresh` as stored: compressed and delta entries stay that way.
+    fn copy_entries(&self, fresh: &mut Self, entries: &[(Hash, usize)]) -> Result<()> {
+        let mut batch       = Vec::new();
+        let mut batch_bytes = 0;
+        for &(hash, pos) in entries {
+            let stored = self.entry_bytes(pos);
+            batch.push((hash, self.entry_size_field(pos), Cow::Borrowed(stored)));
+            batch_bytes += stored.len();
+
+            if batch_bytes >= COMPACT_BATCH_BYTES {
+                fresh.append_entries(&batch)?;
+                batch.clear();
+                batch_bytes = 0;
+            }
+        }
+        fresh.append_entries(&batch)
+    }
+
+    /// `keep` plus every base a kept delta needs, transitively.
+    fn with_delta_bases(&self, keep: &Xxh3HashSet<Hash>) -> Xxh3HashSet<Hash> {
+        let mut all   = keep.clone();
+        let mut stack = keep.iter().copied().collect::<Vec<_>>();
+        while let Some(hash) = stack.pop() {
+            if let Some(base) = self.delta_base(&hash) {
+                if all.insert(base) {
+                    stack.push(base);
+                }
+            }
+        }
+        all
+    }
+
+    /// Every (hash, file offset) in the hash table.
+    #[inline]
+    fn entries(&self) -> impl Iterator<Item = (Hash, usize)> + '_ {
+        (0..self.buckets).filter_map(|bucket| {
+            let pos = self.get_bucket_offset(bucket) as usize;
+            if pos == 0 || pos + ENTRY_HEADER_SIZE > self.mmap.len() {
+                return None;
+            }
+            Some((self.mmap[pos..pos + 32].try_into().unwrap(), pos))
+        })
+    }
+
+    #[inline]
+    fn entry_size_field(&self, pos: usize) -> u32 {
+        u32::from_le_bytes(self.mmap[pos + 32..pos + 36].try_into().unwrap())
+    }
+
+    /// Bytes the entry occupies after its header (compressed size for compressed entries).
+    #[inline]
+    fn entry_size(&self, pos: usize) -> usize {
+        (self.entry_size_field(pos) & ENTRY_SIZE_MASK) as usize
+    }
+
+    #[inline]
+    fn entry_is_compressed(&self, pos: usize) -> bool {
+        self.entry_size_field(pos) & ENTRY_COMPRESSED != 0
+    }
+
+    #[inline]
+    fn entry_is_delta(&self, pos: usize) -> bool {
+        self.entry_size_field(pos) & ENTRY_DELTA != 0
+    }
+
+    /// Whether the whole entry is inside the current mapping (it may have been flushed since the last remap).
+    #[inline]
+    fn entry_is_mapped(&self, pos: usize) -> bool {
+        pos + ENTRY_HEADER_SIZE <= self.mmap.len() && pos + ENTRY_HEADER_SIZE + self.entry_size(pos) <= self.mmap.len()
+    }
+
+    /// Stored bytes with compression undone: the encoded object, or a delta record.
+    #[inline]
+    fn entry_logical_bytes(&self, pos: usize) -> Result<Cow<'_, [u8]>> {
+        let stored = self.entry_bytes(pos);
+        if self.entry_is_compressed(pos) {
+            decompress_entry(stored).map(Cow::Owned)
+        } else {
+            Ok(Cow::Borrowed(stored))
         }
     }
 
+    /// Base and chain depth of a delta entry, None for whole objects.
+    #[inline]
+    fn entry_delta_base(&self, pos: usize) -> Result<Option<(Hash, u8)>> {
+        if !self.entry_is_delta(pos) {
+            return Ok(None);
+        }
+
+        let logical = self.entry_logical_bytes(pos)?;
+        let (base, depth, _) = split_delta_record(&logical)?;
+        Ok(Some((base, depth)))
+    }
+
+    /// Base bytes and the depth a new delta against `base` would have, if that is allowed.
+    fn delta_candidate(&self, base: &Hash) -> Option<(u8, Cow<'_, [u8]>)> {
+        let pos = self.find_entry(base)?;
+        if !self.entry_is_mapped(pos) {
+            return None;
+        }
+
+        let depth = self.entry_delta_base(pos).ok()?.map_or(0, |(_, depth)| depth) + 1;
+        if depth > MAX_DELTA_DEPTH {
+            return None;
+        }
+
+        Some((depth, self.read(base).ok()?))
+    }
+
+    #[inline]
+    fn entry_bytes(&self, pos: usize) -> &[u8] {
+        let start = pos + ENTRY_HEADER_SIZE;
+        &self.mmap[start..start + self.entry_size(pos)]
+    }
+
     /// Push encoded bytes; caller hashes. Used by `write_object`.
     #[inline]
     pub fn write(&mut self, hash: Hash, data: impl Into<Box<[u8]>>) {
@@ -253,12 +840,47 @@ impl Storage {
         Ok(())
     }
 
+    /// Map the whole file as it is now, picking up whatever this or another process appended,
+    /// and the live hash table, which another process may have grown.
     #[inline]
     pub fn remap(&mut self) -> Result<()> {
-        self.mmap = unsafe { MmapOptions::new().map_mut(&self.file)? };
+        self.mmap     = unsafe { MmapOptions::new().map_mut(&self.file)? };
+        self.file_len = self.mmap.len() as u64;
+        (self.table_offset, self.buckets, self.max_load_permille) = read_table_fields(&self.mmap)?;
         Ok(())
     }
 
+    /// Take the cross-process write lock and catch up with the file on disk: appends by other
+    /// processes, a grown table, or a whole new file renamed in by another process's gc/repack.
+    fn lock_for_write(&mut self) -> Result<WriteLock> {
+        loop {
+            let lock = WriteLock::acquire(&self.file)?;
+            if !self.replaced_on_disk()? {
+                self.remap()?;
+                return Ok(lock);
+            }
+
+            drop(lock);
+            let pending = core::mem::take(&mut self.pending_writes);
+            *self = Self::open_existing(&self.path)?;
+            self.pending_writes = pending;
+        }
+    }
+
+    /// Whether `objects.bin` is no longer the file we have open.
+    #[inline]
+    fn replaced_on_disk(&self) -> Result<bool> {
+        #[cfg(unix)] {
+            use std::os::unix::fs::MetadataExt;
+            let (ours, theirs) = (self.file.metadata()?, std::fs::metadata(&self.path)?);
+            Ok(ours.ino() != theirs.ino() || ours.dev() != theirs.dev())
+        }
+
+        #[cfg(not(unix))] {
+            Ok(false)
+        }
+    }
+
     #[inline]
     pub fn evict_pages(data: &[u8]) {
         #[cfg(unix)] {
@@ -278,41 +900,96 @@ impl Storage {
     /// Write encoded objects from caller buffers. One buffer, one `write_at`.
     #[inline]
     pub fn write_batch<'a>(&mut self, writes: impl Iterator<Item = (Hash, &'a [u8])>) -> Result<()> {
+        self.flush_impl(writes.map(|(hash, data)| (hash, data, None)))
+    }
+
+    /// Like `write_batch`, but each object may name a stored object it is likely similar to
+    /// (e.g. the previous version of the same file). It is stored as a delta against it when that is much smaller.
+    #[inline]
+    pub fn write_batch_with_delta_bases<'a>(&mut self, writes: impl Iterator<Item = (Hash, &'a [u8], Option<Hash>)>) -> Result<()> {
         self.flush_impl(writes)
     }
 
     #[inline]
     pub fn flush(&mut self) -> Result<()> {
         let writes = core::mem::take(&mut self.pending_writes);
-        self.flush_impl(writes.iter().map(|p| (p.hash, p.data.as_ref())))?;
+        self.flush_impl(writes.iter().map(|p| (p.hash, p.data.as_ref(), None)))?;
         self.sync()?;
         Ok(())
     }
 
     // @Cleanup
-    pub fn flush_impl<'a>(&mut self, writes: impl Iterator<Item = (Hash, &'a [u8])>) -> Result<()> {
+    pub fn flush_impl<'a>(&mut self, writes: impl Iterator<Item = (Hash, &'a [u8], Option<Hash>)>) -> Result<()> {
         let _span = tracy::span!("Storage::flush");
 
-        let mut buf        = Vec::new();
-        let mut to_insert  = Vec::new();
-        let mut offset     = self.file_len;
+        let writes = writes.collect::<Vec<_>>();
+        if writes.is_empty() {
+            return Ok(());
+        }
 
-        for (hash, encoded) in writes {
-            if self.exists(&hash) {
-                continue;
-            }
+        //
+        // Another process may have appended since we last looked: dedup and append against the file as it is now.
+        //
+        let _lock = self.lock_for_write()?;
+
+        let mut seen = Xxh3HashSet::default();
+        let fresh = writes
+            .into_iter()
+            .filter(|(hash, _, _)| !self.exists(hash) && seen.insert(*hash))
+            .collect::<Vec<_>>();
 
-            buf.extend_from_slice(&hash);
-            buf.extend_from_slice(&(encoded.len() as u32).to_le_bytes());
-            buf.extend_from_slice(encoded);
-            to_insert.push((hash, offset));
-            offset += (ENTRY_HEADER_SIZE + encoded.len()) as u64;
+        if fresh.is_empty() {
+            return Ok(());
         }
 
-        if buf.is_empty() {
+        let entries = fresh
+            .par_iter()
+            .map(|&(hash, encoded, base)| {
+                let candidate = base
+                    .filter(|_| encoded.len() >= MIN_DELTA_SIZE)
+                    .and_then(|base| Some((base, self.delta_candidate(&base)?)));
+
+                let (size_field, stored) = match &candidate {
+                    Some((base, (depth, base_bytes))) => encode_entry(encoded, Some((base, *depth, base_bytes))),
+                    None                              => encode_entry(encoded, None),
+                };
+                (hash, size_field, stored)
+            })
+            .collect::<Vec<_>>();
+
+        self.append_entries(&entries)
+    }
+
+    /// Append already-encoded entries (size field + stored bytes) and index them.
+    /// Callers make sure none of the hashes are stored yet.
+    fn append_entries(&mut self, entries: &[(Hash, u32, Cow<'_, [u8]>)]) -> Result<()> {
+        if entries.is_empty() {
             return Ok(());
         }
 
+        //
+        // The top bits of the size field are flags, so a longer entry would come back with the wrong ones.
+        //
+        if let Some((hash, _, stored)) = entries.iter().find(|(_, _, stored)| stored.len() > ENTRY_SIZE_MASK as usize) {
+            bail!(
+                "object {} takes {} bytes stored, more than the {ENTRY_SIZE_MASK} an entry can hold",
+                crate::hash::hash_to_hex(hash),
+                stored.len()
+            );
+        }
+
+        let mut buf       = Vec::new();
+        let mut to_insert = Vec::with_capacity(entries.len());
+        let mut offset    = self.file_len;
+
+        for (hash, size_field, stored) in entries {
+            buf.extend_from_slice(hash);
+            buf.extend_from_slice(&size_field.to_le_bytes());
+            buf.extend_from_slice(stored);
+            to_insert.push((*hash, offset));
+            offset += (ENTRY_HEADER_SIZE + stored.len()) as u64;
+        }
+
         let current_size = self.file_len;
         self.file_len = offset;
         self.file.set_len(self.file_len)?;
@@ -322,23 +999,142 @@ impl Storage {
         #[cfg(not(unix))]
         { self.file.seek(SeekFrom::Start(current_size))?; self.file.write_all(&buf)?; }
 
+        let count = self.count() + to_insert.len() as u64;
+        if count * 1000 > self.buckets as u64 * u64::from(self.max_load_permille) {
+            self.grow_table(Self::buckets_for(count as usize, self.max_load_permille))?;
+        }
+
         for (hash, offset) in &to_insert {
-            let bucket = Self::hash_to_bucket(hash);
-            let mut current_bucket = bucket;
-            loop {
-                if self.get_bucket_offset(current_bucket) == 0 {
-                    self.set_bucket_offset(current_bucket, *offset);
-                    break;
-                }
+            self.insert_bucket(hash, *offset)?;
+        }
+
+        self.mmap[8..16].copy_from_slice(&count.to_le_bytes());
+
+        //
+        // Older files get bumped to the current version before they can hold a compressed entry.
+        //
+        self.write_table_header();
+
+        Ok(())
+    }
+}
+
+/// Live hash table offset, bucket count and load limit from the header.
+fn read_table_fields(mmap: &[u8]) -> Result<(usize, usize, u32)> {
+    let version = u32::from_le_bytes(mmap[4..8].try_into()?);
+    let (table_offset, buckets, max_load_permille) = match version {
+        1 => (HEADER_SIZE, V1_HASH_TABLE_BUCKETS, DEFAULT_MAX_LOAD_PERMILLE),
+        2..=4 => (
+            u64::from_le_bytes(mmap[24..32].try_into()?) as usize,
+            u64::from_le_bytes(mmap[32..40].try_into()?) as usize,
+            u32::from_le_bytes(mmap[40..44].try_into()?),
+        ),
+        _ => bail!("unsupported object database version {version} (this mog understands up to {VERSION})"),
+    };
+
+    if !buckets.is_power_of_two() || table_offset + buckets * 8 > mmap.len() {
+        bail!("corrupted object database hash table");
+    }
 
-                current_bucket = (current_bucket + 1) % HASH_TABLE_BUCKETS;
-                if current_bucket == bucket { bail!("hash table full"); }
+    Ok((table_offset, buckets, max_load_permille))
+}
+
+/// Advisory `flock` on `objects.bin`, held while appending or rewriting it. Blocks until other
+/// processes are done. Holds its own descriptor so it stays valid if the `Storage` reopens the file.
+struct WriteLock {
+    #[cfg_attr(not(unix), allow(dead_code))]
+    file: File,
+}
+
+impl WriteLock {
+    #[inline]
+    fn acquire(file: &File) -> Result<Self> {
+        let file = file.try_clone()?;
+
+        #[cfg(unix)] {
+            use std::os::unix::io::AsRawFd;
+            if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+                return Err(std::io::Error::last_os_error().into());
             }
         }
 
-        let count = u64::from_le_bytes(self.mmap[8..16].try_into()?);
-        self.mmap[8..16].copy_from_slice(&(count + to_insert.len() as u64).to_le_bytes());
+        Ok(Self { file })
+    }
+}
+
+impl Drop for WriteLock {
+    fn drop(&mut self) {
+        #[cfg(unix)] {
+            use std::os::unix::io::AsRawFd;
+            unsafe { libc::flock(self.file.as_raw_fd(), libc::LOCK_UN) };
+        }
+    }
+}
 
-        Ok(())
+//
+//
+// Compression
+//
+//
+
+/// The size field and bytes to store for `encoded`: a delta record against `base` (base hash, its new
+/// chain depth, base bytes) when that is under half the size, compressed when that helps.
+#[inline]
+fn encode_entry<'a>(encoded: &'a [u8], base: Option<(&Hash, u8, &[u8])>) -> (u32, Cow<'a, [u8]>) {
+    if let Some((base_hash, depth, base_bytes)) = base {
+        let max_ops = (encoded.len() / 2).saturating_sub(DELTA_RECORD_HEADER_SIZE);
+        if let Some(ops) = crate::delta::compute(base_bytes, encoded, max_ops) {
+            let mut record = Vec::with_capacity(DELTA_RECORD_HEADER_SIZE + ops.len());
+            record.extend_from_slice(base_hash);
+            record.push(depth);
+            record.extend_from_slice(&ops);
+
+            let (size_field, stored) = compress_entry(&record);
+            return (size_field | ENTRY_DELTA, Cow::Owned(stored.into_owned()));
+        }
     }
+
+    compress_entry(encoded)
+}
+
+#[inline]
+fn split_delta_record(record: &[u8]) -> Result<(Hash, u8, &[u8])> {
+    if record.len() < DELTA_RECORD_HEADER_SIZE {
+        bail!("corrupted delta object");
+    }
+    Ok((record[..32].try_into()?, record[32], &record[DELTA_RECORD_HEADER_SIZE..]))
+}
+
+/// The size field and bytes to store for `encoded`: a zstd frame when that is smaller, else the raw bytes.
+#[inline]
+fn compress_entry(encoded: &[u8]) -> (u32, Cow<'_, [u8]>) {
+    if encoded.len() >= MIN_COMPRESS_SIZE {
+        if let Ok(frame) = zstd::bulk::compress(encoded, ZSTD_LEVEL) {
+            if 4 + frame.len() < encoded.len() {
+                let mut stored = Vec::with_capacity(4 + frame.len());
+                stored.extend_from_slice(&(encoded.len() as u32).to_le_bytes());
+                stored.extend_from_slice(&frame);
+                return (stored.len() as u32 | ENTRY_COMPRESSED, Cow::Owned(stored));
+            }
+        }
+    }
+
+    (encoded.len() as u32, Cow::Borrowed(encoded))
+}
+
+#[inline]
+fn decompress_entry(stored: &[u8]) -> Result<Vec<u8>> {
+    let _span = tracy::span!("Storage::decompress");
+
+    if stored.len() < 4 {
+        bail!("corrupted compressed object");
+    }
+
+    let len  = u32::from_le_bytes(stored[..4].try_into()?) as usize;
+    let data = zstd::bulk::decompress(&stored[4..], len)?;
+    if data.len() != len {
+        bail!("corrupted compressed object: expected {len} bytes, got {}", data.len());
+    }
+
+    Ok(data)
 }
@@ -2,6 +2,8 @@ use crate::hash::Hash;
 use crate::storage::MogStorage;
 use crate::util::Xxh3HashMap;
 
+use std::borrow::Cow;
+
 use anyhow::Result;
 
 /// In-memory object store for tests. No disk, no mmap, no eviction.
@@ -31,9 +33,9 @@ impl MogStorage for MockStorage {
     }
 
     #[inline]
-    fn read<'a>(&'a self, hash: &Hash) -> Result<&'a [u8]> {
+    fn read<'a>(&'a self, hash: &Hash) -> Result<Cow<'a, [u8]>> {
         self.objects.get(hash)
-            .map(AsRef::as_ref)
+            .map(|data| Cow::Borrowed(data.as_ref()))
             .ok_or_else(|| anyhow::anyhow!("object not found: {}", crate::hash::hash_to_hex(hash)))
     }
 
@@ -58,4 +60,9 @@ impl MogStorage for MockStorage {
 
     #[inline]
     fn evict_pages(_data: &[u8]) {}
+
+    #[inline]
+    fn for_each_hash(&self, f: impl FnMut(&Hash)) {
+        self.objects.keys().for_each(f);
+    }
 }
@@ -1,6 +1,8 @@
 use crate::hash::Hash;
-use crate::commit::{CommitPayloadOwned, CommitPayloadRef};
+use crate::chunk::{ChunkListPayloadOwned, ChunkListPayloadRef};
+use crate::commit::{COMMIT_FORMAT_VERSION, CommitPayloadOwned, CommitPayloadRef, Signature};
 use crate::object::{Object, ObjectTag};
+use crate::tag::{TagPayloadOwned, TagPayloadRef};
 use crate::tree::{TreeEntry, TreeEntryRef, TreePayloadOwned, TreePayloadRef};
 use crate::util::str_from_utf8_data_shouldve_been_valid_or_we_got_hacked;
 use crate::wire::{Decode, Encode, ReadCursor, WriteCursor};
@@ -20,11 +22,21 @@ entity_impl!(TreeId, "tree");
 pub struct CommitId(u32);
 entity_impl!(CommitId, "commit");
 
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TagId(u32);
+entity_impl!(TagId, "tag");
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ChunkListId(u32);
+entity_impl!(ChunkListId, "chunk_list");
+
 #[derive(Default)]
 pub struct Stores {
     pub blob: BlobStore,
     pub tree: TreeStore,
     pub commit: CommitStore,
+    pub tag: TagStore,
+    pub chunk_list: ChunkListStore,
 }
 
 impl Stores {
@@ -58,6 +70,16 @@ impl Stores {
                 let id = self.commit.push_payload_owned(&p);
                 Ok(Object::Commit(id))
             }
+            Some(ObjectTag::Tag) => {
+                let p = TagPayloadOwned::decode(&mut r)?;
+                let id = self.tag.push_payload_owned(&p);
+                Ok(Object::Tag(id))
+            }
+            Some(ObjectTag::ChunkList) => {
+                let p = ChunkListPayloadOwned::decode(&mut r)?;
+                let id = self.chunk_list.push_payload_owned(&p);
+                Ok(Object::ChunkList(id))
+            }
             None => bail!("unknown object type"),
         }
     }
@@ -84,6 +106,14 @@ impl Stores {
                 into.push(ObjectTag::Commit.as_byte());
                 CommitPayloadRef::new(&self.commit, id).view().encode(&mut WriteCursor::new(into));
             }
+            Object::Tag(id) => {
+                into.push(ObjectTag::Tag.as_byte());
+                TagPayloadRef::new(&self.tag, id).view().encode(&mut WriteCursor::new(into));
+            }
+            Object::ChunkList(id) => {
+                into.push(ObjectTag::ChunkList.as_byte());
+                ChunkListPayloadRef::new(&self.chunk_list, id).view().encode(&mut WriteCursor::new(into));
+            }
         }
     }
 }
@@ -179,11 +209,17 @@ impl TreeStore {
     #[must_use]
     #[inline]
     pub fn find_entry(&self, id: TreeId, name: &str) -> Option<Hash> {
+        self.find_entry_with_mode(id, name).map(|(hash, _)| hash)
+    }
+
+    #[must_use]
+    #[inline]
+    pub fn find_entry_with_mode(&self, id: TreeId, name: &str) -> Option<(Hash, u32)> {
         let n = self.entry_count(id);
         for j in 0..n {
-            let TreeEntryRef { hash, name: entry_name, .. } = self.get_entry_ref(id, j);
+            let TreeEntryRef { hash, name: entry_name, mode } = self.get_entry_ref(id, j);
             if entry_name == name {
-                return Some(hash);
+                return Some((hash, mode));
             }
         }
         None
@@ -192,6 +228,8 @@ impl TreeStore {
 
 #[derive(Default)]
 pub struct CommitStore {
+    pub version: Vec<u32>,
+
     pub tree: Vec<Hash>,
 
     pub parent_count: Vec<u32>,
@@ -200,10 +238,23 @@ pub struct CommitStore {
     pub parents: Vec<Hash>,
 
     pub timestamp: Vec<i64>,
+    pub author_tz: Vec<i32>,
 
     pub author_start: Vec<u32>, // Into `strings`
     pub author_len: Vec<u32>,
 
+    pub author_email_start: Vec<u32>, // Into `strings`
+    pub author_email_len: Vec<u32>,
+
+    pub committer_timestamp: Vec<i64>,
+    pub committer_tz: Vec<i32>,
+
+    pub committer_start: Vec<u32>, // Into `strings`
+    pub committer_len: Vec<u32>,
+
+    pub committer_email_start: Vec<u32>, // Into `strings`
+    pub committer_email_len: Vec<u32>,
+
     pub message_start: Vec<u32>, // Into `strings`
     pub message_len: Vec<u32>,
 
@@ -211,21 +262,71 @@ pub struct CommitStore {
 }
 
 impl CommitStore {
+    /// A commit by `author` at `timestamp` (UTC, no email), committed by the author at the same time.
     #[inline]
     pub fn push(&mut self, tree: Hash, parents: &[Hash], timestamp: i64, author: &str, message: &str) -> CommitId {
+        let author = Signature { name: author, email: "", timestamp, tz_offset: 0 };
+        self.push_signed(tree, parents, &author, &author, message)
+    }
+
+    #[inline]
+    pub fn push_signed(
+        &mut self,
+        tree: Hash,
+        parents: &[Hash],
+        author: &Signature<'_>,
+        committer: &Signature<'_>,
+        message: &str,
+    ) -> CommitId {
+        self.push_versioned(COMMIT_FORMAT_VERSION, tree, parents, author, committer, message)
+    }
+
+    /// Keeps the version it was decoded with, so re-encoding reproduces the same bytes (and hash).
+    #[inline]
+    pub fn push_payload_owned(&mut self, p: &CommitPayloadOwned) -> CommitId {
+        self.push_versioned(p.version, p.tree, &p.parents, &p.author_signature(), &p.committer_signature(), &p.message)
+    }
+
+    fn push_versioned(
+        &mut self,
+        version: u32,
+        tree: Hash,
+        parents: &[Hash],
+        author: &Signature<'_>,
+        committer: &Signature<'_>,
+        message: &str,
+    ) -> CommitId {
         let id = CommitId::new(self.tree.len());
 
+        self.version.push(version);
+
         self.tree.push(tree);
 
         self.parent_count.push(parents.len() as u32);
         self.parent_start.push(self.parents.len() as u32);
         self.parents.extend_from_slice(parents);
 
-        self.timestamp.push(timestamp);
+        self.timestamp.push(author.timestamp);
+        self.author_tz.push(author.tz_offset);
 
         self.author_start.push(self.strings.len() as u32);
-        self.strings.extend_from_slice(author.as_bytes());
-        self.author_len.push(author.len() as u32);
+        self.strings.extend_from_slice(author.name.as_bytes());
+        self.author_len.push(author.name.len() as u32);
+
+        self.author_email_start.push(self.strings.len() as u32);
+        self.strings.extend_from_slice(author.email.as_bytes());
+        self.author_email_len.push(author.email.len() as u32);
+
+        self.committer_timestamp.push(committer.timestamp);
+        self.committer_tz.push(committer.tz_offset);
+
+        self.committer_start.push(self.strings.len() as u32);
+        self.strings.extend_from_slice(committer.name.as_bytes());
+        self.committer_len.push(committer.name.len() as u32);
+
+        self.committer_email_start.push(self.strings.len() as u32);
+        self.strings.extend_from_slice(committer.email.as_bytes());
+        self.committer_email_len.push(committer.email.len() as u32);
 
         self.message_start.push(self.strings.len() as u32);
         self.strings.extend_from_slice(message.as_bytes());
@@ -235,8 +336,9 @@ impl CommitStore {
     }
 
     #[inline]
-    pub fn push_payload_owned(&mut self, p: &CommitPayloadOwned) -> CommitId {
-        self.push(p.tree, &p.parents, p.timestamp, &p.author, &p.message)
+    #[must_use]
+    pub fn get_version(&self, id: CommitId) -> u32 {
+        self.version[id.index()]
     }
 
     #[inline]
@@ -254,6 +356,7 @@ impl CommitStore {
         &self.parents[start..start + count]
     }
 
+    /// When the change was authored.
     #[inline]
     #[must_use]
     pub fn get_timestamp(&self, id: CommitId) -> i64 {
@@ -264,17 +367,209 @@ impl CommitStore {
     #[must_use]
     pub fn get_author(&self, id: CommitId) -> &str {
         let i = id.index();
-        let start = self.author_start[i] as usize;
-        let len = self.author_len[i] as usize;
-        str_from_utf8_data_shouldve_been_valid_or_we_got_hacked(&self.strings[start..start + len])
+        self.get_str(self.author_start[i], self.author_len[i])
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn get_author_email(&self, id: CommitId) -> &str {
+        let i = id.index();
+        self.get_str(self.author_email_start[i], self.author_email_len[i])
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn get_author_signature(&self, id: CommitId) -> Signature<'_> {
+        let i = id.index();
+        Signature {
+            name:      self.get_author(id),
+            email:     self.get_author_email(id),
+            timestamp: self.timestamp[i],
+            tz_offset: self.author_tz[i],
+        }
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn get_committer(&self, id: CommitId) -> &str {
+        let i = id.index();
+        self.get_str(self.committer_start[i], self.committer_len[i])
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn get_committer_email(&self, id: CommitId) -> &str {
+        let i = id.index();
+        self.get_str(self.committer_email_start[i], self.committer_email_len[i])
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn get_committer_signature(&self, id: CommitId) -> Signature<'_> {
+        let i = id.index();
+        Signature {
+            name:      self.get_committer(id),
+            email:     self.get_committer_email(id),
+            timestamp: self.committer_timestamp[i],
+            tz_offset: self.committer_tz[i],
+        }
     }
 
     #[inline]
     #[must_use]
     pub fn get_message(&self, id: CommitId) -> &str {
+        let i = id.index();
+        self.get_str(self.message_start[i], self.message_len[i])
+    }
+
+    #[inline]
+    fn get_str(&self, start: u32, len: u32) -> &str {
+        let start = start as usize;
+        let len = len as usize;
+        str_from_utf8_data_shouldve_been_valid_or_we_got_hacked(&self.strings[start..start + len])
+    }
+}
+
+#[derive(Default)]
+pub struct TagStore {
+    pub target: Vec<Hash>,
+
+    pub timestamp: Vec<i64>,
+
+    pub name_start: Vec<u32>, // Into `strings`
+    pub name_len: Vec<u32>,
+
+    pub tagger_start: Vec<u32>, // Into `strings`
+    pub tagger_len: Vec<u32>,
+
+    pub message_start: Vec<u32>, // Into `strings`
+    pub message_len: Vec<u32>,
+
+    pub strings: Vec<u8>,
+}
+
+impl TagStore {
+    #[inline]
+    pub fn push(&mut self, target: Hash, timestamp: i64, name: &str, tagger: &str, message: &str) -> TagId {
+        let id = TagId::new(self.target.len());
+
+        self.target.push(target);
+
+        self.timestamp.push(timestamp);
+
+        self.name_start.push(self.strings.len() as u32);
+        self.strings.extend_from_slice(name.as_bytes());
+        self.name_len.push(name.len() as u32);
+
+        self.tagger_start.push(self.strings.len() as u32);
+        self.strings.extend_from_slice(tagger.as_bytes());
+        self.tagger_len.push(tagger.len() as u32);
+
+        self.message_start.push(self.strings.len() as u32);
+        self.strings.extend_from_slice(message.as_bytes());
+        self.message_len.push(message.len() as u32);
+
+        id
+    }
+
+    #[inline]
+    pub fn push_payload_owned(&mut self, p: &TagPayloadOwned) -> TagId {
+        self.push(p.target, p.timestamp, &p.name, &p.tagger, &p.message)
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn get_target(&self, id: TagId) -> Hash {
+        self.target[id.index()]
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn get_timestamp(&self, id: TagId) -> i64 {
+        self.timestamp[id.index()]
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn get_name(&self, id: TagId) -> &str {
+        let i = id.index();
+        let start = self.name_start[i] as usize;
+        let len = self.name_len[i] as usize;
+        str_from_utf8_data_shouldve_been_valid_or_we_got_hacked(&self.strings[start..start + len])
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn get_tagger(&self, id: TagId) -> &str {
+        let i = id.index();
+        let start = self.tagger_start[i] as usize;
+        let len = self.tagger_len[i] as usize;
+        str_from_utf8_data_shouldve_been_valid_or_we_got_hacked(&self.strings[start..start + len])
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn get_message(&self, id: TagId) -> &str {
         let i = id.index();
         let start = self.message_start[i] as usize;
         let len = self.message_len[i] as usize;
         str_from_utf8_data_shouldve_been_valid_or_we_got_hacked(&self.strings[start..start + len])
     }
 }
+
+#[derive(Default)]
+pub struct ChunkListStore {
+    pub size: Vec<u64>,
+
+    pub chunk_start: Vec<u32>, // Into `hashes`/`lens`
+    pub chunk_count: Vec<u32>,
+
+    pub hashes: Vec<Hash>,
+    pub lens: Vec<u32>,
+}
+
+impl ChunkListStore {
+    #[inline]
+    pub fn push(&mut self, size: u64, hashes: &[Hash], lens: &[u32]) -> ChunkListId {
+        let id = ChunkListId::new(self.size.len());
+
+        self.size.push(size);
+
+        self.chunk_start.push(self.hashes.len() as u32);
+        self.chunk_count.push(hashes.len() as u32);
+
+        self.hashes.extend_from_slice(hashes);
+        self.lens.extend_from_slice(lens);
+
+        id
+    }
+
+    #[inline]
+    pub fn push_payload_owned(&mut self, p: &ChunkListPayloadOwned) -> ChunkListId {
+        self.push(p.size, &p.hashes, &p.lens)
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn get_size(&self, id: ChunkListId) -> u64 {
+        self.size[id.index()]
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn get_hashes(&self, id: ChunkListId) -> &[Hash] {
+        let i = id.index();
+        let start = self.chunk_start[i] as usize;
+        let count = self.chunk_count[i] as usize;
+        &self.hashes[start..start + count]
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn get_lens(&self, id: ChunkListId) -> &[u32] {
+        let i = id.index();
+        let start = self.chunk_start[i] as usize;
+        let count = self.chunk_count[i] as usize;
+        &self.lens[start..start + count]
+    }
+}
@@ -0,0 +1,224 @@
+use crate::hash::{Hash, hash_to_hex};
+use crate::identity;
+use crate::object::Object;
+use crate::repository::Repository;
+use crate::store::{TagId, TagStore};
+use crate::wire::{Decode, Encode, ReadCursor, WriteCursor};
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Result, bail};
+
+crate::payload_triple! {
+    owned TagPayloadOwned {
+        target: Hash,
+        timestamp: i64,
+        name: Box<str>,
+        tagger: Box<str>,
+        message: Box<str>,
+    }
+    view TagPayloadView<'a> {
+        target: Hash,
+        timestamp: i64,
+        name: &'a str,
+        tagger: &'a str,
+        message: &'a str,
+    }
+    ref TagPayloadRef<'a> {
+        store: &'a TagStore,
+        id: TagId,
+    }
+    view_from_owned(o) {
+        TagPayloadView {
+            target: o.target,
+            timestamp: o.timestamp,
+            name: &o.name,
+            tagger: &o.tagger,
+            message: &o.message,
+        }
+    }
+    view_from_ref(r) {
+        TagPayloadView {
+            target: r.store.get_target(r.id),
+            timestamp: r.store.get_timestamp(r.id),
+            name: r.store.get_name(r.id),
+            tagger: r.store.get_tagger(r.id),
+            message: r.store.get_message(r.id),
+        }
+    }
+}
+
+impl TagPayloadOwned {
+    #[must_use]
+    pub fn new(target: Hash, timestamp: i64, name: Box<str>, tagger: Box<str>, message: Box<str>) -> Self {
+        Self {
+            target,
+            timestamp,
+            name,
+            tagger,
+            message,
+        }
+    }
+}
+
+impl Decode for TagPayloadOwned {
+    fn decode(r: &mut ReadCursor<'_>) -> Result<Self> {
+        let target = r.read_hash()?;
+
+        let timestamp = r.read_i64()?;
+
+        let name = r.read_len_prefixed_str()?.into_owned();
+
+        let tagger = r.read_len_prefixed_str()?.into_owned();
+
+        let message = r.read_len_prefixed_str()?.into_owned();
+
+        Ok(TagPayloadOwned::new(
+            target,
+            timestamp,
+            name.into(),
+            tagger.into(),
+            message.into(),
+        ))
+    }
+}
+
+impl<'a> TagPayloadRef<'a> {
+    #[must_use]
+    pub fn new(store: &'a TagStore, id: TagId) -> Self {
+        Self { store, id }
+    }
+}
+
+impl Encode for TagPayloadView<'_> {
+    fn encode(&self, w: &mut WriteCursor<'_>) {
+        w.write_hash(&self.target);
+
+        w.write_i64(self.timestamp);
+
+        w.write_len_prefixed_str(self.name);
+
+        w.write_len_prefixed_str(self.tagger);
+
+        w.write_len_prefixed_str(self.message);
+    }
+}
+
+//
+//
+// Commands
+//
+//
+
+/// Print all tags with the commit they point at; annotated tags also show their message.
+pub fn list(repo: &mut Repository) -> Result<()> {
+    let tags_dir = repo.root.join(".mog/refs/tags");
+    if !tags_dir.exists() {
+        return Ok(());
+    }
+
+    let mut tags = std::fs::read_dir(&tags_dir)?
+        .filter_map(Result::ok)
+        .filter_map(|e| e.file_name().into_string().ok())
+        .collect::<Vec<_>>();
+
+    tags.sort_unstable();
+
+    for tag in tags {
+        let hash = repo.read_ref(&format!("refs/tags/{tag}"))?;
+        let Ok((commit, _)) = repo.peel_to_commit(&hash) else {
+            println!("{tag}  ?");
+            continue;
+        };
+
+        match repo.read_object(&hash)? {
+            Object::Tag(id) => {
+                let subject = repo.tag.get_message(id).lines().next().unwrap_or_default();
+                println!("{tag}  {}  {subject}", &hash_to_hex(&commit)[..8]);
+            }
+            _ => println!("{tag}  {}", &hash_to_hex(&commit)[..8]),
+        }
+    }
+
+    Ok(())
+}
+
+/// Create tag `name` at `target` (or HEAD). With a `message` this writes an annotated tag object,
+/// otherwise the ref points straight at the commit. The tagger defaults to the configured author identity.
+pub fn create(
+    repo: &mut Repository,
+    name: &str,
+    target: Option<&str>,
+    tagger: Option<&str>,
+    message: Option<&str>,
+) -> Result<()> {
+    validate_tag_name(name)?;
+
+    let refname = format!("refs/tags/{name}");
+    if repo.root.join(".mog").join(&refname).exists() {
+        bail!("tag '{name}' already exists");
+    }
+
+    let commit = match target {
+        Some(t) => repo.resolve_to_commit(t)?.0,
+        None    => repo.read_head_commit().map_err(|_| anyhow::anyhow!("cannot tag: no commits yet"))?,
+    };
+
+    let hash = match message {
+        Some(message) => {
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)?
+                .as_secs() as i64;
+
+            let tagger = identity::author(&repo.root, tagger)?.to_string();
+            let tag_id = repo.tag.push(commit, timestamp, name, &tagger, message);
+            let hash = repo.write_object(Object::Tag(tag_id));
+            repo.storage.flush()?;
+            hash
+        }
+        None => commit,
+    };
+
+    repo.write_ref(&refname, &hash, &format!("tag: created at {}", hash_to_hex(&commit)))?;
+    println!("created tag '{name}' at {}", &hash_to_hex(&commit)[..8]);
+
+    Ok(())
+}
+
+pub fn delete(repo: &Repository, name: &str) -> Result<()> {
+    let refname = format!("refs/tags/{name}");
+    if !repo.root.join(".mog").join(&refname).exists() {
+        bail!("tag '{name}' not found");
+    }
+
+    let hash = repo.read_ref(&refname)?;
+    repo.delete_ref(&refname, "tag: deleted")?;
+    println!("deleted tag '{name}' (was {})", &hash_to_hex(&hash)[..8]);
+    Ok(())
+}
+
+/// Same rules as branch names, plus no `@{` so tags never look like reflog selectors.
+pub fn validate_tag_name(name: &str) -> Result<()> {
+    if name.is_empty() {
+        bail!("tag name cannot be empty");
+    }
+    if name.contains('/') {
+        bail!("tag name cannot contain '/'");
+    }
+    if name.contains(' ') || name.contains('\t') {
+        bail!("tag name cannot contain whitespace");
+    }
+    if name.starts_with('-') {
+        bail!("tag name cannot start with '-'");
+    }
+    if name.contains(['~', '^', ':']) || name.contains("..") {
+        bail!("tag name cannot contain '~', '^', ':' or '..' (they mean something in revisions)");
+    }
+    if name.contains("@{") {
+        bail!("tag name cannot contain '@{{'");
+    }
+    if name == "HEAD" {
+        bail!("'HEAD' is not a valid tag name");
+    }
+    Ok(())
+}
@@ -20,7 +20,7 @@ pub fn unstage(repo: &mut Repository, patterns: &[PathBuf]) -> Result<()> {
 
     let default = [PathBuf::from(".")];
     let patterns = if patterns.is_empty() { &default } else { patterns };
-    let (literal_roots, combined_re) = crate::stage::classify_patterns(patterns, &current_dir);
+    let (literal_roots, combined_re) = crate::stage::classify_patterns(patterns, current_dir);
 
     //
     //
@@ -55,12 +55,7 @@ pub fn unstage(repo: &mut Repository, patterns: &[PathBuf]) -> Result<()> {
     paths_to_unstage.sort_unstable();
     paths_to_unstage.dedup();
 
-    let mut unstaged_count = 0usize;
-    for rel_string in &paths_to_unstage {
-        if index.remove(rel_string) {
-            unstaged_count += 1;
-        }
-    }
+    let unstaged_count = index.remove_many(&paths_to_unstage);
 
     if unstaged_count > 0 {
         index.save(&repo.root)?;
@@ -45,6 +45,68 @@ pub fn is_executable(metadata: &std::fs::Metadata) -> bool {
     }
 }
 
+/// Give `path` execute permission wherever it has read permission, or take it all away.
+/// Leaves the file alone (and its ctime) when it already matches.
+#[inline]
+pub fn set_executable(path: &std::path::Path, executable: bool) -> std::io::Result<()> {
+    #[cfg(unix)] {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mut permissions = std::fs::metadata(path)?.permissions();
+        let mode = permissions.mode();
+        let new_mode = if executable { mode | (mode & 0o444) >> 2 } else { mode & !0o111 };
+        if new_mode != mode {
+            permissions.set_mode(new_mode);
+            std::fs::set_permissions(path, permissions)?;
+        }
+        Ok(())
+    }
+
+    #[cfg(not(unix))] {
+        _ = (path, executable);
+        Ok(())
+    }
+}
+
+/// Make `path` a symlink to `target`, the bytes of a `MODE_LINK` blob.
+/// Without symlinks (non-unix) the target is written as a plain file, like git's `core.symlinks=false`.
+#[inline]
+pub fn create_symlink(target: &[u8], path: &std::path::Path) -> std::io::Result<()> {
+    #[cfg(unix)] {
+        use std::os::unix::ffi::OsStrExt;
+        std::os::unix::fs::symlink(std::ffi::OsStr::from_bytes(target), path)
+    }
+
+    #[cfg(not(unix))] {
+        std::fs::write(path, target)
+    }
+}
+
+/// What would be stored for the worktree entry at `path`: a symlink's target, otherwise the file's contents.
+#[inline]
+pub fn read_worktree_file(path: &std::path::Path) -> std::io::Result<Vec<u8>> {
+    if std::fs::symlink_metadata(path)?.is_symlink() {
+        read_symlink(path)
+    } else {
+        std::fs::read(path)
+    }
+}
+
+/// The target of the symlink at `path`, as the bytes stored in its blob.
+#[inline]
+pub fn read_symlink(path: &std::path::Path) -> std::io::Result<Vec<u8>> {
+    let target = std::fs::read_link(path)?;
+
+    #[cfg(unix)] {
+        use std::os::unix::ffi::OsStringExt;
+        Ok(target.into_os_string().into_vec())
+    }
+
+    #[cfg(not(unix))] {
+        Ok(target.to_string_lossy().replace('\\', "/").into_bytes())
+    }
+}
+
 #[macro_export]
 macro_rules! payload_triple {
     (
@@ -61,6 +61,14 @@ impl<'a> ReadCursor<'a> {
         Ok(v)
     }
 
+    #[inline]
+    pub fn read_i32(&mut self) -> Result<i32> {
+        self.ensure(4)?;
+        let v = i32::from_le_bytes(self.data[self.pos..self.pos + 4].try_into()?);
+        self.pos += 4;
+        Ok(v)
+    }
+
     #[inline]
     pub fn read_i64(&mut self) -> Result<i64> {
         self.ensure(8)?;
@@ -122,6 +130,11 @@ impl<'a> WriteCursor<'a> {
         self.buf.extend_from_slice(&v.to_le_bytes());
     }
 
+    #[inline]
+    pub fn write_i32(&mut self, v: i32) {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+    }
+
     #[inline]
     pub fn write_i64(&mut self, v: i64) {
         self.buf.extend_from_slice(&v.to_le_bytes());
@@ -1,9 +1,9 @@
 use crate::repository::Repository;
-use crate::object::{MODE_FILE, MODE_EXEC, MODE_DIR};
+use crate::object::MODE_DIR;
 use crate::object::Object;
 use crate::tree::TreeEntry;
 use crate::hash::Hash;
-use crate::util::is_executable;
+use crate::index::AsMetadata;
 
 use std::path::Path;
 use std::fs::{self, DirEntry};
@@ -67,11 +67,11 @@ fn write_tree_impl(repo: &mut Repository, root: &Path) -> Result<Hash> {
             }
 
             //
-            // Blob: read, hash, write object without pushing into blob store.
+            // Blob (or chunks, for large files): read, hash, write object without pushing into blob store.
+            // `DirEntry::metadata` doesn't follow links, so a symlink is stored as its target.
             //
-            let data = fs::read(&path)?;
-            let hash = repo.write_blob(&data);
-            let mode = if is_executable(&metadata) { MODE_EXEC } else { MODE_FILE };
+            let hash = crate::chunk::write_file(repo, &path)?;
+            let mode = metadata.mode();
 
             stack.last_mut().unwrap().built.push(TreeEntry {
                 hash,
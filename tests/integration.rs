@@ -139,6 +139,25 @@ fn test_unstage_all() {
     assert_eq!(index.count, 0);
 }
 
+#[test]
+fn test_unstage_many_paths_keeps_the_rest_sorted() {
+    let (_dir, root) = setup();
+    for i in 0..300 {
+        write_file(&root, &format!("dir{}/f{i:03}.rs", i % 3), format!("{i}").as_bytes());
+    }
+    stage_all(&root);
+
+    let mut repo = open(&root);
+    mog::unstage::unstage(&mut repo, &[root.join("dir1")]).unwrap();
+
+    let index = mog::index::Index::load(&root).unwrap();
+    let paths = index.iter().map(|e| e.path).collect::<Vec<_>>();
+    assert!(paths.iter().all(|p| !p.starts_with("dir1/")));
+    assert_eq!(paths.iter().filter(|p| p.starts_with("dir")).count(), 200);
+    assert!(paths.is_sorted());
+    assert!(paths.iter().all(|p| index.find(p).is_some()));
+}
+
 //
 //
 // Commit
@@ -194,6 +213,113 @@ fn test_commit_stores_correct_tree() {
     assert!(repo.tree.find_entry(tree_id, "README.md").is_some());
 }
 
+#[test]
+fn test_commit_caches_trees_and_rebuilds_only_changed_directories() {
+    let (_dir, root) = setup();
+    write_file(&root, "src/a/x.rs", b"x");
+    write_file(&root, "src/b/y.rs", b"y");
+    write_file(&root, "docs/z.md",  b"z");
+    stage_all(&root);
+    let c1 = commit_all(&root, "init");
+
+    let mut repo = open(&root);
+    let c1_id    = repo.read_object(&c1).unwrap().try_as_commit_id().unwrap();
+    let tree1    = repo.commit.get_tree(c1_id);
+    let index    = mog::index::Index::load(&root).unwrap();
+    assert_eq!(index.cached_tree(""), Some(tree1));
+    let (src_b, docs) = (index.cached_tree("src/b").unwrap(), index.cached_tree("docs").unwrap());
+
+    //
+    // Only the directories above the edit lose their cached hash.
+    //
+    write_file_later(&root, "src/a/x.rs", b"x2");
+    stage_all(&root);
+    let index = mog::index::Index::load(&root).unwrap();
+    for dir in ["", "src", "src/a"] {
+        assert_eq!(index.cached_tree(dir), None, "{dir:?}");
+    }
+    assert_eq!(index.cached_tree("src/b"), Some(src_b));
+    assert_eq!(index.cached_tree("docs"),  Some(docs));
+
+    //
+    // The commit still gets the same tree as building it from scratch.
+    //
+    let c2 = commit_all(&root, "edit");
+    let mut repo = open(&root);
+    let c2_id    = repo.read_object(&c2).unwrap().try_as_commit_id().unwrap();
+    let tree2    = repo.commit.get_tree(c2_id);
+    assert_eq!(tree2, mog::write_tree::write_tree(&mut repo, &root).unwrap());
+    assert_eq!(mog::index::Index::load(&root).unwrap().cached_tree(""), Some(tree2));
+}
+
+#[test]
+fn test_commit_amend_replaces_head_and_keeps_parents() {
+    let (_dir, root) = setup();
+    write_file(&root, "file.rs", b"v1");
+    stage_all(&root);
+    let h1 = commit_all(&root, "first");
+
+    write_file(&root, "file.rs", b"v2");
+    stage_all(&root);
+    let h2 = commit_all(&root, "second");
+
+    write_file(&root, "extra.rs", b"forgot this");
+    stage_all(&root);
+
+    let mut repo = open(&root);
+    let mut index = mog::index::Index::load(&root).unwrap();
+    let tree     = index.write_tree(&mut repo).unwrap();
+    let amended  = mog::commit::amend(&mut repo, tree, Some("test"), None).unwrap();
+    drop(repo);
+
+    let mut repo  = open(&root);
+    assert_eq!(repo.read_head_commit().unwrap(), amended);
+    assert_eq!(repo.read_ref("refs/heads/main").unwrap(), amended);
+
+    let commit_id = repo.read_object(&amended).unwrap().try_as_commit_id().unwrap();
+    assert_eq!(repo.commit.get_parents(commit_id), &[h1]);
+    assert_eq!(repo.commit.get_message(commit_id), "second");
+    assert_eq!(repo.commit.get_tree(commit_id), tree);
+
+    // The replaced commit is recorded so the amend can be undone.
+    let log = mog::reflog::read(&repo, "refs/heads/main").unwrap();
+    let last = log.last().unwrap();
+    assert_eq!(last.old, h2);
+    assert_eq!(last.new, amended);
+    assert!(last.reason.starts_with("commit (amend)"));
+}
+
+#[test]
+fn test_commit_amend_with_new_message() {
+    let (_dir, root) = setup();
+    write_file(&root, "file.rs", b"v1");
+    stage_all(&root);
+    commit_all(&root, "typo");
+
+    let mut repo = open(&root);
+    let mut index = mog::index::Index::load(&root).unwrap();
+    let tree     = index.write_tree(&mut repo).unwrap();
+    let amended  = mog::commit::amend(&mut repo, tree, Some("test"), Some("fixed")).unwrap();
+    drop(repo);
+
+    let mut repo  = open(&root);
+    let commit_id = repo.read_object(&amended).unwrap().try_as_commit_id().unwrap();
+    assert_eq!(repo.commit.get_message(commit_id), "fixed");
+    assert!(repo.commit.get_parents(commit_id).is_empty());
+}
+
+#[test]
+fn test_commit_amend_without_commits_fails() {
+    let (_dir, root) = setup();
+    write_file(&root, "file.rs", b"v1");
+    stage_all(&root);
+
+    let mut repo = open(&root);
+    let mut index = mog::index::Index::load(&root).unwrap();
+    let tree     = index.write_tree(&mut repo).unwrap();
+    assert!(mog::commit::amend(&mut repo, tree, Some("test"), None).is_err());
+}
+
 //
 //
 // Status
@@ -276,6 +402,127 @@ fn test_status_detects_deleted_on_disk() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_status_catches_same_size_edit_with_the_same_mtime() -> Result<()> {
+    let (_dir, root) = setup();
+    write_file(&root, "file.rs", b"aaaa");
+    stage_all(&root);
+
+    //
+    // Rewritten within the same mtime and with the same size: only the rest of the stat
+    // data, or the contents of a racy entry, can tell.
+    //
+    let mtime = fs::metadata(root.join("file.rs"))?.modified()?;
+    fs::write(root.join("file.rs"), b"bbbb")?;
+    filetime::set_file_mtime(root.join("file.rs"), filetime::FileTime::from_system_time(mtime))?;
+
+    let buckets = mog::status::collect_status(&mut open(&root))?;
+    assert_eq!(buckets.modified, ["file.rs".into()]);
+
+    stage_all(&root);
+    let index = mog::index::Index::load(&root)?;
+    assert_eq!(index.hashes[index.find("file.rs").unwrap()], mog::object::hash_blob(b"bbbb"));
+    Ok(())
+}
+
+#[test]
+fn test_status_skips_unchanged_directories_but_sees_staged_changes() -> Result<()> {
+    let (_dir, root) = setup();
+    write_file(&root, "src/a/x.rs", b"x");
+    write_file(&root, "src/b/y.rs", b"y");
+    write_file(&root, "docs/z.md",  b"z");
+    stage_all(&root);
+    commit_all(&root, "init");
+
+    let mut repo = open(&root);
+    let head     = repo.read_head_commit()?;
+    let head_id  = repo.read_object(&head)?.try_as_commit_id()?;
+    let tree     = repo.commit.get_tree(head_id);
+    let index    = mog::index::Index::load(&root)?;
+    let (flat, unchanged) = mog::status::flatten_tree_against_index(&mut repo, tree, &index)?;
+    assert!(flat.is_empty());
+    assert!(unchanged.contains(""));
+    assert!(mog::status::collect_status(&mut repo)?.staged_new_modified.is_empty());
+
+    //
+    // An edit and a deletion in different directories: only their paths get flattened.
+    //
+    write_file_later(&root, "src/a/x.rs", b"x2");
+    fs::remove_file(root.join("docs/z.md"))?;
+    stage_all(&root);
+
+    let index = mog::index::Index::load(&root)?;
+    let (flat, unchanged) = mog::status::flatten_tree_against_index(&mut repo, tree, &index)?;
+    assert!(flat.lookup("src/a/x.rs").is_some() && flat.lookup("docs/z.md").is_some());
+    assert!(flat.lookup("src/b/y.rs").is_none());
+    assert!(unchanged.contains("src/b"));
+
+    let buckets = mog::status::collect_status(&mut repo)?;
+    assert_eq!(buckets.staged_new_modified, ["src/a/x.rs".into()]);
+    assert_eq!(buckets.staged_deleted,      ["docs/z.md".into()]);
+    assert!(buckets.modified.is_empty() && buckets.deleted.is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_racy_entries_are_rehashed_instead_of_trusted() -> Result<()> {
+    use mog::index::Index;
+
+    let (_dir, root) = setup();
+    let set_mtime = |rel: &str, offset_secs: i64| {
+        let now = filetime::FileTime::now();
+        let t   = filetime::FileTime::from_unix_time(now.unix_seconds() + offset_secs, 0);
+        filetime::set_file_mtime(root.join(rel), t).unwrap();
+    };
+
+    fs::write(root.join("old.rs"), b"old")?;
+    fs::write(root.join("new.rs"), b"new")?;
+    set_mtime("old.rs", -100);
+    set_mtime("new.rs", 100);
+    stage_all(&root);
+
+    //
+    // Modified no earlier than the index was written (here: dated after it): smudged on
+    // write, so dirty even though its stat data matches. Status hashes it and finds it clean.
+    //
+    let index = Index::load(&root)?;
+    let (old, new) = (index.find("old.rs").unwrap(), index.find("new.rs").unwrap());
+    assert!(!index.is_racy(old));
+    assert!(!index.is_dirty(old, &fs::symlink_metadata(root.join("old.rs"))?));
+    assert_eq!(index.mtimes[new], 0);
+    assert!(index.is_dirty(new, &fs::symlink_metadata(root.join("new.rs"))?));
+
+    let buckets = mog::status::collect_status(&mut open(&root))?;
+    assert!(buckets.modified.is_empty(), "{:?}", buckets.modified);
+    Ok(())
+}
+
+#[test]
+fn test_entries_staged_after_the_previous_index_write_keep_their_mtime() -> Result<()> {
+    use mog::index::Index;
+
+    let (_dir, root) = setup();
+    let past = |secs: i64| filetime::FileTime::from_unix_time(filetime::FileTime::now().unix_seconds() - secs, 0);
+
+    write_file(&root, "a.txt", b"a");
+    filetime::set_file_mtime(root.join("a.txt"), past(300))?;
+    stage_all(&root);
+    filetime::set_file_mtime(root.join(".mog/index"), past(200))?;
+
+    //
+    // Newer than the old index file, but well before this write: not racy.
+    //
+    write_file(&root, "b.txt", b"b");
+    filetime::set_file_mtime(root.join("b.txt"), past(100))?;
+    stage_all(&root);
+
+    let index = Index::load(&root)?;
+    let b = index.find("b.txt").unwrap();
+    assert_ne!(index.mtimes[b], 0);
+    assert!(!index.is_dirty(b, &fs::symlink_metadata(root.join("b.txt"))?));
+    Ok(())
+}
+
 //
 //
 // Checkout
@@ -389,6 +636,89 @@ fn test_branch_delete() {
     assert!(!root.join(".mog/refs/heads/to-delete").exists());
 }
 
+#[test]
+fn test_branch_and_tag_names_must_resolve_as_revisions() {
+    let (_dir, root) = setup();
+    write_file(&root, "f.rs", b"x");
+    stage_all(&root);
+    commit_all(&root, "init");
+
+    let mut repo = open(&root);
+    for name in ["a~1", "a^", "a..b", "a:b"] {
+        assert!(mog::branch::create(&mut repo, name, None).is_err(), "branch '{name}' was created");
+        assert!(mog::tag::create(&mut repo, name, None, None, None).is_err(), "tag '{name}' was created");
+    }
+    assert!(mog::branch::create(&mut repo, "a@{1}", None).is_err());
+
+    mog::branch::create(&mut repo, "a.b", None).unwrap();
+    mog::tag::create(&mut repo, "v1.0", None, None, None).unwrap();
+    assert!(mog::revision::resolve_object(&mut repo, "v1.0").is_ok());
+}
+
+//
+//
+// Tag
+//
+//
+
+#[test]
+fn test_tag_lightweight_checkout_and_delete() {
+    let (_dir, root) = setup();
+    write_file(&root, "file.rs", b"v1");
+    stage_all(&root);
+    let h1 = commit_all(&root, "first");
+
+    let mut repo = open(&root);
+    mog::tag::create(&mut repo, "v1", None, Some("test"), None).unwrap();
+    assert_eq!(repo.read_ref("refs/tags/v1").unwrap(), h1);
+    assert!(mog::tag::create(&mut repo, "v1", None, Some("test"), None).is_err());
+    drop(repo);
+
+    write_file(&root, "file.rs", b"v2");
+    stage_all(&root);
+    commit_all(&root, "second");
+
+    let mut repo = open(&root);
+    mog::checkout::checkout(&mut repo, "v1").unwrap();
+    assert_eq!(read_file(&root, "file.rs"), b"v1");
+    assert_eq!(repo.read_head_commit().unwrap(), h1);
+
+    mog::tag::delete(&repo, "v1").unwrap();
+    assert!(!root.join(".mog/refs/tags/v1").exists());
+    assert!(repo.resolve_to_commit("v1").is_err());
+}
+
+#[test]
+fn test_tag_annotated_peels_to_commit() {
+    let (_dir, root) = setup();
+    write_file(&root, "file.rs", b"v1");
+    stage_all(&root);
+    let h1 = commit_all(&root, "first");
+
+    let mut repo = open(&root);
+    mog::tag::create(&mut repo, "v1.0", None, Some("releaser"), Some("first release")).unwrap();
+    drop(repo);
+
+    let mut repo = open(&root);
+    let tag_hash = repo.read_ref("refs/tags/v1.0").unwrap();
+    assert_ne!(tag_hash, h1);
+
+    let tag_id = repo.read_object(&tag_hash).unwrap().try_as_tag_id().unwrap();
+    assert_eq!(repo.tag.get_target(tag_id),  h1);
+    assert_eq!(repo.tag.get_name(tag_id),    "v1.0");
+    assert_eq!(repo.tag.get_tagger(tag_id),  "releaser");
+    assert_eq!(repo.tag.get_message(tag_id), "first release");
+
+    assert_eq!(repo.resolve_to_commit("v1.0").unwrap().0, h1);
+
+    mog::branch::create(&mut repo, "hotfix", Some("v1.0")).unwrap();
+    assert_eq!(repo.read_ref("refs/heads/hotfix").unwrap(), h1);
+
+    let mut buf = String::new();
+    mog::cat_file::cat_file(&mut repo, &mog::hash::hash_to_hex(&tag_hash), &mut buf).unwrap();
+    assert!(buf.starts_with(&format!("object {}", mog::hash::hash_to_hex(&h1))));
+}
+
 //
 //
 // Discard
@@ -441,6 +771,29 @@ fn test_discard_specific_path() {
     assert_eq!(read_file(&root, "b.rs"), b"modified_b"); // b untouched
 }
 
+#[test]
+fn test_discard_drops_uncommitted_files_from_the_index_together() {
+    let (_dir, root) = setup();
+    write_file(&root, "kept.rs", b"kept");
+    stage_all(&root);
+    commit_all(&root, "init");
+
+    for name in ["new/a.rs", "new/b.rs", "new/c.rs"] {
+        write_file(&root, name, name.as_bytes());
+    }
+    stage_all(&root);
+
+    let mut repo = open(&root);
+    mog::discard::discard(&mut repo, &[root.join("new")]).unwrap();
+
+    let index = mog::index::Index::load(&root).unwrap();
+    assert!(index.find("kept.rs").is_some());
+    for name in ["new/a.rs", "new/b.rs", "new/c.rs"] {
+        assert!(!root.join(name).exists(), "{name}");
+        assert!(index.find(name).is_none(), "{name}");
+    }
+}
+
 //
 //
 // Stash
@@ -555,119 +908,2177 @@ fn test_stash_drop() {
 
 //
 //
-// Log
+// Merge
 //
 //
 
 #[test]
-fn test_log_shows_commits_in_order() {
+fn test_merge_fast_forward() {
     let (_dir, root) = setup();
-    write_file(&root, "f.rs", b"v1");
+    write_file(&root, "f.rs", b"base");
     stage_all(&root);
-    commit_all(&root, "first commit");
+    let base = commit_all(&root, "base");
 
-    write_file(&root, "f.rs", b"v2");
+    let mut repo = open(&root);
+    mog::branch::create(&mut repo, "feature", None).unwrap();
+    mog::checkout::checkout(&mut repo, "feature").unwrap();
+    write_file(&root, "g.rs", b"feature");
     stage_all(&root);
-    commit_all(&root, "second commit");
+    let tip = commit_all(&root, "feature work");
 
     let mut repo = open(&root);
-    let mut buf  = String::new();
-    mog::log::log(&mut repo, &mut buf).unwrap();
-
-    let first_pos  = buf.find("first commit").unwrap();
-    let second_pos = buf.find("second commit").unwrap();
+    mog::checkout::checkout(&mut repo, "main").unwrap();
+    let mut repo = open(&root);
+    mog::merge::merge(&mut repo, "feature", Some("test"), None).unwrap();
 
-    // Most recent first.
-    assert!(second_pos < first_pos);
+    let repo = open(&root);
+    assert_eq!(repo.read_head_commit().unwrap(), tip);
+    assert_ne!(base, tip);
+    assert_eq!(read_file(&root, "g.rs"), b"feature");
 }
 
-//
-//
-// Full end-to-end workflow
-//
-//
-
 #[test]
-fn test_full_dev_workflow() {
+fn test_merge_clean_creates_two_parent_commit() {
     let (_dir, root) = setup();
-
-    // Initial commit.
-    write_file(&root, "src/main.rs", b"fn main() {}");
-    write_file(&root, "README.md",   b"# Project");
+    write_file(&root, "f.rs", b"1\n2\n3\n4\n5\n");
     stage_all(&root);
-    commit_all(&root, "initial commit");
+    commit_all(&root, "base");
 
-    // Feature branch.
     let mut repo = open(&root);
     mog::branch::create(&mut repo, "feature", None).unwrap();
     mog::checkout::checkout(&mut repo, "feature").unwrap();
-
-    write_file(&root, "src/feature.rs", b"pub fn feature() {}");
+    write_file(&root, "f.rs", b"1\n2\n3\n4\nfive\n");
+    write_file(&root, "new.rs", b"from feature");
     stage_all(&root);
-    commit_all(&root, "add feature");
+    let theirs = commit_all(&root, "feature edit");
 
-    // Stash mid-work change.
-    write_file(&root, "src/wip.rs", b"// work in progress");
+    let mut repo = open(&root);
+    mog::checkout::checkout(&mut repo, "main").unwrap();
+    write_file(&root, "f.rs", b"one\n2\n3\n4\n5\n");
     stage_all(&root);
-    touch_future(&root, "src/wip.rs");
+    let ours = commit_all(&root, "main edit");
+
     let mut repo = open(&root);
-    mog::stash::stash(&mut repo).unwrap();
+    mog::merge::merge(&mut repo, "feature", Some("test"), None).unwrap();
 
-    assert!(!file_exists(&root, "src/wip.rs"));
+    assert_eq!(read_file(&root, "f.rs"), b"one\n2\n3\n4\nfive\n");
+    assert_eq!(read_file(&root, "new.rs"), b"from feature");
 
-    // Switch back to main, verify feature.rs absent.
     let mut repo = open(&root);
-    mog::checkout::checkout(&mut repo, "main").unwrap();
-    assert!(!file_exists(&root, "src/feature.rs"));
-    assert_eq!(read_file(&root, "src/main.rs"), b"fn main() {}");
+    let head     = repo.read_head_commit().unwrap();
+    let id       = repo.read_object(&head).unwrap().try_as_commit_id().unwrap();
+    assert_eq!(repo.commit.get_parents(id), &[ours, theirs]);
+
+    let buckets = mog::status::collect_status(&mut repo).unwrap();
+    assert!(buckets.staged_new_modified.is_empty());
+    assert!(buckets.modified.is_empty());
+}
+
+#[test]
+fn test_merge_conflict_then_commit_after_resolve() {
+    let (_dir, root) = setup();
+    write_file(&root, "f.rs", b"a\nb\nc\n");
+    stage_all(&root);
+    commit_all(&root, "base");
 
-    // Back to feature, pop stash.
     let mut repo = open(&root);
+    mog::branch::create(&mut repo, "feature", None).unwrap();
     mog::checkout::checkout(&mut repo, "feature").unwrap();
-    let mut repo = open(&root);
-    mog::stash::stash_pop(&mut repo).unwrap();
-    assert!(file_exists(&root, "src/wip.rs"));
+    write_file(&root, "f.rs", b"a\ntheirs\nc\n");
+    stage_all(&root);
+    let theirs = commit_all(&root, "feature edit");
 
-    // Discard wip.
     let mut repo = open(&root);
-    mog::discard::discard(&mut repo, &[std::path::PathBuf::from("src/wip.rs")]).unwrap();
-    assert!(!file_exists(&root, "src/wip.rs"));
+    mog::checkout::checkout(&mut repo, "main").unwrap();
+    write_file(&root, "f.rs", b"a\nours\nc\n");
+    stage_all(&root);
+    let ours = commit_all(&root, "main edit");
 
-    // Status should be clean.
     let mut repo = open(&root);
-    let buckets  = mog::status::collect_status(&mut repo).unwrap();
-    assert!(buckets.staged_new_modified.is_empty());
-    assert!(buckets.modified.is_empty());
-    assert!(buckets.untracked.is_empty());
-}
+    mog::merge::merge(&mut repo, "feature", Some("test"), None).unwrap();
 
+    let content = read_file(&root, "f.rs");
+    assert!(mog::merge::has_conflict_markers(&content));
+    assert_eq!(open(&root).read_head_commit().unwrap(), ours);
 
-//
-//
-// Helpers
-//
-//
+    // Committing with markers still in place is refused.
+    assert!(mog::merge::resolved_merge_head(&open(&root)).is_err());
 
-fn setup() -> (TempDir, std::path::PathBuf) {
-    let dir  = TempDir::new().unwrap();
-    let root = dir.path().to_path_buf();
-    mog::repository::Repository::init(&root).unwrap();
-    (dir, root)
-}
+    write_file(&root, "f.rs", b"a\nboth\nc\n");
+    stage_all(&root);
 
-fn open(root: &Path) -> mog::repository::Repository {
-    mog::repository::Repository::open(root).unwrap()
-}
+    let repo = open(&root);
+    assert_eq!(mog::merge::resolved_merge_head(&repo).unwrap(), Some(theirs));
 
-fn write_file(root: &Path, rel: &str, content: &[u8]) {
-    let abs = root.join(rel);
-    if let Some(parent) = abs.parent() {
-        fs::create_dir_all(parent).unwrap();
-    }
-    fs::write(&abs, content).unwrap();
-    // Force mtime to change even within the same second.
-    let mtime = std::time::SystemTime::now() + std::time::Duration::from_secs(1);
-    filetime::set_file_mtime(&abs, filetime::FileTime::from_system_time(mtime)).unwrap();
+    let mut repo = open(&root);
+    let mut index = mog::index::Index::load(&repo.root).unwrap();
+    let tree     = index.write_tree(&mut repo).unwrap();
+    let merge    = mog::commit::commit(&mut repo, tree, [ours, theirs], Some("test"), "merge").unwrap();
+    mog::merge::clear_merge_state(&repo).unwrap();
+
+    let mut repo = open(&root);
+    let id       = repo.read_object(&merge).unwrap().try_as_commit_id().unwrap();
+    assert_eq!(repo.commit.get_parents(id), &[ours, theirs]);
+    assert!(mog::merge::resolved_merge_head(&repo).unwrap().is_none());
+}
+
+#[test]
+fn test_merge_abort_restores_head() {
+    let (_dir, root) = setup();
+    write_file(&root, "f.rs", b"a\nb\nc\n");
+    stage_all(&root);
+    commit_all(&root, "base");
+
+    let mut repo = open(&root);
+    mog::branch::create(&mut repo, "feature", None).unwrap();
+    mog::checkout::checkout(&mut repo, "feature").unwrap();
+    write_file(&root, "f.rs", b"a\ntheirs\nc\n");
+    stage_all(&root);
+    commit_all(&root, "feature edit");
+
+    let mut repo = open(&root);
+    mog::checkout::checkout(&mut repo, "main").unwrap();
+    write_file(&root, "f.rs", b"a\nours\nc\n");
+    stage_all(&root);
+    commit_all(&root, "main edit");
+
+    let mut repo = open(&root);
+    mog::merge::merge(&mut repo, "feature", Some("test"), None).unwrap();
+    let mut repo = open(&root);
+    mog::merge::merge_abort(&mut repo).unwrap();
+
+    assert_eq!(read_file(&root, "f.rs"), b"a\nours\nc\n");
+    assert!(!root.join(".mog/MERGE_HEAD").exists());
+}
+
+#[test]
+fn test_merge_refuses_untracked_collision_before_touching_anything() {
+    let (_dir, root) = setup();
+    write_file(&root, "a.rs", b"base a");
+    write_file(&root, "m.rs", b"base m");
+    stage_all(&root);
+    let base = commit_all(&root, "base");
+
+    let mut repo = open(&root);
+    mog::branch::create(&mut repo, "feature", None).unwrap();
+    mog::checkout::checkout(&mut repo, "feature").unwrap();
+    write_file(&root, "a.rs", b"feature a");
+    write_file(&root, "z.rs", b"feature z");
+    stage_all(&root);
+    commit_all(&root, "feature");
+
+    let mut repo = open(&root);
+    mog::checkout::checkout(&mut repo, "main").unwrap();
+    write_file(&root, "m.rs", b"main m");
+    stage_all(&root);
+    let ours = commit_all(&root, "main");
+    write_file(&root, "z.rs", b"untracked");
+
+    //
+    // a.rs sorts before z.rs, so it must not have been taken yet when the merge is refused.
+    //
+    let mut repo = open(&root);
+    let err = mog::merge::merge(&mut repo, "feature", Some("test"), None).unwrap_err();
+    assert!(err.to_string().contains("untracked file 'z.rs'"), "{err}");
+    assert_eq!(read_file(&root, "a.rs"), b"base a");
+    assert_eq!(read_file(&root, "z.rs"), b"untracked");
+    assert!(!root.join(".mog/MERGE_HEAD").exists());
+    assert_eq!(open(&root).read_head_commit().unwrap(), ours);
+    assert_ne!(base, ours);
+}
+
+#[test]
+#[cfg(unix)]
+fn test_merge_keeps_their_mode_change_when_we_changed_the_content() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let (_dir, root) = setup();
+    write_file(&root, "run.sh", b"echo 1\necho 2\n");
+    stage_all(&root);
+    commit_all(&root, "base");
+
+    let mut repo = open(&root);
+    mog::branch::create(&mut repo, "feature", None).unwrap();
+    mog::checkout::checkout(&mut repo, "feature").unwrap();
+    fs::set_permissions(root.join("run.sh"), fs::Permissions::from_mode(0o755)).unwrap();
+    stage_all(&root);
+    commit_all(&root, "chmod +x");
+
+    let mut repo = open(&root);
+    mog::checkout::checkout(&mut repo, "main").unwrap();
+    write_file(&root, "run.sh", b"echo one\necho 2\n");
+    stage_all(&root);
+    commit_all(&root, "edit");
+
+    let mut repo = open(&root);
+    mog::merge::merge(&mut repo, "feature", Some("test"), None).unwrap();
+
+    assert_eq!(read_file(&root, "run.sh"), b"echo one\necho 2\n");
+    assert!(mog::util::is_executable(&fs::metadata(root.join("run.sh")).unwrap()));
+
+    let index = mog::index::Index::load(&root).unwrap();
+    assert_eq!(index.modes[index.find("run.sh").unwrap()], mog::object::MODE_EXEC);
+}
+
+//
+//
+// Rebase
+//
+//
+
+/// main: base -> m1, feature: base -> f1 -> f2. Returns (f1, f2, m1).
+fn setup_diverged(root: &Path, f_content: &[u8], m_content: &[u8]) -> (mog::hash::Hash, mog::hash::Hash, mog::hash::Hash) {
+    write_file(root, "shared.rs", b"a\nb\nc\n");
+    stage_all(root);
+    commit_all(root, "base");
+
+    let mut repo = open(root);
+    mog::branch::create(&mut repo, "feature", None).unwrap();
+    mog::checkout::checkout(&mut repo, "feature").unwrap();
+    write_file(root, "shared.rs", f_content);
+    stage_all(root);
+    let f1 = commit_all(root, "feature one");
+    write_file(root, "feature.rs", b"feature");
+    stage_all(root);
+    let f2 = commit_all(root, "feature two");
+
+    let mut repo = open(root);
+    mog::checkout::checkout(&mut repo, "main").unwrap();
+    write_file(root, "shared.rs", m_content);
+    stage_all(root);
+    let m1 = commit_all(root, "main one");
+
+    let mut repo = open(root);
+    mog::checkout::checkout(&mut repo, "feature").unwrap();
+
+    (f1, f2, m1)
+}
+
+#[test]
+fn test_rebase_replays_commits_onto_upstream() {
+    let (_dir, root) = setup();
+    let (f1, f2, m1) = setup_diverged(&root, b"a\nb\nC\n", b"A\nb\nc\n");
+
+    let mut repo = open(&root);
+    mog::rebase::rebase(&mut repo, "main").unwrap();
+
+    assert_eq!(read_file(&root, "shared.rs"), b"A\nb\nC\n");
+    assert_eq!(read_file(&root, "feature.rs"), b"feature");
+
+    let mut repo = open(&root);
+    assert_eq!(repo.current_branch().unwrap().as_deref(), Some("feature"));
+
+    let new_f2    = repo.read_head_commit().unwrap();
+    let new_f2_id = repo.read_object(&new_f2).unwrap().try_as_commit_id().unwrap();
+    let new_f1    = repo.commit.get_parents(new_f2_id)[0];
+    let new_f1_id = repo.read_object(&new_f1).unwrap().try_as_commit_id().unwrap();
+    assert_eq!(repo.commit.get_parents(new_f1_id), &[m1]);
+    assert_eq!(repo.commit.get_message(new_f2_id), "feature two");
+
+    let old_f1_id = repo.read_object(&f1).unwrap().try_as_commit_id().unwrap();
+    let old_f2_id = repo.read_object(&f2).unwrap().try_as_commit_id().unwrap();
+    assert_eq!(repo.commit.get_timestamp(new_f1_id), repo.commit.get_timestamp(old_f1_id));
+    assert_eq!(repo.commit.get_timestamp(new_f2_id), repo.commit.get_timestamp(old_f2_id));
+    assert_eq!(repo.commit.get_author(new_f1_id), repo.commit.get_author(old_f1_id));
+    assert!(!mog::rebase::rebase_in_progress(&repo));
+}
+
+#[test]
+fn test_rebase_conflict_continue() {
+    let (_dir, root) = setup();
+    let (_f1, _f2, m1) = setup_diverged(&root, b"a\nfeature\nc\n", b"a\nmain\nc\n");
+
+    let mut repo = open(&root);
+    assert!(mog::rebase::rebase(&mut repo, "main").is_err(), "a conflict must not look like success");
+
+    assert!(mog::rebase::rebase_in_progress(&open(&root)));
+    assert!(mog::merge::has_conflict_markers(&read_file(&root, "shared.rs")));
+
+    // Unresolved conflicts block --continue.
+    assert!(mog::rebase::rebase_continue(&mut open(&root)).is_err());
+
+    write_file(&root, "shared.rs", b"a\nmain+feature\nc\n");
+    stage_all(&root);
+    mog::rebase::rebase_continue(&mut open(&root)).unwrap();
+
+    let mut repo = open(&root);
+    assert!(!mog::rebase::rebase_in_progress(&repo));
+    assert_eq!(repo.current_branch().unwrap().as_deref(), Some("feature"));
+    assert!(repo.reachable_commits(&repo.read_head_commit().unwrap()).contains(&m1));
+    assert_eq!(read_file(&root, "shared.rs"), b"a\nmain+feature\nc\n");
+    assert_eq!(read_file(&root, "feature.rs"), b"feature");
+}
+
+#[test]
+fn test_rebase_abort_restores_branch() {
+    let (_dir, root) = setup();
+    let (_f1, f2, _m1) = setup_diverged(&root, b"a\nfeature\nc\n", b"a\nmain\nc\n");
+
+    let mut repo = open(&root);
+    assert!(mog::rebase::rebase(&mut repo, "main").is_err());
+    mog::rebase::rebase_abort(&mut open(&root)).unwrap();
+
+    let repo = open(&root);
+    assert!(!mog::rebase::rebase_in_progress(&repo));
+    assert_eq!(repo.current_branch().unwrap().as_deref(), Some("feature"));
+    assert_eq!(repo.read_head_commit().unwrap(), f2);
+    assert_eq!(read_file(&root, "shared.rs"), b"a\nfeature\nc\n");
+}
+
+#[test]
+fn test_rebase_error_leaves_a_rebase_to_continue_or_abort() -> Result<()> {
+    let (_dir, root) = setup();
+    let (_f1, f2, m1) = setup_diverged(&root, b"a\nb\nC\n", b"A\nb\nc\n");
+
+    //
+    // Committing a pick needs the committer from the config, so a broken one fails mid-replay.
+    //
+    fs::write(root.join(".mog/config"), "[user\n")?;
+    assert!(mog::rebase::rebase(&mut open(&root), "main").is_err());
+    assert!(mog::rebase::rebase_in_progress(&open(&root)));
+
+    mog::rebase::rebase_abort(&mut open(&root))?;
+    let repo = open(&root);
+    assert_eq!(repo.current_branch()?.as_deref(), Some("feature"));
+    assert_eq!(repo.read_head_commit()?, f2);
+
+    //
+    // Again, then fix it and carry on from the pick that failed.
+    //
+    assert!(mog::rebase::rebase(&mut open(&root), "main").is_err());
+    fs::remove_file(root.join(".mog/config"))?;
+    mog::rebase::rebase_continue(&mut open(&root))?;
+
+    let mut repo = open(&root);
+    assert!(!mog::rebase::rebase_in_progress(&repo));
+    assert_eq!(repo.current_branch()?.as_deref(), Some("feature"));
+    assert!(repo.reachable_commits(&repo.read_head_commit()?).contains(&m1));
+    assert_eq!(read_file(&root, "shared.rs"), b"A\nb\nC\n");
+    assert_eq!(read_file(&root, "feature.rs"), b"feature");
+    Ok(())
+}
+
+#[test]
+fn test_rebase_stops_at_upstream_history_reached_through_a_merge() {
+    let (_dir, root) = setup();
+    write_file(&root, "f", b"a\n");
+    stage_all(&root);
+    let a = commit_all(&root, "A");
+
+    let mut repo = open(&root);
+    mog::branch::create(&mut repo, "feature", None).unwrap();
+    write_file(&root, "f", b"b\n");
+    stage_all(&root);
+    let b = commit_all(&root, "B");
+
+    let mut repo = open(&root);
+    mog::checkout::checkout(&mut repo, "feature").unwrap();
+    write_file(&root, "c.txt", b"c");
+    stage_all(&root);
+    let c = commit_all(&root, "C");
+
+    //
+    // Feature merges main, so B is only on the second-parent side of feature's history.
+    //
+    mog::merge::merge(&mut open(&root), "main", Some("test"), None).unwrap();
+
+    let mut repo = open(&root);
+    mog::checkout::checkout(&mut repo, "main").unwrap();
+    write_file(&root, "d.txt", b"d");
+    stage_all(&root);
+    let d = commit_all(&root, "D");
+
+    let mut repo = open(&root);
+    mog::checkout::checkout(&mut repo, "feature").unwrap();
+    mog::rebase::rebase(&mut open(&root), "main").unwrap();
+
+    //
+    // Only C is replayed (the merge adds nothing on top of it and D): A and B are already upstream.
+    //
+    let mut repo = open(&root);
+    assert!(!mog::rebase::rebase_in_progress(&repo));
+    let head    = repo.read_head_commit().unwrap();
+    let head_id = repo.read_object(&head).unwrap().try_as_commit_id().unwrap();
+    assert_eq!(repo.commit.get_message(head_id), "C");
+    assert_eq!(repo.commit.get_parents(head_id), &[d]);
+    assert_ne!(head, c);
+    assert!(repo.reachable_commits(&head).contains(&a) && repo.reachable_commits(&head).contains(&b));
+    assert_eq!(read_file(&root, "f"), b"b\n");
+    assert_eq!(read_file(&root, "c.txt"), b"c");
+    assert_eq!(read_file(&root, "d.txt"), b"d");
+}
+
+#[test]
+fn test_rebase_skip_drops_conflicting_commit() {
+    let (_dir, root) = setup();
+    let (_f1, _f2, m1) = setup_diverged(&root, b"a\nfeature\nc\n", b"a\nmain\nc\n");
+
+    let mut repo = open(&root);
+    assert!(mog::rebase::rebase(&mut repo, "main").is_err());
+    mog::rebase::rebase_skip(&mut open(&root)).unwrap();
+
+    let mut repo = open(&root);
+    let head     = repo.read_head_commit().unwrap();
+    let head_id  = repo.read_object(&head).unwrap().try_as_commit_id().unwrap();
+    assert_eq!(repo.commit.get_parents(head_id), &[m1]);
+    assert_eq!(read_file(&root, "shared.rs"), b"a\nmain\nc\n");
+    assert_eq!(read_file(&root, "feature.rs"), b"feature");
+}
+
+//
+//
+// Reflog
+//
+//
+
+#[test]
+fn test_reflog_records_commits_and_checkouts() {
+    let (_dir, root) = setup();
+    write_file(&root, "file.rs", b"v1");
+    stage_all(&root);
+    let h1 = commit_all(&root, "first");
+
+    let mut repo = open(&root);
+    mog::branch::create(&mut repo, "feature", None).unwrap();
+    mog::checkout::checkout(&mut repo, "feature").unwrap();
+    drop(repo);
+
+    write_file(&root, "file.rs", b"v2");
+    stage_all(&root);
+    let h2 = commit_all(&root, "second");
+
+    let repo = open(&root);
+    let head = mog::reflog::read(&repo, "HEAD").unwrap();
+    let reasons = head.iter().map(|e| &*e.reason).collect::<Vec<_>>();
+    assert_eq!(reasons, ["commit (initial): first", "checkout: moving from main to feature", "commit: second"]);
+    assert_eq!(head[0].old, mog::reflog::ZERO_HASH);
+    assert_eq!(head[2].old, h1);
+    assert_eq!(head[2].new, h2);
+
+    let feature = mog::reflog::read(&repo, "refs/heads/feature").unwrap();
+    assert_eq!(feature.len(), 2);
+    assert_eq!(feature[0].reason.as_ref(), "branch: Created from HEAD");
+
+    let mut buf = String::new();
+    mog::reflog::show(&repo, "feature", &mut buf).unwrap();
+    assert!(buf.starts_with(&format!("{} feature@{{0}}: commit: second", &mog::hash::hash_to_hex(&h2)[..8])));
+}
+
+#[test]
+fn test_reflog_selector_resolves_previous_values() {
+    let (_dir, root) = setup();
+    write_file(&root, "file.rs", b"v1");
+    stage_all(&root);
+    let h1 = commit_all(&root, "first");
+
+    write_file(&root, "file.rs", b"v2");
+    stage_all(&root);
+    let h2 = commit_all(&root, "second");
+
+    let mut repo = open(&root);
+    assert_eq!(repo.resolve_to_commit("main@{0}").unwrap().0, h2);
+    assert_eq!(repo.resolve_to_commit("main@{1}").unwrap().0, h1);
+    assert_eq!(repo.resolve_to_commit("HEAD@{1}").unwrap().0, h1);
+    assert_eq!(repo.resolve_to_commit("@{1}").unwrap().0, h1);
+    assert!(repo.resolve_to_commit("main@{2}").is_err());
+}
+
+#[test]
+fn test_reflog_recovers_force_deleted_branch() {
+    let (_dir, root) = setup();
+    write_file(&root, "file.rs", b"v1");
+    stage_all(&root);
+    commit_all(&root, "first");
+
+    let mut repo = open(&root);
+    mog::branch::create(&mut repo, "feature", None).unwrap();
+    mog::checkout::checkout(&mut repo, "feature").unwrap();
+    drop(repo);
+
+    write_file(&root, "file.rs", b"lost work");
+    stage_all(&root);
+    let lost = commit_all(&root, "lost");
+
+    let mut repo = open(&root);
+    mog::checkout::checkout(&mut repo, "main").unwrap();
+    mog::branch::force_delete(&mut repo, "feature").unwrap();
+
+    assert!(repo.resolve_to_commit("feature@{0}").is_err());
+    assert_eq!(repo.resolve_to_commit("feature@{1}").unwrap().0, lost);
+
+    mog::checkout::checkout(&mut repo, "feature@{1}").unwrap();
+    assert_eq!(read_file(&root, "file.rs"), b"lost work");
+}
+
+#[test]
+fn test_reflog_undoes_amend() {
+    let (_dir, root) = setup();
+    write_file(&root, "file.rs", b"v1");
+    stage_all(&root);
+    let original = commit_all(&root, "first");
+
+    let mut repo = open(&root);
+    let mut index = mog::index::Index::load(&root).unwrap();
+    let tree     = index.write_tree(&mut repo).unwrap();
+    let amended  = mog::commit::amend(&mut repo, tree, Some("test"), Some("reworded")).unwrap();
+    drop(repo);
+
+    let mut repo = open(&root);
+    assert_eq!(repo.resolve_to_commit("HEAD@{0}").unwrap().0, amended);
+    assert_eq!(repo.resolve_to_commit("HEAD@{1}").unwrap().0, original);
+}
+
+#[test]
+fn test_reflog_moves_with_renamed_branch() {
+    let (_dir, root) = setup();
+    write_file(&root, "file.rs", b"v1");
+    stage_all(&root);
+    let h1 = commit_all(&root, "first");
+
+    let repo = open(&root);
+    mog::branch::rename(&repo, "main", "trunk").unwrap();
+
+    assert!(mog::reflog::read(&repo, "refs/heads/main").unwrap().is_empty());
+    let log = mog::reflog::read(&repo, "refs/heads/trunk").unwrap();
+    assert_eq!(log.len(), 2);
+    assert_eq!(log[1].new, h1);
+    assert!(log[1].reason.starts_with("branch: renamed"));
+}
+
+//
+//
+// Revisions
+//
+//
+
+#[test]
+fn test_revision_ancestry_suffixes() {
+    let (_dir, root) = setup();
+    write_file(&root, "file.rs", b"v1");
+    stage_all(&root);
+    let h1 = commit_all(&root, "first");
+
+    write_file(&root, "file.rs", b"v2");
+    stage_all(&root);
+    let h2 = commit_all(&root, "second");
+
+    write_file(&root, "file.rs", b"v3");
+    stage_all(&root);
+    let h3 = commit_all(&root, "third");
+
+    let mut repo = open(&root);
+    assert_eq!(repo.resolve_to_commit("HEAD").unwrap().0,     h3);
+    assert_eq!(repo.resolve_to_commit("@").unwrap().0,        h3);
+    assert_eq!(repo.resolve_to_commit("HEAD~").unwrap().0,    h2);
+    assert_eq!(repo.resolve_to_commit("HEAD~2").unwrap().0,   h1);
+    assert_eq!(repo.resolve_to_commit("main^").unwrap().0,    h2);
+    assert_eq!(repo.resolve_to_commit("main^^").unwrap().0,   h1);
+    assert_eq!(repo.resolve_to_commit("main~1^1").unwrap().0, h1);
+    assert_eq!(repo.resolve_to_commit("HEAD^0").unwrap().0,   h3);
+    assert!(repo.resolve_to_commit("HEAD~3").is_err());
+    assert!(repo.resolve_to_commit("HEAD^2").is_err());
+
+    mog::checkout::checkout(&mut repo, "HEAD~2").unwrap();
+    assert_eq!(read_file(&root, "file.rs"), b"v1");
+}
+
+#[test]
+fn test_revision_second_parent_of_merge() {
+    let (_dir, root) = setup();
+    let (_, f2, m1) = setup_diverged(&root, b"feature\n", b"main\n");
+
+    let mut repo = open(&root);
+    let mut index = mog::index::Index::load(&root).unwrap();
+    let tree     = index.write_tree(&mut repo).unwrap();
+    mog::commit::commit(&mut repo, tree, [m1, f2], Some("test"), "merge").unwrap();
+    drop(repo);
+
+    let mut repo = open(&root);
+    assert_eq!(repo.resolve_to_commit("HEAD^1").unwrap().0, m1);
+    assert_eq!(repo.resolve_to_commit("HEAD^2").unwrap().0, f2);
+}
+
+#[test]
+fn test_revision_short_hash() {
+    let (_dir, root) = setup();
+    write_file(&root, "file.rs", b"v1");
+    stage_all(&root);
+    let h1 = commit_all(&root, "first");
+
+    let hex = mog::hash::hash_to_hex(&h1);
+    let mut repo = open(&root);
+    assert_eq!(repo.resolve_to_commit(&hex[..12]).unwrap().0, h1);
+    assert_eq!(repo.resolve_to_commit(&hex[..12].to_uppercase()).unwrap().0, h1);
+    assert_eq!(repo.resolve_to_commit(&format!("{}~0", &hex[..12])).unwrap().0, h1);
+    assert!(repo.resolve_to_commit(&hex[..3]).is_err());
+    assert!(repo.resolve_to_commit("no-such-branch").is_err());
+
+    let mut buf = String::new();
+    mog::cat_file::cat_file(&mut repo, &hex[..10], &mut buf).unwrap();
+    assert!(buf.starts_with("tree "));
+}
+
+#[test]
+fn test_revision_ranges() {
+    let (_dir, root) = setup();
+    let (f1, f2, m1) = setup_diverged(&root, b"feature\n", b"main\n");
+
+    let mut repo = open(&root);
+    let base = repo.resolve_to_commit("main~1").unwrap().0;
+
+    let range = mog::revision::parse_range(&mut repo, "main..feature").unwrap();
+    assert_eq!(range, mog::revision::RevRange::Range { exclude: m1, include: f2 });
+    let commits = mog::revision::range_commits(&mut repo, &range).unwrap();
+    assert_eq!(commits.len(), 2);
+    assert!(commits.contains(&f1) && commits.contains(&f2));
+    assert!(!commits.contains(&base));
+
+    let range = mog::revision::parse_range(&mut repo, "main...feature").unwrap();
+    let commits = mog::revision::range_commits(&mut repo, &range).unwrap();
+    assert_eq!(commits.len(), 3);
+    assert!(commits.contains(&m1));
+
+    // Empty side defaults to HEAD (feature is checked out).
+    let range = mog::revision::parse_range(&mut repo, "..main").unwrap();
+    assert_eq!(mog::revision::range_commits(&mut repo, &range).unwrap(), vec![m1]);
+
+    let mut buf = String::new();
+    mog::log::log(&mut repo, &log_options(&["main..feature"]), &mut buf).unwrap();
+    assert_eq!(buf.matches("commit ").count(), 2);
+
+    assert!(repo.resolve_to_commit("main..feature").is_err());
+}
+
+//
+//
+// Log
+//
+//
+
+#[test]
+fn test_log_shows_commits_in_order() {
+    let (_dir, root) = setup();
+    write_file(&root, "f.rs", b"v1");
+    stage_all(&root);
+    commit_all(&root, "first commit");
+
+    write_file(&root, "f.rs", b"v2");
+    stage_all(&root);
+    commit_all(&root, "second commit");
+
+    let mut repo = open(&root);
+    let mut buf  = String::new();
+    mog::log::log(&mut repo, &log_options(&[]), &mut buf).unwrap();
+
+    let first_pos  = buf.find("first commit").unwrap();
+    let second_pos = buf.find("second commit").unwrap();
+
+    // Most recent first.
+    assert!(second_pos < first_pos);
+}
+
+#[test]
+fn test_log_date_formats_use_the_recorded_zone() {
+    let (_dir, root) = setup();
+    write_file(&root, "f.rs", b"v1");
+    stage_all(&root);
+
+    let mut repo = open(&root);
+    let tree     = mog::index::Index::load(&root).unwrap().write_tree(&mut repo).unwrap();
+    let author   = mog::commit::Signature { name: "Alice", email: "a@example.com", timestamp: 1792152187, tz_offset: 120 };
+    let id       = repo.commit.push_signed(tree, &[], &author, &author, "dated");
+    let hash     = repo.write_object(mog::object::Object::Commit(id));
+    repo.storage.flush().unwrap();
+    repo.update_head(&hash, "commit (initial): dated").unwrap();
+    drop(repo);
+
+    let log = |date: &str| {
+        let mut repo = open(&root);
+        let mut buf  = String::new();
+        mog::log::log(&mut repo, &mog::log::LogOptions { date: mog::date::DateFormat::parse(date).unwrap(), ..Default::default() }, &mut buf).unwrap();
+        buf
+    };
+    assert!(log("default").contains("Date:   Fri, 16 Oct 2026 14:03:07 +0200\n"));
+    assert!(log("iso").contains("Date:   2026-10-16T14:03:07+02:00\n"));
+    assert!(log("unix").contains("Date:   1792152187\n"));
+    assert!(log("relative").contains(" ago\n") || log("relative").contains("in the future"));
+}
+
+#[test]
+fn test_log_graph_shows_both_sides_of_a_merge() {
+    let (_dir, root) = setup();
+    let mut repo = open(&root);
+    let base   = commit_files_at(&mut repo, &[("f", "0")], &[], "dev", 1000, "base");
+    let main1  = commit_files_at(&mut repo, &[("f", "1")], &[base], "dev", 2000, "main one");
+    let feat1  = commit_files_at(&mut repo, &[("f", "0"), ("src/x", "1")], &[base], "dev", 2500, "feature one");
+    let main2  = commit_files_at(&mut repo, &[("f", "2")], &[main1], "dev", 3000, "main two");
+    let merged = commit_files_at(&mut repo, &[("f", "2"), ("src/x", "1")], &[main2, feat1], "dev", 4000, "merge");
+    repo.update_head(&merged, "test").unwrap();
+
+    let subjects = mog::log::LogFormat::Template("%s".into());
+    let mut buf = String::new();
+    mog::log::log(&mut repo, &mog::log::LogOptions { graph: true, format: subjects.clone(), ..Default::default() }, &mut buf).unwrap();
+    assert_eq!(buf, "\
+*   merge
+|\\
+* | main two
+| * feature one
+* | main one
+|/
+* base
+");
+
+    // Path limiting skips commits that left src/ alone; the graph reconnects around them.
+    let mut buf = String::new();
+    let opts = mog::log::LogOptions { graph: true, format: subjects, paths: vec!["src/".into()], ..Default::default() };
+    mog::log::log(&mut repo, &opts, &mut buf).unwrap();
+    assert_eq!(buf, "* feature one\n");
+}
+
+#[test]
+fn test_log_filters_ranges_and_formats() {
+    let (_dir, root) = setup();
+    let mut repo = open(&root);
+    let base  = commit_files_at(&mut repo, &[("f", "0")], &[], "Alice <alice@example.com>", 1000, "base");
+    let fix   = commit_files_at(&mut repo, &[("f", "1")], &[base], "Bob <bob@example.com>", 2000, "fix: crash on empty input");
+    let feat  = commit_files_at(&mut repo, &[("f", "2")], &[fix], "Alice <alice@example.com>", 3000, "feat: colors\n\nWith a body.");
+    repo.update_head(&feat, "test").unwrap();
+    repo.write_ref("refs/heads/side", &base, "test").unwrap();
+
+    let mut subjects = |opts: mog::log::LogOptions| {
+        let mut buf = String::new();
+        let opts = mog::log::LogOptions { format: mog::log::LogFormat::Template("%s".into()), ..opts };
+        mog::log::log(&mut repo, &opts, &mut buf).unwrap();
+        buf.lines().map(str::to_owned).collect::<Vec<_>>()
+    };
+
+    let author = |re: &str| Some(regex::Regex::new(re).unwrap());
+    assert_eq!(subjects(mog::log::LogOptions { author: author("Alice"), ..Default::default() }), ["feat: colors", "base"]);
+    assert_eq!(subjects(mog::log::LogOptions { author: author("bob@"), ..Default::default() }), ["fix: crash on empty input"]);
+    assert_eq!(subjects(mog::log::LogOptions { grep: author("^fix:"), ..Default::default() }), ["fix: crash on empty input"]);
+    assert_eq!(subjects(mog::log::LogOptions { since: Some(2000), ..Default::default() }), ["feat: colors", "fix: crash on empty input"]);
+    assert_eq!(subjects(mog::log::LogOptions { until: Some(1999), ..Default::default() }), ["base"]);
+    assert_eq!(subjects(mog::log::LogOptions { max_count: Some(1), ..Default::default() }), ["feat: colors"]);
+    assert_eq!(subjects(log_options(&["HEAD~2..HEAD"])), ["feat: colors", "fix: crash on empty input"]);
+    assert_eq!(subjects(log_options(&["HEAD", "^HEAD~1"])), ["feat: colors"]);
+    assert_eq!(subjects(log_options(&["side"])), ["base"]);
+
+    let mut buf = String::new();
+    let format = mog::log::LogFormat::parse("format:%h|%an|%ae|%at|%ad|%b|%p|%%|%x").unwrap();
+    let opts = mog::log::LogOptions { format, max_count: Some(1), date: mog::date::DateFormat::Iso, ..Default::default() };
+    mog::log::log(&mut repo, &opts, &mut buf).unwrap();
+    let short = |h: &mog::hash::Hash| mog::hash::hash_to_hex(h)[..8].to_owned();
+    assert_eq!(buf, format!("{}|Alice|alice@example.com|3000|1970-01-01T00:50:00+00:00|With a body.|{}|%|%x\n", short(&feat), short(&fix)));
+
+    let mut buf = String::new();
+    mog::log::log(&mut repo, &mog::log::LogOptions { format: mog::log::LogFormat::Oneline, ..Default::default() }, &mut buf).unwrap();
+    assert_eq!(buf.lines().next().unwrap(), format!("{} feat: colors", short(&feat)));
+    assert!(mog::log::LogFormat::parse("fancy").is_err());
+}
+
+//
+//
+// Storage
+//
+//
+
+#[test]
+fn test_storage_table_grows_and_survives_reopen() {
+    let dir = TempDir::new().unwrap();
+    let mut hashes = Vec::new();
+    {
+        let mut storage = mog::storage::Storage::new(dir.path()).unwrap();
+        // Several flushes so growth happens with objects written before the last remap.
+        for chunk in 0..4 {
+            for i in 0..2500 {
+                let data = format!("object {chunk} {i}");
+                let mut buf = Vec::new();
+                let hash = mog::object::encode_blob_and_hash(data.as_bytes(), &mut buf);
+                storage.write(hash, buf);
+                hashes.push(hash);
+            }
+            storage.flush().unwrap();
+        }
+        storage.remap().unwrap();
+
+        let (count, load) = storage.load();
+        assert_eq!(count, 10_000);
+        assert!(load <= 0.7, "load factor {load} above limit");
+        assert!(hashes.iter().all(|h| storage.exists(h)));
+    }
+
+    let storage = mog::storage::Storage::new(dir.path()).unwrap();
+    for (i, hash) in hashes.iter().enumerate() {
+        let raw  = storage.read(hash).unwrap();
+        let data = mog::object::decode_blob_bytes(&raw).unwrap();
+        assert_eq!(data, format!("object {} {}", i / 2500, i % 2500).as_bytes());
+    }
+
+    // The tables retired by each growth sit between entries and must not read as garbage.
+    let check = storage.verify();
+    assert_eq!(check.objects, 10_000);
+    assert_eq!(check.issues, []);
+
+    let header = fs::read(dir.path().join("objects.bin")).unwrap();
+    assert_eq!(u32::from_le_bytes(header[4..8].try_into().unwrap()), 4);
+}
+
+#[test]
+fn test_storage_concurrent_writers_share_the_file() {
+    let dir = TempDir::new().unwrap();
+    drop(mog::storage::Storage::new(dir.path()).unwrap());
+
+    //
+    // Each thread has its own handle, like separate processes: own mapping, own idea of the file length.
+    //
+    let threads = (0..4).map(|t| {
+        let path = dir.path().to_path_buf();
+        std::thread::spawn(move || {
+            let mut storage = mog::storage::Storage::new(&path).unwrap();
+            for batch in 0..8 {
+                for i in 0..250 {
+                    let mut buf = Vec::new();
+                    let hash = mog::object::encode_blob_and_hash(format!("thread {t} batch {batch} object {i}").as_bytes(), &mut buf);
+                    storage.write(hash, buf);
+                }
+                storage.flush().unwrap();
+            }
+        })
+    }).collect::<Vec<_>>();
+    threads.into_iter().for_each(|t| t.join().unwrap());
+
+    let storage = mog::storage::Storage::new(dir.path()).unwrap();
+    assert_eq!(storage.load().0, 4 * 8 * 250);
+    for t in 0..4 {
+        for batch in 0..8 {
+            for i in 0..250 {
+                let data = format!("thread {t} batch {batch} object {i}");
+                let raw  = storage.read(&mog::object::hash_blob(data.as_bytes())).unwrap();
+                assert_eq!(mog::object::decode_blob_bytes(&raw).unwrap(), data.as_bytes());
+            }
+        }
+    }
+    assert_eq!(storage.verify().issues, []);
+}
+
+#[test]
+fn test_storage_stale_handle_tolerates_growth_and_replacement() {
+    let dir = TempDir::new().unwrap();
+    let blob = |data: &str| {
+        let mut buf = Vec::new();
+        (mog::object::encode_blob_and_hash(data.as_bytes(), &mut buf), buf)
+    };
+
+    let mut writer = mog::storage::Storage::new(dir.path()).unwrap();
+    let (old, buf) = blob("old");
+    writer.write(old, buf);
+    writer.flush().unwrap();
+
+    let mut stale = mog::storage::Storage::new(dir.path()).unwrap();
+
+    //
+    // Grow the file and its table behind the stale handle's back.
+    //
+    let mut fresh = Vec::new();
+    for i in 0..5000 {
+        let (hash, buf) = blob(&format!("new {i}"));
+        writer.write(hash, buf);
+        fresh.push(hash);
+    }
+    writer.flush().unwrap();
+
+    assert!(stale.exists(&old));
+    assert!(fresh.iter().all(|h| !stale.exists(h) || stale.read(h).is_ok()));
+    stale.remap().unwrap();
+    assert!(fresh.iter().all(|h| stale.exists(h)));
+
+    //
+    // gc renames a new file over the old one; the stale handle's next write must land in it.
+    //
+    writer.compact(&fresh.iter().copied().chain([old]).collect()).unwrap();
+    let (late, buf) = blob("written after gc");
+    stale.write(late, buf);
+    stale.flush().unwrap();
+    drop((writer, stale));
+
+    let storage = mog::storage::Storage::new(dir.path()).unwrap();
+    assert!(storage.exists(&late) && storage.exists(&old));
+    assert_eq!(storage.load().0, 5002);
+    assert_eq!(storage.verify().issues, []);
+}
+
+#[test]
+fn test_storage_reads_and_extends_v1_file() {
+    const V1_BUCKETS: usize = 1 << 21;
+    const HEADER: usize = 128;
+
+    let dir = TempDir::new().unwrap();
+
+    //
+    // Hand-build a version 1 file: fixed 2M-bucket table right after the header, one object.
+    //
+    let mut old_buf = Vec::new();
+    let old_hash = mog::object::encode_blob_and_hash(b"from v1", &mut old_buf);
+
+    let data_start = HEADER + V1_BUCKETS * 8;
+    let mut file = vec![0u8; data_start];
+    file[0..4].copy_from_slice(b"MOGS");
+    file[4..8].copy_from_slice(&1u32.to_le_bytes());
+    file[8..16].copy_from_slice(&1u64.to_le_bytes());
+    file[16..24].copy_from_slice(&(data_start as u64).to_le_bytes());
+
+    let bucket = u64::from_le_bytes(old_hash[..8].try_into().unwrap()) as usize % V1_BUCKETS;
+    file[HEADER + bucket * 8..HEADER + bucket * 8 + 8].copy_from_slice(&(data_start as u64).to_le_bytes());
+    file.extend_from_slice(&old_hash);
+    file.extend_from_slice(&(old_buf.len() as u32).to_le_bytes());
+    file.extend_from_slice(&old_buf);
+    fs::write(dir.path().join("objects.bin"), &file).unwrap();
+
+    let mut new_buf = Vec::new();
+    let new_hash = mog::object::encode_blob_and_hash(b"written by v2", &mut new_buf);
+    {
+        let mut storage = mog::storage::Storage::new(dir.path()).unwrap();
+        assert_eq!(mog::object::decode_blob_bytes(&storage.read(&old_hash).unwrap()).unwrap(), b"from v1");
+
+        storage.write(new_hash, new_buf);
+        storage.flush().unwrap();
+    }
+
+    let storage = mog::storage::Storage::new(dir.path()).unwrap();
+    assert_eq!(mog::object::decode_blob_bytes(&storage.read(&old_hash).unwrap()).unwrap(), b"from v1");
+    assert_eq!(mog::object::decode_blob_bytes(&storage.read(&new_hash).unwrap()).unwrap(), b"written by v2");
+    assert_eq!(storage.load().0, 2);
+    assert_eq!(storage.verify().issues, []);
+}
+
+#[test]
+fn test_storage_new_repo_starts_small() {
+    let (_dir, root) = setup();
+    let size = fs::metadata(root.join(".mog/objects.bin")).unwrap().len();
+    assert!(size < 1 << 20, "fresh objects.bin is {size} bytes");
+}
+
+#[test]
+fn test_storage_compresses_only_when_it_helps() {
+    let dir = tempfile::tempdir().unwrap();
+
+    let text = "fn main() { println!(\"hello\"); }\n".repeat(2000);
+    let mut noise = Vec::with_capacity(64 << 10);
+    let mut x = 0x9E37_79B9_7F4A_7C15u64;
+    while noise.len() < 64 << 10 {
+        x ^= x << 13; x ^= x >> 7; x ^= x << 17;
+        noise.extend_from_slice(&x.to_le_bytes());
+    }
+
+    let mut hashes = Vec::new();
+    {
+        let mut storage = mog::storage::Storage::new(dir.path()).unwrap();
+        for content in [text.as_bytes(), &noise, b"tiny"] {
+            let mut buf = Vec::new();
+            let hash = mog::object::encode_blob_and_hash(content, &mut buf);
+            assert_eq!(hash, mog::object::hash_blob(content));
+            storage.write(hash, buf);
+            hashes.push(hash);
+        }
+        storage.flush().unwrap();
+    }
+
+    let size = fs::metadata(dir.path().join("objects.bin")).unwrap().len() as usize;
+    assert!(size < text.len() + noise.len(), "objects.bin is {size} bytes");
+
+    let storage = mog::storage::Storage::new(dir.path()).unwrap();
+    assert_eq!(storage.is_compressed(&hashes[0]), Some(true));
+    assert_eq!(storage.is_compressed(&hashes[1]), Some(false));
+    assert_eq!(storage.is_compressed(&hashes[2]), Some(false));
+
+    for (hash, content) in hashes.iter().zip([text.as_bytes(), &noise, b"tiny"]) {
+        let raw = storage.read(hash).unwrap();
+        assert_eq!(mog::object::decode_blob_bytes(&raw).unwrap(), content);
+        assert_eq!(mog::hash::hash_bytes(&raw), *hash, "hash must cover the uncompressed encoding");
+    }
+}
+
+#[test]
+fn test_compressed_objects_survive_checkout_and_gc() {
+    let (_dir, root) = setup();
+    let text = "line of very repetitive source text\n".repeat(500);
+    write_file(&root, "big.txt", text.as_bytes());
+    stage_all(&root);
+    commit_all(&root, "big");
+
+    let hash = mog::object::hash_blob(text.as_bytes());
+    assert_eq!(open(&root).storage.is_compressed(&hash), Some(true));
+
+    fs::remove_file(root.join("big.txt")).unwrap();
+    mog::gc::gc(&mut open(&root), false).unwrap();
+
+    let repo = open(&root);
+    assert_eq!(repo.storage.is_compressed(&hash), Some(true), "gc copies entries as stored");
+    assert_eq!(&*repo.read_blob_bytes_without_touching_cache(&hash).unwrap(), text.as_bytes());
+    drop(repo);
+
+    mog::discard::discard(&mut open(&root), &[]).unwrap();
+    assert_eq!(fs::read_to_string(root.join("big.txt")).unwrap(), text);
+}
+
+#[test]
+fn test_stage_stores_modified_file_as_delta() {
+    let (_dir, root) = setup();
+    let v1 = numbered_lines(2000, "");
+    write_file(&root, "big.rs", v1.as_bytes());
+    stage_all(&root);
+
+    let v2 = numbered_lines(2000, "changed");
+    write_file_later(&root, "big.rs", v2.as_bytes());
+    stage_all(&root);
+
+    let h1 = mog::object::hash_blob(v1.as_bytes());
+    let h2 = mog::object::hash_blob(v2.as_bytes());
+
+    let repo = open(&root);
+    assert_eq!(repo.storage.delta_base(&h2), Some(h1));
+    assert_eq!(repo.storage.delta_base(&h1), None);
+
+    let raw = repo.storage.read(&h2).unwrap();
+    assert_eq!(mog::object::decode_blob_bytes(&raw).unwrap(), v2.as_bytes());
+    assert_eq!(mog::hash::hash_bytes(&raw), h2);
+}
+
+#[test]
+fn test_gc_keeps_unreachable_delta_base() {
+    let (_dir, root) = setup();
+    let v1 = numbered_lines(2000, "");
+    write_file(&root, "big.rs", v1.as_bytes());
+    stage_all(&root);
+
+    // v1 is never committed, but the committed v2 is stored as a delta against it.
+    let v2 = numbered_lines(2000, "changed");
+    write_file_later(&root, "big.rs", v2.as_bytes());
+    stage_all(&root);
+    commit_all(&root, "only v2");
+
+    let h1 = mog::object::hash_blob(v1.as_bytes());
+    let h2 = mog::object::hash_blob(v2.as_bytes());
+
+    mog::gc::gc(&mut open(&root), false).unwrap();
+
+    let repo = open(&root);
+    assert!(repo.storage.exists(&h1));
+    assert_eq!(&*repo.read_blob_bytes_without_touching_cache(&h2).unwrap(), v2.as_bytes());
+}
+
+#[test]
+fn test_repack_deltas_history_against_newer_versions() {
+    let (_dir, root) = setup();
+    let versions = ["", "second", "third"].map(|tag| numbered_lines(2000, tag));
+
+    let mut commits = Vec::new();
+    for (i, version) in versions.iter().enumerate() {
+        write_file_later(&root, "big.rs", version.as_bytes());
+        write_file(&root, "small.rs", format!("fn v{i}() {{}}").as_bytes());
+        stage_all(&root);
+        commits.push(commit_all(&root, &format!("v{i}")));
+    }
+
+    let hashes = versions.each_ref().map(|v| mog::object::hash_blob(v.as_bytes()));
+
+    let mut repo = open(&root);
+    mog::repack::repack(&mut repo).unwrap();
+    drop(repo);
+
+    let repo = open(&root);
+    assert_eq!(repo.storage.delta_base(&hashes[2]), None, "newest version is stored whole");
+    assert_eq!(repo.storage.delta_base(&hashes[1]), Some(hashes[2]));
+    assert_eq!(repo.storage.delta_base(&hashes[0]), Some(hashes[1]));
+    drop(repo);
+
+    let mut repo = open(&root);
+    mog::checkout::checkout(&mut repo, &mog::hash::hash_to_hex(&commits[0])).unwrap();
+    assert_eq!(read_file(&root, "big.rs"), versions[0].as_bytes());
+    assert_eq!(read_file(&root, "small.rs"), b"fn v0() {}");
+}
+
+#[test]
+fn test_stage_chunks_large_file_and_shares_unchanged_chunks() {
+    let (_dir, root) = setup();
+    let v1 = numbered_lines(200_000, "");
+    let v2 = v1.replacen("let x100000 = 100000;", "let x100000 = -1;", 1);
+    assert!(v1.len() as u64 > 3 * mog::chunk::CHUNK_THRESHOLD);
+
+    write_file(&root, "big.rs", v1.as_bytes());
+    stage_all(&root);
+    let c1 = commit_all(&root, "v1");
+
+    write_file_later(&root, "big.rs", v2.as_bytes());
+    stage_all(&root);
+    commit_all(&root, "v2");
+
+    let h1 = mog::chunk::hash_contents(v1.as_bytes());
+    let h2 = mog::chunk::hash_contents(v2.as_bytes());
+
+    let repo  = open(&root);
+    let index = mog::index::Index::load(&repo.root).unwrap();
+    assert_eq!(index.hashes[index.find("big.rs").unwrap()], h2);
+    assert_eq!(mog::chunk::hash_file(&root.join("big.rs")).unwrap(), h2);
+
+    let list1 = mog::chunk::decode_chunk_list(&repo.storage.read(&h1).unwrap()).unwrap().unwrap();
+    let list2 = mog::chunk::decode_chunk_list(&repo.storage.read(&h2).unwrap()).unwrap().unwrap();
+    assert_eq!(list2.size, v2.len() as u64);
+    assert!(list1.hashes.len() > 1);
+
+    // A one-line edit only touches the chunks around it.
+    let shared = list2.hashes.iter().filter(|h| list1.hashes.contains(h)).count();
+    assert!(shared + 2 >= list2.hashes.len(), "{shared} of {} chunks shared", list2.hashes.len());
+
+    let mut out = String::new();
+    mog::cat_file::cat_file(&mut open(&root), &mog::hash::hash_to_hex(&h2), &mut out).unwrap();
+    assert_eq!(out.trim_end().as_bytes(), v2.trim_end().as_bytes());
+    drop(repo);
+
+    fs::remove_file(root.join("big.rs")).unwrap();
+    mog::discard::discard(&mut open(&root), &[]).unwrap();
+    assert_eq!(read_file(&root, "big.rs"), v2.as_bytes());
+
+    mog::checkout::checkout(&mut open(&root), &mog::hash::hash_to_hex(&c1)).unwrap();
+    assert_eq!(read_file(&root, "big.rs"), v1.as_bytes());
+}
+
+//
+//
+// Gc
+//
+//
+
+#[test]
+fn test_gc_removes_unreachable_blobs_and_keeps_history() {
+    let (_dir, root) = setup();
+    write_file(&root, "file.rs", b"committed");
+    stage_all(&root);
+    let h1 = commit_all(&root, "first");
+
+    // Staged but replaced before committing: only the second version stays reachable.
+    write_file(&root, "file.rs", b"staged then replaced");
+    stage_all(&root);
+    write_file(&root, "file.rs", b"final");
+    stage_all(&root);
+
+    let dead = mog::object::hash_blob(b"staged then replaced");
+    let live = mog::object::hash_blob(b"final");
+
+    let mut repo = open(&root);
+    let roots = mog::gc::collect_roots(&mut repo).unwrap();
+    let keep  = repo.reachable_objects(roots).unwrap();
+    let dry   = repo.storage.compact_stats(&keep);
+    assert_eq!(dry.removed, 1);
+    assert!(dry.bytes_after < dry.bytes_before);
+
+    mog::gc::gc(&mut repo, true).unwrap();
+    assert!(repo.storage.exists(&dead), "dry run must not delete anything");
+
+    mog::gc::gc(&mut repo, false).unwrap();
+    assert!(!repo.storage.exists(&dead));
+    assert!(repo.storage.exists(&live));
+    drop(repo);
+
+    let size = fs::metadata(root.join(".mog/objects.bin")).unwrap().len();
+    assert_eq!(size, dry.bytes_after);
+    assert!(!root.join(".mog/objects.bin.gc").exists());
+
+    let mut repo = open(&root);
+    assert_eq!(repo.resolve_to_commit("HEAD").unwrap().0, h1);
+    mog::checkout::checkout(&mut repo, "HEAD").unwrap();
+    assert_eq!(read_file(&root, "file.rs"), b"committed");
+}
+
+#[test]
+fn test_gc_stops_on_a_corrupt_live_object() {
+    let (_dir, root) = setup();
+    write_file(&root, "needle_9c1e.rs", b"committed");
+    stage_all(&root);
+    commit_all(&root, "first");
+    write_file(&root, "needle_9c1e.rs", b"staged then replaced");
+    stage_all(&root);
+    write_file(&root, "needle_9c1e.rs", b"committed");
+    stage_all(&root);
+
+    //
+    // Damage the committed tree: gc must not treat its blob as unreachable and drop it.
+    //
+    let objects = root.join(".mog/objects.bin");
+    let mut bytes = fs::read(&objects).unwrap();
+    let at = bytes.windows(11).position(|w| w == b"needle_9c1e").unwrap();
+    let tree = bytes[..at].windows(4).rposition(|w| w == b"MG01").unwrap();
+    bytes[tree + 4] = 0xee;
+    fs::write(&objects, &bytes).unwrap();
+
+    assert!(mog::gc::gc(&mut open(&root), false).is_err());
+    assert_eq!(fs::read(&objects).unwrap(), bytes);
+}
+
+#[test]
+fn test_gc_keeps_objects_referenced_by_reflog_and_stash() {
+    let (_dir, root) = setup();
+    write_file(&root, "file.rs", b"v1");
+    stage_all(&root);
+    commit_all(&root, "first");
+
+    let mut repo = open(&root);
+    mog::branch::create(&mut repo, "feature", None).unwrap();
+    mog::checkout::checkout(&mut repo, "feature").unwrap();
+    drop(repo);
+
+    write_file(&root, "file.rs", b"only on feature");
+    stage_all(&root);
+    let lost = commit_all(&root, "feature work");
+
+    let mut repo = open(&root);
+    mog::checkout::checkout(&mut repo, "main").unwrap();
+    mog::branch::force_delete(&mut repo, "feature").unwrap();
+    drop(repo);
+
+    write_file(&root, "file.rs", b"stashed");
+    let mut repo = open(&root);
+    mog::stash::stash(&mut repo).unwrap();
+
+    mog::gc::gc(&mut repo, false).unwrap();
+    drop(repo);
+
+    let mut repo = open(&root);
+    assert_eq!(repo.resolve_to_commit("feature@{1}").unwrap().0, lost);
+    mog::checkout::checkout(&mut repo, "feature@{1}").unwrap();
+    assert_eq!(read_file(&root, "file.rs"), b"only on feature");
+
+    mog::checkout::checkout(&mut repo, "main").unwrap();
+    mog::stash::stash_pop(&mut repo).unwrap();
+    assert_eq!(read_file(&root, "file.rs"), b"stashed");
+}
+
+//
+//
+// Locking
+//
+//
+
+#[test]
+fn test_stale_lock_blocks_commands_until_removed() {
+    let (_dir, root) = setup();
+    write_file(&root, "a.txt", b"a");
+    stage_all(&root);
+    commit_all(&root, "first");
+
+    let long_ago = filetime::FileTime::from_unix_time(filetime::FileTime::now().unix_seconds() - 3600, 0);
+    for lock in [".mog/index.lock", ".mog/HEAD.lock", ".mog/refs/heads/main.lock"] {
+        fs::write(root.join(lock), b"").unwrap();
+        filetime::set_file_mtime(root.join(lock), long_ago).unwrap();
+
+        let Err(e) = mog::repository::Repository::open(&root) else {
+            panic!("opened the repository with {lock} present");
+        };
+        assert!(e.to_string().contains(lock.trim_start_matches(".mog/")), "{e}");
+
+        fs::remove_file(root.join(lock)).unwrap();
+        open(&root);
+    }
+}
+
+#[test]
+fn test_live_lock_only_blocks_writers_of_the_locked_file() {
+    let (_dir, root) = setup();
+    write_file(&root, "a.txt", b"a");
+    stage_all(&root);
+    commit_all(&root, "first");
+
+    //
+    // Another command is writing the index right now: status still runs, staging waits.
+    //
+    let lock = mog::lockfile::LockFile::acquire(&root.join(".mog/index")).unwrap();
+    write_file(&root, "a.txt", b"changed");
+
+    let mut repo = open(&root);
+    assert_eq!(mog::status::collect_status(&mut repo).unwrap().modified, ["a.txt".into()]);
+    assert!(mog::stage::stage(&mut repo, std::slice::from_ref(&root)).is_err());
+
+    drop(lock);
+    stage_all(&root);
+}
+
+#[test]
+fn test_lock_file_is_exclusive_and_released_on_drop() {
+    let dir  = TempDir::new().unwrap();
+    let path = dir.path().join("state");
+    fs::write(&path, b"old").unwrap();
+
+    {
+        let mut lock = mog::lockfile::LockFile::acquire(&path).unwrap();
+        lock.write_all(b"half-writ").unwrap();
+        assert!(mog::lockfile::LockFile::acquire(&path).is_err(), "second writer got the lock");
+        assert!(mog::lockfile::write_atomic(&path, b"other").is_err());
+        // Dropped without commit, like an error halfway through.
+    }
+    assert_eq!(fs::read(&path).unwrap(), b"old");
+    assert!(!dir.path().join("state.lock").exists());
+
+    mog::lockfile::write_atomic(&path, b"new").unwrap();
+    assert_eq!(fs::read(&path).unwrap(), b"new");
+    assert!(!dir.path().join("state.lock").exists());
+}
+
+#[test]
+fn test_ref_and_stash_writes_leave_no_locks_behind() {
+    let (_dir, root) = setup();
+    write_file(&root, "a.txt", b"a");
+    stage_all(&root);
+    commit_all(&root, "first");
+
+    write_file_later(&root, "a.txt", b"changed");
+    mog::stash::stash(&mut open(&root)).unwrap();
+    write_file_later(&root, "a.txt", b"changed again");
+    mog::stash::stash(&mut open(&root)).unwrap();
+    mog::stash::stash_drop(&open(&root), 1).unwrap();
+    mog::stash::stash_pop(&mut open(&root)).unwrap();
+    assert_eq!(read_file(&root, "a.txt"), b"changed again");
+
+    mog::branch::create(&mut open(&root), "feature", None).unwrap();
+    mog::checkout::checkout(&mut open(&root), "feature").unwrap();
+    mog::branch::rename(&open(&root), "feature", "renamed").unwrap();
+    assert_eq!(open(&root).current_branch().unwrap().as_deref(), Some("renamed"));
+
+    let leftovers = walkdir::WalkDir::new(root.join(".mog"))
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|e| e.path().to_string_lossy().ends_with(".lock"))
+        .count();
+    assert_eq!(leftovers, 0);
+}
+
+//
+//
+// Remotes
+//
+//
+
+#[test]
+fn test_clone_copies_history_and_checks_out_the_branch() {
+    let (_dir, origin) = setup();
+    write_file(&origin, "a.txt", b"one");
+    stage_all(&origin);
+    commit_all(&origin, "first");
+    write_file(&origin, "src/b.rs", b"two");
+    stage_all(&origin);
+    let head = commit_all(&origin, "second");
+
+    let dest = TempDir::new().unwrap();
+    let clone = dest.path().join("clone");
+    mog::remote::clone(origin.to_str().unwrap(), Some(&clone)).unwrap();
+
+    let repo = open(&clone);
+    assert_eq!(repo.current_branch().unwrap().as_deref(), Some("main"));
+    assert_eq!(repo.read_head_commit().unwrap(), head);
+    assert_eq!(repo.read_ref("refs/remotes/origin/main").unwrap(), head);
+    assert_eq!(read_file(&clone, "a.txt"), b"one");
+    assert_eq!(read_file(&clone, "src/b.rs"), b"two");
+
+    let report = mog::fsck::check(&mut open(&clone)).unwrap();
+    assert!(report.findings.is_empty(), "{:?}", report.findings);
+
+    assert!(mog::remote::clone(origin.to_str().unwrap(), Some(&clone)).is_err(), "cloned into a non-empty directory");
+}
+
+#[test]
+fn test_fetch_updates_tracking_refs_and_copies_only_missing_objects() {
+    let (_dir, origin) = setup();
+    write_file(&origin, "a.txt", b"one");
+    stage_all(&origin);
+    commit_all(&origin, "first");
+
+    let dest = TempDir::new().unwrap();
+    let clone = dest.path().join("clone");
+    mog::remote::clone(origin.to_str().unwrap(), Some(&clone)).unwrap();
+
+    write_file_later(&origin, "a.txt", b"one, changed");
+    stage_all(&origin);
+    let head = commit_all(&origin, "second");
+    mog::branch::create(&mut open(&origin), "feature", None).unwrap();
+
+    let mut repo = open(&clone);
+    let missing = mog::remote::missing_objects(&open(&origin), &[head], |hash| repo.storage.exists(hash)).unwrap();
+    assert_eq!(missing.len(), 3, "commit, tree and the changed blob");
+
+    mog::remote::fetch(&mut repo, "origin").unwrap();
+    assert_eq!(repo.read_ref("refs/remotes/origin/main").unwrap(), head);
+    assert_eq!(repo.read_ref("refs/remotes/origin/feature").unwrap(), head);
+    assert_ne!(repo.read_head_commit().unwrap(), head, "fetch must not move local branches");
+    assert!(mog::remote::missing_objects(&open(&origin), &[head], |hash| repo.storage.exists(hash)).unwrap().is_empty());
+
+    let (resolved, _) = repo.resolve_to_commit("origin/main").unwrap();
+    assert_eq!(resolved, head);
+
+    mog::branch::delete(&mut open(&origin), "feature").unwrap();
+    mog::remote::fetch(&mut repo, "origin").unwrap();
+    assert!(repo.read_ref("refs/remotes/origin/feature").is_err());
+}
+
+#[test]
+fn test_push_fast_forwards_a_checked_out_branch() {
+    let (_dir, origin) = setup();
+    write_file(&origin, "a.txt", b"one");
+    stage_all(&origin);
+    commit_all(&origin, "first");
+
+    let dest = TempDir::new().unwrap();
+    let clone = dest.path().join("clone");
+    mog::remote::clone(origin.to_str().unwrap(), Some(&clone)).unwrap();
+
+    write_file_later(&clone, "a.txt", b"pushed");
+    stage_all(&clone);
+    let head = commit_all(&clone, "second");
+
+    mog::remote::push(&mut open(&clone), "origin", "main", false, false).unwrap();
+    assert_eq!(open(&origin).read_head_commit().unwrap(), head);
+    assert_eq!(open(&clone).read_ref("refs/remotes/origin/main").unwrap(), head);
+    assert_eq!(read_file(&origin, "a.txt"), b"pushed");
+
+    // Nothing new to send.
+    mog::remote::push(&mut open(&clone), "origin", "main", false, false).unwrap();
+
+    let report = mog::fsck::check(&mut open(&origin)).unwrap();
+    assert_eq!(report.errors(), 0, "{:?}", report.findings);
+}
+
+#[test]
+fn test_push_rejects_non_fast_forward_unless_forced() {
+    let (_dir, origin) = setup();
+    write_file(&origin, "a.txt", b"one");
+    stage_all(&origin);
+    commit_all(&origin, "first");
+
+    let dest = TempDir::new().unwrap();
+    let clone = dest.path().join("clone");
+    mog::remote::clone(origin.to_str().unwrap(), Some(&clone)).unwrap();
+
+    mog::branch::create(&mut open(&origin), "topic", None).unwrap();
+    write_file_later(&origin, "b.txt", b"theirs");
+    stage_all(&origin);
+    let theirs = commit_all(&origin, "theirs");
+
+    mog::branch::create(&mut open(&clone), "topic", None).unwrap();
+    mog::checkout::checkout(&mut open(&clone), "topic").unwrap();
+    write_file_later(&clone, "c.txt", b"ours");
+    stage_all(&clone);
+    let ours = commit_all(&clone, "ours");
+
+    let mut repo = open(&clone);
+    let Err(e) = mog::remote::push(&mut repo, "origin", "main", false, false) else {
+        panic!("pushed a branch that isn't ahead of the remote");
+    };
+    assert!(e.to_string().contains("rejected"), "{e}");
+    assert_eq!(open(&origin).read_head_commit().unwrap(), theirs);
+
+    // Not checked out over there, so only the ref moves.
+    mog::remote::push(&mut repo, "origin", "topic", false, false).unwrap();
+    assert_eq!(open(&origin).read_ref("refs/heads/topic").unwrap(), ours);
+
+    mog::checkout::checkout(&mut repo, "main").unwrap();
+    write_file_later(&clone, "d.txt", b"forced");
+    stage_all(&clone);
+    let forced = commit_all(&clone, "forced");
+    mog::remote::push(&mut open(&clone), &origin.to_string_lossy(), "main", true, false).unwrap();
+    assert_eq!(open(&origin).read_head_commit().unwrap(), forced);
+    assert!(!file_exists(&origin, "b.txt"));
+}
+
+fn serve_in_thread(root: &Path) -> (std::thread::JoinHandle<Result<()>>, mog::protocol::Connection) {
+    let (ours, theirs) = std::os::unix::net::UnixStream::pair().unwrap();
+    let root = root.to_path_buf();
+    let server = std::thread::spawn(move || {
+        let conn = mog::protocol::Connection::new(theirs.try_clone()?, theirs);
+        mog::protocol::serve(&mut open(&root), conn)
+    });
+    (server, mog::protocol::Connection::new(ours.try_clone().unwrap(), ours))
+}
+
+fn object_count(root: &Path) -> usize {
+    let mut count = 0;
+    open(root).storage.for_each_hash(|_| count += 1);
+    count
+}
+
+#[test]
+fn test_fetch_over_pipe_sends_only_what_is_missing() {
+    let (_dir, origin) = setup();
+    for i in 0..20 {
+        write_file(&origin, &format!("file{i}.txt"), format!("version {i}").as_bytes());
+        stage_all(&origin);
+        commit_all(&origin, &format!("commit {i}"));
+    }
+
+    let (_local_dir, local) = setup();
+    let remote = mog::remote::Remote {
+        name:     Some("origin".to_owned()),
+        location: mog::remote::Location::Path(origin.clone()),
+    };
+
+    let (server, conn) = serve_in_thread(&origin);
+    let refs = mog::remote::fetch_from(&mut open(&local), &remote, mog::remote::Peer::Pipe(conn)).unwrap();
+    server.join().unwrap().unwrap();
+
+    let head = open(&origin).read_head_commit().unwrap();
+    assert_eq!(refs.head_branch.as_deref(), Some("main"));
+    assert_eq!(refs.head, Some(head));
+    assert_eq!(open(&local).read_ref("refs/remotes/origin/main").unwrap(), head);
+    let report = mog::fsck::check(&mut open(&local)).unwrap();
+    assert_eq!(report.errors(), 0, "{:?}", report.findings);
+
+    write_file(&origin, "file20.txt", b"one more");
+    stage_all(&origin);
+    let head = commit_all(&origin, "one more");
+
+    let before = object_count(&local);
+    let (server, conn) = serve_in_thread(&origin);
+    mog::remote::fetch_from(&mut open(&local), &remote, mog::remote::Peer::Pipe(conn)).unwrap();
+    server.join().unwrap().unwrap();
+
+    assert_eq!(object_count(&local) - before, 3, "commit, tree and the new blob");
+    assert_eq!(open(&local).read_ref("refs/remotes/origin/main").unwrap(), head);
+}
+
+#[test]
+fn test_push_over_pipe_updates_the_branch_and_refuses_stale_updates() {
+    let (_dir, origin) = setup();
+    write_file(&origin, "a.txt", b"one");
+    stage_all(&origin);
+    let base = commit_all(&origin, "first");
+
+    let dest = TempDir::new().unwrap();
+    let clone = dest.path().join("clone");
+    mog::remote::clone(origin.to_str().unwrap(), Some(&clone)).unwrap();
+
+    mog::branch::create(&mut open(&clone), "topic", None).unwrap();
+    mog::checkout::checkout(&mut open(&clone), "topic").unwrap();
+    write_file_later(&clone, "b.txt", b"two");
+    stage_all(&clone);
+    let ours = commit_all(&clone, "ours");
+
+    let remote = mog::remote::Remote {
+        name:     Some("origin".to_owned()),
+        location: mog::remote::Location::Path(origin.clone()),
+    };
+    let (server, conn) = serve_in_thread(&origin);
+    mog::remote::push_to(&mut open(&clone), &remote, mog::remote::Peer::Pipe(conn), "topic", false).unwrap();
+    server.join().unwrap().unwrap();
+
+    assert_eq!(open(&origin).read_ref("refs/heads/topic").unwrap(), ours);
+    assert_eq!(open(&clone).read_ref("refs/remotes/origin/topic").unwrap(), ours);
+    let report = mog::fsck::check(&mut open(&origin)).unwrap();
+    assert_eq!(report.errors(), 0, "{:?}", report.findings);
+
+    // Someone else moved it between our look and our update.
+    let Err(e) = mog::remote::update_pushed_branch(&mut open(&origin), "topic", Some(base), ours, true, "test") else {
+        panic!("accepted an update based on a stale value");
+    };
+    assert!(e.to_string().contains("changed while pushing"), "{e}");
+    let Err(e) = mog::remote::update_pushed_branch(&mut open(&origin), "topic", Some(ours), base, false, "test") else {
+        panic!("accepted a non-fast-forward");
+    };
+    assert!(e.to_string().contains("rejected"), "{e}");
+}
+
+#[test]
+fn test_fetch_over_pipe_rejects_objects_that_dont_match_their_hash() {
+    let (_dir, local) = setup();
+    let (ours, theirs) = std::os::unix::net::UnixStream::pair().unwrap();
+    let claimed = mog::object::hash_blob(b"what we asked for");
+
+    //
+    // A server that advertises a branch and then sends something else under its hash.
+    //
+    let server = std::thread::spawn(move || -> Result<()> {
+        let mut conn = mog::protocol::Connection::new(theirs.try_clone()?, theirs);
+        conn.recv()?; // hello
+        conn.send(|w| {
+            w.write_len_prefixed_str("main");
+            w.write_hash(&claimed);
+            w.write_u32(1);
+            w.write_len_prefixed_str("main");
+            w.write_hash(&claimed);
+            w.write_u32(0);
+        })?;
+        conn.recv()?; // wants
+        conn.recv()?; // done: we have nothing to offer
+        conn.send(|w| {
+            w.write_slice(b"MGPK");
+            w.write_u64(1);
+        })?;
+        let mut forged = Vec::new();
+        mog::object::encode_blob_and_hash(b"something else", &mut forged);
+        conn.send(|w| {
+            w.write_hash(&claimed);
+            w.write_slice(&forged);
+        })?;
+        conn.finish()
+    });
+
+    let remote = mog::remote::Remote { name: Some("evil".to_owned()), location: mog::remote::Location::Path(local.clone()) };
+    let peer = mog::remote::Peer::Pipe(mog::protocol::Connection::new(ours.try_clone().unwrap(), ours));
+    let Err(e) = mog::remote::fetch_from(&mut open(&local), &remote, peer) else {
+        panic!("accepted a forged object");
+    };
+    assert!(e.to_string().contains("doesn't match its hash"), "{e}");
+    server.join().unwrap().unwrap();
+
+    assert!(!open(&local).storage.exists(&claimed));
+    assert!(open(&local).read_ref("refs/remotes/evil/main").is_err());
+}
+
+#[test]
+fn test_fetch_over_pipe_rejects_ref_names_that_escape_refs() {
+    let (_dir, local) = setup();
+    let claimed = mog::object::hash_blob(b"anything");
+
+    //
+    // Servers advertising a HEAD branch, a branch and a tag that walk out of `.mog/refs`.
+    //
+    let adverts: [(&str, &str, &str); 3] = [
+        ("../../../PWNED", "main", "v1"),
+        ("main", "../../../PWNED", "v1"),
+        ("main", "main", "../../../PWNED"),
+    ];
+    for (head_branch, branch, tag) in adverts {
+        let (ours, theirs) = std::os::unix::net::UnixStream::pair().unwrap();
+        let server = std::thread::spawn(move || -> Result<()> {
+            let mut conn = mog::protocol::Connection::new(theirs.try_clone()?, theirs);
+            conn.recv()?; // hello
+            conn.send(|w| {
+                w.write_len_prefixed_str(head_branch);
+                w.write_hash(&claimed);
+                w.write_u32(1);
+                w.write_len_prefixed_str(branch);
+                w.write_hash(&claimed);
+                w.write_u32(1);
+                w.write_len_prefixed_str(tag);
+                w.write_hash(&claimed);
+            })?;
+            conn.finish()
+        });
+
+        let remote = mog::remote::Remote { name: Some("evil".to_owned()), location: mog::remote::Location::Path(local.clone()) };
+        let peer = mog::remote::Peer::Pipe(mog::protocol::Connection::new(ours.try_clone().unwrap(), ours));
+        let Err(e) = mog::remote::fetch_from(&mut open(&local), &remote, peer) else {
+            panic!("accepted an advertisement naming '../../../PWNED'");
+        };
+        assert!(e.to_string().contains("bad ref name '../../../PWNED'"), "{e}");
+        server.join().unwrap().unwrap();
+    }
+    assert!(!local.join("PWNED").exists() && !local.parent().unwrap().join("PWNED").exists());
+}
+
+#[test]
+fn test_serve_push_rejects_bad_branch_names_and_non_commits() {
+    let (_dir, origin) = setup();
+    write_file(&origin, "a.txt", b"one");
+    stage_all(&origin);
+    let head = commit_all(&origin, "first");
+
+    let mut repo = open(&origin);
+    let id   = repo.read_object(&head).unwrap().try_as_commit_id().unwrap();
+    let tree = repo.commit.get_tree(id);
+    drop(repo);
+
+    let push = |branch: &str, new: mog::hash::Hash| -> String {
+        let (server, mut conn) = serve_in_thread(&origin);
+        mog::protocol::hello(&mut conn, mog::protocol::Service::Push).unwrap();
+        conn.send(|w| {
+            w.write_len_prefixed_str(branch);
+            w.write_hash(&head);
+            w.write_hash(&new);
+            w.write_u32(1);
+        }).unwrap();
+        conn.send(|w| {
+            w.write_slice(b"MGPK");
+            w.write_u64(0);
+        }).unwrap();
+
+        let frame = conn.recv().unwrap();
+        let mut r = mog::wire::ReadCursor::new(&frame);
+        assert_eq!(r.read_u32().unwrap(), 1, "push of {branch} was accepted");
+        let message = r.read_len_prefixed_str().unwrap().into_owned();
+        conn.finish().unwrap();
+        server.join().unwrap().unwrap();
+        message
+    };
+
+    assert!(push("../../../PWNED", head).contains("bad ref name"));
+    assert!(!origin.join("PWNED").exists());
+    assert!(!origin.join(".mog/logs/PWNED").exists());
+
+    assert!(push("main", tree).contains("is not a commit"));
+    assert_eq!(open(&origin).read_head_commit().unwrap(), head);
+}
+
+#[test]
+fn test_clone_tracks_upstream_and_counts_ahead_behind() {
+    let (_dir, origin) = setup();
+    write_file(&origin, "a.txt", b"one");
+    stage_all(&origin);
+    commit_all(&origin, "first");
+
+    let dest = TempDir::new().unwrap();
+    let clone = dest.path().join("clone");
+    mog::remote::clone(origin.to_str().unwrap(), Some(&clone)).unwrap();
+
+    let config = mog::config::load(Some(&clone)).unwrap();
+    assert_eq!(config.get("remote.origin.url"), Some(&*origin.canonicalize().unwrap().to_string_lossy()));
+    let upstream = mog::branch::upstream(&config, "main").unwrap();
+    assert_eq!(upstream.tracking_ref(), "refs/remotes/origin/main");
+    assert_eq!(mog::remote::default_remote(&open(&clone)).unwrap(), "origin");
+
+    write_file(&origin, "b.txt", b"theirs");
+    stage_all(&origin);
+    commit_all(&origin, "theirs");
+    write_file(&clone, "c.txt", b"ours");
+    stage_all(&clone);
+    commit_all(&clone, "ours 1");
+    write_file(&clone, "d.txt", b"ours");
+    stage_all(&clone);
+    let ours = commit_all(&clone, "ours 2");
+
+    let mut repo = open(&clone);
+    mog::remote::fetch(&mut repo, "origin").unwrap();
+    let theirs = repo.read_ref(&upstream.tracking_ref()).unwrap();
+    assert_eq!(mog::branch::ahead_behind(&mut repo, &ours, &theirs), (2, 1));
+    mog::branch::list(&mut repo).unwrap();
+
+    //
+    // The upstream follows renames and goes away with the branch.
+    //
+    mog::branch::create(&mut repo, "topic", None).unwrap();
+    mog::branch::set_upstream_to(&repo, Some("topic"), "origin/main").unwrap();
+    assert!(mog::branch::set_upstream_to(&repo, Some("topic"), "origin/nope").is_err());
+    mog::branch::rename(&repo, "topic", "renamed").unwrap();
+
+    let config = mog::config::load(Some(&clone)).unwrap();
+    assert!(mog::branch::upstream(&config, "topic").is_none());
+    assert_eq!(mog::branch::upstream(&config, "renamed"), Some(upstream.clone()));
+
+    mog::branch::force_delete(&mut repo, "renamed").unwrap();
+    assert!(mog::branch::upstream(&mog::config::load(Some(&clone)).unwrap(), "renamed").is_none());
+    assert!(mog::branch::upstream(&mog::config::load(Some(&clone)).unwrap(), "main").is_some());
+}
+
+#[test]
+fn test_push_set_upstream_records_tracking_branch() {
+    let (_dir, origin) = setup();
+    write_file(&origin, "a.txt", b"one");
+    stage_all(&origin);
+    commit_all(&origin, "first");
+
+    let (_local_dir, local) = setup();
+    mog::remote::add_remote(&open(&local), "upstream", &mog::remote::Location::Path(origin.clone())).unwrap();
+    assert!(mog::remote::add_remote(&open(&local), "upstream", &mog::remote::Location::Path(origin.clone())).is_err());
+
+    write_file(&local, "b.txt", b"two");
+    stage_all(&local);
+    let head = commit_all(&local, "unrelated");
+    mog::branch::create(&mut open(&local), "feature", None).unwrap();
+
+    mog::remote::push(&mut open(&local), "upstream", "feature", false, true).unwrap();
+    assert_eq!(open(&origin).read_ref("refs/heads/feature").unwrap(), head);
+
+    let config = mog::config::load(Some(&local)).unwrap();
+    let upstream = mog::branch::upstream(&config, "feature").unwrap();
+    assert_eq!((upstream.remote.as_str(), upstream.branch.as_str()), ("upstream", "feature"));
+    assert_eq!(open(&local).read_ref(&upstream.tracking_ref()).unwrap(), head);
+}
+
+//
+//
+// Config
+//
+//
+
+#[test]
+fn test_config_set_get_unset_in_repository() {
+    let (_dir, root) = setup();
+    use mog::config::Scope;
+
+    mog::config::set(Some(&root), Scope::Repo, "core.pager", "less -R").unwrap();
+    mog::config::set(Some(&root), Scope::Repo, "alias.lg", " log --graph # with spaces ").unwrap();
+    mog::config::set(Some(&root), Scope::Repo, "Core.Pager", "more").unwrap();
+
+    let config = mog::config::load_scope(Some(&root), Scope::Repo).unwrap();
+    assert_eq!(config.get("core.pager"), Some("more"));
+    assert_eq!(config.get("alias.lg"), Some(" log --graph # with spaces "));
+    assert_eq!(config.entries().count(), 2);
+
+    mog::config::unset(Some(&root), Scope::Repo, "core.pager").unwrap();
+    assert!(mog::config::unset(Some(&root), Scope::Repo, "core.pager").is_err());
+    assert_eq!(mog::config::load_scope(Some(&root), Scope::Repo).unwrap().get("core.pager"), None);
+
+    assert!(mog::config::set(Some(&root), Scope::Repo, "nosection", "x").is_err());
+    assert!(!root.join(".mog/config.lock").exists());
+    assert!(mog::config::set(None, Scope::Repo, "core.pager", "x").is_err());
+}
+
+#[test]
+fn test_commit_identity_comes_from_config_and_amend_keeps_the_author() {
+    let (_dir, root) = setup();
+    use mog::config::Scope;
+
+    mog::config::set(Some(&root), Scope::Repo, "user.name", "Alice").unwrap();
+    mog::config::set(Some(&root), Scope::Repo, "user.email", "alice@example.com").unwrap();
+
+    write_file(&root, "a.txt", b"a");
+    stage_all(&root);
+    let mut repo = open(&root);
+    let tree     = mog::index::Index::load(&root).unwrap().write_tree(&mut repo).unwrap();
+    let first    = mog::commit::commit(&mut repo, tree, None, None, "first").unwrap();
+    drop(repo);
+
+    let mut repo = open(&root);
+    let id       = repo.read_object(&first).unwrap().try_as_commit_id().unwrap();
+    let author   = repo.commit.get_author_signature(id);
+    assert_eq!((author.name, author.email), ("Alice", "alice@example.com"));
+    assert_eq!(repo.commit.get_committer_signature(id), author);
+    let authored = (author.timestamp, author.tz_offset);
+    drop(repo);
+
+    // Someone else rewords it: still Alice's change, but they committed it.
+    mog::config::set(Some(&root), Scope::Repo, "user.name", "Bob").unwrap();
+    mog::config::set(Some(&root), Scope::Repo, "user.email", "bob@example.com").unwrap();
+    let mut repo = open(&root);
+    let amended  = mog::commit::amend(&mut repo, tree, None, Some("reworded")).unwrap();
+    drop(repo);
+
+    let mut repo  = open(&root);
+    let id        = repo.read_object(&amended).unwrap().try_as_commit_id().unwrap();
+    let author    = repo.commit.get_author_signature(id);
+    let committer = repo.commit.get_committer_signature(id);
+    assert_eq!((author.name, author.email), ("Alice", "alice@example.com"));
+    assert_eq!((author.timestamp, author.tz_offset), authored);
+    assert_eq!((committer.name, committer.email), ("Bob", "bob@example.com"));
+
+    let mut out = String::new();
+    mog::cat_file::cat_file(&mut repo, &mog::hash::hash_to_hex(&amended), &mut out).unwrap();
+    assert!(out.contains("\nauthor Alice <alice@example.com> "), "{out}");
+    assert!(out.contains("\ncommitter Bob <bob@example.com> "), "{out}");
+}
+
+//
+//
+// Fsck
+//
+//
+
+#[test]
+fn test_fsck_clean_repo_reports_only_dangling_objects() {
+    let (_dir, root) = setup();
+    write_file(&root, "a.txt", b"kept");
+    write_file(&root, "big.rs", numbered_lines(2000, "").as_bytes());
+    stage_all(&root);
+    commit_all(&root, "first");
+
+    // Staged, then replaced before committing: nothing points at it any more.
+    write_file_later(&root, "a.txt", b"lost");
+    stage_all(&root);
+    write_file_later(&root, "a.txt", b"kept again");
+    write_file_later(&root, "big.rs", numbered_lines(2000, "changed").as_bytes());
+    stage_all(&root);
+    commit_all(&root, "second");
+
+    let report = mog::fsck::check(&mut open(&root)).unwrap();
+    assert_eq!(report.errors(), 0, "{:?}", report.findings);
+    assert_eq!(report.count(mog::fsck::FindingKind::Unaccounted), 0);
+
+    let dangling = report.findings.iter()
+        .filter(|f| f.kind == mog::fsck::FindingKind::Dangling)
+        .map(|f| (f.hash.unwrap(), f.detail.as_str()))
+        .collect::<Vec<_>>();
+    assert_eq!(dangling, [(mog::object::hash_blob(b"lost"), "blob")]);
+
+    mog::gc::gc(&mut open(&root), false).unwrap();
+    let report = mog::fsck::check(&mut open(&root)).unwrap();
+    assert!(report.findings.is_empty(), "{:?}", report.findings);
+}
+
+#[test]
+fn test_fsck_detects_corrupt_and_missing_objects() {
+    let (_dir, root) = setup();
+    write_file(&root, "a.txt", b"needle 8f3a");
+    write_file(&root, "b.txt", b"other");
+    stage_all(&root);
+    commit_all(&root, "first");
+
+    //
+    // Flip a byte of the stored blob, point a branch at nothing, stage a hash that isn't stored.
+    //
+    let objects = root.join(".mog/objects.bin");
+    let mut bytes = fs::read(&objects).unwrap();
+    let at = bytes.windows(11).position(|w| w == b"needle 8f3a").unwrap();
+    bytes[at] ^= 0x20;
+    fs::write(&objects, bytes).unwrap();
+
+    let ghost = mog::object::hash_blob(b"never stored");
+    fs::write(root.join(".mog/refs/heads/ghost"), format!("{}\n", mog::hash::hash_to_hex(&ghost))).unwrap();
+
+    let mut index = mog::index::Index::load(&root).unwrap();
+    index.add("b.txt", ghost, &fs::metadata(root.join("b.txt")).unwrap());
+    index.save(&root).unwrap();
+
+    let report = mog::fsck::check(&mut open(&root)).unwrap();
+    let kinds = |hash| report.findings.iter().filter(|f| f.hash == Some(hash)).map(|f| f.kind).collect::<Vec<_>>();
+
+    use mog::fsck::FindingKind::{Corrupt, Missing};
+    assert_eq!(kinds(mog::object::hash_blob(b"needle 8f3a")), [Corrupt]);
+    assert_eq!(kinds(ghost), [Missing, Missing]);
+    assert_eq!(report.errors(), 3, "{:?}", report.findings);
+
+    assert!(mog::fsck::fsck(&mut open(&root), true).is_err());
+}
+
+//
+//
+// Symlinks and file modes
+//
+//
+
+#[test]
+#[cfg(unix)]
+fn test_symlinks_are_stored_as_their_target_and_checked_out_as_links() -> Result<()> {
+    use mog::object::{hash_blob, MODE_FILE, MODE_LINK};
+
+    let (_dir, root) = setup();
+    write_file(&root, "a.txt", b"contents of a");
+    write_symlink(&root, "link", "a.txt");
+    write_symlink(&root, "dangling", "nowhere");
+    stage_all(&root);
+    commit_all(&root, "base");
+
+    //
+    // The link is its target, not a second copy of a.txt; the dangling one isn't a delete.
+    //
+    let mut repo = open(&root);
+    let head     = repo.read_head_commit()?;
+    let commit   = repo.read_object(&head)?.try_as_commit_id()?;
+    let tree     = repo.commit.get_tree(commit);
+    let flat     = mog::status::flatten_tree(&mut repo, tree)?;
+    assert_eq!(flat.lookup_entry("link"),     Some((hash_blob(b"a.txt"), MODE_LINK)));
+    assert_eq!(flat.lookup_entry("dangling"), Some((hash_blob(b"nowhere"), MODE_LINK)));
+    assert_eq!(flat.lookup_entry("a.txt"),    Some((hash_blob(b"contents of a"), MODE_FILE)));
+
+    let buckets = mog::status::collect_status(&mut repo)?;
+    assert!(buckets.modified.is_empty() && buckets.deleted.is_empty() && buckets.untracked.is_empty());
+
+    mog::branch::create(&mut repo, "feature", None)?;
+    mog::checkout::checkout(&mut repo, "feature")?;
+    write_symlink(&root, "link", "elsewhere/b.txt");
+    fs::remove_file(root.join("dangling"))?;
+    stage_all(&root);
+    commit_all(&root, "retarget");
+
+    let mut repo = open(&root);
+    mog::checkout::checkout(&mut repo, "main")?;
+    assert_eq!(link_target(&root, "link").as_deref(),     Some("a.txt"));
+    assert_eq!(link_target(&root, "dangling").as_deref(), Some("nowhere"));
+    assert_eq!(read_file(&root, "a.txt"), b"contents of a");
+
+    let mut repo = open(&root);
+    mog::checkout::checkout(&mut repo, "feature")?;
+    assert_eq!(link_target(&root, "link").as_deref(), Some("elsewhere/b.txt"));
+    assert!(fs::symlink_metadata(root.join("dangling")).is_err());
+
+    //
+    // Checking out a single path recreates the link too.
+    //
+    let mut repo = open(&root);
+    mog::checkout::checkout_path(&mut repo, "main", "link")?;
+    assert_eq!(link_target(&root, "link").as_deref(), Some("a.txt"));
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn test_symlink_changes_show_in_status_and_are_undone_by_discard_and_stash() -> Result<()> {
+    let (_dir, root) = setup();
+    write_file(&root, "a.txt", b"a");
+    write_symlink(&root, "link", "a.txt");
+    stage_all(&root);
+    commit_all(&root, "base");
+
+    //
+    // New targets differ in length: same-second changes are only caught by size.
+    //
+    let modified = |root: &Path| -> Result<Vec<Box<str>>> {
+        Ok(mog::status::collect_status(&mut open(root))?.modified)
+    };
+
+    write_symlink(&root, "link", "elsewhere.txt");
+    assert_eq!(modified(&root)?, ["link".into()]);
+
+    mog::discard::discard(&mut open(&root), &[std::path::PathBuf::from("link")])?;
+    assert_eq!(link_target(&root, "link").as_deref(), Some("a.txt"));
+
+    //
+    // A link turned into a regular file holding the same bytes is still a change.
+    //
+    fs::remove_file(root.join("link"))?;
+    write_file(&root, "link", b"a.txt");
+    assert_eq!(modified(&root)?, ["link".into()]);
+
+    mog::discard::discard(&mut open(&root), &[])?;
+    assert_eq!(link_target(&root, "link").as_deref(), Some("a.txt"));
+    assert_eq!(read_file(&root, "a.txt"), b"a", "discard must not write through the link");
+
+    write_symlink(&root, "link", "elsewhere.txt");
+    mog::stash::stash(&mut open(&root))?;
+    assert_eq!(link_target(&root, "link").as_deref(), Some("a.txt"));
+
+    mog::stash::stash_pop(&mut open(&root))?;
+    assert_eq!(link_target(&root, "link").as_deref(), Some("elsewhere.txt"));
+    assert_eq!(modified(&root)?, ["link".into()]);
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn test_chmod_is_a_change_and_checkout_discard_and_stash_restore_the_exec_bit() -> Result<()> {
+    use mog::object::{MODE_EXEC, MODE_FILE};
+
+    let (_dir, root) = setup();
+    write_file(&root, "run.sh", b"echo hi\n");
+    stage_all(&root);
+    commit_all(&root, "base");
+
+    let modified = |root: &Path| -> Result<Vec<Box<str>>> {
+        Ok(mog::status::collect_status(&mut open(root))?.modified)
+    };
+    let staged_mode = |root: &Path| -> Result<u32> {
+        let index = mog::index::Index::load(root)?;
+        Ok(index.modes[index.find("run.sh").unwrap()])
+    };
+
+    //
+    // Same contents, size and mtime: only the mode tells.
+    //
+    set_executable(&root, "run.sh", true);
+    assert_eq!(modified(&root)?, ["run.sh".into()]);
+
+    stage_all(&root);
+    assert_eq!(staged_mode(&root)?, MODE_EXEC);
+    assert!(modified(&root)?.is_empty());
+    commit_all(&root, "make it executable");
+
+    let mut repo = open(&root);
+    mog::checkout::checkout(&mut repo, "HEAD~1")?;
+    assert!(!is_executable(&root, "run.sh"));
+    mog::checkout::checkout(&mut repo, "main")?;
+    assert!(is_executable(&root, "run.sh"));
+
+    set_executable(&root, "run.sh", false);
+    mog::discard::discard(&mut open(&root), &[])?;
+    assert!(is_executable(&root, "run.sh"));
+
+    set_executable(&root, "run.sh", false);
+    mog::discard::discard(&mut open(&root), &[std::path::PathBuf::from("run.sh")])?;
+    assert!(is_executable(&root, "run.sh"));
+
+    set_executable(&root, "run.sh", false);
+    mog::stash::stash(&mut open(&root))?;
+    assert!(is_executable(&root, "run.sh"));
+    mog::stash::stash_pop(&mut open(&root))?;
+    assert!(!is_executable(&root, "run.sh"));
+
+    stage_all(&root);
+    assert_eq!(staged_mode(&root)?, MODE_FILE);
+    Ok(())
+}
+
+//
+//
+// Full end-to-end workflow
+//
+//
+
+#[test]
+fn test_full_dev_workflow() {
+    let (_dir, root) = setup();
+
+    // Initial commit.
+    write_file(&root, "src/main.rs", b"fn main() {}");
+    write_file(&root, "README.md",   b"# Project");
+    stage_all(&root);
+    commit_all(&root, "initial commit");
+
+    // Feature branch.
+    let mut repo = open(&root);
+    mog::branch::create(&mut repo, "feature", None).unwrap();
+    mog::checkout::checkout(&mut repo, "feature").unwrap();
+
+    write_file(&root, "src/feature.rs", b"pub fn feature() {}");
+    stage_all(&root);
+    commit_all(&root, "add feature");
+
+    // Stash mid-work change.
+    write_file(&root, "src/wip.rs", b"// work in progress");
+    stage_all(&root);
+    touch_future(&root, "src/wip.rs");
+    let mut repo = open(&root);
+    mog::stash::stash(&mut repo).unwrap();
+
+    assert!(!file_exists(&root, "src/wip.rs"));
+
+    // Switch back to main, verify feature.rs absent.
+    let mut repo = open(&root);
+    mog::checkout::checkout(&mut repo, "main").unwrap();
+    assert!(!file_exists(&root, "src/feature.rs"));
+    assert_eq!(read_file(&root, "src/main.rs"), b"fn main() {}");
+
+    // Back to feature, pop stash.
+    let mut repo = open(&root);
+    mog::checkout::checkout(&mut repo, "feature").unwrap();
+    let mut repo = open(&root);
+    mog::stash::stash_pop(&mut repo).unwrap();
+    assert!(file_exists(&root, "src/wip.rs"));
+
+    // Discard wip.
+    let mut repo = open(&root);
+    mog::discard::discard(&mut repo, &[std::path::PathBuf::from("src/wip.rs")]).unwrap();
+    assert!(!file_exists(&root, "src/wip.rs"));
+
+    // Status should be clean.
+    let mut repo = open(&root);
+    let buckets  = mog::status::collect_status(&mut repo).unwrap();
+    assert!(buckets.staged_new_modified.is_empty());
+    assert!(buckets.modified.is_empty());
+    assert!(buckets.untracked.is_empty());
+}
+
+
+//
+//
+// Helpers
+//
+//
+
+fn setup() -> (TempDir, std::path::PathBuf) {
+    let dir  = TempDir::new().unwrap();
+    let root = dir.path().to_path_buf();
+    mog::repository::Repository::init(&root).unwrap();
+    (dir, root)
+}
+
+fn open(root: &Path) -> mog::repository::Repository {
+    mog::repository::Repository::open(root).unwrap()
+}
+
+fn write_file(root: &Path, rel: &str, content: &[u8]) {
+    let abs = root.join(rel);
+    if let Some(parent) = abs.parent() {
+        fs::create_dir_all(parent).unwrap();
+    }
+    fs::write(&abs, content).unwrap();
+    // Force mtime to change even within the same second.
+    let mtime = std::time::SystemTime::now() + std::time::Duration::from_secs(1);
+    filetime::set_file_mtime(&abs, filetime::FileTime::from_system_time(mtime)).unwrap();
 }
 
 fn write_file_later(root: &Path, rel: &str, content: &[u8]) {
@@ -675,6 +3086,47 @@ fn write_file_later(root: &Path, rel: &str, content: &[u8]) {
     write_file(root, rel, content);
 }
 
+/// `n` source-like lines, every 500th one carrying `edit` so versions differ in a few places.
+fn numbered_lines(n: usize, edit: &str) -> String {
+    (0..n).map(|i| {
+        if i % 500 == 0 { format!("let x{i} = {i}; // {edit}\n") } else { format!("let x{i} = {i};\n") }
+    }).collect()
+}
+
+/// Replace `rel` with a symlink to `target`, with a later mtime like `write_file`.
+#[cfg(unix)]
+fn write_symlink(root: &Path, rel: &str, target: &str) {
+    let abs = root.join(rel);
+    if fs::symlink_metadata(&abs).is_ok() {
+        fs::remove_file(&abs).unwrap();
+    }
+    std::os::unix::fs::symlink(target, &abs).unwrap();
+    let mtime = filetime::FileTime::from_system_time(std::time::SystemTime::now() + std::time::Duration::from_secs(1));
+    filetime::set_symlink_file_times(&abs, mtime, mtime).unwrap();
+}
+
+#[cfg(unix)]
+fn set_executable(root: &Path, rel: &str, executable: bool) {
+    use std::os::unix::fs::PermissionsExt;
+    let mode = if executable { 0o755 } else { 0o644 };
+    fs::set_permissions(root.join(rel), fs::Permissions::from_mode(mode)).unwrap();
+}
+
+#[cfg(unix)]
+fn is_executable(root: &Path, rel: &str) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    fs::metadata(root.join(rel)).unwrap().permissions().mode() & 0o111 != 0
+}
+
+/// Where `rel` points, or None if it isn't a symlink.
+fn link_target(root: &Path, rel: &str) -> Option<String> {
+    let abs = root.join(rel);
+    if !fs::symlink_metadata(&abs).ok()?.is_symlink() {
+        return None;
+    }
+    Some(fs::read_link(abs).ok()?.to_string_lossy().into_owned())
+}
+
 fn touch_future(root: &Path, rel: &str) {
     let abs    = root.join(rel);
     let future = std::time::SystemTime::now() + std::time::Duration::from_secs(2);
@@ -695,10 +3147,57 @@ fn stage_all(root: &Path) {
     mog::stage::stage(&mut repo, &[root.to_path_buf()]).unwrap();
 }
 
+fn log_options(revisions: &[&str]) -> mog::log::LogOptions {
+    mog::log::LogOptions {
+        revisions: revisions.iter().map(|r| (*r).to_owned()).collect(),
+        ..Default::default()
+    }
+}
+
+/// Write a commit straight to the store: `files` are `path` (at most one directory deep) and contents.
+fn commit_files_at(
+    repo: &mut mog::repository::Repository,
+    files: &[(&str, &str)],
+    parents: &[mog::hash::Hash],
+    author: &str,
+    time: i64,
+    message: &str,
+) -> mog::hash::Hash {
+    use mog::object::{Object, MODE_DIR, MODE_FILE};
+    use mog::tree::TreeEntry;
+
+    let mut top  = Vec::new();
+    let mut dirs = std::collections::BTreeMap::<&str, Vec<TreeEntry>>::new();
+    for (path, contents) in files {
+        let hash = repo.write_blob(contents.as_bytes());
+        match path.split_once('/') {
+            Some((dir, name)) => dirs.entry(dir).or_default().push(TreeEntry { hash, name: name.into(), mode: MODE_FILE }),
+            None              => top.push(TreeEntry { hash, name: (*path).into(), mode: MODE_FILE }),
+        }
+    }
+    for (dir, entries) in dirs {
+        let id = repo.tree.push(&entries);
+        top.push(TreeEntry { hash: repo.write_object(Object::Tree(id)), name: dir.into(), mode: MODE_DIR });
+    }
+    top.sort_by(|a, b| a.name.cmp(&b.name));
+    let tree_id = repo.tree.push(&top);
+    let tree    = repo.write_object(Object::Tree(tree_id));
+
+    let author    = mog::identity::Identity::parse(author).unwrap();
+    let signature = author.sign(time, 0);
+    let commit_id = repo.commit.push_signed(tree, parents, &signature, &signature, message);
+    let hash      = repo.write_object(Object::Commit(commit_id));
+    repo.storage.flush().unwrap();
+    repo.storage.remap().unwrap();
+    hash
+}
+
 fn commit_all(root: &Path, message: &str) -> mog::hash::Hash {
     let mut repo  = open(root);
-    let index     = mog::index::Index::load(&repo.root).unwrap();
+    let mut index = mog::index::Index::load(&repo.root).unwrap();
     let tree      = index.write_tree(&mut repo).unwrap();
     let parent    = repo.read_head_commit().ok();
-    mog::commit::commit(&mut repo, tree, parent, "test", message).unwrap()
+    let commit    = mog::commit::commit(&mut repo, tree, parent, Some("test"), message).unwrap();
+    index.save(&repo.root).unwrap();
+    commit
 }
@@ -17,7 +17,7 @@ fn test_blob_roundtrip() {
     let hash = repo.write_blob(data);
     assert!(repo.storage.exists(&hash));
     let raw = repo.storage.read(&hash).unwrap();
-    let got = mog::object::decode_blob_bytes(raw).unwrap();
+    let got = mog::object::decode_blob_bytes(&raw).unwrap();
     assert_eq!(got, data);
 }
 
@@ -36,7 +36,7 @@ fn test_empty_blob() {
     let mut repo = mock_repo();
     let hash = repo.write_blob(b"");
     let raw  = repo.storage.read(&hash).unwrap();
-    let got  = mog::object::decode_blob_bytes(raw).unwrap();
+    let got  = mog::object::decode_blob_bytes(&raw).unwrap();
     assert_eq!(got, b"");
 }
 
@@ -46,7 +46,7 @@ fn test_large_blob() {
     let data: Vec<u8> = (0..100_000).map(|i| (i % 256) as u8).collect();
     let hash = repo.write_blob(&data);
     let raw  = repo.storage.read(&hash).unwrap();
-    let got  = mog::object::decode_blob_bytes(raw).unwrap();
+    let got  = mog::object::decode_blob_bytes(&raw).unwrap();
     assert_eq!(got, data.as_slice());
 }
 
@@ -76,7 +76,7 @@ fn test_tree_roundtrip() {
     assert!(repo.storage.exists(&tree_hash));
 
     let raw      = repo.storage.read(&tree_hash).unwrap();
-    let obj      = repo.stores.decode_and_push_object(raw).unwrap();
+    let obj      = repo.stores.decode_and_push_object(&raw).unwrap();
     let tid      = obj.try_as_tree_id().unwrap();
     assert_eq!(repo.tree.entry_count(tid), 1);
 
@@ -152,6 +152,319 @@ fn test_commit_with_parent() {
     assert_eq!(repo.commit.get_parents(c2_id), &[c1_hash]);
 }
 
+#[test]
+fn test_commit_v1_decodes_and_reencodes_to_the_same_bytes() {
+    use mog::wire::{Decode, Encode, ReadCursor, WriteCursor};
+
+    // A commit as written before committer and time zone were recorded.
+    let tree = [7u8; 32];
+    let mut v1 = Vec::new();
+    {
+        let mut w = WriteCursor::new(&mut v1);
+        w.write_hash(&tree);
+        w.write_u32(0);
+        w.write_i64(1000);
+        w.write_len_prefixed_str("Alice");
+        w.write_len_prefixed_str("old commit");
+    }
+
+    let p = mog::commit::CommitPayloadOwned::decode(&mut ReadCursor::new(&v1)).unwrap();
+    assert_eq!(p.version, 1);
+    assert_eq!(&*p.author, "Alice");
+    assert_eq!(&*p.author_email, "");
+    assert_eq!(p.committer_signature(), p.author_signature(), "committed by its author");
+
+    let mut repo = mock_repo();
+    let id = repo.commit.push_payload_owned(&p);
+    let mut again = Vec::new();
+    mog::commit::CommitPayloadRef::new(&repo.commit, id).view().encode(&mut WriteCursor::new(&mut again));
+    assert_eq!(again, v1, "re-encoding an old commit must not change its hash");
+}
+
+#[test]
+fn test_commit_roundtrips_committer_and_time_zone() {
+    use mog::wire::{Decode, Encode, ReadCursor, WriteCursor};
+    use mog::commit::Signature;
+
+    let mut repo = mock_repo();
+    let author    = Signature { name: "Alice", email: "alice@example.com", timestamp: 1000, tz_offset: 120 };
+    let committer = Signature { name: "Bob", email: "bob@example.com", timestamp: 2000, tz_offset: -330 };
+    let id = repo.commit.push_signed([1u8; 32], &[[2u8; 32]], &author, &committer, "msg");
+
+    let mut bytes = Vec::new();
+    mog::commit::CommitPayloadRef::new(&repo.commit, id).view().encode(&mut WriteCursor::new(&mut bytes));
+    let p = mog::commit::CommitPayloadOwned::decode(&mut ReadCursor::new(&bytes)).unwrap();
+
+    assert_eq!(p.version, mog::commit::COMMIT_FORMAT_VERSION);
+    assert_eq!(p.author_signature(), author);
+    assert_eq!(p.committer_signature(), committer);
+    assert_eq!(&*p.message, "msg");
+    assert_eq!(committer.to_string(), "Bob <bob@example.com> 2000 -0530");
+
+    // Written by a newer mog.
+    let mut future = bytes.clone();
+    // tree, parent count, parent, author time, author, message
+    let version_at = 32 + 4 + 32 + 8 + (4 + 5) + (4 + 3);
+    future[version_at..version_at + 4].copy_from_slice(&3u32.to_le_bytes());
+    assert!(mog::commit::CommitPayloadOwned::decode(&mut ReadCursor::new(&future)).is_err());
+}
+
+#[test]
+fn test_identity_from_environment_then_config_then_login() {
+    use mog::identity::{Identity, Role};
+
+    let config = mog::config::Config::parse("[user]\n\tname = Config Name\n\temail = config@example.com\n").unwrap();
+    let env = |vars: &'static [(&'static str, &'static str)]| {
+        move |var: &str| vars.iter().find(|(k, _)| *k == var).map(|(_, v)| (*v).to_owned())
+    };
+
+    let from_config = Identity::from_config(&config, env(&[]), Role::Author);
+    assert_eq!(from_config.to_string(), "Config Name <config@example.com>");
+
+    let vars = &[("MOG_AUTHOR_NAME", "Env Name"), ("MOG_AUTHOR_EMAIL", "env@example.com")];
+    assert_eq!(Identity::from_config(&config, env(vars), Role::Author).to_string(), "Env Name <env@example.com>");
+    assert_eq!(Identity::from_config(&config, env(vars), Role::Committer), from_config, "author variables don't set the committer");
+
+    let empty = mog::config::Config::default();
+    let login = Identity::from_config(&empty, env(&[("USER", "someone")]), Role::Committer);
+    assert_eq!(login, Identity { name: "someone".into(), email: String::new() });
+
+    assert_eq!(Identity::parse("A. Person <a@example.com>").unwrap().to_string(), "A. Person <a@example.com>");
+    assert_eq!(Identity::parse("Just Name").unwrap().email, "");
+    assert!(Identity::parse("<a@example.com>").is_err());
+    assert!(Identity::parse("Name <a@example.com").is_err());
+}
+
+//
+//
+// Tag tests
+//
+//
+
+#[test]
+fn test_tag_roundtrip_through_storage() {
+    let mut repo = mock_repo();
+
+    let tree_hash   = write_simple_tree(&mut repo, b"x", "x.rs");
+    let commit_id   = repo.commit.push(tree_hash, &[], 1000, "author", "release");
+    let commit_hash = repo.write_object(mog::object::Object::Commit(commit_id));
+
+    let tag_id   = repo.tag.push(commit_hash, 2000, "v1.0", "tagger", "first release");
+    let tag_hash = repo.write_object(mog::object::Object::Tag(tag_id));
+
+    let decoded = repo.read_object(&tag_hash).unwrap().try_as_tag_id().unwrap();
+    assert_eq!(repo.tag.get_target(decoded),    commit_hash);
+    assert_eq!(repo.tag.get_timestamp(decoded), 2000);
+    assert_eq!(repo.tag.get_name(decoded),      "v1.0");
+    assert_eq!(repo.tag.get_tagger(decoded),    "tagger");
+    assert_eq!(repo.tag.get_message(decoded),   "first release");
+}
+
+#[test]
+fn test_tag_peels_to_commit_through_nested_tags() {
+    let mut repo = mock_repo();
+
+    let tree_hash   = write_simple_tree(&mut repo, b"x", "x.rs");
+    let commit_id   = repo.commit.push(tree_hash, &[], 1000, "author", "release");
+    let commit_hash = repo.write_object(mog::object::Object::Commit(commit_id));
+
+    let inner = repo.tag.push(commit_hash, 2000, "v1.0", "tagger", "inner");
+    let inner_hash = repo.write_object(mog::object::Object::Tag(inner));
+    let outer = repo.tag.push(inner_hash, 3000, "v1.0-signed", "tagger", "outer");
+    let outer_hash = repo.write_object(mog::object::Object::Tag(outer));
+
+    let (peeled, _) = repo.peel_to_commit(&outer_hash).unwrap();
+    assert_eq!(peeled, commit_hash);
+    assert!(repo.peel_to_commit(&tree_hash).is_err());
+}
+
+//
+//
+// Delta tests
+//
+//
+
+#[test]
+fn test_delta_roundtrip_with_edits() {
+    let base = (0..400).map(|i| format!("line {i}\n")).collect::<String>().into_bytes();
+
+    let mut target = base.clone();
+    target.splice(100..100, b"inserted in the middle\n".iter().copied());
+    target.truncate(target.len() - 50);
+    target.extend_from_slice(b"new tail\n");
+    target[0] = b'L';
+
+    let delta = mog::delta::compute(&base, &target, usize::MAX).unwrap();
+    assert!(delta.len() < target.len() / 10, "delta is {} bytes", delta.len());
+    assert_eq!(mog::delta::apply(&base, &delta).unwrap(), target);
+}
+
+#[test]
+fn test_delta_gives_up_past_max_len() {
+    let base   = vec![b'a'; 4096];
+    let target = (0..4096u32).map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8).collect::<Vec<_>>();
+
+    assert!(mog::delta::compute(&base, &target, 1024).is_none());
+
+    let delta = mog::delta::compute(&base, &target, usize::MAX).unwrap();
+    assert_eq!(mog::delta::apply(&base, &delta).unwrap(), target);
+}
+
+#[test]
+fn test_delta_apply_rejects_bad_input() {
+    let base  = b"0123456789abcdef0123456789abcdef".to_vec();
+    let delta = mog::delta::compute(&base, &base, usize::MAX).unwrap();
+    assert!(mog::delta::apply(&base[..8], &delta).is_err(), "copy past the end of the base");
+    assert!(mog::delta::apply(&base, &delta[..delta.len() - 1]).is_err(), "truncated op");
+    assert!(mog::delta::apply(&base, &[0, 0, 0, 0, 9]).is_err(), "unknown op");
+}
+
+//
+//
+// Chunk tests
+//
+//
+
+#[test]
+fn test_chunking_is_deterministic_and_bounded() {
+    let data = noise(12 * 1024 * 1024);
+
+    let mut chunks = Vec::new();
+    mog::chunk::for_each_chunk(&data[..], |c| { chunks.push(c.to_vec()); Ok(()) }).unwrap();
+
+    assert!(chunks.len() > 2);
+    assert!(chunks.iter().all(|c| c.len() <= 4 * 1024 * 1024));
+    assert!(chunks[..chunks.len() - 1].iter().all(|c| c.len() >= 256 * 1024));
+    assert_eq!(chunks.concat(), data);
+
+    // Reading in small pieces must not move the cut points.
+    let mut again = Vec::new();
+    mog::chunk::for_each_chunk(std::io::Read::chain(&data[..1000], &data[1000..]), |c| { again.push(c.len()); Ok(()) }).unwrap();
+    assert_eq!(again, chunks.iter().map(Vec::len).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_chunked_contents_roundtrip_through_storage() {
+    let mut repo = Repository::new_mock();
+
+    let small = b"small file".to_vec();
+    assert_eq!(mog::chunk::hash_contents(&small), mog::object::hash_blob(&small));
+
+    let large = noise(4 * 1024 * 1024);
+    let hash  = mog::chunk::write_contents(&mut repo, &large).unwrap();
+    assert_eq!(hash, mog::chunk::hash_contents(&large));
+    assert_eq!(&*repo.read_blob_bytes_without_touching_cache(&hash).unwrap(), &large[..]);
+
+    let mut pieces = 0;
+    repo.for_each_file_chunk(&hash, |_| { pieces += 1; Ok(()) }).unwrap();
+    assert!(pieces > 1);
+}
+
+//
+//
+// Config tests
+//
+//
+
+#[test]
+fn test_config_parses_sections_quotes_and_comments() {
+    let text = "\
+# user settings
+[Core]
+\tpager = less -R   ; trailing comment
+\tbare
+[remote \"my.server\"]
+\turl = \"ssh://host/srv/a b\"  # quoted keeps the spaces
+\turl = ssh://host/srv/second
+[branch \"main\"]
+\tremote = my.server
+";
+    let config = mog::config::Config::parse(text).unwrap();
+    assert_eq!(config.get("core.pager"), Some("less -R"));
+    assert_eq!(config.get("CORE.BARE"), Some("true"));
+    assert_eq!(config.get("remote.my.server.url"), Some("ssh://host/srv/second"), "last value wins");
+    assert_eq!(config.get("remote.My.server.url"), None, "subsections are case-sensitive");
+    assert_eq!(config.subsections("remote").collect::<Vec<_>>(), ["my.server"]);
+
+    let again = mog::config::Config::parse(&config.encode()).unwrap();
+    assert_eq!(again, config);
+
+    // A repository's settings override the user's.
+    let mut merged = mog::config::Config::parse("[core]\n\tpager = more\n\teditor = vi\n").unwrap();
+    merged.merge(config);
+    assert_eq!(merged.get("core.pager"), Some("less -R"));
+    assert_eq!(merged.get("core.editor"), Some("vi"));
+
+    assert!(mog::config::Config::parse("pager = less\n").is_err(), "outside a section");
+    assert!(mog::config::Config::parse("[core\n").is_err());
+    assert!(mog::config::Config::parse("[remote origin]\n").is_err());
+    assert!(mog::config::Config::parse("[core]\nx = \"open\n").is_err());
+}
+
+//
+//
+// Date tests
+//
+//
+
+#[test]
+fn test_dates_render_in_the_recorded_zone() {
+    use mog::date::{format_date, DateFormat};
+
+    let ts = 1792152187;
+    assert_eq!(format_date(ts, 120, DateFormat::Rfc2822, 0), "Fri, 16 Oct 2026 14:03:07 +0200");
+    assert_eq!(format_date(ts, -330, DateFormat::Rfc2822, 0), "Fri, 16 Oct 2026 06:33:07 -0530");
+    assert_eq!(format_date(ts, 120, DateFormat::Iso, 0), "2026-10-16T14:03:07+02:00");
+    assert_eq!(format_date(ts, -330, DateFormat::Iso, 0), "2026-10-16T06:33:07-05:30");
+    assert_eq!(format_date(ts, 120, DateFormat::Unix, 0), "1792152187");
+
+    assert_eq!(format_date(951782400, 0, DateFormat::Rfc2822, 0), "Tue, 29 Feb 2000 00:00:00 +0000", "leap day");
+    assert_eq!(format_date(-86400, 0, DateFormat::Iso, 0), "1969-12-31T00:00:00+00:00", "before the epoch");
+    assert_eq!(format_date(253402300799, 0, DateFormat::Iso, 0), "9999-12-31T23:59:59+00:00");
+}
+
+#[test]
+fn test_relative_dates() {
+    use mog::date::{format_date, DateFormat};
+
+    let now = 1_000_000_000;
+    let ago = |secs: i64| format_date(now - secs, 0, DateFormat::Relative, now);
+    assert_eq!(ago(1), "1 second ago");
+    assert_eq!(ago(45), "45 seconds ago");
+    assert_eq!(ago(60 * 5), "5 minutes ago");
+    assert_eq!(ago(3600 * 3), "3 hours ago");
+    assert_eq!(ago(86400 * 2), "2 days ago");
+    assert_eq!(ago(86400 * 21), "3 weeks ago");
+    assert_eq!(ago(86400 * 120), "4 months ago");
+    assert_eq!(ago(86400 * 365 * 2), "2 years ago");
+    assert_eq!(ago(-10), "in the future");
+
+    assert_eq!(DateFormat::parse("iso").unwrap(), DateFormat::Iso);
+    assert_eq!(DateFormat::parse("default").unwrap(), DateFormat::Rfc2822);
+    assert!(DateFormat::parse("yesterday").is_err());
+}
+
+#[test]
+fn test_parse_dates_for_since_and_until() {
+    use mog::date::parse_date;
+
+    let now = 1792152187;
+    assert_eq!(parse_date("1700000000", now).unwrap(), 1700000000);
+    assert_eq!(parse_date("@42", now).unwrap(), 42);
+    assert_eq!(parse_date("2026-10-16T14:03:07+02:00", now).unwrap(), now);
+    assert_eq!(parse_date("2026-10-16 12:03:07 Z", now).unwrap(), now);
+    assert_eq!(parse_date("2000-02-29 00:00 +0000", now).unwrap(), 951782400);
+    assert_eq!(parse_date("3 days ago", now).unwrap(), now - 3 * 86400);
+    assert_eq!(parse_date("2.weeks.ago", now).unwrap(), now - 14 * 86400);
+    assert_eq!(parse_date("1 hour ago", now).unwrap(), now - 3600);
+    assert_eq!(parse_date("now", now).unwrap(), now);
+    assert!(parse_date("yesterday", now).unwrap() < now);
+
+    assert!(parse_date("2026-13-01", now).is_err());
+    assert!(parse_date("3 fortnights ago", now).is_err());
+    assert!(parse_date("soon", now).is_err());
+}
+
 //
 //
 // Index tests
@@ -176,6 +489,35 @@ fn test_index_add_find_remove() {
     assert!(index.find("src/main.rs").is_none());
 }
 
+#[test]
+fn test_index_remove_many() {
+    let mut index = Index::default();
+    for i in 0..10u8 {
+        index.add(format!("f{i}.rs"), [i; 32], &make_fake_meta(i64::from(i), u64::from(i)));
+    }
+
+    // Missing paths and repeats don't count.
+    let removed = index.remove_many(["f1.rs", "f3.rs", "f3.rs", "nope.rs", "f9.rs"]);
+    assert_eq!(removed, 3);
+    assert_eq!(index.count, 7);
+    assert_eq!(index.remove_many(["f1.rs"]), 0);
+
+    for i in 0..10u8 {
+        let path = format!("f{i}.rs");
+        match index.find(&path) {
+            Some(j) => {
+                assert!(![1, 3, 9].contains(&i));
+                assert_eq!((index.hashes[j], index.mtimes[j], index.sizes[j]), ([i; 32], i64::from(i), u64::from(i)));
+            }
+            None => assert!([1, 3, 9].contains(&i), "{path}"),
+        }
+    }
+
+    let decoded = Index::decode_for_test(&index.encode_for_test()).unwrap();
+    assert_eq!(decoded.count, 7);
+    assert_eq!(decoded.hashes[decoded.find("f8.rs").unwrap()], [8; 32]);
+}
+
 #[test]
 fn test_index_dedup_on_re_add() {
     let mut index = Index::default();
@@ -231,6 +573,26 @@ fn test_index_encode_decode_roundtrip() {
     assert_eq!(decoded.hashes[j], [0xadu8; 32]);
 }
 
+#[test]
+fn test_index_is_written_sorted_by_path() {
+    let mut index = Index::default();
+    index.add("b.rs",   [0x02u8; 32], &make_fake_meta(2, 2));
+    index.add("a/z.rs", [0x01u8; 32], &make_fake_meta(1, 1));
+    index.add("c.rs",   [0x03u8; 32], &make_fake_meta(3, 3));
+    index.add("a.rs",   [0x04u8; 32], &make_fake_meta(4, 4));
+
+    let sorted = ["a.rs", "a/z.rs", "b.rs", "c.rs"];
+    assert_eq!(index.iter_sorted().map(|e| e.path).collect::<Vec<_>>(), sorted);
+
+    let decoded = Index::decode_for_test(&index.encode_for_test()).unwrap();
+    assert_eq!(decoded.iter().map(|e| e.path).collect::<Vec<_>>(), sorted);
+    assert_eq!(decoded.sorted_order(), [0, 1, 2, 3]);
+    for (path, hash, size) in [("a.rs", 0x04u8, 4), ("a/z.rs", 0x01, 1), ("b.rs", 0x02, 2)] {
+        let i = decoded.find(path).unwrap();
+        assert_eq!((decoded.hashes[i], decoded.sizes[i]), ([hash; 32], size));
+    }
+}
+
 //
 //
 // Hash table / storage stress test
@@ -251,7 +613,7 @@ fn test_mock_storage_many_objects() {
 
     for (i, hash) in hashes.iter().enumerate() {
         let raw  = repo.storage.read(hash).unwrap();
-        let got  = mog::object::decode_blob_bytes(raw).unwrap();
+        let got  = mog::object::decode_blob_bytes(&raw).unwrap();
         let want = format!("object number {i}");
         assert_eq!(got, want.as_bytes());
     }
@@ -316,6 +678,118 @@ fn test_index_clear() {
     assert!(index.find("b.rs").is_none());
 }
 
+#[test]
+fn test_index_encode_decode_keeps_stat_data() {
+    let dir  = tempfile::TempDir::new().unwrap();
+    let path = dir.path().join("file.rs");
+    std::fs::write(&path, b"contents").unwrap();
+    let meta = std::fs::symlink_metadata(&path).unwrap();
+
+    let mut index = Index::default();
+    index.add("file.rs", [0x11u8; 32], &meta);
+
+    let decoded = Index::decode_for_test(&index.encode_for_test()).unwrap();
+    assert_eq!(decoded.mtime_nsecs, index.mtime_nsecs);
+    assert_eq!(decoded.ctimes,      index.ctimes);
+    assert_eq!(decoded.ctime_nsecs, index.ctime_nsecs);
+    assert_eq!(decoded.inodes,      index.inodes);
+    assert_eq!(decoded.devices,     index.devices);
+    assert!(!decoded.is_dirty(0, &meta));
+}
+
+#[test]
+fn test_index_v1_still_decodes() {
+    let mut v1 = Vec::new();
+    v1.extend_from_slice(b"MOGI");
+    v1.extend_from_slice(&1u32.to_le_bytes());
+    v1.extend_from_slice(&1u32.to_le_bytes());
+    v1.extend_from_slice(&0o100_644u32.to_le_bytes());
+    v1.extend_from_slice(&[0x22u8; 32]);
+    v1.extend_from_slice(&1234i64.to_le_bytes());
+    v1.extend_from_slice(&5u64.to_le_bytes());
+    v1.extend_from_slice(&0u32.to_le_bytes());
+    v1.extend_from_slice(&7u32.to_le_bytes());
+    v1.extend_from_slice(b"file.rs");
+
+    let index = Index::decode_for_test(&v1).unwrap();
+    let i = index.find("file.rs").unwrap();
+    assert_eq!(index.hashes[i], [0x22u8; 32]);
+    assert_eq!((index.mtimes[i], index.sizes[i], index.inodes[i]), (1234, 5, 0));
+
+    //
+    // Written back as v2.
+    //
+    let encoded = index.encode_for_test();
+    assert_eq!(&encoded[4..8], &2u32.to_le_bytes());
+    assert_eq!(Index::decode_for_test(&encoded).unwrap().get_path(0), "file.rs");
+}
+
+#[test]
+fn test_index_tree_cache_roundtrips_and_is_optional() {
+    let mut index = Index::default();
+    index.add("a/b/x.rs", [0x01u8; 32], &make_fake_meta(1, 1));
+    index.add("c.rs",     [0x02u8; 32], &make_fake_meta(2, 2));
+
+    //
+    // No cache, no extension.
+    //
+    let bare = index.encode_for_test();
+    assert_eq!(Index::decode_for_test(&bare).unwrap().cached_tree_count(), 0);
+
+    index.cache_tree("",    [0xaau8; 32]);
+    index.cache_tree("a/b", [0xbbu8; 32]);
+    let encoded = index.encode_for_test();
+    assert!(encoded.starts_with(&bare));
+
+    let decoded = Index::decode_for_test(&encoded).unwrap();
+    assert_eq!(decoded.count, 2);
+    assert_eq!(decoded.cached_tree_count(), 2);
+    assert_eq!(decoded.cached_tree(""),    Some([0xaau8; 32]));
+    assert_eq!(decoded.cached_tree("a/b"), Some([0xbbu8; 32]));
+    assert_eq!(decoded.cached_tree("a"),   None);
+
+    //
+    // Unknown extensions are skipped, truncated ones rejected.
+    //
+    let mut extended = encoded.clone();
+    extended.extend_from_slice(b"XTRA");
+    extended.extend_from_slice(&3u32.to_le_bytes());
+    extended.extend_from_slice(b"abc");
+    assert_eq!(Index::decode_for_test(&extended).unwrap().cached_tree_count(), 2);
+    assert!(Index::decode_for_test(&extended[..extended.len() - 1]).is_err());
+}
+
+#[test]
+fn test_index_tree_cache_invalidated_only_along_changed_path() {
+    let mut index = Index::default();
+    index.add("a/b/x.rs", [0x01u8; 32], &make_fake_meta(1, 1));
+    index.add("a/c/y.rs", [0x02u8; 32], &make_fake_meta(2, 2));
+    index.add("d/z.rs",   [0x03u8; 32], &make_fake_meta(3, 3));
+    for dir in ["", "a", "a/b", "a/c", "d"] {
+        index.cache_tree(dir, [0xeeu8; 32]);
+    }
+
+    //
+    // Re-staging the same contents (a touch) keeps everything.
+    //
+    index.add("a/b/x.rs", [0x01u8; 32], &make_fake_meta(10, 1));
+    assert_eq!(index.cached_tree_count(), 5);
+
+    index.add("a/b/x.rs", [0x11u8; 32], &make_fake_meta(11, 1));
+    assert_eq!(index.cached_tree(""),    None);
+    assert_eq!(index.cached_tree("a"),   None);
+    assert_eq!(index.cached_tree("a/b"), None);
+    assert!(index.cached_tree("a/c").is_some());
+    assert!(index.cached_tree("d").is_some());
+
+    index.remove("d/z.rs");
+    assert_eq!(index.cached_tree("d"), None);
+    assert!(index.cached_tree("a/c").is_some());
+
+    index.add("a/c/new.rs", [0x04u8; 32], &make_fake_meta(4, 4));
+    assert_eq!(index.cached_tree_count(), 0);
+}
+
 #[test]
 fn test_index_encode_decode_empty() {
     let index   = Index::default();
@@ -430,7 +904,7 @@ fn test_commit_chain() {
     let mut current = *hashes.last().unwrap();
     for i in (0..10).rev() {
         let obj = repo.stores.decode_and_push_object(
-            repo.storage.read(&current).unwrap()
+            &repo.storage.read(&current).unwrap()
         ).unwrap();
         let id  = obj.try_as_commit_id().unwrap();
         assert_eq!(repo.commit.get_message(id), format!("commit {i}"));
@@ -488,7 +962,7 @@ fn test_blob_with_null_bytes() {
     let data = b"hello\x00world\x00";
     let hash = repo.write_blob(data);
     let raw  = repo.storage.read(&hash).unwrap();
-    let got  = mog::object::decode_blob_bytes(raw).unwrap();
+    let got  = mog::object::decode_blob_bytes(&raw).unwrap();
     assert_eq!(got, data);
 }
 
@@ -498,7 +972,7 @@ fn test_blob_with_unicode() {
     let data = "こんにちは世界 🦀".as_bytes();
     let hash = repo.write_blob(data);
     let raw  = repo.storage.read(&hash).unwrap();
-    let got  = mog::object::decode_blob_bytes(raw).unwrap();
+    let got  = mog::object::decode_blob_bytes(&raw).unwrap();
     assert_eq!(got, data);
 }
 
@@ -561,7 +1035,7 @@ fn test_full_commit_workflow() {
     // Traverse root -> src dir -> main.rs
     //
     let t2_obj = repo.stores.decode_and_push_object(
-        repo.storage.read(&tree_hash2).unwrap()
+        &repo.storage.read(&tree_hash2).unwrap()
     ).unwrap();
     let t2_id = t2_obj.try_as_tree_id().unwrap();
 
@@ -569,14 +1043,14 @@ fn test_full_commit_workflow() {
     let src_hash = repo.tree.find_entry(t2_id, "src").unwrap();
 
     let src_obj = repo.stores.decode_and_push_object(
-        repo.storage.read(&src_hash).unwrap()
+        &repo.storage.read(&src_hash).unwrap()
     ).unwrap();
     let src_id = src_obj.try_as_tree_id().unwrap();
 
     // src tree has "main.rs".
     let main_hash = repo.tree.find_entry(src_id, "main.rs").unwrap();
     let raw  = repo.storage.read(&main_hash).unwrap();
-    let data = mog::object::decode_blob_bytes(raw).unwrap();
+    let data = mog::object::decode_blob_bytes(&raw).unwrap();
     assert_eq!(data, b"fn main() { println!(\"hello\"); }");
 }
 
@@ -599,6 +1073,47 @@ fn test_tree_content_addressed_dedup() {
     assert_eq!(t1, t2);
 }
 
+#[test]
+fn test_write_tree_reuses_cached_subtrees() {
+    let mut repo = mock_repo();
+
+    let blobs = ["a/b/x.rs", "a/c/y.rs", "d/z.rs", "top.rs"].map(|path| (path, repo.write_blob(path.as_bytes())));
+    let mut index = Index::default();
+    for (path, hash) in blobs {
+        index.add(path, hash, &make_fake_meta(1, 1));
+    }
+
+    let t1 = index.write_tree(&mut repo).unwrap();
+    assert_eq!(index.cached_tree(""), Some(t1));
+    assert_eq!(index.cached_tree_count(), 5);
+    assert_eq!(index.write_tree(&mut repo).unwrap(), t1);
+
+    //
+    // After a change, the rebuilt tree is the same one a cold index builds.
+    //
+    let h = repo.write_blob(b"changed");
+    index.add("a/b/x.rs", h, &make_fake_meta(2, 7));
+    let t2 = index.write_tree(&mut repo).unwrap();
+    assert_ne!(t1, t2);
+
+    let mut cold = Index::default();
+    for i in 0..index.count {
+        cold.add(index.get_path(i), index.hashes[i], &make_fake_meta(1, 1));
+    }
+    assert_eq!(cold.write_tree(&mut repo).unwrap(), t2);
+
+    //
+    // Cached directories really are taken as-is rather than rebuilt.
+    //
+    let a_c = index.cached_tree("a/c").unwrap();
+    index.add("top.rs", h, &make_fake_meta(3, 7));
+    index.cache_tree("d", a_c);
+    let t3 = index.write_tree(&mut repo).unwrap();
+
+    let t3_id = repo.read_object(&t3).unwrap().try_as_tree_id().unwrap();
+    assert_eq!(repo.tree.find_entry(t3_id, "d"), Some(a_c));
+}
+
 #[test]
 fn test_status_clean_after_add() {
     let mut repo = mock_repo();
@@ -749,9 +1264,9 @@ fn test_sorted_flat_tree_binary_search_correctness() {
     assert_eq!(flat.len(), 26);
 
     // Every entry should be findable.
-    for i in 0..26usize {
+    for (i, hash) in hashes.iter().enumerate() {
         let name = format!("{}.rs", (b'a' + i as u8) as char);
-        assert_eq!(flat.lookup(&name), Some(hashes[i]), "failed to find {name}");
+        assert_eq!(flat.lookup(&name), Some(*hash), "failed to find {name}");
     }
 
     // Non-existent entries should return None.
@@ -778,6 +1293,22 @@ fn test_sorted_flat_tree_single_entry() {
     assert_eq!(flat.lookup("other.rs"), None);
 }
 
+#[test]
+fn test_sorted_flat_tree_lookup_entry_keeps_modes() {
+    use mog::object::{MODE_EXEC, MODE_FILE, MODE_LINK};
+
+    let mut builder = mog::status::FlatTreeBuilder::new();
+    builder.push_with_mode("z_link", [1; 32], MODE_LINK);
+    builder.push("m_file.rs", [2; 32]);
+    builder.push_with_mode("a_script.sh", [3; 32], MODE_EXEC);
+    let flat = builder.build();
+
+    assert_eq!(flat.lookup_entry("z_link"),      Some(([1; 32], MODE_LINK)));
+    assert_eq!(flat.lookup_entry("m_file.rs"),   Some(([2; 32], MODE_FILE)));
+    assert_eq!(flat.lookup_entry("a_script.sh"), Some(([3; 32], MODE_EXEC)));
+    assert_eq!(flat.lookup("z_link"), Some([1; 32]));
+}
+
 #[test]
 fn test_commit_history_chain_integrity() {
     let mut repo = mock_repo();
@@ -807,17 +1338,17 @@ fn test_commit_history_chain_integrity() {
     let mut current = commit_hashes.last().unwrap().0;
     for i in (0..5).rev() {
         let raw       = repo.storage.read(&current).unwrap();
-        let obj       = repo.stores.decode_and_push_object(raw).unwrap();
+        let obj       = repo.stores.decode_and_push_object(&raw).unwrap();
         let cid       = obj.try_as_commit_id().unwrap();
         let tree_hash = repo.commit.get_tree(cid);
 
         let tree_raw  = repo.storage.read(&tree_hash).unwrap();
-        let tree_obj  = repo.stores.decode_and_push_object(tree_raw).unwrap();
+        let tree_obj  = repo.stores.decode_and_push_object(&tree_raw).unwrap();
         let tid       = tree_obj.try_as_tree_id().unwrap();
 
         let blob_hash = repo.tree.find_entry(tid, "file.rs").unwrap();
         let blob_raw  = repo.storage.read(&blob_hash).unwrap();
-        let blob_data = mog::object::decode_blob_bytes(blob_raw).unwrap();
+        let blob_data = mog::object::decode_blob_bytes(&blob_raw).unwrap();
         assert_eq!(blob_data, format!("version {i}").as_bytes());
 
         let parents = repo.commit.get_parents(cid);
@@ -841,7 +1372,7 @@ fn test_blob_single_byte() {
     for b in 0u8..=255 {
         let hash = repo.write_blob(&[b]);
         let raw  = repo.storage.read(&hash).unwrap();
-        let got  = mog::object::decode_blob_bytes(raw).unwrap();
+        let got  = mog::object::decode_blob_bytes(&raw).unwrap();
         assert_eq!(got, &[b], "failed for byte 0x{b:02x}");
     }
     // All 256 single-byte blobs should be distinct objects.
@@ -903,7 +1434,7 @@ fn test_index_remove_all_entries_one_by_one() {
     let n = 50usize;
 
     for i in 0..n {
-        index.add(&format!("file_{i}.rs"), [i as u8; 32], &make_fake_meta(i as i64, i as u64));
+        index.add(format!("file_{i}.rs"), [i as u8; 32], &make_fake_meta(i as i64, i as u64));
     }
     assert_eq!(index.count, n);
 
@@ -963,7 +1494,7 @@ fn test_tree_entry_name_with_spaces_and_dots() {
 
     // Roundtrip.
     let raw     = repo.storage.read(&tree_hash).unwrap();
-    let obj     = repo.stores.decode_and_push_object(raw).unwrap();
+    let obj     = repo.stores.decode_and_push_object(&raw).unwrap();
     let tid     = obj.try_as_tree_id().unwrap();
 
     assert_eq!(repo.tree.find_entry(tid, "my file.txt"),      Some(h));
@@ -1063,7 +1594,7 @@ fn test_index_encode_decode_large() {
     // Spot-check a few.
     for i in [0, 1, 99, 100, 999, 4999] {
         let path = format!("src/module_{:04}/file_{:04}.rs", i / 100, i % 100);
-        let idx  = decoded.find(&path).expect(&format!("missing {path}"));
+        let idx  = decoded.find(&path).unwrap_or_else(|| panic!("missing {path}"));
         let mut expected_hash = [0u8; 32];
         expected_hash[..8].copy_from_slice(&(i as usize).to_le_bytes());
         assert_eq!(decoded.hashes[idx], expected_hash);
@@ -1109,32 +1640,32 @@ fn test_checkout_restores_correct_historical_state() {
     let _c3_h = repo.write_object(mog::object::Object::Commit(c3));
 
     // Now "checkout" commit 1 and verify state.
-    let t1_obj  = repo.stores.decode_and_push_object(repo.storage.read(&t1).unwrap()).unwrap();
+    let t1_obj  = repo.stores.decode_and_push_object(&repo.storage.read(&t1).unwrap()).unwrap();
     let t1_id   = t1_obj.try_as_tree_id().unwrap();
     let src_h   = repo.tree.find_entry(t1_id, "src").unwrap();
-    let src_obj = repo.stores.decode_and_push_object(repo.storage.read(&src_h).unwrap()).unwrap();
+    let src_obj = repo.stores.decode_and_push_object(&repo.storage.read(&src_h).unwrap()).unwrap();
     let src_id  = src_obj.try_as_tree_id().unwrap();
 
     // At commit 1, main.rs should be v1.
     let main_h = repo.tree.find_entry(src_id, "main.rs").unwrap();
-    let data   = mog::object::decode_blob_bytes(repo.storage.read(&main_h).unwrap()).unwrap();
+    let data   = mog::object::decode_blob_bytes(&repo.storage.read(&main_h).unwrap()).unwrap().to_vec();
     assert_eq!(data, b"fn main() {}");
 
     // At commit 1, README should not exist.
     assert_eq!(repo.tree.find_entry(t1_id, "README.md"), None);
 
     // At commit 2, README should be v1.
-    let t2_obj  = repo.stores.decode_and_push_object(repo.storage.read(&t2).unwrap()).unwrap();
+    let t2_obj  = repo.stores.decode_and_push_object(&repo.storage.read(&t2).unwrap()).unwrap();
     let t2_id   = t2_obj.try_as_tree_id().unwrap();
     let readme_h = repo.tree.find_entry(t2_id, "README.md").unwrap();
-    let readme   = mog::object::decode_blob_bytes(repo.storage.read(&readme_h).unwrap()).unwrap();
+    let readme   = mog::object::decode_blob_bytes(&repo.storage.read(&readme_h).unwrap()).unwrap().to_vec();
     assert_eq!(readme, b"# My Project");
 
     // At commit 3, README should be v2.
-    let t3_obj   = repo.stores.decode_and_push_object(repo.storage.read(&t3).unwrap()).unwrap();
+    let t3_obj   = repo.stores.decode_and_push_object(&repo.storage.read(&t3).unwrap()).unwrap();
     let t3_id    = t3_obj.try_as_tree_id().unwrap();
     let readme_h = repo.tree.find_entry(t3_id, "README.md").unwrap();
-    let readme   = mog::object::decode_blob_bytes(repo.storage.read(&readme_h).unwrap()).unwrap();
+    let readme   = mog::object::decode_blob_bytes(&repo.storage.read(&readme_h).unwrap()).unwrap().to_vec();
     assert_eq!(readme, b"# My Project\n\nA great project.");
 }
 
@@ -1169,13 +1700,13 @@ fn test_diverging_branches() {
     let c_b_h = repo.write_object(mog::object::Object::Commit(c_b));
 
     // Branch A should not contain feature_b.rs.
-    let ta_obj = repo.stores.decode_and_push_object(repo.storage.read(&t_a).unwrap()).unwrap();
+    let ta_obj = repo.stores.decode_and_push_object(&repo.storage.read(&t_a).unwrap()).unwrap();
     let ta_id  = ta_obj.try_as_tree_id().unwrap();
     assert!(repo.tree.find_entry(ta_id, "feature_a.rs").is_some());
     assert!(repo.tree.find_entry(ta_id, "feature_b.rs").is_none());
 
     // Branch B should not contain feature_a.rs.
-    let tb_obj = repo.stores.decode_and_push_object(repo.storage.read(&t_b).unwrap()).unwrap();
+    let tb_obj = repo.stores.decode_and_push_object(&repo.storage.read(&t_b).unwrap()).unwrap();
     let tb_id  = tb_obj.try_as_tree_id().unwrap();
     assert!(repo.tree.find_entry(tb_id, "feature_b.rs").is_some());
     assert!(repo.tree.find_entry(tb_id, "feature_a.rs").is_none());
@@ -1226,7 +1757,7 @@ fn test_rename_file_across_commits() {
     assert_ne!(t1, t2);
 
     // Content is identical - same hash, no duplicate blob.
-    let t2_obj  = repo.stores.decode_and_push_object(repo.storage.read(&t2).unwrap()).unwrap();
+    let t2_obj  = repo.stores.decode_and_push_object(&repo.storage.read(&t2).unwrap()).unwrap();
     let t2_id   = t2_obj.try_as_tree_id().unwrap();
     let new_h   = repo.tree.find_entry(t2_id, "new_name.rs").unwrap();
     assert_eq!(new_h, h);
@@ -1255,15 +1786,15 @@ fn test_delete_file_recoverable_from_history() {
     let _c2_h = repo.write_object(mog::object::Object::Commit(c2));
 
     // Current tree: precious.rs gone.
-    let t2_obj = repo.stores.decode_and_push_object(repo.storage.read(&t2).unwrap()).unwrap();
+    let t2_obj = repo.stores.decode_and_push_object(&repo.storage.read(&t2).unwrap()).unwrap();
     let t2_id  = t2_obj.try_as_tree_id().unwrap();
     assert!(repo.tree.find_entry(t2_id, "precious.rs").is_none());
 
     // Historical tree at c1: precious.rs recoverable.
-    let t1_obj = repo.stores.decode_and_push_object(repo.storage.read(&t1).unwrap()).unwrap();
+    let t1_obj = repo.stores.decode_and_push_object(&repo.storage.read(&t1).unwrap()).unwrap();
     let t1_id  = t1_obj.try_as_tree_id().unwrap();
     let old_h  = repo.tree.find_entry(t1_id, "precious.rs").unwrap();
-    let data   = mog::object::decode_blob_bytes(repo.storage.read(&old_h).unwrap()).unwrap();
+    let data   = mog::object::decode_blob_bytes(&repo.storage.read(&old_h).unwrap()).unwrap().to_vec();
     assert_eq!(data, precious);
 }
 
@@ -1299,27 +1830,27 @@ fn test_deep_nested_tree_structure() {
     let tree_hash = idx.write_tree(&mut repo).unwrap();
 
     // Verify by walking the tree manually for a few deep paths.
-    let root_obj = repo.stores.decode_and_push_object(repo.storage.read(&tree_hash).unwrap()).unwrap();
+    let root_obj = repo.stores.decode_and_push_object(&repo.storage.read(&tree_hash).unwrap()).unwrap();
     let root_id  = root_obj.try_as_tree_id().unwrap();
 
     // src/core/storage/backend.rs
     let src_h    = repo.tree.find_entry(root_id, "src").unwrap();
-    let src_obj  = repo.stores.decode_and_push_object(repo.storage.read(&src_h).unwrap()).unwrap();
+    let src_obj  = repo.stores.decode_and_push_object(&repo.storage.read(&src_h).unwrap()).unwrap();
     let src_id   = src_obj.try_as_tree_id().unwrap();
     let core_h   = repo.tree.find_entry(src_id, "core").unwrap();
-    let core_obj = repo.stores.decode_and_push_object(repo.storage.read(&core_h).unwrap()).unwrap();
+    let core_obj = repo.stores.decode_and_push_object(&repo.storage.read(&core_h).unwrap()).unwrap();
     let core_id  = core_obj.try_as_tree_id().unwrap();
     let stor_h   = repo.tree.find_entry(core_id, "storage").unwrap();
-    let stor_obj = repo.stores.decode_and_push_object(repo.storage.read(&stor_h).unwrap()).unwrap();
+    let stor_obj = repo.stores.decode_and_push_object(&repo.storage.read(&stor_h).unwrap()).unwrap();
     let stor_id  = stor_obj.try_as_tree_id().unwrap();
 
     let backend_h = repo.tree.find_entry(stor_id, "backend.rs").unwrap();
-    let data = mog::object::decode_blob_bytes(repo.storage.read(&backend_h).unwrap()).unwrap();
+    let data = mog::object::decode_blob_bytes(&repo.storage.read(&backend_h).unwrap()).unwrap().to_vec();
     assert_eq!(data, b"pub struct Backend;");
 
     // Cargo.toml at root.
     let cargo_h = repo.tree.find_entry(root_id, "Cargo.toml").unwrap();
-    let data = mog::object::decode_blob_bytes(repo.storage.read(&cargo_h).unwrap()).unwrap();
+    let data = mog::object::decode_blob_bytes(&repo.storage.read(&cargo_h).unwrap()).unwrap().to_vec();
     assert_eq!(data, b"[package]");
 
     // Encode/decode the index and verify count.
@@ -1389,10 +1920,10 @@ fn test_revert_commit() {
     assert_eq!(repo.commit.get_parents(c_revert), &[c2_h]);
 
     // Verify the content is restored.
-    let rt_obj = repo.stores.decode_and_push_object(repo.storage.read(&t1).unwrap()).unwrap();
+    let rt_obj = repo.stores.decode_and_push_object(&repo.storage.read(&t1).unwrap()).unwrap();
     let rt_id  = rt_obj.try_as_tree_id().unwrap();
     let app_h  = repo.tree.find_entry(rt_id, "app.rs").unwrap();
-    let data   = mog::object::decode_blob_bytes(repo.storage.read(&app_h).unwrap()).unwrap();
+    let data   = mog::object::decode_blob_bytes(&repo.storage.read(&app_h).unwrap()).unwrap().to_vec();
     assert_eq!(data, b"good code");
 }
 
@@ -1474,6 +2005,23 @@ fn test_deterministic_hashing_across_repos() {
 //
 
 /// Verify blake3 avalanche effect - single bit flip produces completely different hash.
+#[test]
+fn test_hash_hex_prefix_matching() {
+    let hash = mog::hash::hash_bytes(b"prefix");
+    let hex  = mog::hash::hash_to_hex(&hash);
+
+    assert!(mog::hash::hash_has_hex_prefix(&hash, ""));
+    assert!(mog::hash::hash_has_hex_prefix(&hash, &hex[..1]));
+    assert!(mog::hash::hash_has_hex_prefix(&hash, &hex[..7]));
+    assert!(mog::hash::hash_has_hex_prefix(&hash, &hex));
+    assert!(mog::hash::hash_has_hex_prefix(&hash, &hex[..9].to_uppercase()));
+    assert!(!mog::hash::hash_has_hex_prefix(&hash, "xyz"));
+    assert!(!mog::hash::hash_has_hex_prefix(&hash, &format!("{hex}0")));
+
+    let flipped = if hex.as_bytes()[4] == b'0' { '1' } else { '0' };
+    assert!(!mog::hash::hash_has_hex_prefix(&hash, &format!("{}{flipped}", &hex[..4])));
+}
+
 #[test]
 fn test_hash_avalanche_effect() {
     let mut repo = mock_repo();
@@ -1559,7 +2107,7 @@ fn test_tree_of_only_directories() {
     ]);
     let root_hash = repo.write_object(mog::object::Object::Tree(root));
 
-    let obj  = repo.stores.decode_and_push_object(repo.storage.read(&root_hash).unwrap()).unwrap();
+    let obj  = repo.stores.decode_and_push_object(&repo.storage.read(&root_hash).unwrap()).unwrap();
     let id   = obj.try_as_tree_id().unwrap();
     assert_eq!(repo.tree.entry_count(id), 3);
     assert_eq!(repo.tree.get_entry(id, 0).mode, mog::object::MODE_DIR);
@@ -1568,7 +2116,7 @@ fn test_tree_of_only_directories() {
 
     // Verify each dir subtree is reachable.
     let mb_h  = repo.tree.find_entry(id, "mod_b").unwrap();
-    let mb_obj = repo.stores.decode_and_push_object(repo.storage.read(&mb_h).unwrap()).unwrap();
+    let mb_obj = repo.stores.decode_and_push_object(&repo.storage.read(&mb_h).unwrap()).unwrap();
     let mb_id  = mb_obj.try_as_tree_id().unwrap();
     assert_eq!(repo.tree.find_entry(mb_id, "b.rs"), Some(h2));
 }
@@ -1621,7 +2169,7 @@ fn test_octopus_merge() {
         let content   = format!("branch {i} content");
         let bh        = repo.write_blob(content.as_bytes());
         let mut idx   = Index::default();
-        idx.add(&format!("branch_{i}.rs"), bh, &make_fake_meta(i as i64, content.len() as u64));
+        idx.add(format!("branch_{i}.rs"), bh, &make_fake_meta(i as i64, content.len() as u64));
         let t         = idx.write_tree(&mut repo).unwrap();
         let c         = repo.commit.push(t, &[base_hash], 2000 + i as i64, "dev", &format!("branch {i}"));
         let ch        = repo.write_object(mog::object::Object::Commit(c));
@@ -1656,6 +2204,60 @@ fn test_commit_hash_changes_with_parent() {
     assert_ne!(c1_h, c2_h);
 }
 
+//
+//
+// Three-way line merge
+//
+//
+
+#[test]
+fn test_merge_lines_non_overlapping_changes() {
+    let base   = "a\nb\nc\nd\ne\n";
+    let ours   = "A\nb\nc\nd\ne\n";
+    let theirs = "a\nb\nc\nd\nE\n";
+
+    let merged = mog::merge::merge_lines(base, ours, theirs, "HEAD", "feature");
+    assert_eq!(merged.conflicts, 0);
+    assert_eq!(merged.text, "A\nb\nc\nd\nE\n");
+}
+
+#[test]
+fn test_merge_lines_identical_changes_are_clean() {
+    let base = "a\nb\nc\n";
+    let both = "a\nB\nc\n";
+
+    let merged = mog::merge::merge_lines(base, both, both, "HEAD", "feature");
+    assert_eq!(merged.conflicts, 0);
+    assert_eq!(merged.text, both);
+}
+
+#[test]
+fn test_merge_lines_overlapping_changes_conflict() {
+    let base   = "a\nb\nc\n";
+    let ours   = "a\nours\nc\n";
+    let theirs = "a\ntheirs\nc\n";
+
+    let merged = mog::merge::merge_lines(base, ours, theirs, "HEAD", "feature");
+    assert_eq!(merged.conflicts, 1);
+    assert_eq!(
+        merged.text,
+        "a\n<<<<<<< HEAD\nours\n=======\ntheirs\n>>>>>>> feature\nc\n"
+    );
+    assert!(mog::merge::has_conflict_markers(merged.text.as_bytes()));
+}
+
+#[test]
+fn test_merge_lines_missing_trailing_newline() {
+    let base   = "a\nb";
+    let ours   = "a\nours";
+    let theirs = "a\ntheirs";
+
+    let merged = mog::merge::merge_lines(base, ours, theirs, "HEAD", "feature");
+    assert_eq!(merged.conflicts, 1);
+    // Markers always start on their own line.
+    assert!(merged.text.contains("ours\n=======\ntheirs\n>>>>>>> feature\n"));
+}
+
 //
 //
 // Status correctness under complex mutations
@@ -1778,7 +2380,7 @@ fn test_encode_decode_identity_property() {
     for &data in cases {
         let hash = repo.write_blob(data);
         let raw  = repo.storage.read(&hash).unwrap();
-        let got  = mog::object::decode_blob_bytes(raw).unwrap();
+        let got  = mog::object::decode_blob_bytes(&raw).unwrap();
         assert_eq!(got, data);
     }
 }
@@ -1792,13 +2394,13 @@ fn test_index_encode_decode_identity_property() {
     for i in 0..100usize {
         let mut h = [0u8; 32];
         h[..8].copy_from_slice(&i.to_le_bytes());
-        index.add(&format!("file_{i:03}.rs"), h, &make_fake_meta(i as i64 * 1000, i as u64 * 100));
+        index.add(format!("file_{i:03}.rs"), h, &make_fake_meta(i as i64 * 1000, i as u64 * 100));
     }
 
     // Remove every third.
     let to_remove: Vec<_> = (0..100usize).filter(|i| i % 3 == 0).collect();
     for i in to_remove {
-        index.remove(&format!("file_{i:03}.rs"));
+        index.remove(format!("file_{i:03}.rs"));
     }
 
     let encoded = index.encode_for_test();
@@ -1868,3 +2470,14 @@ fn mock_repo() -> Repository<mog::storage_mock::MockStorage> {
 fn make_fake_meta(mtime: i64, size: u64) -> mog::index::FakeMeta {
     mog::index::FakeMeta { mtime, size }
 }
+
+/// Xorshift bytes: no repeating structure, so cut points land like they would in real data.
+fn noise(len: usize) -> Vec<u8> {
+    let mut x = 0x2545_F491_4F6C_DD1Du64;
+    (0..len).map(|_| {
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        (x >> 32) as u8
+    }).collect()
+}